@@ -0,0 +1,93 @@
+//! Per-request cost of the proxy before any upstream fetch
+//!
+//! Served from `data:` URIs and rejected signatures, so no network is
+//! involved and the numbers only cover routing, decoding and verification.
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use camo::server::{batch, config::Config, config::OutputFormat, create_router};
+use camo::CamoUrl;
+use clap::Parser;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::hint::black_box;
+use tower::ServiceExt;
+
+const KEY: &str = "0x24FEEDFACEDEADBEEFCAFE";
+const TARGET: &str = "data:image/png;base64,iVBORw0KGgo=";
+
+fn router(c: &mut Criterion) {
+    let config = Config::parse_from(["camo", "--key", KEY, "--allowed-schemes", "data"]);
+    let router = create_router(&config).unwrap();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("Failed to create runtime");
+
+    let signed = CamoUrl::new(KEY).sign(TARGET);
+    let (hex, base64) = (signed.to_path(), signed.clone().base64().to_path());
+    let forged = CamoUrl::new("other-key").sign(TARGET).to_path();
+    let request = |path: &str, status: StatusCode| {
+        let request = Request::get(path).body(Body::empty()).unwrap();
+        let response = runtime.block_on(router.clone().oneshot(request)).unwrap();
+        assert_eq!(response.status(), status);
+    };
+
+    let mut group = c.benchmark_group("router");
+    group.bench_function("hex", |b| b.iter(|| request(black_box(&hex), StatusCode::OK)));
+    group.bench_function("base64", |b| {
+        b.iter(|| request(black_box(&base64), StatusCode::OK))
+    });
+    group.bench_function("digest_mismatch", |b| {
+        b.iter(|| request(black_box(&forged), StatusCode::BAD_REQUEST))
+    });
+    group.finish();
+}
+
+fn sign_lines(c: &mut Criterion) {
+    let camo = CamoUrl::new(KEY);
+    let input: String = (0..1000)
+        .map(|i| format!("https://example.com/images/{}.png\n", i))
+        .collect();
+
+    let mut group = c.benchmark_group("sign_lines");
+    group.throughput(Throughput::Elements(1000));
+    for (name, format) in [("plain", OutputFormat::Plain), ("json", OutputFormat::Json)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                batch::sign_lines(&camo, "", format, input.as_bytes(), std::io::sink()).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// The same hot URL over and over, with and without the verification cache
+fn verify_cache(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("Failed to create runtime");
+    let path = CamoUrl::new(KEY).sign(TARGET).to_path();
+
+    let mut group = c.benchmark_group("verify_cache");
+    for (name, size) in [("enabled", "1024"), ("disabled", "0")] {
+        let config = Config::parse_from([
+            "camo",
+            "--key",
+            KEY,
+            "--allowed-schemes",
+            "data",
+            "--verify-cache-size",
+            size,
+        ]);
+        let router = create_router(&config).unwrap();
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let request = Request::get(black_box(&path)).body(Body::empty()).unwrap();
+                runtime.block_on(router.clone().oneshot(request)).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, router, sign_lines, verify_cache);
+criterion_main!(benches);
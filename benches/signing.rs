@@ -0,0 +1,87 @@
+//! Signing throughput: digests, URL encodings and whole links
+//!
+//! `cargo bench --bench signing -- --save-baseline <name>` keeps a run to
+//! compare later ones against with `--baseline <name>`.
+
+use camo::{encode_url_base64, encode_url_hex, generate_digest, verify_digest, CamoUrl};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+
+const KEY: &str = "0x24FEEDFACEDEADBEEFCAFE";
+
+/// A typical image URL and one with a long query string
+fn urls() -> [(&'static str, String); 2] {
+    let long = format!(
+        "https://images.example.com/photos/2024/summer/beach.jpg?{}",
+        "w=1200&h=800&fit=crop&auto=format&sig=abcdef0123456789&".repeat(20)
+    );
+    [("short", "https://example.com/image.png".to_string()), ("long", long)]
+}
+
+fn digest(c: &mut Criterion) {
+    let mut group = c.benchmark_group("digest");
+    for (name, url) in urls() {
+        group.throughput(Throughput::Bytes(url.len() as u64));
+        group.bench_with_input(BenchmarkId::new("generate", name), &url, |b, url| {
+            b.iter(|| generate_digest(black_box(KEY), black_box(url)))
+        });
+
+        let digest = generate_digest(KEY, &url);
+        group.bench_with_input(BenchmarkId::new("verify", name), &url, |b, url| {
+            b.iter(|| verify_digest(black_box(KEY), black_box(url), black_box(&digest)))
+        });
+    }
+    group.finish();
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    for (name, url) in urls() {
+        group.throughput(Throughput::Bytes(url.len() as u64));
+        group.bench_with_input(BenchmarkId::new("hex", name), &url, |b, url| {
+            b.iter(|| encode_url_hex(black_box(url)))
+        });
+        group.bench_with_input(BenchmarkId::new("base64", name), &url, |b, url| {
+            b.iter(|| encode_url_base64(black_box(url)))
+        });
+    }
+    group.finish();
+}
+
+fn sign(c: &mut Criterion) {
+    let camo = CamoUrl::new(KEY);
+    let mut group = c.benchmark_group("sign");
+    for (name, url) in urls() {
+        group.bench_with_input(BenchmarkId::new("to_url", name), &url, |b, url| {
+            b.iter(|| camo.sign(black_box(url)).to_url("https://camo.example.com"))
+        });
+    }
+
+    let batch: Vec<String> = (0..1000)
+        .map(|i| format!("https://example.com/images/{}.png", i))
+        .collect();
+    group.throughput(Throughput::Elements(batch.len() as u64));
+    group.bench_function("batch_1000", |b| {
+        b.iter(|| {
+            batch
+                .iter()
+                .map(|url| camo.sign(url).to_url("https://camo.example.com"))
+                .collect::<Vec<_>>()
+        })
+    });
+    group.bench_function("batch_1000_into", |b| {
+        let mut out = String::new();
+        b.iter(|| {
+            for url in &batch {
+                out.clear();
+                out.push_str("https://camo.example.com");
+                camo.sign_into(url, &mut out);
+                black_box(&out);
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, digest, encode, sign);
+criterion_main!(benches);
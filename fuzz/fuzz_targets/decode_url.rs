@@ -0,0 +1,21 @@
+#![no_main]
+
+use camo::server::decode::decode_url;
+use camo::CamoUrl;
+use libfuzzer_sys::fuzz_target;
+
+// Same properties as `check_decoded` in src/utils/encoding.rs
+fuzz_target!(|data: &[u8]| {
+    let Ok(encoded) = std::str::from_utf8(data) else {
+        return;
+    };
+    let Ok(url) = decode_url(encoded) else {
+        return;
+    };
+    assert!(url.len() <= 8192);
+    assert!(!url.chars().any(char::is_control), "{:?}", url);
+    assert!(url::Url::parse(&url).is_ok(), "{:?}", url);
+    let signed = CamoUrl::new("").sign(&url);
+    assert_eq!(decode_url(&signed.encoded_url).as_deref(), Ok(url.as_str()));
+    assert_eq!(decode_url(&signed.base64().encoded_url).as_deref(), Ok(url.as_str()));
+});
@@ -0,0 +1,42 @@
+#![no_main]
+
+use axum::{body::Body, http::Request, http::StatusCode, Router};
+use camo::server::{config::Config, create_router};
+use camo::CamoUrl;
+use clap::Parser;
+use libfuzzer_sys::fuzz_target;
+use std::sync::LazyLock;
+use tower::ServiceExt;
+
+// Only data: targets, so nothing ever reaches the network
+static ROUTER: LazyLock<Router> = LazyLock::new(|| {
+    let config = Config::parse_from(["camo", "--key", "secret", "--allowed-schemes", "data"]);
+    create_router(&config).unwrap()
+});
+
+static RUNTIME: LazyLock<tokio::runtime::Runtime> = LazyLock::new(|| {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("Failed to create runtime")
+});
+
+// Same requests as `check_path` in src/server/router.rs
+fuzz_target!(|data: &[u8]| {
+    let Ok(input) = std::str::from_utf8(data) else {
+        return;
+    };
+    let signed = CamoUrl::new("secret").sign(input);
+    let uris = [
+        format!("/{}", input.trim_start_matches('/')),
+        signed.to_path(),
+        signed.clone().base64().to_path(),
+        format!("/{}?url={}", signed.digest, urlencoding::encode(input)),
+    ];
+    for uri in uris {
+        let Ok(request) = Request::get(uri).body(Body::empty()) else {
+            continue;
+        };
+        let response = RUNTIME.block_on(ROUTER.clone().oneshot(request)).unwrap();
+        assert_ne!(response.status(), StatusCode::INTERNAL_SERVER_ERROR, "{:?}", input);
+    }
+});
@@ -0,0 +1,50 @@
+/* Generated by cbindgen from src/capi.rs, do not edit */
+
+#ifndef CAMO_H
+#define CAMO_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Sign `url` and return the proxy URL under `base`, e.g. `https://camo.example.com`
+ *
+ * Returns NULL if an argument is NULL or not valid UTF-8.
+ *
+ * # Safety
+ *
+ * Each argument must be NULL or point to a NUL-terminated string.
+ */
+char *camo_sign_url(const char *key, const char *url, const char *base);
+
+/**
+ * Whether `digest` is the signature of `url` under `key`
+ *
+ * Returns false if an argument is NULL or not valid UTF-8.
+ *
+ * # Safety
+ *
+ * Each argument must be NULL or point to a NUL-terminated string.
+ */
+bool camo_verify(const char *key, const char *url, const char *digest);
+
+/**
+ * Release a string returned by this library; NULL is ignored
+ *
+ * # Safety
+ *
+ * `s` must be NULL or a string returned by this library, not yet freed.
+ */
+void camo_string_free(char *s);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CAMO_H */
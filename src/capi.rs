@@ -0,0 +1,106 @@
+//! C ABI for the signer, for Ruby, Python, PHP and other apps calling it over FFI
+//!
+//! Build with `--features capi` to get `libcamo` and the `include/camo.h`
+//! header. Strings are NUL-terminated UTF-8; strings returned by the library
+//! must be released with [`camo_string_free`].
+
+use crate::CamoUrl;
+use std::ffi::{c_char, CStr, CString};
+
+/// Sign `url` and return the proxy URL under `base`, e.g. `https://camo.example.com`
+///
+/// Returns NULL if an argument is NULL or not valid UTF-8.
+///
+/// # Safety
+///
+/// Each argument must be NULL or point to a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn camo_sign_url(
+    key: *const c_char,
+    url: *const c_char,
+    base: *const c_char,
+) -> *mut c_char {
+    let args = unsafe { (str(key), str(url), str(base)) };
+    let (Some(key), Some(url), Some(base)) = args else {
+        return std::ptr::null_mut();
+    };
+
+    match CString::new(CamoUrl::new(key).sign_url(url, base)) {
+        Ok(signed) => signed.into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Whether `digest` is the signature of `url` under `key`
+///
+/// Returns false if an argument is NULL or not valid UTF-8.
+///
+/// # Safety
+///
+/// Each argument must be NULL or point to a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn camo_verify(
+    key: *const c_char,
+    url: *const c_char,
+    digest: *const c_char,
+) -> bool {
+    match unsafe { (str(key), str(url), str(digest)) } {
+        (Some(key), Some(url), Some(digest)) => CamoUrl::new(key).verify(url, digest),
+        _ => false,
+    }
+}
+
+/// Release a string returned by this library; NULL is ignored
+///
+/// # Safety
+///
+/// `s` must be NULL or a string returned by this library, not yet freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn camo_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(unsafe { CString::from_raw(s) });
+    }
+}
+
+unsafe fn str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    unsafe { CStr::from_ptr(s) }.to_str().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = CString::new("secret").unwrap();
+        let url = CString::new("http://example.com/a.png").unwrap();
+        let base = CString::new("https://camo.example.com").unwrap();
+
+        let signed = unsafe { camo_sign_url(key.as_ptr(), url.as_ptr(), base.as_ptr()) };
+        assert!(!signed.is_null());
+        let signed_url = unsafe { CStr::from_ptr(signed) }.to_str().unwrap().to_string();
+        unsafe { camo_string_free(signed) };
+        assert_eq!(
+            signed_url,
+            CamoUrl::new("secret").sign_url("http://example.com/a.png", "https://camo.example.com")
+        );
+
+        let digest = CamoUrl::new("secret").sign("http://example.com/a.png").digest;
+        let digest = CString::new(digest).unwrap();
+        assert!(unsafe { camo_verify(key.as_ptr(), url.as_ptr(), digest.as_ptr()) });
+        assert!(!unsafe { camo_verify(key.as_ptr(), base.as_ptr(), digest.as_ptr()) });
+    }
+
+    #[test]
+    fn test_null_arguments() {
+        let key = CString::new("secret").unwrap();
+
+        let signed = unsafe { camo_sign_url(key.as_ptr(), std::ptr::null(), std::ptr::null()) };
+        assert!(signed.is_null());
+        assert!(!unsafe { camo_verify(std::ptr::null(), key.as_ptr(), key.as_ptr()) });
+        unsafe { camo_string_free(std::ptr::null_mut()) };
+    }
+}
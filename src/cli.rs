@@ -1,78 +1,243 @@
 use camo::{
     server::{
-        config::{Command, Config},
-        router::{create_router, AppState},
+        config::{Command, Config, OutputFormat},
+        admin::{self, AdminState},
+        audit, batch, bench, check, config_file, decode, listener, prometheus, secret,
+        router::{create_router, default_client, AppState},
+        signature::Scheme,
+        tenant::TenantKeys,
     },
     {CamoUrl, Encoding},
 };
-use clap::Parser;
-use std::sync::Arc;
-use tracing::info;
-use tracing_subscriber::EnvFilter;
+use clap::CommandFactory;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tracing_subscriber::{filter::filter_fn, prelude::*, reload, EnvFilter};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let cli = Config::parse();
+    let matches = Config::command().get_matches();
+    let mut cli = config_file::load(&matches)?;
+    secret::load_key(&mut cli).await?;
 
-    let key = cli
-        .key
-        .as_ref()
-        .ok_or_else(|| anyhow::anyhow!("CAMO_KEY is required for signing"))?;
+    let key = || {
+        cli.key
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("CAMO_KEY is required for signing"))
+    };
 
     match &cli.command {
-        Some(Command::Sign { url, base, base64 }) => {
-            let camo = CamoUrl::new(key).with_encoding(if *base64 {
+        Some(Command::Decode { url }) => {
+            let target = decode::target_url(url)
+                .ok_or_else(|| anyhow::anyhow!("No target URL found in {}", url))?;
+            println!("{}", target);
+        }
+        Some(Command::Sign {
+            url,
+            base,
+            base64,
+            input,
+            output,
+            format,
+            tenant,
+            ..
+        }) => {
+            let camo = signer(&cli, tenant.as_deref())?.with_encoding(if *base64 {
                 Encoding::Base64
             } else {
                 Encoding::Hex
             });
 
-            let signed = camo.sign(url);
+            let mut output: Box<dyn Write> = match output {
+                Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                None => Box::new(io::stdout().lock()),
+            };
 
-            if base.is_empty() {
-                println!("Digest: {}", signed.digest);
-                println!("Encoded URL: {}", signed.encoded_url);
-                println!("Path: {}", signed.to_path());
-            } else {
-                println!("{}", signed.to_url(base));
+            match url {
+                Some(url) => {
+                    if let Some(format) = format {
+                        writeln!(output, "{}", batch::format_line(&camo, url, base, *format))?;
+                    } else if base.is_empty() {
+                        let signed = camo.sign(url);
+                        writeln!(output, "Digest: {}", signed.digest)?;
+                        writeln!(output, "Encoded URL: {}", signed.encoded_url)?;
+                        writeln!(output, "Path: {}", signed.to_path())?;
+                    } else {
+                        writeln!(output, "{}", camo.sign(url).to_url(base))?;
+                    }
+                    output.flush()?;
+                }
+                // One URL per line from --input or --stdin
+                None => {
+                    let input: Box<dyn BufRead> = match input {
+                        Some(path) => Box::new(BufReader::new(File::open(path)?)),
+                        None => Box::new(io::stdin().lock()),
+                    };
+                    let format = format.unwrap_or(OutputFormat::Plain);
+                    let count = batch::sign_lines(&camo, base, format, input, output)?;
+                    eprintln!("Signed {} URLs", count);
+                }
+            }
+        }
+        Some(Command::Check { url }) => {
+            let config = Config {
+                key: Some(key()?),
+                ..cli.clone()
+            };
+
+            let steps = check::diagnose(&config, url).await;
+            for step in &steps {
+                match &step.result {
+                    Ok(detail) => println!("[ok]   {:<10} {}", step.name, detail),
+                    Err(e) => println!("[fail] {:<10} {}", step.name, e),
+                }
             }
+
+            match steps.last().and_then(|step| step.result.as_ref().err()) {
+                Some(e) => {
+                    match check::hint(e) {
+                        Some(option) => println!("Blocked by policy, see {}", option),
+                        None => println!("The request failed upstream"),
+                    }
+                    std::process::exit(1);
+                }
+                None => println!("The proxy would serve this URL"),
+            }
+        }
+        Some(Command::Bench {
+            target,
+            urls,
+            concurrency,
+            requests,
+            timeout,
+            tenant,
+        }) => {
+            let camo = signer(&cli, tenant.as_deref())?;
+            let urls = std::fs::read_to_string(urls)?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect();
+
+            let options = bench::Options {
+                target: target.clone(),
+                urls,
+                concurrency: *concurrency,
+                requests: *requests,
+                timeout: Duration::from_secs(*timeout),
+            };
+            print!("{}", bench::run(&camo, options).await?);
         }
         Some(Command::Serve) | None => {
-            // Initialize logging
-            tracing_subscriber::fmt()
-                .with_env_filter(
-                    EnvFilter::try_from_default_env()
-                        .unwrap_or_else(|_| EnvFilter::new(&cli.log_level)),
-                )
+            // A public key verifies links on its own
+            let key = match cli.verify_pubkey {
+                Some(_) => cli.key.clone(),
+                None => Some(key()?),
+            };
+            // Built as for serving, so init containers can check a rollout
+            if cli.print_config {
+                let config = Config { key, ..cli };
+                default_client(&AppState::from_config(&config)?)?;
+                let config = admin::redacted_config(&config)?;
+                println!("{}", serde_json::to_string_pretty(&config)?);
+                return Ok(());
+            }
+            // Initialize logging, with a filter the admin API can swap at runtime
+            let (log_filter, log_filter_handle) = reload::Layer::new(
+                EnvFilter::try_from_default_env()
+                    .unwrap_or_else(|_| EnvFilter::new(&cli.log_level)),
+            );
+            // Audit events go to their own file, as JSON lines, when one is set
+            let audit_file = match &cli.audit_log {
+                Some(path) => Some(File::options().create(true).append(true).open(path)?),
+                None => None,
+            };
+            let audit_apart = audit_file.is_some();
+            tracing_subscriber::registry()
+                .with(log_filter)
+                .with(tracing_subscriber::fmt::layer().with_filter(filter_fn(move |meta| {
+                    !audit_apart || meta.target() != audit::TARGET
+                })))
+                .with(audit_file.map(|file| {
+                    tracing_subscriber::fmt::layer()
+                        .json()
+                        .with_writer(Mutex::new(file))
+                        .with_filter(filter_fn(|meta| meta.target() == audit::TARGET))
+                }))
                 .init();
 
             // Initialize metrics if enabled
             if cli.metrics {
-                let builder = metrics_exporter_prometheus::PrometheusBuilder::new();
-                builder
-                    .install()
-                    .expect("Failed to install Prometheus recorder");
+                prometheus::install()?;
             }
 
-            let listen = cli.listen.clone();
-            let config = Arc::new(Config {
-                key: Some(key.clone()),
-                ..cli
-            });
+            let config = Arc::new(Config { key, ..cli });
 
-            // Create app state
-            let state = Arc::new(AppState::from_config(&config));
+            // Create router, rebuilt on config or key file changes when either is used.
+            // The admin API follows the state of the latest router.
+            let build = |config: &Config| -> io::Result<_> {
+                let state = Arc::new(AppState::from_config(config)?);
+                Ok((state.clone(), create_router(state)?))
+            };
+            let (state, app) = build(&config)?;
+            let app_state = Arc::new(RwLock::new(state));
+            let app = if config.config_file.is_some() || config.key_file.is_some() {
+                let app_state = app_state.clone();
+                config_file::reloadable(matches, (*config).clone(), app, move |config| {
+                    let (state, router) = build(config)?;
+                    let mut current = app_state.write().unwrap();
+                    state.deny_list.carry_over(&current.deny_list);
+                    *current = state;
+                    Ok(router)
+                })
+            } else {
+                app
+            };
 
-            // Create router
-            let app = create_router(state);
+            let admin = async {
+                match &config.admin_listen {
+                    Some(listen) => {
+                        let state = AdminState {
+                            app: app_state,
+                            log_filter: log_filter_handle,
+                        };
+                        admin::serve(listen, state).await
+                    }
+                    None => Ok(()),
+                }
+            };
 
             // Start server
-            let listener = tokio::net::TcpListener::bind(&listen).await?;
-            info!("camo-rs listening on {}", listen);
-
-            axum::serve(listener, app).await?;
+            tokio::try_join!(listener::serve(&config, app), admin)?;
         }
     }
 
     Ok(())
 }
+
+/// Signer for links the server accepts: with `--signature`, and the tenant's key if one is given
+fn signer(cli: &Config, tenant: Option<&str>) -> anyhow::Result<CamoUrl> {
+    let (camo, key) = match tenant {
+        Some(tenant) => {
+            let tenants = TenantKeys::new(&cli.tenant_keys).map_err(anyhow::Error::msg)?;
+            let key = tenants
+                .key(tenant)
+                .ok_or_else(|| anyhow::anyhow!("No --tenant-key for {}", tenant))?;
+            (CamoUrl::for_tenant(tenant, key), key.to_string())
+        }
+        None => {
+            let key = cli
+                .key
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("CAMO_KEY is required for signing"))?;
+            (CamoUrl::new(&key), key)
+        }
+    };
+    Ok(match cli.signature() {
+        Scheme::HmacSha1 => camo,
+        scheme => camo.with_signer(scheme.signer(&key)),
+    })
+}
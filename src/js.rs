@@ -0,0 +1,43 @@
+//! `sign` and `verify` for JavaScript
+//!
+//! Lets Node services and edge functions generate the same URLs as the Rust
+//! signer:
+//!
+//! ```js
+//! import { sign, verify } from "camo-rs";
+//!
+//! const url = sign(key, "http://example.com/image.png", "https://camo.example.com");
+//! ```
+
+use crate::CamoUrl;
+use wasm_bindgen::prelude::*;
+
+/// Sign `url` and return the proxy URL under `base`
+#[wasm_bindgen]
+pub fn sign(key: &str, url: &str, base: &str) -> String {
+    CamoUrl::new(key).sign_url(url, base)
+}
+
+/// Whether `digest` is the signature of `url` under `key`
+#[wasm_bindgen]
+pub fn verify(key: &str, url: &str, digest: &str) -> bool {
+    CamoUrl::new(key).verify(url, digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let url = sign("secret", "http://example.com/a.png", "https://camo.example.com");
+        assert_eq!(
+            url,
+            crate::sign_url("secret", "http://example.com/a.png", "https://camo.example.com")
+        );
+
+        let digest = url.split('/').nth(3).unwrap();
+        assert!(verify("secret", "http://example.com/a.png", digest));
+        assert!(!verify("other", "http://example.com/a.png", digest));
+    }
+}
@@ -0,0 +1,31 @@
+use camo::server::{
+    config::Config,
+    config_file, lambda,
+    router::{create_router, AppState},
+    secret,
+};
+use clap::CommandFactory;
+use std::sync::Arc;
+use tracing_subscriber::EnvFilter;
+
+#[tokio::main]
+async fn main() -> Result<(), lambda_http::Error> {
+    // Same CAMO_* variables as the server; Lambda passes no arguments
+    let matches = Config::command().get_matches();
+    let mut config = config_file::load(&matches)?;
+    secret::load_key(&mut config).await?;
+    if config.key.is_none() && config.verify_pubkey.is_none() {
+        return Err("CAMO_KEY or CAMO_VERIFY_PUBKEY is required".into());
+    }
+
+    // CloudWatch adds its own timestamps and does not render colors
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&config.log_level)),
+        )
+        .with_ansi(false)
+        .without_time()
+        .init();
+
+    lambda::run(create_router(Arc::new(AppState::from_config(&config)?))?).await
+}
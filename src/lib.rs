@@ -32,12 +32,18 @@
 //!
 //! Generated URLs follow the format: `<base>/<digest>/<encoded_url>`
 
-#[cfg(all(feature = "server", feature = "worker"))]
-compile_error!("Features 'server' and 'worker' are mutually exclusive. Please enable only one.");
+#[cfg(any(
+    all(feature = "server", feature = "worker"),
+    all(feature = "server", feature = "wasm-fetch"),
+    all(feature = "worker", feature = "wasm-fetch"),
+))]
+compile_error!(
+    "Features 'server', 'worker' and 'wasm-fetch' are mutually exclusive. Please enable only one."
+);
 
 mod utils;
 
-#[cfg(any(feature = "server", feature = "worker"))]
+#[cfg(any(feature = "server", feature = "worker", feature = "wasm-fetch"))]
 pub mod server;
 
 #[cfg(feature = "worker")]
@@ -45,7 +51,27 @@ mod worker;
 #[cfg(feature = "worker")]
 pub use worker::*;
 
+#[cfg(feature = "wasm-fetch")]
+mod wasm_fetch;
+#[cfg(feature = "wasm-fetch")]
+pub use wasm_fetch::*;
+
 #[cfg(feature = "client")]
 mod camo;
 #[cfg(feature = "client")]
-pub use camo::{CamoUrl, Encoding, SignedUrl, sign_url};
+pub use camo::{
+    CamoUrl, ContentClass, Encoding, HmacSha1, LinkParams, ParseSignedUrlError, SignError,
+    SignedUrl, SignedUrlRef, Signer, UserinfoPolicy, Verifier, encode_url_base64, encode_url_hex,
+    generate_digest, sign_url, verify_digest,
+};
+#[cfg(all(feature = "client", feature = "signers"))]
+pub use camo::{Blake3Keyed, Ed25519, Ed25519PublicKey, HmacSha256};
+
+#[cfg(feature = "capi")]
+pub mod capi;
+
+#[cfg(feature = "wasm-bindgen")]
+pub mod js;
+
+#[cfg(any(feature = "tera", feature = "minijinja", feature = "askama"))]
+pub mod template;
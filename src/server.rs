@@ -1,6 +1,120 @@
+#[cfg(feature = "server")]
+pub mod admin;
+#[cfg(feature = "server")]
+pub mod audit;
+#[cfg(feature = "server")]
+pub mod body_digest;
+#[cfg(all(feature = "server", feature = "client"))]
+pub mod batch;
+#[cfg(all(feature = "server", feature = "client"))]
+pub mod bench;
+#[cfg(feature = "server")]
+mod cache;
+#[cfg(feature = "server")]
+pub mod cancellation;
+#[cfg(all(feature = "server", feature = "client"))]
+pub mod check;
+pub mod compat;
 mod content_types;
 
 pub mod config;
+#[cfg(feature = "server")]
+pub mod config_file;
+pub mod conditional;
+pub mod cors;
+pub mod data_uri;
+pub mod decode;
+#[cfg(feature = "server")]
+pub mod deny_list;
+#[cfg(any(feature = "worker", feature = "wasm-fetch"))]
+pub mod env_config;
 pub mod error;
+pub mod forward;
+#[cfg(feature = "server")]
+pub mod geoip;
 pub mod http_client;
-pub mod router;
\ No newline at end of file
+pub mod homograph;
+#[cfg(feature = "server")]
+pub mod health;
+#[cfg(feature = "server")]
+pub mod host_metrics;
+#[cfg(feature = "http3")]
+pub mod http3;
+#[cfg(feature = "lambda")]
+pub mod lambda;
+#[cfg(feature = "server")]
+pub mod listener;
+#[cfg(feature = "server")]
+pub mod load;
+#[cfg(feature = "server")]
+pub mod memory_budget;
+#[cfg(feature = "server")]
+pub mod negative_cache;
+#[cfg(feature = "server")]
+pub mod network;
+#[cfg(feature = "server")]
+pub mod prometheus;
+#[cfg(feature = "server")]
+pub mod quota;
+#[cfg(feature = "server")]
+pub mod rate_limit;
+pub mod redirect;
+#[cfg(feature = "redis")]
+pub mod redis_pool;
+pub mod referer;
+#[cfg(feature = "server")]
+pub mod reputation;
+#[cfg(feature = "server")]
+pub mod revalidate;
+#[cfg(feature = "server")]
+pub mod secret;
+#[cfg(feature = "server")]
+pub mod service;
+pub mod session;
+#[cfg(feature = "server")]
+pub mod shadow;
+pub mod request_policy;
+pub mod signature;
+#[cfg(feature = "server")]
+pub mod slow_client;
+#[cfg(any(feature = "secret-manager", feature = "object-store"))]
+mod sigv4;
+pub mod tenant;
+#[cfg(feature = "server")]
+pub mod validator_cache;
+pub mod verify_cache;
+pub mod router;
+
+#[cfg(feature = "server")]
+pub use service::CamoService;
+
+use std::sync::Arc;
+
+/// Proxy router for `config`, to serve directly or mount inside an existing axum app
+///
+/// Requests are handled exactly as by the `camo` binary. When nested, sign
+/// URLs against the mount point, e.g. `https://example.com/camo`.
+///
+/// ```no_run
+/// use axum::{routing::get, Router};
+/// use camo::server::config::Config;
+/// use clap::Parser;
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let config = Config::parse_from(["camo", "--key", "your-secret-key"]);
+/// let app = Router::new()
+///     .route("/", get(|| async { "Hello" }))
+///     .nest("/camo", camo::server::create_router(&config)?);
+///
+/// let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
+/// axum::serve(listener, app).await
+/// # }
+/// ```
+///
+/// Apps with their own router state can mount it with
+/// [`Router::nest_service`](axum::Router::nest_service) instead.
+///
+/// Fails on an invalid configuration, like an unreadable deny list or key.
+pub fn create_router(config: &config::Config) -> std::io::Result<axum::Router> {
+    router::create_router(Arc::new(router::AppState::from_config(config)?))
+}
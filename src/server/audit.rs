@@ -0,0 +1,174 @@
+use super::config::Config;
+use super::error::CamoError;
+
+use axum::http::HeaderMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Tracing target of audit events, so they can be filtered and routed apart
+pub const TARGET: &str = "audit";
+
+/// Why a request was blocked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    DigestMismatch,
+    Credentials,
+    PrivateNetwork,
+    BlockedNetwork,
+    Denied,
+    ContentType,
+    TooLarge,
+    Rejected,
+    Policy,
+    Referer,
+    Session,
+    Homograph,
+}
+
+impl Reason {
+    const ALL: [Reason; 12] = [
+        Reason::DigestMismatch,
+        Reason::Credentials,
+        Reason::PrivateNetwork,
+        Reason::BlockedNetwork,
+        Reason::Denied,
+        Reason::ContentType,
+        Reason::TooLarge,
+        Reason::Rejected,
+        Reason::Policy,
+        Reason::Referer,
+        Reason::Session,
+        Reason::Homograph,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Reason::DigestMismatch => "digest_mismatch",
+            Reason::Credentials => "credentials",
+            Reason::PrivateNetwork => "private_network",
+            Reason::BlockedNetwork => "blocked_network",
+            Reason::Denied => "denied",
+            Reason::ContentType => "content_type",
+            Reason::TooLarge => "too_large",
+            Reason::Rejected => "rejected",
+            Reason::Policy => "policy",
+            Reason::Referer => "referer",
+            Reason::Session => "session",
+            Reason::Homograph => "homograph",
+        }
+    }
+
+    /// The reason behind an upstream error, if it is a policy decision
+    pub fn of(error: &CamoError) -> Option<Self> {
+        match error {
+            CamoError::PrivateNetworkNotAllowed => Some(Reason::PrivateNetwork),
+            CamoError::NetworkNotAllowed => Some(Reason::BlockedNetwork),
+            CamoError::ContentTypeNotAllowed(_) => Some(Reason::ContentType),
+            CamoError::ContentTooLarge(_) => Some(Reason::TooLarge),
+            CamoError::Rejected(_) => Some(Reason::Rejected),
+            _ => None,
+        }
+    }
+}
+
+/// Trail of blocked requests, kept apart from the debug logs
+///
+/// Every blocked request is counted in `camo_blocked_requests_total`; one
+/// in `--audit-sample` per reason is also logged on the `audit` target with
+/// the reason, target host and client address. Sampling per reason keeps a
+/// flood of one kind from hiding the rare ones.
+pub struct AuditLog {
+    sample: u64,
+    seen: [AtomicU64; Reason::ALL.len()],
+}
+
+impl AuditLog {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            sample: config.audit_sample,
+            seen: Default::default(),
+        }
+    }
+
+    /// Whether this occurrence of `reason` is logged
+    fn sampled(&self, reason: Reason) -> bool {
+        if self.sample == 0 {
+            return false;
+        }
+        let seen = self.seen[reason as usize].fetch_add(1, Ordering::Relaxed);
+        seen.is_multiple_of(self.sample)
+    }
+
+    /// Record a blocked request for `target`, which may be unverified
+    ///
+    /// `client` is the peer address when the listener provides one; a
+    /// `X-Forwarded-For` header is logged next to it as received.
+    pub fn blocked(
+        &self,
+        reason: Reason,
+        target: &str,
+        headers: &HeaderMap,
+        client: Option<SocketAddr>,
+    ) {
+        metrics::counter!("camo_blocked_requests_total", "reason" => reason.name()).increment(1);
+        if !self.sampled(reason) {
+            return;
+        }
+
+        let host = url::Url::parse(target)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_default();
+        let client_ip = client.map(|addr| addr.ip().to_string()).unwrap_or_default();
+        // Header values that convert to `str` are visible ASCII, safe to log as is
+        let forwarded_for = headers
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        tracing::warn!(
+            target: TARGET,
+            reason = reason.name(),
+            host = %host,
+            client_ip = %client_ip,
+            forwarded_for = %forwarded_for,
+            "blocked request"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audit(sample: u64) -> AuditLog {
+        AuditLog {
+            sample,
+            seen: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_sampling() {
+        let every = audit(1);
+        assert!((0..5).all(|_| every.sampled(Reason::Denied)));
+
+        let third = audit(3);
+        let logged = (0..9).filter(|_| third.sampled(Reason::TooLarge)).count();
+        assert_eq!(logged, 3);
+        // Counted per reason
+        assert!(third.sampled(Reason::DigestMismatch));
+
+        let off = audit(0);
+        assert!(!off.sampled(Reason::Denied));
+    }
+
+    #[test]
+    fn test_reason_of() {
+        assert_eq!(
+            Reason::of(&CamoError::PrivateNetworkNotAllowed),
+            Some(Reason::PrivateNetwork)
+        );
+        assert_eq!(Reason::of(&CamoError::ContentTooLarge(1)), Some(Reason::TooLarge));
+        assert_eq!(Reason::of(&CamoError::Timeout), None);
+    }
+}
@@ -0,0 +1,116 @@
+//! Signing many URLs at once for the `sign` subcommand
+
+use super::config::OutputFormat;
+use crate::CamoUrl;
+
+use std::io::{self, BufRead, Write};
+
+/// One output line for a signed URL
+///
+/// Without a base URL only the path (`/<digest>/<encoded_url>`) is printed.
+pub fn format_line(camo: &CamoUrl, url: &str, base: &str, format: OutputFormat) -> String {
+    let signed = camo.sign(url);
+    let target = if base.is_empty() {
+        signed.to_path()
+    } else {
+        signed.to_url(base)
+    };
+
+    match format {
+        OutputFormat::Plain => target,
+        OutputFormat::Tsv => format!("{}\t{}", url, target),
+        OutputFormat::Json => serde_json::json!({
+            "url": url,
+            "digest": signed.digest,
+            "signed": target,
+        })
+        .to_string(),
+    }
+}
+
+/// Sign every non-empty line of `input` and write one result per line
+///
+/// Returns the number of URLs signed.
+pub fn sign_lines(
+    camo: &CamoUrl,
+    base: &str,
+    format: OutputFormat,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<usize> {
+    let mut count = 0;
+
+    for line in input.lines() {
+        let line = line?;
+        let url = line.trim();
+        if url.is_empty() {
+            continue;
+        }
+
+        writeln!(output, "{}", format_line(camo, url, base, format))?;
+        count += 1;
+    }
+
+    output.flush()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "https://example.com/a.png\n\n  https://example.com/b.png  \n";
+
+    fn sign(format: OutputFormat, base: &str) -> Vec<String> {
+        let camo = CamoUrl::new("secret");
+        let mut output = Vec::new();
+        let count = sign_lines(&camo, base, format, INPUT.as_bytes(), &mut output).unwrap();
+        assert_eq!(count, 2);
+
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_plain() {
+        let camo = CamoUrl::new("secret");
+        let lines = sign(OutputFormat::Plain, "https://camo.example.com/");
+
+        assert_eq!(
+            lines,
+            vec![
+                camo.sign_url("https://example.com/a.png", "https://camo.example.com"),
+                camo.sign_url("https://example.com/b.png", "https://camo.example.com"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tsv() {
+        let camo = CamoUrl::new("secret");
+        let lines = sign(OutputFormat::Tsv, "");
+
+        assert_eq!(
+            lines[1],
+            format!(
+                "https://example.com/b.png\t{}",
+                camo.sign("https://example.com/b.png").to_path()
+            )
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let camo = CamoUrl::new("secret");
+        let lines = sign(OutputFormat::Json, "https://camo.example.com");
+        let value: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+
+        let signed = camo.sign("https://example.com/a.png");
+        assert_eq!(value["url"], "https://example.com/a.png");
+        assert_eq!(value["digest"], signed.digest);
+        assert_eq!(value["signed"], signed.to_url("https://camo.example.com"));
+    }
+}
@@ -0,0 +1,209 @@
+//! Load generator for the `bench` subcommand
+
+use crate::CamoUrl;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Result of a single request: status code, or the kind of failure
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Outcome {
+    Status(u16),
+    Timeout,
+    Connect,
+    Error,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Status(status) => write!(f, "HTTP {}", status),
+            Outcome::Timeout => f.write_str("timeout"),
+            Outcome::Connect => f.write_str("connection error"),
+            Outcome::Error => f.write_str("other error"),
+        }
+    }
+}
+
+pub struct Options {
+    /// Base URL of the camo instance under test
+    pub target: String,
+    pub urls: Vec<String>,
+    pub concurrency: usize,
+    pub requests: usize,
+    pub timeout: Duration,
+}
+
+/// Send `options.requests` signed requests, cycling through the URLs
+pub async fn run(camo: &CamoUrl, options: Options) -> anyhow::Result<Report> {
+    anyhow::ensure!(!options.urls.is_empty(), "No URLs to request");
+
+    let client = reqwest::Client::builder()
+        .timeout(options.timeout)
+        .build()?;
+    let signed: Arc<Vec<String>> = Arc::new(
+        options
+            .urls
+            .iter()
+            .map(|url| camo.sign_url(url, &options.target))
+            .collect(),
+    );
+
+    let next = Arc::new(AtomicUsize::new(0));
+    let samples = Arc::new(Mutex::new(Vec::with_capacity(options.requests)));
+    let started = Instant::now();
+
+    let workers: Vec<_> = (0..options.concurrency.max(1))
+        .map(|_| {
+            let (client, signed, next, samples) =
+                (client.clone(), signed.clone(), next.clone(), samples.clone());
+            let requests = options.requests;
+
+            tokio::spawn(async move {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= requests {
+                        break;
+                    }
+
+                    let start = Instant::now();
+                    let outcome = fetch(&client, &signed[i % signed.len()]).await;
+                    samples.lock().unwrap().push((start.elapsed(), outcome));
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        worker.await?;
+    }
+
+    let samples = std::mem::take(&mut *samples.lock().unwrap());
+    Ok(Report::new(samples, started.elapsed()))
+}
+
+async fn fetch(client: &reqwest::Client, url: &str) -> Outcome {
+    let response = match client.get(url).send().await {
+        Ok(response) => response,
+        Err(e) => return error_outcome(&e),
+    };
+
+    let status = response.status().as_u16();
+    // Read the whole body so the latency covers the transfer
+    match response.bytes().await {
+        Ok(_) => Outcome::Status(status),
+        Err(e) => error_outcome(&e),
+    }
+}
+
+fn error_outcome(e: &reqwest::Error) -> Outcome {
+    if e.is_timeout() {
+        Outcome::Timeout
+    } else if e.is_connect() {
+        Outcome::Connect
+    } else {
+        Outcome::Error
+    }
+}
+
+/// Latency percentiles and outcome counts of a run
+pub struct Report {
+    pub elapsed: Duration,
+    /// Sorted ascending
+    pub latencies: Vec<Duration>,
+    pub outcomes: BTreeMap<Outcome, usize>,
+}
+
+impl Report {
+    pub fn new(samples: Vec<(Duration, Outcome)>, elapsed: Duration) -> Self {
+        let mut latencies = Vec::with_capacity(samples.len());
+        let mut outcomes = BTreeMap::new();
+
+        for (latency, outcome) in samples {
+            latencies.push(latency);
+            *outcomes.entry(outcome).or_insert(0) += 1;
+        }
+        latencies.sort();
+
+        Self {
+            elapsed,
+            latencies,
+            outcomes,
+        }
+    }
+
+    /// Nearest-rank percentile, `p` in `0.0..=100.0`
+    pub fn percentile(&self, p: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+
+        let rank = (p / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.latencies.len();
+        let rate = total as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON);
+
+        writeln!(f, "Requests:  {} in {:.2?} ({:.1} req/s)", total, self.elapsed, rate)?;
+        writeln!(f, "Latency:")?;
+        for (label, p) in [("p50", 50.0), ("p90", 90.0), ("p99", 99.0), ("max", 100.0)] {
+            writeln!(f, "  {:<5} {:.2?}", label, self.percentile(p))?;
+        }
+        writeln!(f, "Outcomes:")?;
+        for (outcome, count) in &self.outcomes {
+            writeln!(f, "  {:<18} {}", outcome.to_string(), count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_percentile() {
+        let samples = (1..=100)
+            .rev()
+            .map(|i| (ms(i), Outcome::Status(200)))
+            .collect();
+        let report = Report::new(samples, Duration::from_secs(1));
+
+        assert_eq!(report.percentile(50.0), ms(50));
+        assert_eq!(report.percentile(99.0), ms(99));
+        assert_eq!(report.percentile(100.0), ms(100));
+        assert_eq!(report.percentile(0.0), ms(1));
+    }
+
+    #[test]
+    fn test_outcomes() {
+        let samples = vec![
+            (ms(1), Outcome::Status(200)),
+            (ms(2), Outcome::Status(403)),
+            (ms(3), Outcome::Status(200)),
+            (ms(4), Outcome::Timeout),
+        ];
+        let report = Report::new(samples, Duration::from_secs(1));
+
+        assert_eq!(report.outcomes[&Outcome::Status(200)], 2);
+        assert_eq!(report.outcomes[&Outcome::Status(403)], 1);
+        assert_eq!(report.outcomes[&Outcome::Timeout], 1);
+    }
+
+    #[test]
+    fn test_empty() {
+        let report = Report::new(Vec::new(), Duration::ZERO);
+        assert_eq!(report.percentile(50.0), Duration::ZERO);
+    }
+}
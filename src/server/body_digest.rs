@@ -0,0 +1,158 @@
+use super::error::{CamoError, Result};
+use super::http_client::UpstreamResponse;
+use super::memory_budget;
+
+use axum::body::{Body, Bytes};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures_core::Stream;
+use sha2::{Digest, Sha256};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// RFC 9530 digest of the representation
+const REPR_DIGEST: HeaderName = HeaderName::from_static("repr-digest");
+/// RFC 3230 predecessor, still what most tooling reads
+const DIGEST: HeaderName = HeaderName::from_static("digest");
+
+/// SHA-256 of the proxied body, for integrity checks and dedup analytics
+///
+/// Bodies with a `Content-Length` of at most `max_size` are buffered, and
+/// their digest sent as `Repr-Digest` and `Digest` headers. Others are
+/// hashed as they stream, too late for a header, as are partial bodies,
+/// whose digest is not that of the representation. Either way the digest
+/// is logged with the target once the body is complete.
+pub async fn apply(
+    mut response: UpstreamResponse,
+    max_size: u64,
+    target: &str,
+) -> Result<UpstreamResponse> {
+    // Those of upstream describe what it sent, which may not be what we send
+    response.headers.remove(&REPR_DIGEST);
+    response.headers.remove(&DIGEST);
+
+    let target = target.to_string();
+    if response.headers.contains_key(header::CONTENT_RANGE)
+        || content_length(&response.headers).is_none_or(|length| length > max_size)
+    {
+        let stream = DigestStream {
+            inner: response.body.into_data_stream(),
+            hasher: Some(Sha256::new()),
+            bytes: 0,
+            target,
+        };
+        response.body = Body::from_stream(stream);
+        return Ok(response);
+    }
+
+    let body = memory_budget::buffer(response.body, max_size as usize)
+        .await
+        .map_err(|e| match e.into_inner().downcast::<CamoError>() {
+            Ok(e) => *e,
+            Err(e) => CamoError::Upstream(e.to_string()),
+        })?;
+    let digest = STANDARD.encode(Sha256::digest(&body));
+    log(&target, body.len() as u64, &digest);
+    for (name, value) in [
+        (REPR_DIGEST, format!("sha-256=:{}:", digest)),
+        (DIGEST, format!("sha-256={}", digest)),
+    ] {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response.headers.insert(name, value);
+        }
+    }
+    response.body = body.into();
+    Ok(response)
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
+
+fn log(target: &str, bytes: u64, digest: &str) {
+    tracing::info!(url = %target, bytes, sha256 = %digest, "body digest");
+}
+
+/// Body stream that hashes what passes through and logs the digest at its end
+///
+/// Bodies that fail part way are not logged.
+#[pin_project::pin_project]
+struct DigestStream<S> {
+    #[pin]
+    inner: S,
+    hasher: Option<Sha256>,
+    bytes: u64,
+    target: String,
+}
+
+impl<S> Stream for DigestStream<S>
+where
+    S: Stream<Item = std::result::Result<Bytes, axum::Error>>,
+{
+    type Item = std::result::Result<Bytes, axum::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let poll = this.inner.poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if let Some(hasher) = this.hasher {
+                    hasher.update(chunk);
+                }
+                *this.bytes += chunk.len() as u64;
+            }
+            Poll::Ready(Some(Err(_))) => *this.hasher = None,
+            Poll::Ready(None) => {
+                if let Some(hasher) = this.hasher.take() {
+                    log(this.target, *this.bytes, &STANDARD.encode(hasher.finalize()));
+                }
+            }
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SHA-256 of "hello", base64
+    const HELLO: &str = "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=";
+
+    fn response(body: &'static str, length: bool) -> UpstreamResponse {
+        let mut headers = HeaderMap::new();
+        if length {
+            headers.insert(header::CONTENT_LENGTH, body.len().into());
+        }
+        headers.insert(DIGEST, HeaderValue::from_static("sha-256=upstream"));
+        UpstreamResponse {
+            headers,
+            body: Body::from(body),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_digest_header() {
+        let response = apply(response("hello", true), 16, "https://example.com/").await.unwrap();
+        assert_eq!(response.headers[REPR_DIGEST], format!("sha-256=:{}:", HELLO));
+        assert_eq!(response.headers[DIGEST], format!("sha-256={}", HELLO));
+        let body = axum::body::to_bytes(response.body, usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_streamed_without_header() {
+        for (length, max_size) in [(false, 16), (true, 4)] {
+            let response = apply(response("hello", length), max_size, "https://example.com/");
+            let response = response.await.unwrap();
+            assert!(!response.headers.contains_key(REPR_DIGEST));
+            assert!(!response.headers.contains_key(DIGEST));
+            let body = axum::body::to_bytes(response.body, usize::MAX).await.unwrap();
+            assert_eq!(&body[..], b"hello");
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use super::tenant::DEFAULT_KEY_ID;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Cache key of a target URL fetched for a link signed by `tenant`
+///
+/// Entries of links signed with `--key` are keyed by the bare URL, and
+/// a tenant's by its name, a space and the URL, so one tenant's entries
+/// can be flushed together when its key is rotated. Canonical URLs never
+/// contain a space, and tenant names cannot.
+pub fn partitioned_key(tenant: Option<&str>, url: &str) -> String {
+    match tenant {
+        Some(tenant) => format!("{} {}", tenant, url),
+        None => url.to_string(),
+    }
+}
+
+/// The key ID and target URL of a [partitioned key](partitioned_key)
+pub fn split_key(key: &str) -> (&str, &str) {
+    key.split_once(' ').unwrap_or((DEFAULT_KEY_ID, key))
+}
+
+/// Result of looking a key up in a [`TtlCache`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup<V> {
+    /// The entry is within its TTL
+    Fresh(V),
+    /// The entry existed, but has expired
+    Stale,
+    /// There is no entry for the key
+    Miss,
+}
+
+struct Entry<V> {
+    value: V,
+    expires_at: Instant,
+}
+
+/// Small bounded in-memory cache with a TTL per entry
+///
+/// Expired entries are kept until they are replaced or evicted, so callers
+/// can tell a revalidation apart from a first lookup.
+pub struct TtlCache<V> {
+    capacity: usize,
+    entries: Mutex<HashMap<String, Entry<V>>>,
+}
+
+impl<V: Clone> TtlCache<V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn lookup(&self, key: &str) -> Lookup<V> {
+        self.lookup_at(key, Instant::now())
+    }
+
+    pub fn insert(&self, key: &str, value: V, ttl: Duration) {
+        self.insert_at(key, value, Instant::now() + ttl);
+    }
+
+    /// The value for a key, fresh or expired
+    pub fn peek(&self, key: &str) -> Option<V> {
+        self.entries.lock().unwrap().get(key).map(|entry| entry.value.clone())
+    }
+
+    /// Returns whether an entry was removed
+    pub fn remove(&self, key: &str) -> bool {
+        self.entries.lock().unwrap().remove(key).is_some()
+    }
+
+    /// Remove the entries whose key matches; returns how many there were
+    pub fn remove_matching(&self, mut matches: impl FnMut(&str) -> bool) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|key, _| !matches(key));
+        before - entries.len()
+    }
+
+    /// The entries that have not expired yet, in no particular order
+    pub fn fresh(&self) -> Vec<(String, V)> {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .filter(|(_, entry)| entry.expires_at > now)
+            .map(|(key, entry)| (key.clone(), entry.value.clone()))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// False once a panic while holding the lock has left the cache unusable
+    pub fn is_healthy(&self) -> bool {
+        !self.entries.is_poisoned()
+    }
+
+    fn lookup_at(&self, key: &str, now: Instant) -> Lookup<V> {
+        match self.entries.lock().unwrap().get(key) {
+            Some(entry) if entry.expires_at > now => Lookup::Fresh(entry.value.clone()),
+            Some(_) => Lookup::Stale,
+            None => Lookup::Miss,
+        }
+    }
+
+    fn insert_at(&self, key: &str, value: V, expires_at: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len() >= self.capacity && !entries.contains_key(key) {
+            let now = Instant::now();
+            entries.retain(|_, entry| entry.expires_at > now);
+
+            // Still full of live entries: drop the one closest to expiry
+            if entries.len() >= self.capacity
+                && let Some(oldest) = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.expires_at)
+                    .map(|(key, _)| key.clone())
+            {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(key.to_string(), Entry { value, expires_at });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fresh_and_stale() {
+        let cache = TtlCache::new(16);
+        let now = Instant::now();
+
+        assert_eq!(cache.lookup_at("example.com", now), Lookup::Miss);
+
+        cache.insert_at("example.com", 1, now + Duration::from_secs(60));
+        assert_eq!(
+            cache.lookup_at("example.com", now + Duration::from_secs(59)),
+            Lookup::Fresh(1)
+        );
+        assert_eq!(
+            cache.lookup_at("example.com", now + Duration::from_secs(60)),
+            Lookup::Stale
+        );
+
+        cache.remove("example.com");
+        assert_eq!(cache.lookup_at("example.com", now), Lookup::Miss);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let cache = TtlCache::new(2);
+
+        cache.insert("a.example", 1, Duration::from_secs(60));
+        cache.insert("b.example", 2, Duration::from_secs(61));
+        cache.insert("c.example", 3, Duration::from_secs(62));
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.lookup("a.example"), Lookup::Miss);
+        assert_eq!(cache.lookup("c.example"), Lookup::Fresh(3));
+    }
+
+    #[test]
+    fn test_expired_entries_evicted_first() {
+        let cache = TtlCache::new(2);
+        let now = Instant::now();
+
+        cache.insert_at("a.example", 1, now);
+        cache.insert("b.example", 2, Duration::from_secs(10));
+        cache.insert("c.example", 3, Duration::from_secs(20));
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.lookup("b.example"), Lookup::Fresh(2));
+
+        let mut fresh = cache.fresh();
+        fresh.sort();
+        assert_eq!(fresh, [("b.example".to_string(), 2), ("c.example".to_string(), 3)]);
+    }
+
+    #[test]
+    fn test_partitioned_key() {
+        let url = "https://example.com/a.png";
+        assert_eq!(split_key(&partitioned_key(None, url)), (DEFAULT_KEY_ID, url));
+        assert_eq!(split_key(&partitioned_key(Some("shop"), url)), ("shop", url));
+
+        let cache = TtlCache::new(16);
+        for key in [partitioned_key(None, url), partitioned_key(Some("shop"), url)] {
+            cache.insert(&key, 1, Duration::from_secs(60));
+        }
+        assert_eq!(cache.remove_matching(|key| split_key(key).0 == "shop"), 1);
+        assert_eq!(cache.remove_matching(|key| split_key(key).0 == "shop"), 0);
+        assert_eq!(cache.lookup(url), Lookup::Fresh(1));
+    }
+
+    #[test]
+    fn test_zero_capacity_disables() {
+        let cache = TtlCache::new(0);
+        cache.insert("example.com", 1, Duration::from_secs(60));
+        assert_eq!(cache.lookup("example.com"), Lookup::Miss);
+    }
+}
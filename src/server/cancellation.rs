@@ -0,0 +1,171 @@
+//! Requests abandoned by their client, and the upstream fetches behind them
+//!
+//! A client going away, like a page closed mid-load, makes hyper drop the
+//! handler if it is still running, or else the response body. Either way
+//! the upstream request is dropped with it: reqwest then closes the
+//! connection, or resets the HTTP/2 stream, instead of downloading the rest
+//! for nobody. Nothing may hold on to an upstream body past its response
+//! for this to work. Abandoned requests are counted in
+//! `camo_upstream_cancelled_total{phase}`, `headers` when the client left
+//! before the response started and `body` when it left during it.
+
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::Request;
+use axum::http::{header, Method};
+use axum::middleware::Next;
+use axum::response::Response;
+use http_body::{Frame, SizeHint};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+fn record(phase: &'static str) {
+    tracing::debug!(phase, "Client went away, cancelling the upstream request");
+    metrics::counter!("camo_upstream_cancelled_total", "phase" => phase).increment(1);
+}
+
+/// Counts the handler being dropped before it returned
+struct Pending(bool);
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if self.0 {
+            record("headers");
+        }
+    }
+}
+
+/// Middleware counting requests whose client left before the whole response was sent
+///
+/// Goes on outside of anything that drops a response on its own, like
+/// `--send-timeout`. Responses to `HEAD` have their body dropped unread by
+/// design and are left out.
+pub async fn track(request: Request, next: Next) -> Response {
+    if request.method() == Method::HEAD {
+        return next.run(request).await;
+    }
+    let mut pending = Pending(true);
+    let response = next.run(request).await;
+    pending.0 = false;
+    let length = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse().ok());
+    response.map(|body| Body::new(TrackedBody::new(body, length)))
+}
+
+/// Body counting being dropped before its end
+struct TrackedBody {
+    inner: Body,
+    /// Bytes left of the `Content-Length`, after which HTTP/1 stops reading
+    remaining: Option<u64>,
+    ended: bool,
+}
+
+impl TrackedBody {
+    fn new(inner: Body, length: Option<u64>) -> Self {
+        Self {
+            inner,
+            remaining: length,
+            ended: false,
+        }
+    }
+
+    /// Whether the client stopped reading before the end
+    ///
+    /// A body that failed ended too: the error, not the client, cut it short.
+    fn is_abandoned(&self) -> bool {
+        !self.ended && self.remaining != Some(0) && !self.inner.is_end_stream()
+    }
+}
+
+impl HttpBody for TrackedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) => {
+                let size = frame.data_ref().map_or(0, |data| data.len() as u64);
+                self.remaining = self.remaining.map(|remaining| remaining.saturating_sub(size));
+            }
+            Poll::Ready(_) => self.ended = true,
+            Poll::Pending => {}
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for TrackedBody {
+    fn drop(&mut self) {
+        if self.is_abandoned() {
+            record("body");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_core::Stream;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// Endless upstream body, telling when it was dropped
+    struct Upstream(Arc<AtomicBool>);
+
+    impl Stream for Upstream {
+        type Item = Result<Bytes, std::io::Error>;
+
+        fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            Poll::Ready(Some(Ok(Bytes::from_static(b"chunk"))))
+        }
+    }
+
+    impl Drop for Upstream {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    async fn next(body: &mut TrackedBody) -> Option<Result<Frame<Bytes>, axum::Error>> {
+        std::future::poll_fn(|cx| Pin::new(&mut *body).poll_frame(cx)).await
+    }
+
+    #[tokio::test]
+    async fn test_abandoned() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let inner = Body::from_stream(Upstream(dropped.clone()));
+        let mut body = TrackedBody::new(inner, None);
+        assert!(next(&mut body).await.is_some());
+        assert!(body.is_abandoned());
+        // The upstream goes with the response
+        drop(body);
+        assert!(dropped.load(Ordering::Relaxed));
+
+        let mut body = TrackedBody::new(Body::from("done"), None);
+        while next(&mut body).await.is_some() {}
+        assert!(!body.is_abandoned());
+        assert!(!TrackedBody::new(Body::empty(), None).is_abandoned());
+
+        // HTTP/1 stops reading at the `Content-Length`
+        let dropped = Arc::new(AtomicBool::new(false));
+        let inner = Body::from_stream(Upstream(dropped.clone()));
+        let mut body = TrackedBody::new(inner, Some(10));
+        next(&mut body).await;
+        assert!(body.is_abandoned());
+        next(&mut body).await;
+        assert!(!body.is_abandoned());
+    }
+}
@@ -0,0 +1,220 @@
+//! Run a URL through the proxy pipeline and report where it is rejected
+
+use super::config::Config;
+use super::deny_list::DenyList;
+use super::error::CamoError;
+use super::data_uri;
+use super::decode;
+use super::forward::ForwardPolicy;
+#[cfg(feature = "object-store")]
+use super::http_client::ObjectStoreClient;
+use super::homograph::is_mixed_script;
+use super::http_client::{HttpClient, ReqwestClient, ResponseFilter, UpgradingClient};
+use super::network::NetworkPolicy;
+use crate::utils::encoding::decode_url;
+use crate::utils::scheme::resolve_scheme;
+use crate::CamoUrl;
+
+use axum::http::{header, HeaderMap};
+use std::sync::Arc;
+use url::{Host, Url};
+
+/// Outcome of one pipeline stage
+pub struct Step {
+    pub name: &'static str,
+    pub result: Result<String, CamoError>,
+}
+
+/// Sign `url` and run it through every stage of the proxy
+///
+/// Stops at the first stage that rejects the URL, which is the last step.
+pub async fn diagnose(config: &Config, url: &str) -> Vec<Step> {
+    let mut steps = Vec::new();
+    if let Err((name, e)) = run(config, url, &mut steps).await {
+        steps.push(Step {
+            name,
+            result: Err(e),
+        });
+    }
+    steps
+}
+
+/// The option that controls the policy behind an error, if any
+pub fn hint(e: &CamoError) -> Option<&'static str> {
+    match e {
+        CamoError::PrivateNetworkNotAllowed => Some("--block-private"),
+        CamoError::NetworkNotAllowed => Some("--block-cidr / --block-cidr-file"),
+        CamoError::ContentTypeNotAllowed(_) => {
+            Some("--allow-video / --allow-audio / --allow-content-type")
+        }
+        CamoError::ContentTooLarge(_) => Some("--max-size / --data-uri-max-size"),
+        CamoError::TooManyRedirects => Some("--max-redirects"),
+        CamoError::Timeout => Some("--timeout"),
+        CamoError::Denied => Some("--deny-list"),
+        _ => None,
+    }
+}
+
+type StepError = (&'static str, CamoError);
+
+async fn run(config: &Config, url: &str, steps: &mut Vec<Step>) -> Result<(), StepError> {
+    let mut passed = |name, detail: String| {
+        steps.push(Step {
+            name,
+            result: Ok(detail),
+        })
+    };
+
+    let key = config.key.as_deref().unwrap_or_default();
+    let signer = config.signature().signer(key);
+    let signed = CamoUrl::new(key).with_signer(signer.clone()).sign(url);
+    if decode_url(&signed.encoded_url).ok().as_deref() != Some(url)
+        || !signer.verify(url, &signed.digest)
+    {
+        return Err(("signature", CamoError::DigestMismatch));
+    }
+    passed("signature", signed.to_path());
+
+    let parsed = Url::parse(&resolve_scheme(url, &config.default_scheme))
+        .map_err(|e| ("url", CamoError::InvalidUrl(e.to_string())))?;
+    if !config.allows_scheme(parsed.scheme()) {
+        let e = CamoError::InvalidUrl(format!("Scheme {} not allowed", parsed.scheme()));
+        return Err(("url", e));
+    }
+    if config.reject_mixed_scripts && parsed.host_str().is_some_and(is_mixed_script) {
+        return Err(("url", CamoError::InvalidUrl("Host mixes scripts".into())));
+    }
+    if decode::is_signed_link(signer.as_ref(), &parsed) {
+        return Err(("url", CamoError::LoopDetected));
+    }
+    passed("url", format!("{} scheme", parsed.scheme()));
+
+    let deny_list = DenyList::from_config(config).map_err(|e| {
+        let e = CamoError::Upstream(format!("failed to load deny list: {}", e));
+        ("deny-list", e)
+    })?;
+    if deny_list.is_denied(&signed.digest, &parsed) {
+        return Err(("deny-list", CamoError::Denied));
+    }
+    passed("deny-list", "not taken down".into());
+
+    if parsed.scheme() == "data" {
+        let data = data_uri::decode(&parsed, config.data_uri_max_size).map_err(|e| ("data", e))?;
+        if !ResponseFilter::new(config).is_allowed_content_type(&data.content_type) {
+            return Err(("data", CamoError::ContentTypeNotAllowed(data.content_type)));
+        }
+        passed("data", format!("{}, {} bytes", data.content_type, data.body.len()));
+        return Ok(());
+    }
+
+    let policy = NetworkPolicy::from_config(config).map_err(|e| {
+        let e = CamoError::Upstream(format!("failed to load blocked networks: {}", e));
+        ("network", e)
+    })?;
+    // Lists given as URLs would otherwise still be loading in the background
+    if let Some(reputation) = policy.reputation() {
+        reputation.fetch_urls().await;
+    }
+    // The bucket name is not a host; the storage endpoint is checked when fetching
+    if parsed.scheme() != "s3" {
+        policy.check(&parsed).map_err(|e| ("network", e))?;
+        if let Some(Host::Domain(host)) = parsed.host() {
+            policy.resolve(host).await.map_err(|e| ("network", e))?;
+        }
+        passed("network", "host resolves to allowed addresses".into());
+    }
+
+    // Static headers and credentials apply; there is no client to forward headers from
+    let forward = ForwardPolicy::from_config(config).map_err(|e| {
+        let e = CamoError::Upstream(format!("failed to load upstream credentials: {}", e));
+        ("fetch", e)
+    })?;
+    let client = ReqwestClient::new(config).map_err(|e| ("fetch", CamoError::Upstream(e.to_string())))?;
+    let client: Arc<dyn HttpClient> = Arc::new(client);
+    let client: Arc<dyn HttpClient> = if config.upgrade_insecure_upstream {
+        Arc::new(UpgradingClient::new(client, !config.https_only_upstream))
+    } else {
+        client
+    };
+    #[cfg(feature = "object-store")]
+    let client: Arc<dyn HttpClient> = Arc::new(ObjectStoreClient::new(config, client));
+    let response = client
+        .get(parsed.clone(), forward.headers(&HeaderMap::new(), &parsed))
+        .await
+        .map_err(|e| ("fetch", e))?;
+    let content_type = response
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("-")
+        .to_string();
+    passed("fetch", format!("content type {}", content_type));
+
+    let body = axum::body::to_bytes(response.body, usize::MAX)
+        .await
+        .map_err(|e| match e.into_inner().downcast::<CamoError>() {
+            Ok(e) => ("body", *e),
+            Err(e) => ("body", CamoError::Upstream(e.to_string())),
+        })?;
+    let limit = ResponseFilter::new(config).max_size(&content_type);
+    passed("body", format!("{} bytes, limit {}", body.len(), limit));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn config(args: &[&str]) -> Config {
+        Config::parse_from(["camo", "--key", "secret"].iter().chain(args))
+    }
+
+    fn failure(steps: &[Step]) -> Option<(&'static str, &CamoError)> {
+        let last = steps.last()?;
+        last.result.as_ref().err().map(|e| (last.name, e))
+    }
+
+    #[tokio::test]
+    async fn test_private_network() {
+        let steps = diagnose(&config(&[]), "http://127.0.0.1/image.png").await;
+
+        assert_eq!(steps.len(), 4);
+        assert!(steps[0].result.is_ok());
+        assert!(matches!(
+            failure(&steps),
+            Some(("network", CamoError::PrivateNetworkNotAllowed))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_blocked_cidr() {
+        let mut config = config(&["--block-cidr", "127.0.0.0/8"]);
+        config.block_private = false;
+        let steps = diagnose(&config, "http://127.0.0.1/image.png").await;
+
+        let (name, e) = failure(&steps).unwrap();
+        assert_eq!(name, "network");
+        assert_eq!(hint(e), Some("--block-cidr / --block-cidr-file"));
+    }
+
+    #[tokio::test]
+    async fn test_data_uri() {
+        let uri = "data:image/png;base64,iVBORw0KGgo=";
+
+        let steps = diagnose(&config(&[]), uri).await;
+        assert!(matches!(failure(&steps), Some(("url", CamoError::InvalidUrl(_)))));
+
+        let steps = diagnose(&config(&["--allowed-schemes", "https,data"]), uri).await;
+        assert!(failure(&steps).is_none());
+        assert_eq!(steps.last().unwrap().name, "data");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_scheme() {
+        let steps = diagnose(&config(&[]), "ftp://example.com/image.png").await;
+
+        assert!(matches!(failure(&steps), Some(("url", CamoError::InvalidUrl(_)))));
+    }
+}
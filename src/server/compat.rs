@@ -0,0 +1,111 @@
+use super::error::CamoError;
+
+use std::borrow::Cow;
+
+/// Quirks of other camo implementations to follow, from `--compat`
+///
+/// Links that the implementation being replaced accepted should keep
+/// working after a switch to camo-rs:
+///
+/// - `node`: only the first segment after the digest is the encoded URL,
+///   as Node camo splits the path at most twice, and a path URL that does
+///   not decode falls back to the `url` query parameter.
+/// - `go`: digests may be uppercase hex.
+/// - `strict`: digests must be lowercase hex, and a `url` parameter may
+///   only appear once.
+///
+/// Without `--compat` none of these apply, and of several `url`
+/// parameters the first is used.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compat {
+    #[default]
+    Native,
+    Node,
+    Go,
+    Strict,
+}
+
+impl Compat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "node" => Some(Compat::Node),
+            "go" => Some(Compat::Go),
+            "strict" => Some(Compat::Strict),
+            _ => None,
+        }
+    }
+
+    /// Whether a digest of hex digits is well-formed
+    pub fn accepts_digest(self, digest: &str) -> bool {
+        self != Compat::Strict || !digest.bytes().any(|b| b.is_ascii_uppercase())
+    }
+
+    /// The digest in the form it is verified in
+    pub fn digest(self, digest: &str) -> Cow<'_, str> {
+        match self {
+            Compat::Go => Cow::Owned(digest.to_ascii_lowercase()),
+            _ => Cow::Borrowed(digest),
+        }
+    }
+
+    /// The encoded URL in what follows the digest in a path-format link
+    pub fn encoded_url(self, path: &str) -> &str {
+        match self {
+            Compat::Node => path.split('/').next().unwrap_or_default(),
+            _ => path,
+        }
+    }
+
+    /// Whether a path URL that does not decode is looked for in the query string
+    pub fn falls_back_to_query(self) -> bool {
+        self == Compat::Node
+    }
+
+    /// The `url` query parameter, percent-decoded
+    pub fn url_param(self, query: &str) -> Result<Option<String>, CamoError> {
+        let mut urls = url::form_urlencoded::parse(query.as_bytes())
+            .filter(|(name, _)| name == "url")
+            .map(|(_, url)| url.into_owned());
+        let url = urls.next();
+        if self == Compat::Strict && urls.next().is_some() {
+            return Err(CamoError::InvalidUrl("Repeated url parameter".into()));
+        }
+        Ok(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "54CEC8E46F18F585268E3972432CD8DA7AEC6DC1";
+
+    #[test]
+    fn test_digest() {
+        assert!(Compat::Native.accepts_digest(DIGEST));
+        assert!(!Compat::Strict.accepts_digest(DIGEST));
+        assert!(Compat::Strict.accepts_digest(&DIGEST.to_lowercase()));
+        assert_eq!(Compat::Go.digest(DIGEST), DIGEST.to_lowercase());
+        assert_eq!(Compat::Node.digest(DIGEST), DIGEST);
+    }
+
+    #[test]
+    fn test_encoded_url() {
+        assert_eq!(Compat::Native.encoded_url("6874/extra"), "6874/extra");
+        assert_eq!(Compat::Node.encoded_url("6874/extra"), "6874");
+        assert_eq!(Compat::Node.encoded_url("6874/"), "6874");
+        assert_eq!(Compat::Go.encoded_url("aHR0cA=="), "aHR0cA==");
+    }
+
+    #[test]
+    fn test_url_param() {
+        let query = "url=https%3A%2F%2Fa.example%2F&url=https%3A%2F%2Fb.example%2F";
+        assert_eq!(
+            Compat::Native.url_param(query).unwrap().as_deref(),
+            Some("https://a.example/")
+        );
+        assert!(Compat::Strict.url_param(query).is_err());
+        assert!(Compat::Strict.url_param("url=https%3A%2F%2Fa.example%2F").is_ok());
+        assert_eq!(Compat::Go.url_param("other=1").unwrap(), None);
+    }
+}
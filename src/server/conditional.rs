@@ -0,0 +1,284 @@
+use axum::http::{header, HeaderMap, HeaderValue};
+use sha1::{Digest, Sha1};
+use std::time::Duration;
+
+/// Headers a 304 repeats from the full response (RFC 9110 section 15.4.5)
+const NOT_MODIFIED_HEADERS: [header::HeaderName; 5] = [
+    header::CACHE_CONTROL,
+    header::DATE,
+    header::ETAG,
+    header::EXPIRES,
+    header::VARY,
+];
+
+/// Headers of a `304 Not Modified` answer if the request's validators match `stored`
+///
+/// `If-None-Match` takes precedence over `If-Modified-Since`. A stored
+/// response with `Vary: *` is never reused. Date validators only identify
+/// the variant when the response varies on nothing but `Origin`, which the
+/// proxy sets per request itself; entity tags identify it on their own.
+pub fn not_modified(request: &HeaderMap, stored: &HeaderMap) -> Option<HeaderMap> {
+    let vary = varies_on(stored);
+    if vary.iter().any(|name| name == "*") {
+        return None;
+    }
+
+    let matches = if let Some(if_none_match) = request.get(header::IF_NONE_MATCH) {
+        let etag = stored.get(header::ETAG)?;
+        etag_matches(if_none_match.to_str().ok()?, etag.to_str().ok()?)
+    } else if let Some(since) = request.get(header::IF_MODIFIED_SINCE) {
+        if vary.iter().any(|name| name != "origin") {
+            return None;
+        }
+        let since = parse_http_date(since.to_str().ok()?)?;
+        let modified = parse_http_date(stored.get(header::LAST_MODIFIED)?.to_str().ok()?)?;
+        modified <= since
+    } else {
+        return None;
+    };
+    if !matches {
+        return None;
+    }
+
+    let mut headers = HeaderMap::new();
+    for name in NOT_MODIFIED_HEADERS {
+        for value in stored.get_all(&name) {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+    // Only useful to caches when there is no entity tag
+    if !headers.contains_key(header::ETAG)
+        && let Some(modified) = stored.get(header::LAST_MODIFIED)
+    {
+        headers.insert(header::LAST_MODIFIED, modified.clone());
+    }
+    Some(headers)
+}
+
+/// Weak entity tag derived from a body, for responses upstream sent without validators
+///
+/// Weak, since the proxy cannot promise the bytes are identical to what
+/// another request would have been served, only that they hash alike.
+pub fn weak_etag(body: &[u8]) -> HeaderValue {
+    let digest = Sha1::digest(body);
+    let tag = format!("W/\"{}\"", hex::encode(&digest[..12]));
+    HeaderValue::from_str(&tag).expect("hex is a valid header value")
+}
+
+/// Whether the request carries validators at all
+pub fn is_conditional(request: &HeaderMap) -> bool {
+    request.contains_key(header::IF_NONE_MATCH) || request.contains_key(header::IF_MODIFIED_SINCE)
+}
+
+/// How long a response may be reused, from its `Cache-Control` and `Age`
+///
+/// `None` when it must not be reused without asking the origin again.
+pub fn freshness(headers: &HeaderMap) -> Option<Duration> {
+    let mut max_age = None;
+    for directive in headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+    {
+        let (name, value) = directive.split_once('=').unwrap_or((directive, ""));
+        let name = name.trim().to_ascii_lowercase();
+        match name.as_str() {
+            "no-store" | "no-cache" | "private" => return None,
+            "max-age" => max_age = value.trim().trim_matches('"').parse::<u64>().ok(),
+            _ => {}
+        }
+    }
+
+    let age = headers
+        .get(header::AGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or(0);
+    max_age
+        .and_then(|max_age| max_age.checked_sub(age))
+        .filter(|ttl| *ttl > 0)
+        .map(Duration::from_secs)
+}
+
+/// Lowercase field names listed in `Vary`
+fn varies_on(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Weak comparison of an `If-None-Match` list against an entity tag
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if if_none_match.trim() == "*" {
+        return true;
+    }
+
+    let opaque = |tag: &str| {
+        let tag = tag.trim();
+        tag.strip_prefix("W/").unwrap_or(tag).to_string()
+    };
+    let etag = opaque(etag);
+
+    // Entity tags are quoted and may themselves contain commas
+    let mut tags = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in if_none_match.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                tags.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    tags.push(current);
+
+    tags.iter().any(|tag| opaque(tag) == etag)
+}
+
+/// Seconds since the epoch of an IMF-fixdate such as `Sun, 06 Nov 1994 08:49:37 GMT`
+///
+/// The obsolete RFC 850 and asctime formats are not accepted; a request
+/// using them simply gets the full response.
+fn parse_http_date(date: &str) -> Option<i64> {
+    let mut parts = date.trim().split_ascii_whitespace().skip(1);
+    let (day, month, year, time, zone) = (
+        parts.next()?.parse::<i64>().ok()?,
+        parts.next()?,
+        parts.next()?.parse::<i64>().ok()?,
+        parts.next()?,
+        parts.next()?,
+    );
+    if zone != "GMT" || parts.next().is_some() {
+        return None;
+    }
+
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+
+    let mut time = time.split(':').map(|v| v.parse::<i64>().ok());
+    let (Some(Some(h)), Some(Some(m)), Some(Some(s)), None) =
+        (time.next(), time.next(), time.next(), time.next())
+    else {
+        return None;
+    };
+
+    // Days since the epoch from a civil date (Howard Hinnant's algorithm)
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    Some(days * 86400 + h * 3600 + m * 60 + s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    const STORED: [(&str, &str); 5] = [
+        ("etag", "\"v1\""),
+        ("last-modified", "Sun, 06 Nov 1994 08:49:37 GMT"),
+        ("cache-control", "public, max-age=600"),
+        ("content-type", "image/png"),
+        ("content-length", "42"),
+    ];
+
+    #[test]
+    fn test_if_none_match() {
+        let stored = headers(&STORED);
+
+        let matched = not_modified(&headers(&[("if-none-match", "W/\"v1\"")]), &stored).unwrap();
+        assert_eq!(matched[header::ETAG], "\"v1\"");
+        assert_eq!(matched[header::CACHE_CONTROL], "public, max-age=600");
+        assert!(!matched.contains_key(header::CONTENT_TYPE));
+        assert!(!matched.contains_key(header::LAST_MODIFIED));
+
+        let list = headers(&[("if-none-match", "\"a,b\", \"v1\"")]);
+        assert!(not_modified(&list, &stored).is_some());
+        assert!(not_modified(&headers(&[("if-none-match", "*")]), &stored).is_some());
+        assert!(not_modified(&headers(&[("if-none-match", "\"v2\"")]), &stored).is_none());
+
+        // If-None-Match wins over a matching If-Modified-Since
+        let both = headers(&[
+            ("if-none-match", "\"v2\""),
+            ("if-modified-since", "Sun, 06 Nov 1994 08:49:37 GMT"),
+        ]);
+        assert!(not_modified(&both, &stored).is_none());
+        assert!(not_modified(&headers(&[]), &stored).is_none());
+    }
+
+    #[test]
+    fn test_if_modified_since() {
+        let stored = headers(&STORED[1..]);
+        let since = |date: &'static str| headers(&[("if-modified-since", date)]);
+
+        let matched = not_modified(&since("Sun, 06 Nov 1994 08:49:37 GMT"), &stored).unwrap();
+        assert_eq!(matched[header::LAST_MODIFIED], "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert!(not_modified(&since("Mon, 07 Nov 1994 00:00:00 GMT"), &stored).is_some());
+        assert!(not_modified(&since("Sat, 05 Nov 1994 08:49:37 GMT"), &stored).is_none());
+        assert!(not_modified(&since("Sunday, 06-Nov-94 08:49:37 GMT"), &stored).is_none());
+    }
+
+    #[test]
+    fn test_vary() {
+        let mut stored = headers(&STORED);
+        stored.insert(header::VARY, HeaderValue::from_static("Accept"));
+
+        let matched = not_modified(&headers(&[("if-none-match", "\"v1\"")]), &stored).unwrap();
+        assert_eq!(matched[header::VARY], "Accept");
+        let since = headers(&[("if-modified-since", "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        assert!(not_modified(&since, &stored).is_none());
+
+        stored.insert(header::VARY, HeaderValue::from_static("Origin"));
+        assert!(not_modified(&since, &stored).is_some());
+
+        stored.insert(header::VARY, HeaderValue::from_static("*"));
+        assert!(not_modified(&headers(&[("if-none-match", "\"v1\"")]), &stored).is_none());
+    }
+
+    #[test]
+    fn test_freshness() {
+        assert_eq!(freshness(&headers(&STORED)), Some(Duration::from_secs(600)));
+
+        let aged = headers(&[("cache-control", "max-age=600"), ("age", "100")]);
+        assert_eq!(freshness(&aged), Some(Duration::from_secs(500)));
+        let expired = headers(&[("cache-control", "max-age=600"), ("age", "600")]);
+        assert_eq!(freshness(&expired), None);
+
+        assert_eq!(freshness(&headers(&[("cache-control", "max-age=60, no-cache")])), None);
+        assert_eq!(freshness(&headers(&[("cache-control", "private, max-age=60")])), None);
+        assert_eq!(freshness(&headers(&[])), None);
+    }
+
+    #[test]
+    fn test_parse_http_date() {
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784111777));
+        assert_eq!(parse_http_date("Thu, 29 Feb 2024 23:59:59 GMT"), Some(1709251199));
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), None);
+    }
+}
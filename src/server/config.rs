@@ -1,9 +1,19 @@
-use super::content_types::{AUDIO_TYPES, IMAGE_TYPES, VIDEO_TYPES};
+use super::compat::Compat;
+use super::content_types::{AUDIO_TYPES, DOCUMENT_TYPES, FONT_TYPES, IMAGE_TYPES, VIDEO_TYPES};
+use super::http_client::{DispositionPolicy, OversizePolicy};
 #[cfg(feature = "server")]
-use clap::{Parser, Subcommand};
+use super::signature::Scheme;
+use crate::utils::encoding::Encoding;
+use crate::utils::userinfo::UserinfoPolicy;
+#[cfg(feature = "server")]
+use clap::{Args, Parser, Subcommand, ValueEnum};
+#[cfg(feature = "server")]
+use serde::{Deserialize, Serialize};
+use ipnet::IpNet;
+use std::path::PathBuf;
 
 #[derive(Debug, Clone)]
-#[cfg_attr(feature = "server", derive(Parser))]
+#[cfg_attr(feature = "server", derive(Parser, Serialize, Deserialize))]
 #[cfg_attr(
     feature = "server",
     command(name = "camo-rs", about = "SSL image proxy server")
@@ -11,28 +21,264 @@ use clap::{Parser, Subcommand};
 pub struct Config {
     #[cfg(feature = "server")]
     #[command(subcommand)]
+    #[serde(skip)]
     pub command: Option<Command>,
 
+    /// Configuration file (TOML or YAML), watched and reloaded on change
+    #[cfg(feature = "server")]
+    #[arg(long = "config", env = "CAMO_CONFIG")]
+    #[serde(skip)]
+    pub config_file: Option<PathBuf>,
+
+    /// Print the resolved configuration as JSON, secrets redacted, and exit
+    #[cfg(feature = "server")]
+    #[arg(long)]
+    #[serde(skip)]
+    pub print_config: bool,
+
     /// HMAC key for URL signing
     #[cfg_attr(feature = "server", arg(short, long, env = "CAMO_KEY", global = true))]
     pub key: Option<String>,
 
-    /// Listen address
+    /// File containing the HMAC key (or an `aws-sm://`/`gcp-sm://` secret URI), re-read on SIGHUP
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_KEY_FILE", global = true)]
+    pub key_file: Option<String>,
+
+    /// Environment variable holding the base64-encoded HMAC key
+    #[cfg(feature = "server")]
+    #[arg(long, global = true)]
+    pub key_env_b64: Option<String>,
+
+    /// Ed25519 public key (hex or base64) verifying links without a tenant, in place of --key
+    ///
+    /// Links are then signed with the private key by the application alone,
+    /// so the proxy cannot sign any itself.
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_VERIFY_PUBKEY", global = true)]
+    pub verify_pubkey: Option<String>,
+
+    /// Signature scheme of links, for every key: hmac-sha1 (default), hmac-sha256 or blake3
+    #[cfg(feature = "server")]
+    #[arg(
+        long,
+        env = "CAMO_SIGNATURE",
+        value_parser = ["hmac-sha1", "hmac-sha256", "blake3"],
+        global = true
+    )]
+    pub signature: Option<String>,
+
+    /// Named HMAC key as `tenant=secret`, verifying links under `/t/<tenant>/`
+    #[cfg_attr(
+        feature = "server",
+        arg(long = "tenant-key", env = "CAMO_TENANT_KEYS", value_delimiter = ',', global = true)
+    )]
+    pub tenant_keys: Vec<String>,
+
+    /// Requests each tenant may make per quota window (0 for no limit)
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_TENANT_QUOTA_REQUESTS", default_value_t = 0)]
+    pub tenant_quota_requests: u64,
+
+    /// Response body bytes each tenant may be served per quota window (0 for no limit)
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_TENANT_QUOTA_BYTES", default_value_t = 0)]
+    pub tenant_quota_bytes: u64,
+
+    /// Quota of one tenant as `tenant=requests:bytes`, overriding the defaults above
+    #[cfg(feature = "server")]
+    #[arg(long = "tenant-quota", env = "CAMO_TENANT_QUOTAS", value_delimiter = ',')]
+    pub tenant_quotas: Vec<String>,
+
+    /// Length of the tenant quota window in seconds
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_TENANT_QUOTA_WINDOW", default_value_t = 3600)]
+    pub tenant_quota_window: u64,
+
+    /// Redis URL to keep tenant quota counters in, shared by all instances (default: in memory)
+    #[cfg(feature = "redis")]
+    #[arg(long, env = "CAMO_TENANT_QUOTA_REDIS")]
+    pub tenant_quota_redis: Option<String>,
+
+    /// Requests per second each client address may make (0 for no limit)
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_RATE_LIMIT", default_value_t = 0.0)]
+    pub rate_limit: f64,
+
+    /// Requests a client may make at once before the rate limit applies (0 for one second's worth)
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_RATE_LIMIT_BURST", default_value_t = 0)]
+    pub rate_limit_burst: u64,
+
+    /// Redis URL to keep rate limit buckets in, shared by all instances (default: in memory)
+    #[cfg(feature = "redis")]
+    #[arg(long, env = "CAMO_RATE_LIMIT_REDIS")]
+    pub rate_limit_redis: Option<String>,
+
+    /// Response bytes held in memory above which new requests are shed with 503 (0 for no limit)
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_MEMORY_BUDGET", default_value_t = 0)]
+    pub memory_budget: u64,
+
+    /// Listen address (`host:port` or `unix:/path/to.sock`)
     #[cfg_attr(feature = "server", arg(long, env = "CAMO_LISTEN", default_value = "0.0.0.0:8080"))]
     pub listen: String,
 
+    /// TLS certificate chain (PEM) for serving HTTPS directly
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_TLS_CERT", requires = "tls_key"))]
+    pub tls_cert: Option<PathBuf>,
+
+    /// TLS private key (PEM) for serving HTTPS directly
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_TLS_KEY", requires = "tls_cert"))]
+    pub tls_key: Option<PathBuf>,
+
+    /// Also serve HTTP/3 over QUIC on the listen port (experimental, requires TLS)
+    #[cfg(feature = "http3")]
+    #[arg(long, env = "CAMO_HTTP3", requires = "tls_cert")]
+    pub http3: bool,
+
     /// Maximum content length in bytes
     #[cfg_attr(feature = "server", arg(long, env = "CAMO_LENGTH_LIMIT", default_value_t = 5 * 1024 * 1024))]
     pub max_size: u64,
 
+    /// Maximum content length in bytes for images (defaults to --max-size)
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_MAX_SIZE_IMAGE"))]
+    pub max_size_image: Option<u64>,
+
+    /// Maximum content length in bytes for video (defaults to --max-size)
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_MAX_SIZE_VIDEO"))]
+    pub max_size_video: Option<u64>,
+
+    /// Maximum content length in bytes for audio (defaults to --max-size)
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_MAX_SIZE_AUDIO"))]
+    pub max_size_audio: Option<u64>,
+
+    /// Hard size cap in bytes; bodies between the size limit and this are served and logged
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_HARD_MAX_SIZE"))]
+    pub hard_max_size: Option<u64>,
+
+    /// What to do with bodies over the hard cap: fail them, or cut them off at it
+    #[cfg_attr(
+        feature = "server",
+        arg(
+            long,
+            env = "CAMO_OVERSIZE",
+            value_parser = ["reject", "truncate"],
+            default_value = "reject"
+        )
+    )]
+    pub oversize: String,
+
+    /// What to do with video and audio over the hard cap (defaults to --oversize);
+    /// `partial` serves the bytes up to the cap as 206 Partial Content
+    #[cfg_attr(
+        feature = "server",
+        arg(long, env = "CAMO_OVERSIZE_STRATEGY", value_parser = ["reject", "truncate", "partial"])
+    )]
+    pub oversize_strategy: Option<String>,
+
+    /// Maximum total size in bytes of the upstream response headers
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_MAX_HEADER_SIZE", default_value_t = 65536))]
+    pub max_header_size: usize,
+
+    /// Maximum number of upstream response headers
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_MAX_HEADER_COUNT", default_value_t = 100))]
+    pub max_header_count: usize,
+
+    /// Maximum length of the encoded target URL in a request
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_MAX_URL_LENGTH", default_value_t = 8192))]
+    pub max_url_length: usize,
+
+    /// Encodings accepted for the target URL in the path (detected per request)
+    #[cfg_attr(
+        feature = "server",
+        arg(
+            long = "url-encoding",
+            env = "CAMO_URL_ENCODINGS",
+            value_delimiter = ',',
+            value_parser = ["hex", "base64"],
+            default_values = ["hex", "base64"]
+        )
+    )]
+    pub url_encodings: Vec<String>,
+
+    /// Follow the link quirks of the camo implementation being replaced: node, go or strict
+    #[cfg_attr(
+        feature = "server",
+        arg(long, env = "CAMO_COMPAT", value_parser = ["node", "go", "strict"])
+    )]
+    pub compat: Option<String>,
+
     /// Maximum number of redirects to follow
     #[cfg_attr(feature = "server", arg(long, env = "CAMO_MAX_REDIRECTS", default_value_t = 4))]
     pub max_redirects: u32,
 
+    /// Camo proxies a request may already have passed through, counted in `X-Camo-Depth`
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_MAX_DEPTH", default_value_t = 2))]
+    pub max_depth: u32,
+
     /// Socket timeout in seconds
     #[cfg_attr(feature = "server", arg(long, env = "CAMO_SOCKET_TIMEOUT", default_value_t = 10))]
     pub timeout: u64,
 
+    /// Seconds a client may accept no response bytes before it is disconnected (0 for no limit)
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_CLIENT_WRITE_TIMEOUT", default_value_t = 30)]
+    pub client_write_timeout: u64,
+
+    /// Seconds from a request arriving to its whole response being sent (0 for no limit)
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_SEND_TIMEOUT", default_value_t = 0)]
+    pub send_timeout: u64,
+
+    /// Retries for upstream connection failures and 502/503 responses
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_UPSTREAM_RETRIES", default_value_t = 0))]
+    pub upstream_retries: u32,
+
+    /// Base delay in milliseconds before the first retry, doubled on each attempt
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_UPSTREAM_RETRY_BACKOFF", default_value_t = 100))]
+    pub upstream_retry_backoff: u64,
+
+    /// Client request headers passed on upstream; cookies and credentials never are
+    #[cfg_attr(
+        feature = "server",
+        arg(
+            long = "forward-header",
+            env = "CAMO_FORWARD_HEADERS",
+            value_delimiter = ',',
+            value_parser = super::forward::FORWARDABLE_HEADERS
+        )
+    )]
+    pub forward_headers: Vec<String>,
+
+    /// Static header sent to an upstream host, as `host=Name: value` (comma-separated or repeated)
+    #[cfg_attr(feature = "server", arg(long = "upstream-header", env = "CAMO_UPSTREAM_HEADERS", value_delimiter = ',', value_parser = super::forward::parse_upstream_header))]
+    pub upstream_headers: Vec<String>,
+
+    /// File of upstream credentials, one `host bearer <token>` or `host basic <user>:<password>` per line
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_UPSTREAM_AUTH_FILE"))]
+    pub upstream_auth_file: Option<PathBuf>,
+
+    /// S3-compatible endpoint for object storage, e.g. `https://storage.googleapis.com` (default: AWS S3)
+    #[cfg(feature = "object-store")]
+    #[arg(long, env = "CAMO_OBJECT_STORE_ENDPOINT")]
+    pub object_store_endpoint: Option<String>,
+
+    /// Region object storage requests are signed for (default: `AWS_REGION`, then `us-east-1`)
+    #[cfg(feature = "object-store")]
+    #[arg(long, env = "CAMO_OBJECT_STORE_REGION")]
+    pub object_store_region: Option<String>,
+
+    /// Hosts fetched from a bucket instead of over HTTP, as `host=bucket[/prefix]` (comma-separated or repeated)
+    #[cfg(feature = "object-store")]
+    #[arg(long = "object-store-host", env = "CAMO_OBJECT_STORE_HOSTS", value_delimiter = ',')]
+    pub object_store_hosts: Vec<String>,
+
+    /// clamd to scan every upstream body with before it is forwarded (`host:port` or `unix:/path`)
+    #[cfg(feature = "clamd")]
+    #[arg(long, env = "CAMO_CLAMD")]
+    pub clamd: Option<String>,
+
     /// Allow video content types
     #[cfg_attr(feature = "server", arg(long, env = "CAMO_ALLOW_VIDEO", default_value_t = false))]
     pub allow_video: bool,
@@ -41,17 +287,440 @@ pub struct Config {
     #[cfg_attr(feature = "server", arg(long, env = "CAMO_ALLOW_AUDIO", default_value_t = false))]
     pub allow_audio: bool,
 
+    /// Allow web fonts (woff, woff2, ttf, otf), sent with CORS and CORP headers
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_ALLOW_FONTS", default_value_t = false))]
+    pub allow_fonts: bool,
+
+    /// Allow documents (application/pdf), sandboxed by their Content-Security-Policy
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_ALLOW_DOCUMENTS", default_value_t = false))]
+    pub allow_documents: bool,
+
+    /// Extra content types to proxy (comma-separated or repeated)
+    #[cfg_attr(feature = "server", arg(long = "allow-content-type", env = "CAMO_ALLOW_CONTENT_TYPES", value_delimiter = ','))]
+    pub allow_content_types: Vec<String>,
+
+    /// Content types never to proxy, even built-in ones (comma-separated or repeated)
+    #[cfg_attr(feature = "server", arg(long = "deny-content-type", env = "CAMO_DENY_CONTENT_TYPES", value_delimiter = ','))]
+    pub deny_content_types: Vec<String>,
+
+    /// Extra upstream response headers to strip (comma-separated or repeated)
+    #[cfg_attr(feature = "server", arg(long = "strip-header", env = "CAMO_STRIP_HEADERS", value_delimiter = ','))]
+    pub strip_headers: Vec<String>,
+
+    /// Cache-Control sent when upstream has no caching headers
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_DEFAULT_CACHE_CONTROL"))]
+    pub default_cache_control: Option<String>,
+
+    /// Raise upstream max-age values below this many seconds (0 to disable)
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_MIN_CACHE_TTL", default_value_t = 0))]
+    pub min_cache_ttl: u64,
+
+    /// Origins allowed via Access-Control-Allow-Origin and Timing-Allow-Origin (`*` for any)
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_CORS_ORIGIN", value_delimiter = ','))]
+    pub cors_origin: Vec<String>,
+
+    #[cfg_attr(feature = "server", command(flatten), serde(flatten))]
+    pub security_headers: SecurityHeaders,
+
+    /// Compress SVG and other compressible responses for clients that accept gzip or brotli
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_COMPRESSION", default_value_t = true)]
+    pub compression: bool,
+
+    /// URL schemes that may be proxied (add `data` to serve signed data: URIs, `s3` for object storage)
+    #[cfg_attr(
+        feature = "server",
+        arg(
+            long,
+            env = "CAMO_ALLOWED_SCHEMES",
+            value_delimiter = ',',
+            value_parser = ["http", "https", "data", "s3"],
+            default_values = ["http", "https"]
+        )
+    )]
+    pub allowed_schemes: Vec<String>,
+
+    /// Scheme protocol-relative targets (`//example.com/image.png`) are fetched with
+    #[cfg_attr(
+        feature = "server",
+        arg(
+            long,
+            env = "CAMO_DEFAULT_SCHEME",
+            value_parser = ["http", "https"],
+            default_value = "https"
+        )
+    )]
+    pub default_scheme: String,
+
+    /// Never fetch over plain `http:`, neither targets nor redirects, even if allowed by scheme
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_HTTPS_ONLY_UPSTREAM", default_value_t = false))]
+    pub https_only_upstream: bool,
+
+    /// Try `http:` targets over HTTPS first, falling back to plain HTTP if that fails
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_UPGRADE_INSECURE_UPSTREAM", default_value_t = false))]
+    pub upgrade_insecure_upstream: bool,
+
+    /// Refuse target hosts with a label mixing scripts, like `еxample.com` with a Cyrillic `е`
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_REJECT_MIXED_SCRIPTS", default_value_t = false))]
+    pub reject_mixed_scripts: bool,
+
+    /// What to do with credentials (`user:password@`) in a target URL
+    #[cfg_attr(
+        feature = "server",
+        arg(
+            long,
+            env = "CAMO_USERINFO",
+            value_parser = ["reject", "strip", "allow"],
+            default_value = "reject"
+        )
+    )]
+    pub userinfo: String,
+
+    /// How upstream Content-Disposition is sent: `inline` with a sanitized filename, a
+    /// `synthesize`d `image.<ext>` filename, or `strip`ped
+    #[cfg_attr(
+        feature = "server",
+        arg(
+            long,
+            env = "CAMO_CONTENT_DISPOSITION",
+            value_parser = ["inline", "synthesize", "strip"],
+            default_value = "inline"
+        )
+    )]
+    pub content_disposition: String,
+
+    /// Format of error responses; clients sending `Accept: application/json` always get JSON
+    #[cfg_attr(
+        feature = "server",
+        arg(
+            long,
+            env = "CAMO_ERROR_FORMAT",
+            value_parser = ["text", "json"],
+            default_value = "text"
+        )
+    )]
+    pub error_format: String,
+
+    /// Maximum decoded size of a data: URI in bytes
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_DATA_URI_MAX_SIZE", default_value_t = 65536))]
+    pub data_uri_max_size: u64,
+
+    /// HTTPS hosts redirected to with a 302 instead of proxied (`*.example.com` for subdomains)
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_REDIRECT_HTTPS_HOSTS", value_delimiter = ','))]
+    pub redirect_https_hosts: Vec<String>,
+
+    /// Hosts of the pages allowed to embed proxied resources, checked against `Origin` or
+    /// `Referer` (`*.example.com` for subdomains; any page when empty)
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_REFERER_HOSTS", value_delimiter = ','))]
+    pub referer_hosts: Vec<String>,
+
+    /// Whether requests without `Origin` and `Referer` pass `--referer-hosts`
+    #[cfg_attr(
+        feature = "server",
+        arg(
+            long,
+            env = "CAMO_EMPTY_REFERER",
+            value_parser = ["allow", "deny"],
+            default_value = "allow"
+        )
+    )]
+    pub empty_referer: String,
+
+    /// Cookie holding the session ID that session-bound links are checked against
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_SESSION_COOKIE"))]
+    pub session_cookie: Option<String>,
+
+    /// Request header holding the session ID, looked at before `--session-cookie`
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_SESSION_HEADER"))]
+    pub session_header: Option<String>,
+
     /// Block requests to private/internal networks (RFC1918)
     #[cfg_attr(feature = "server", arg(long, env = "CAMO_BLOCK_PRIVATE", default_value_t = true))]
     pub block_private: bool,
 
+    /// Additional networks to block, as CIDR ranges (comma-separated or repeated)
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_BLOCK_CIDR", value_delimiter = ',', value_parser = super::network::parse_cidr))]
+    pub block_cidr: Vec<IpNet>,
+
+    /// File with additional networks to block, one CIDR range per line
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_BLOCK_CIDR_FILE"))]
+    pub block_cidr_file: Option<PathBuf>,
+
+    /// Blocklist of IPs, CIDR ranges and hostnames to refuse as targets, as a file or http(s) URL
+    #[cfg(feature = "server")]
+    #[arg(long = "reputation-list", env = "CAMO_REPUTATION_LISTS", value_delimiter = ',')]
+    pub reputation_lists: Vec<String>,
+
+    /// Seconds between reloads of the --reputation-list lists (0 only loads them at startup)
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_REPUTATION_REFRESH", default_value_t = 3600)]
+    pub reputation_refresh: u64,
+
+    /// MaxMind DB files to look up the country and ASN of upstream addresses in (comma-separated)
+    #[cfg(feature = "server")]
+    #[arg(long = "geoip-db", env = "CAMO_GEOIP_DB", value_delimiter = ',')]
+    pub geoip_dbs: Vec<PathBuf>,
+
+    /// Autonomous systems not to fetch from, like `AS64500` (needs --geoip-db)
+    #[cfg(feature = "server")]
+    #[arg(long = "block-asn", env = "CAMO_BLOCK_ASN", value_delimiter = ',')]
+    pub block_asns: Vec<String>,
+
+    /// Countries not to fetch from, as ISO 3166 codes like `KP` (needs --geoip-db)
+    #[cfg(feature = "server")]
+    #[arg(long = "block-country", env = "CAMO_BLOCK_COUNTRY", value_delimiter = ',')]
+    pub block_countries: Vec<String>,
+
+    /// Seconds to trust a hostname's validated DNS result (0 disables the cache)
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_DNS_CACHE_TTL", default_value_t = 60))]
+    pub dns_cache_ttl: u64,
+
+    /// Maximum number of hostnames kept in the DNS cache
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_DNS_CACHE_SIZE", default_value_t = 1024))]
+    pub dns_cache_size: usize,
+
+    /// Seconds connection failures to an upstream address are remembered to try others first (0 disables)
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_UPSTREAM_HEALTH_TTL", default_value_t = 60)]
+    pub upstream_health_ttl: u64,
+
+    /// Milliseconds to wait for connections to an upstream hostname's addresses (0 waits for --timeout)
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_UPSTREAM_CONNECT_TIMEOUT", default_value_t = 0)]
+    pub upstream_connect_timeout: u64,
+
+    /// Seconds to remember upstream 404/410 responses (0 disables)
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_NEGATIVE_TTL_NOT_FOUND", default_value_t = 60))]
+    pub negative_ttl_not_found: u64,
+
+    /// Seconds to remember oversized responses (0 disables)
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_NEGATIVE_TTL_TOO_LARGE", default_value_t = 300))]
+    pub negative_ttl_too_large: u64,
+
+    /// Seconds to remember disallowed content types (0 disables)
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_NEGATIVE_TTL_CONTENT_TYPE", default_value_t = 300))]
+    pub negative_ttl_content_type: u64,
+
+    /// Seconds to remember unresolvable or unreachable hosts (0 disables)
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_NEGATIVE_TTL_UNREACHABLE", default_value_t = 10))]
+    pub negative_ttl_unreachable: u64,
+
+    /// Maximum number of URLs kept in the negative cache (0 disables)
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_NEGATIVE_CACHE_SIZE", default_value_t = 4096))]
+    pub negative_cache_size: usize,
+
+    /// Token that skips the negative cache when sent in `X-Camo-Cache-Bypass`
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_NEGATIVE_CACHE_BYPASS_TOKEN"))]
+    pub negative_cache_bypass_token: Option<String>,
+
+    /// Maximum number of URLs whose validators answer conditional requests with 304 (0 disables)
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_VALIDATOR_CACHE_SIZE", default_value_t = 4096))]
+    pub validator_cache_size: usize,
+
+    /// Seconds between background revalidations of the most requested validator cache entries (0 disables)
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_REVALIDATE_INTERVAL", default_value_t = 0)]
+    pub revalidate_interval: u64,
+
+    /// Validator cache entries revalidated per run, the most requested since the previous run
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_REVALIDATE_TOP", default_value_t = 100)]
+    pub revalidate_top: usize,
+
+    /// Revalidation requests in flight at once
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_REVALIDATE_CONCURRENCY", default_value_t = 4)]
+    pub revalidate_concurrency: usize,
+
+    /// Largest body in bytes buffered to give it a weak ETag when upstream sends no validators (0 disables)
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_ETAG_MAX_SIZE", default_value_t = 0))]
+    pub etag_max_size: u64,
+
+    /// Log the SHA-256 of proxied bodies, and send it in Repr-Digest for those up to this many bytes
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_BODY_DIGEST_MAX_SIZE")]
+    pub body_digest_max_size: Option<u64>,
+
+    /// File of taken-down digests or target URLs, answered with 410 Gone
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_DENY_LIST")]
+    pub deny_list: Option<PathBuf>,
+
+    /// Log one in this many blocked requests per reason on the `audit` target (0 disables)
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_AUDIT_SAMPLE", default_value_t = 1)]
+    pub audit_sample: u64,
+
+    /// On a digest mismatch, log the URL that was hashed, its canonical form and how it was encoded
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_DEBUG_DIGEST")]
+    pub debug_digest: bool,
+
+    /// Base URL of another camo deployment to mirror sampled requests to, logging differences
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_SHADOW_TO")]
+    pub shadow_to: Option<String>,
+
+    /// Mirror one in this many proxy requests to --shadow-to (0 disables)
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_SHADOW_SAMPLE", default_value_t = 1)]
+    pub shadow_sample: u64,
+
+    /// File the audit trail is appended to as JSON lines, instead of the regular log
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_AUDIT_LOG")]
+    pub audit_log: Option<PathBuf>,
+
+    /// Listen address for the admin API (disabled when unset)
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_ADMIN_LISTEN", requires = "admin_token")]
+    pub admin_listen: Option<String>,
+
+    /// Bearer token required by the admin API
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+
     /// Enable metrics endpoint at /metrics
     #[cfg_attr(feature = "server", arg(long, env = "CAMO_METRICS", default_value_t = false))]
     pub metrics: bool,
 
+    /// Upstream hosts always labeled in `camo_upstream_requests_total`, exact or `*.example.com`
+    #[cfg(feature = "server")]
+    #[arg(long = "metrics-host", env = "CAMO_METRICS_HOSTS", value_delimiter = ',')]
+    pub metrics_hosts: Vec<String>,
+
+    /// Other upstream hosts labeled by name before the rest are counted as `other`
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_METRICS_HOST_LIMIT", default_value_t = 20)]
+    pub metrics_host_limit: usize,
+
+    /// Hostname resolved by /readyz to check DNS (empty to skip the check)
+    #[cfg(feature = "server")]
+    #[arg(long, env = "CAMO_READY_DNS_HOST", default_value = "example.com")]
+    pub ready_dns_host: String,
+
+    /// Digest verification results kept for repeated requests (0 disables)
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_VERIFY_CACHE_SIZE", default_value_t = 1024))]
+    pub verify_cache_size: usize,
+
     /// Log level (trace, debug, info, warn, error)
     #[cfg_attr(feature = "server", arg(long, env = "CAMO_LOG_LEVEL", default_value = "info"))]
     pub log_level: String,
+
+    /// Store successful responses in the Cloudflare edge cache
+    #[cfg(feature = "worker")]
+    pub edge_cache: bool,
+
+    /// Seconds responses are kept in the R2/KV persistent cache
+    #[cfg(feature = "worker")]
+    pub persistent_cache_ttl: u64,
+
+    /// Largest body in bytes stored in KV; larger bodies go to R2
+    #[cfg(feature = "worker")]
+    pub persistent_cache_kv_max_size: u64,
+
+    /// Seconds Cloudflare caches upstream responses (`cf.cacheTtl`)
+    #[cfg(feature = "worker")]
+    pub cf_cache_ttl: Option<u32>,
+
+    /// Cache upstream responses regardless of their headers (`cf.cacheEverything`)
+    #[cfg(feature = "worker")]
+    pub cf_cache_everything: bool,
+
+    /// Polish mode applied to upstream images (`cf.polish`)
+    #[cfg(feature = "worker")]
+    pub cf_polish: Option<worker::PolishConfig>,
+
+    /// Image Resizing options applied to upstream images (`cf.image`)
+    #[cfg(feature = "worker")]
+    pub cf_image: Option<worker::ResizeConfig>,
+
+    /// PEM bundle of additional CA certificates trusted for upstream requests
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_TLS_CA_FILE"))]
+    pub tls_ca_file: Option<PathBuf>,
+
+    /// Minimum TLS version accepted from upstream servers
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_TLS_MIN_VERSION", value_enum))]
+    pub tls_min_version: Option<TlsVersion>,
+
+    /// Disable upstream certificate verification (insecure, debugging only)
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_INSECURE_SKIP_VERIFY", default_value_t = false))]
+    pub insecure_skip_verify: bool,
+}
+
+/// Default `Content-Security-Policy`: nothing but the image itself may load
+pub const DEFAULT_CSP: &str = "default-src 'none'; img-src data:; style-src 'unsafe-inline'";
+
+/// Security headers added to every proxied response; an empty value omits the header
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "server", derive(Args, Serialize, Deserialize))]
+pub struct SecurityHeaders {
+    /// Content-Security-Policy sent with proxied responses
+    #[cfg_attr(
+        feature = "server",
+        arg(long, env = "CAMO_CONTENT_SECURITY_POLICY", default_value = DEFAULT_CSP)
+    )]
+    pub content_security_policy: String,
+
+    /// X-Content-Type-Options sent with proxied responses
+    #[cfg_attr(
+        feature = "server",
+        arg(long, env = "CAMO_X_CONTENT_TYPE_OPTIONS", default_value = "nosniff")
+    )]
+    pub x_content_type_options: String,
+
+    /// X-Frame-Options sent with proxied responses
+    #[cfg_attr(feature = "server", arg(long, env = "CAMO_X_FRAME_OPTIONS", default_value = "deny"))]
+    pub x_frame_options: String,
+
+    /// Referrer-Policy sent with proxied responses
+    #[cfg_attr(
+        feature = "server",
+        arg(long, env = "CAMO_REFERRER_POLICY", default_value = "no-referrer")
+    )]
+    pub referrer_policy: String,
+
+    /// Cross-Origin-Resource-Policy sent with proxied responses
+    #[cfg_attr(
+        feature = "server",
+        arg(long, env = "CAMO_CROSS_ORIGIN_RESOURCE_POLICY", default_value = "cross-origin")
+    )]
+    pub cross_origin_resource_policy: String,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self {
+            content_security_policy: DEFAULT_CSP.to_string(),
+            x_content_type_options: "nosniff".to_string(),
+            x_frame_options: "deny".to_string(),
+            referrer_policy: "no-referrer".to_string(),
+            cross_origin_resource_policy: "cross-origin".to_string(),
+        }
+    }
+}
+
+/// TLS protocol version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "server", derive(ValueEnum, Serialize, Deserialize))]
+pub enum TlsVersion {
+    #[cfg_attr(feature = "server", value(name = "1.2"), serde(rename = "1.2"))]
+    Tls12,
+    #[cfg_attr(feature = "server", value(name = "1.3"), serde(rename = "1.3"))]
+    Tls13,
+}
+
+/// Output format of the `sign` subcommand
+#[cfg(feature = "server")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Signed URL only
+    Plain,
+    /// Original and signed URL, tab-separated
+    Tsv,
+    /// One JSON object per line with the URL, digest and signed URL
+    Json,
 }
 
 #[cfg(feature = "server")]
@@ -60,10 +729,14 @@ pub enum Command {
     /// Start the proxy server (default)
     Serve,
 
-    /// Generate a signed URL
+    /// Generate a signed URL, or sign a list of URLs with --stdin/--input
     Sign {
         /// The URL to sign
-        url: String,
+        #[arg(
+            required_unless_present_any = ["stdin", "input"],
+            conflicts_with_all = ["stdin", "input"]
+        )]
+        url: Option<String>,
 
         /// Camo server base URL
         #[arg(long, default_value = "")]
@@ -72,12 +745,113 @@ pub enum Command {
         /// Use base64 encoding instead of hex
         #[arg(long, default_value_t = false)]
         base64: bool,
+
+        /// Read URLs to sign from stdin, one per line
+        #[arg(long, default_value_t = false, conflicts_with = "input")]
+        stdin: bool,
+
+        /// Read URLs to sign from a file, one per line
+        #[arg(long)]
+        input: Option<PathBuf>,
+
+        /// Write signed URLs to a file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+
+        /// Output format (default for a single URL: digest, encoded URL and path)
+        #[arg(long, value_enum)]
+        format: Option<OutputFormat>,
+
+        /// Sign with this tenant's `--tenant-key`, under its `/t/<tenant>` prefix
+        #[arg(long)]
+        tenant: Option<String>,
+    },
+
+    /// Sign a URL and fetch it locally, reporting which policy would block it
+    Check {
+        /// The URL to check
+        url: String,
+    },
+
+    /// Send signed requests to a running instance and report latency and errors
+    Bench {
+        /// Base URL of the instance under test
+        #[arg(long, default_value = "http://localhost:8080")]
+        target: String,
+
+        /// File of upstream URLs to request, one per line
+        #[arg(long)]
+        urls: PathBuf,
+
+        /// Number of requests in flight at once
+        #[arg(long, default_value_t = 64)]
+        concurrency: usize,
+
+        /// Total number of requests, cycling through the URLs
+        #[arg(long, default_value_t = 1000)]
+        requests: usize,
+
+        /// Per-request timeout in seconds
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+
+        /// Sign with this tenant's `--tenant-key`, under its `/t/<tenant>` prefix
+        #[arg(long)]
+        tenant: Option<String>,
+    },
+
+    /// Print the target URL of a camo URL, path or encoded segment (no key needed)
+    Decode {
+        /// Camo URL, `/<digest>/<encoded_url>` path or encoded URL
+        url: String,
     },
 }
 
 impl Config {
-    pub fn allowed_content_types(&self) -> Vec<&'static str> {
-        let mut types: Vec<&'static str> = IMAGE_TYPES.to_vec();
+    pub fn allows_scheme(&self, scheme: &str) -> bool {
+        // Upgraded ones are still fetched over HTTPS alone
+        if scheme == "http" && self.https_only_upstream && !self.upgrade_insecure_upstream {
+            return false;
+        }
+        self.allowed_schemes.iter().any(|allowed| allowed == scheme)
+    }
+
+    pub fn userinfo_policy(&self) -> UserinfoPolicy {
+        UserinfoPolicy::from_name(&self.userinfo).unwrap_or(UserinfoPolicy::Reject)
+    }
+
+    pub fn disposition_policy(&self) -> DispositionPolicy {
+        DispositionPolicy::from_name(&self.content_disposition).unwrap_or_default()
+    }
+
+    pub fn oversize_policy(&self) -> OversizePolicy {
+        OversizePolicy::from_name(&self.oversize).unwrap_or_default()
+    }
+
+    #[cfg(feature = "server")]
+    pub fn signature(&self) -> Scheme {
+        self.signature.as_deref().and_then(Scheme::from_name).unwrap_or_default()
+    }
+
+    pub fn compat(&self) -> Compat {
+        self.compat.as_deref().and_then(Compat::from_name).unwrap_or_default()
+    }
+
+    pub fn json_errors(&self) -> bool {
+        self.error_format == "json"
+    }
+
+    pub fn url_encodings(&self) -> Vec<Encoding> {
+        self.url_encodings
+            .iter()
+            .filter_map(|name| Encoding::from_name(name))
+            .collect()
+    }
+
+    /// Built-in image types, plus the enabled profiles and the configured
+    /// extra types, without the denied ones; all lowercase
+    pub fn allowed_content_types(&self) -> Vec<String> {
+        let mut types: Vec<&str> = IMAGE_TYPES.to_vec();
 
         if self.allow_video {
             types.extend(VIDEO_TYPES);
@@ -87,15 +861,36 @@ impl Config {
             types.extend(AUDIO_TYPES);
         }
 
-        types
+        if self.allow_fonts {
+            types.extend(FONT_TYPES);
+        }
+
+        if self.allow_documents {
+            types.extend(DOCUMENT_TYPES);
+        }
+
+        let normalize = |types: &[String]| -> Vec<String> {
+            types
+                .iter()
+                .map(|t| t.trim().to_lowercase())
+                .filter(|t| !t.is_empty())
+                .collect()
+        };
+        let deny = normalize(&self.deny_content_types);
+        let mut allowed: Vec<String> = types.into_iter().map(str::to_string).collect();
+        for extra in normalize(&self.allow_content_types) {
+            if !allowed.contains(&extra) {
+                allowed.push(extra);
+            }
+        }
+        allowed.retain(|t| !deny.contains(t));
+        allowed
     }
 
     pub fn is_allowed_content_type(&self, content_type: &str) -> bool {
         let ct_lower = content_type.to_lowercase();
         let mime_type = ct_lower.split(';').next().unwrap_or("").trim();
 
-        self.allowed_content_types()
-            .iter()
-            .any(|allowed| *allowed == mime_type)
+        self.allowed_content_types().iter().any(|t| t == mime_type)
     }
 }
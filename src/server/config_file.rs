@@ -0,0 +1,413 @@
+use super::config::Config;
+
+use anyhow::Context as _;
+use axum::{extract::Request, Router};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tower::ServiceExt;
+use tracing::{error, info, warn};
+
+/// How often the configuration file is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Build the configuration from the command line, environment and config file
+///
+/// Command line arguments and environment variables take precedence over the
+/// file, which in turn overrides the built-in defaults.
+pub fn load(matches: &ArgMatches) -> anyhow::Result<Config> {
+    let config = Config::from_arg_matches(matches)?;
+
+    match &config.config_file {
+        Some(path) => merge(config.clone(), matches, &read(path)?),
+        None => Ok(config),
+    }
+}
+
+/// Parse a TOML or YAML file, chosen by extension (TOML by default)
+fn read(path: &Path) -> anyhow::Result<toml::Table> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+
+    let table = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yaml" | "yml") => serde_yaml::from_str(&contents)?,
+        _ => toml::from_str(&contents)?,
+    };
+    Ok(table)
+}
+
+fn merge(config: Config, matches: &ArgMatches, file: &toml::Table) -> anyhow::Result<Config> {
+    let command = Config::command();
+    let args: Vec<_> = command
+        .get_arguments()
+        .filter(|arg| arg.get_id() != "config_file")
+        .collect();
+
+    let toml::Value::Table(mut merged) = toml::Value::try_from(&config)? else {
+        unreachable!("Config serializes to a table");
+    };
+
+    for (key, value) in file {
+        // Accept both the flag spelling and the field name
+        let Some(id) = args
+            .iter()
+            .find(|arg| arg.get_long() == Some(key) || arg.get_id().as_str() == key.replace('-', "_"))
+            .map(|arg| arg.get_id().to_string())
+        else {
+            anyhow::bail!("Unknown key in config file: {}", key);
+        };
+
+        let explicit = matches!(
+            matches.value_source(&id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        );
+        if !explicit {
+            validate(&command, &id, key, value)?;
+            merged.insert(id, value.clone());
+        }
+    }
+
+    let mut merged: Config = toml::Value::Table(merged).try_into()?;
+    merged.command = config.command;
+    merged.config_file = config.config_file;
+    merged.print_config = config.print_config;
+    Ok(merged)
+}
+
+/// Run a file value through the argument's own parser, as if given on the command line
+///
+/// Serde alone would take any string for fields whose values clap limits,
+/// like `oversize` or `allowed-schemes`. Arrays are checked element-wise.
+fn validate(
+    command: &clap::Command,
+    id: &str,
+    key: &str,
+    value: &toml::Value,
+) -> anyhow::Result<()> {
+    let Some(arg) = command.get_arguments().find(|arg| arg.get_id() == id) else {
+        return Ok(());
+    };
+    // Flags take no value to parse, and their booleans are checked by serde
+    if !arg.get_action().takes_values() {
+        return Ok(());
+    }
+    // The parser alone, without the constraints tying the argument to others
+    let parser = clap::Command::new("camo").no_binary_name(true).arg(
+        clap::Arg::new("value")
+            .value_parser(arg.get_value_parser().clone())
+            .allow_hyphen_values(true),
+    );
+
+    let values = match value {
+        toml::Value::Array(values) => values.iter().collect(),
+        value => vec![value],
+    };
+    for value in values {
+        let raw = match value {
+            toml::Value::String(s) => s.clone(),
+            toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) => {
+                value.to_string()
+            }
+            // Nothing clap could parse; left to serde
+            _ => continue,
+        };
+        parser
+            .clone()
+            .try_get_matches_from([raw])
+            .map_err(|e| anyhow::anyhow!("Invalid value for {} in config file: {}", key, e))?;
+    }
+    Ok(())
+}
+
+/// Settings only read at startup: changes are reported and kept at their old value
+fn keep_startup_settings(active: &Config, new: &mut Config) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+
+    macro_rules! keep {
+        ($($field:ident),*) => {$(
+            if new.$field != active.$field {
+                changed.push(stringify!($field));
+                new.$field = active.$field.clone();
+            }
+        )*};
+    }
+    keep!(
+        listen,
+        tls_cert,
+        tls_key,
+        metrics,
+        log_level,
+        admin_listen,
+        client_write_timeout,
+        audit_log
+    );
+    #[cfg(feature = "http3")]
+    keep!(http3);
+
+    changed
+}
+
+/// Router that rebuilds itself when the configuration changes
+///
+/// Starts out as `router`, built by the caller for `config`. The
+/// configuration, including the key from `--key-file`, is loaded again
+/// when the config file changes or the process receives SIGHUP. `build` is
+/// called with every successfully loaded configuration and requests are
+/// dispatched to the most recent router. Caches are rebuilt as well. A
+/// configuration `build` refuses is logged, and the previous router kept.
+pub fn reloadable<F>(matches: ArgMatches, config: Config, router: Router, build: F) -> Router
+where
+    F: Fn(&Config) -> io::Result<Router> + Send + Sync + 'static,
+{
+    let current = Arc::new(RwLock::new(router));
+
+    let router = current.clone();
+    tokio::spawn(async move {
+        let path = config.config_file.clone();
+        let mut modified = path.as_deref().and_then(modified_time);
+        let mut hangup = Hangup::new();
+        let mut active = config;
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(POLL_INTERVAL), if path.is_some() => {
+                    let now = path.as_deref().and_then(modified_time);
+                    if now == modified {
+                        continue;
+                    }
+                    modified = now;
+                }
+                _ = hangup.recv() => info!("Received SIGHUP, reloading configuration"),
+            }
+
+            let mut new = match reload(&matches).await {
+                Ok(new) => new,
+                Err(e) => {
+                    error!("Failed to reload configuration: {:#}", e);
+                    continue;
+                }
+            };
+
+            for key in keep_startup_settings(&active, &mut new) {
+                warn!("Config key `{}` changed, restart to apply it", key);
+            }
+
+            match build(&new) {
+                Ok(new_router) => *router.write().unwrap() = new_router,
+                Err(e) => {
+                    error!("Invalid configuration, keeping the previous one: {}", e);
+                    continue;
+                }
+            }
+            active = new;
+            info!("Reloaded configuration");
+        }
+    });
+
+    Router::new().fallback_service(tower::service_fn(move |req: Request| {
+        let router = current.read().unwrap().clone();
+        router.oneshot(req)
+    }))
+}
+
+async fn reload(matches: &ArgMatches) -> anyhow::Result<Config> {
+    let mut config = load(matches)?;
+    super::secret::load_key(&mut config).await?;
+    Ok(config)
+}
+
+/// SIGHUP notifications, or nothing on platforms without signals
+struct Hangup {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangup {
+    fn new() -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let signal = signal(SignalKind::hangup())
+                .inspect_err(|e| warn!("Failed to install SIGHUP handler: {}", e))
+                .ok();
+            Self { signal }
+        }
+
+        #[cfg(not(unix))]
+        Self {}
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            signal.recv().await;
+            return;
+        }
+
+        std::future::pending().await
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(args: &[&str]) -> ArgMatches {
+        Config::command().get_matches_from(std::iter::once("camo").chain(args.iter().copied()))
+    }
+
+    fn merged(args: &[&str], file: &str) -> anyhow::Result<Config> {
+        let matches = matches(args);
+        let config = Config::from_arg_matches(&matches)?;
+        merge(config, &matches, &toml::from_str(file)?)
+    }
+
+    #[test]
+    fn test_file_overrides_defaults() {
+        let config = merged(
+            &[],
+            r#"
+                max-size = 1024
+                allow_video = true
+                block-cidr = ["192.0.2.0/24"]
+                strip-header = ["x-debug"]
+                tls-min-version = "1.3"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.max_size, 1024);
+        assert!(config.allow_video);
+        assert_eq!(config.block_cidr, vec!["192.0.2.0/24".parse().unwrap()]);
+        assert_eq!(config.strip_headers, vec!["x-debug".to_string()]);
+        assert_eq!(config.tls_min_version, Some(super::super::config::TlsVersion::Tls13));
+    }
+
+    #[test]
+    fn test_command_line_overrides_file() {
+        let config = merged(&["--max-size", "2048"], "max-size = 1024\ntimeout = 3").unwrap();
+
+        assert_eq!(config.max_size, 2048);
+        assert_eq!(config.timeout, 3);
+    }
+
+    #[test]
+    fn test_invalid_value() {
+        assert!(merged(&[], "oversize = \"truncat\"").is_err());
+        assert!(merged(&[], "compat = \"nodee\"").is_err());
+        assert!(merged(&[], "userinfo = \"allw\"").is_err());
+        assert!(merged(&[], "allowed-schemes = [\"https\", \"ftp\"]").is_err());
+        assert!(merged(&[], "max-size = -1").is_err());
+
+        let config = merged(&[], "oversize = \"truncate\"\nallowed-schemes = [\"https\"]").unwrap();
+        assert_eq!(config.oversize, "truncate");
+    }
+
+    #[test]
+    fn test_unknown_key() {
+        assert!(merged(&[], "max-sise = 1024").is_err());
+        assert!(merged(&[], "config = \"other.toml\"").is_err());
+    }
+
+    #[test]
+    fn test_yaml() {
+        let dir = std::env::temp_dir().join(format!("camo-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("camo.yaml");
+        std::fs::write(&path, "max-size: 1024\nstrip-headers: [x-debug]\n").unwrap();
+
+        let table = read(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(table["max-size"].as_integer(), Some(1024));
+        assert!(table["strip-headers"].is_array());
+    }
+
+    #[test]
+    fn test_keep_startup_settings() {
+        let active = Config::from_arg_matches(&matches(&[])).unwrap();
+        let mut new = Config {
+            listen: "127.0.0.1:9000".into(),
+            max_size: 1,
+            ..active.clone()
+        };
+
+        assert_eq!(keep_startup_settings(&active, &mut new), vec!["listen"]);
+        assert_eq!(new.listen, active.listen);
+        assert_eq!(new.max_size, 1);
+    }
+
+    type Change = fn(&mut Config);
+
+    /// Every setting only read at startup, with a change to it
+    const STARTUP_SETTINGS: &[(&str, Change)] = &[
+        ("listen", |c| c.listen = "127.0.0.1:9000".into()),
+        ("tls_cert", |c| c.tls_cert = Some("cert.pem".into())),
+        ("tls_key", |c| c.tls_key = Some("key.pem".into())),
+        ("metrics", |c| c.metrics = !c.metrics),
+        ("log_level", |c| c.log_level = "trace".into()),
+        ("admin_listen", |c| c.admin_listen = Some("127.0.0.1:9001".into())),
+        ("client_write_timeout", |c| c.client_write_timeout += 1),
+        ("audit_log", |c| c.audit_log = Some("audit.log".into())),
+        #[cfg(feature = "http3")]
+        ("http3", |c| c.http3 = !c.http3),
+    ];
+
+    #[test]
+    fn test_keep_every_startup_setting() {
+        let active = Config::from_arg_matches(&matches(&[])).unwrap();
+        for (name, change) in STARTUP_SETTINGS {
+            let mut new = active.clone();
+            change(&mut new);
+            assert_eq!(keep_startup_settings(&active, &mut new), vec![*name], "{}", name);
+            assert!(keep_startup_settings(&active, &mut new).is_empty(), "{} not kept", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_reload_keeps_router() {
+        use crate::server::router::AppState;
+        use axum::routing::get;
+
+        let dir = std::env::temp_dir().join(format!("camo-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("camo.toml");
+        std::fs::write(&path, "max-size = 1").unwrap();
+
+        let matches = matches(&["--key", "secret", "--config", path.to_str().unwrap()]);
+        let config = load(&matches).unwrap();
+        let build = |config: &Config| {
+            AppState::from_config(config)?;
+            let size = config.max_size.to_string();
+            Ok(Router::new().route("/", get(move || async move { size })))
+        };
+        let router = reloadable(matches, config.clone(), build(&config).unwrap(), build);
+        let max_size = || async {
+            let response = router.clone().oneshot(Request::new(axum::body::Body::empty())).await;
+            let body = axum::body::to_bytes(response.unwrap().into_body(), 64).await.unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+        let edit = |contents: &str| {
+            // Past the modification time granularity of any file system
+            std::thread::sleep(Duration::from_millis(1100));
+            std::fs::write(&path, contents).unwrap();
+        };
+
+        edit("max-size = 2\ntenant-key = [\"no separator\"]");
+        tokio::time::sleep(POLL_INTERVAL + Duration::from_millis(500)).await;
+        assert_eq!(max_size().await, "1");
+
+        edit("max-size = 3");
+        tokio::time::sleep(POLL_INTERVAL + Duration::from_millis(500)).await;
+        assert_eq!(max_size().await, "3");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
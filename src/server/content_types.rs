@@ -66,12 +66,61 @@ pub const AUDIO_TYPES: &[&str] = &[
     "audio/flac",
 ];
 
+/// Web font content types
+pub const FONT_TYPES: &[&str] = &[
+    "font/woff",
+    "font/woff2",
+    "font/ttf",
+    "font/otf",
+    "application/font-woff",
+    "application/x-font-ttf",
+    "application/x-font-otf",
+];
+
+/// Document content types
+pub const DOCUMENT_TYPES: &[&str] = &["application/pdf"];
+
+/// Usual file extension for a content type, parameters and case ignored
+pub fn extension(content_type: &str) -> Option<&'static str> {
+    let ct_lower = content_type.to_lowercase();
+    let mime_type = ct_lower.split(';').next().unwrap_or("").trim();
+    Some(match mime_type {
+        "image/jpeg" | "image/jpg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/avif" => "avif",
+        "image/heic" => "heic",
+        "image/heif" => "heif",
+        "image/svg+xml" => "svg",
+        "image/bmp" => "bmp",
+        "image/tiff" => "tiff",
+        "image/x-icon" | "image/vnd.microsoft.icon" => "ico",
+        "video/mp4" => "mp4",
+        "video/webm" => "webm",
+        "video/ogg" => "ogv",
+        "video/quicktime" => "mov",
+        "video/x-msvideo" => "avi",
+        "audio/mpeg" => "mp3",
+        "audio/ogg" => "ogg",
+        "audio/wav" => "wav",
+        "audio/webm" => "weba",
+        "audio/flac" => "flac",
+        "font/woff" | "application/font-woff" => "woff",
+        "font/woff2" => "woff2",
+        "font/ttf" | "application/x-font-ttf" => "ttf",
+        "font/otf" | "application/x-font-otf" => "otf",
+        "application/pdf" => "pdf",
+        _ => return None,
+    })
+}
+
 /// Check if a content type is an allowed image type
 #[allow(dead_code)]
 pub fn is_allowed_image_type(content_type: &str) -> bool {
     let ct_lower = content_type.to_lowercase();
     let mime_type = ct_lower.split(';').next().unwrap_or("").trim();
-    IMAGE_TYPES.iter().any(|allowed| *allowed == mime_type)
+    IMAGE_TYPES.contains(&mime_type)
 }
 
 /// Check if content type is allowed (with optional video/audio support)
@@ -80,15 +129,15 @@ pub fn is_allowed_content_type(content_type: &str, allow_video: bool, allow_audi
     let ct_lower = content_type.to_lowercase();
     let mime_type = ct_lower.split(';').next().unwrap_or("").trim();
 
-    if IMAGE_TYPES.iter().any(|t| *t == mime_type) {
+    if IMAGE_TYPES.contains(&mime_type) {
         return true;
     }
 
-    if allow_video && VIDEO_TYPES.iter().any(|t| *t == mime_type) {
+    if allow_video && VIDEO_TYPES.contains(&mime_type) {
         return true;
     }
 
-    if allow_audio && AUDIO_TYPES.iter().any(|t| *t == mime_type) {
+    if allow_audio && AUDIO_TYPES.contains(&mime_type) {
         return true;
     }
 
@@ -0,0 +1,128 @@
+use axum::http::{header, HeaderMap, HeaderValue};
+
+/// `Access-Control-Allow-Origin` and `Timing-Allow-Origin` for proxied responses
+///
+/// With a single configured origin (or `*`) the value is sent as is. With
+/// several, the request's `Origin` is echoed back when it is on the list.
+#[derive(Clone, Default)]
+pub struct CorsPolicy {
+    origins: Vec<HeaderValue>,
+    any: bool,
+}
+
+impl CorsPolicy {
+    /// Invalid origins are ignored
+    pub fn new(origins: &[String]) -> Self {
+        Self {
+            origins: origins
+                .iter()
+                .filter_map(|origin| HeaderValue::from_str(origin.trim()).ok())
+                .collect(),
+            any: origins.iter().any(|origin| origin.trim() == "*"),
+        }
+    }
+
+    pub fn apply(&self, request: &HeaderMap, response: &mut HeaderMap) {
+        let allowed = if self.any {
+            HeaderValue::from_static("*")
+        } else {
+            match self.origins.as_slice() {
+                [] => return,
+                [origin] => origin.clone(),
+                origins => {
+                    response.append(header::VARY, HeaderValue::from_static("Origin"));
+                    match request.get(header::ORIGIN) {
+                        Some(origin) if origins.contains(origin) => origin.clone(),
+                        _ => return,
+                    }
+                }
+            }
+        };
+
+        response.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allowed.clone());
+        response.insert("timing-allow-origin", allowed);
+    }
+
+    /// Take out what [`apply`](Self::apply) added, from a response kept to answer other requests
+    pub fn strip(&self, response: &mut HeaderMap) {
+        if !self.any && self.origins.is_empty() {
+            return;
+        }
+        response.remove(header::ACCESS_CONTROL_ALLOW_ORIGIN);
+        response.remove("timing-allow-origin");
+        let vary: Vec<HeaderValue> = response
+            .get_all(header::VARY)
+            .iter()
+            .filter(|v| *v != "Origin")
+            .cloned()
+            .collect();
+        response.remove(header::VARY);
+        for value in vary {
+            response.append(header::VARY, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(origin: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(origin) = origin {
+            headers.insert(header::ORIGIN, HeaderValue::from_static(origin));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut response = HeaderMap::new();
+        CorsPolicy::new(&[]).apply(&request(Some("https://a.example")), &mut response);
+        assert!(response.is_empty());
+    }
+
+    #[test]
+    fn test_single_origin() {
+        let mut response = HeaderMap::new();
+        CorsPolicy::new(&["*".into()]).apply(&request(None), &mut response);
+        assert_eq!(response[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert_eq!(response["timing-allow-origin"], "*");
+
+        let mut response = HeaderMap::new();
+        CorsPolicy::new(&["https://a.example".into()]).apply(&request(None), &mut response);
+        assert_eq!(response[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://a.example");
+    }
+
+    #[test]
+    fn test_origin_list() {
+        let policy = CorsPolicy::new(&["https://a.example".into(), "https://b.example".into()]);
+
+        let mut response = HeaderMap::new();
+        policy.apply(&request(Some("https://b.example")), &mut response);
+        assert_eq!(response[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://b.example");
+        assert_eq!(response[header::VARY], "Origin");
+
+        let mut response = HeaderMap::new();
+        policy.apply(&request(Some("https://evil.example")), &mut response);
+        assert!(!response.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert_eq!(response[header::VARY], "Origin");
+    }
+
+    #[test]
+    fn test_strip() {
+        let policy = CorsPolicy::new(&["https://a.example".into(), "https://b.example".into()]);
+        let mut response = HeaderMap::new();
+        response.insert(header::VARY, HeaderValue::from_static("Accept"));
+        policy.apply(&request(Some("https://a.example")), &mut response);
+        policy.strip(&mut response);
+        assert_eq!(response.len(), 1);
+        assert_eq!(response[header::VARY], "Accept");
+
+        // Without origins, a font's own `*` is not the policy's to take out
+        let mut response = HeaderMap::new();
+        response.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+        CorsPolicy::new(&[]).strip(&mut response);
+        assert_eq!(response[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+    }
+}
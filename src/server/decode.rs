@@ -0,0 +1,173 @@
+//! Recover the target URL from a camo URL without the key
+
+use crate::utils::crypto::Verifier;
+use crate::utils::encoding::Encoding;
+pub use crate::utils::encoding::{decode_url, DecodeError};
+
+use url::Url;
+
+/// A camo link taken apart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded {
+    /// Digest from the path, if the input had one
+    pub digest: Option<String>,
+    /// How the target was encoded: `hex`, `base64` or `query`
+    pub encoding: &'static str,
+    /// Target URL
+    pub url: String,
+}
+
+/// Decode the target URL from a camo URL, path or encoded segment
+///
+/// Accepts `https://camo.example.com/<digest>/<encoded>`, `/<digest>/<encoded>`,
+/// `<digest>?url=<url>` (with or without host) and a bare encoded segment.
+/// The digest is not verified. Returns `None` unless the result is an
+/// absolute URL.
+pub fn target_url(input: &str) -> Option<String> {
+    decode(input).map(|decoded| decoded.url)
+}
+
+/// Like [`target_url`], also returning the digest and the encoding used
+pub fn decode(input: &str) -> Option<Decoded> {
+    let input = input.trim();
+
+    // Drop scheme and host from a full camo URL
+    let path = match Url::parse(input) {
+        Ok(url) if url.has_host() => match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        },
+        _ => input.to_string(),
+    };
+
+    // A query without `url` holds the parameters of a path format link
+    let (path, query) = path.split_once('?').unwrap_or((&path, ""));
+    let query_url = url::form_urlencoded::parse(query.as_bytes())
+        .find(|(name, _)| name == "url")
+        .map(|(_, value)| value.into_owned());
+    let (digest, encoding, url) = match query_url {
+        Some(url) => (path.trim_matches('/'), "query", url),
+        None => {
+            let path = path.trim_start_matches('/');
+            // `<digest>/<encoded>`, or just `<encoded>`
+            let (digest, encoded) = path.split_once('/').unwrap_or(("", path));
+            let (encoding, url) = decode_segment(encoded)?;
+            (digest, encoding, url)
+        }
+    };
+
+    Url::parse(&url).ok()?;
+    Some(Decoded {
+        digest: (!digest.is_empty()).then(|| digest.to_string()),
+        encoding,
+        url,
+    })
+}
+
+/// Whether `url` is a camo link `verifier` accepts, i.e. one pointing back at this proxy
+///
+/// Also matches when the proxy is mounted below a path prefix.
+pub fn is_signed_link(verifier: &dyn Verifier, url: &Url) -> bool {
+    let path = url.path();
+    let query = url.query().map(|q| format!("?{}", q)).unwrap_or_default();
+
+    path.match_indices('/').any(|(start, _)| {
+        decode(&format!("{}{}", &path[start..], query)).is_some_and(|link| {
+            link.digest
+                .is_some_and(|digest| verifier.verify(&link.url, &digest))
+        })
+    })
+}
+
+/// Same detection as the proxy, accepting both encodings
+fn decode_segment(encoded: &str) -> Option<(&'static str, String)> {
+    let url = decode_url(encoded).ok()?;
+    Some((Encoding::detect(encoded).name(), url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGET: &str = "https://example.com/image.png";
+    const HEX: &str = "68747470733a2f2f6578616d706c652e636f6d2f696d6167652e706e67";
+    const DIGEST: &str = "54cec8e46f18f585268e3972432cd8da7aec6dc1";
+
+    #[test]
+    fn test_encoded_segment() {
+        assert_eq!(target_url(HEX).as_deref(), Some(TARGET));
+        assert_eq!(
+            target_url("aHR0cHM6Ly9leGFtcGxlLmNvbS9pbWFnZS5wbmc").as_deref(),
+            Some(TARGET)
+        );
+    }
+
+    #[test]
+    fn test_path_and_url() {
+        let path = format!("/{}/{}", DIGEST, HEX);
+        assert_eq!(target_url(&path).as_deref(), Some(TARGET));
+
+        let url = format!("https://camo.example.com{}", path);
+        assert_eq!(target_url(&url).as_deref(), Some(TARGET));
+    }
+
+    #[test]
+    fn test_query_format() {
+        let query = format!("{}?url=https%3A%2F%2Fexample.com%2Fimage.png", DIGEST);
+        assert_eq!(target_url(&query).as_deref(), Some(TARGET));
+
+        let url = format!("https://camo.example.com/{}", query);
+        assert_eq!(target_url(&url).as_deref(), Some(TARGET));
+    }
+
+    #[test]
+    fn test_decode_details() {
+        let decoded = decode(&format!("/{}/{}", DIGEST, HEX)).unwrap();
+        assert_eq!(decoded.digest.as_deref(), Some(DIGEST));
+        assert_eq!(decoded.encoding, "hex");
+
+        let decoded = decode(&format!("{}?url={}", DIGEST, TARGET)).unwrap();
+        assert_eq!(decoded.digest.as_deref(), Some(DIGEST));
+        assert_eq!(decoded.encoding, "query");
+
+        // Link parameters after a path format link
+        let decoded = decode(&format!("/{}/{}?max_size=4", DIGEST, HEX)).unwrap();
+        assert_eq!(decoded.url, TARGET);
+        assert_eq!(decoded.encoding, "hex");
+
+        let decoded = decode("aHR0cHM6Ly9leGFtcGxlLmNvbS9pbWFnZS5wbmc").unwrap();
+        assert_eq!(decoded.digest, None);
+        assert_eq!(decoded.encoding, "base64");
+    }
+
+    #[test]
+    fn test_is_signed_link() {
+        let digest = crate::utils::crypto::generate_digest("secret", TARGET);
+        let secret = crate::utils::crypto::HmacSha1::new("secret");
+        let signed = |link: String| is_signed_link(&secret, &Url::parse(&link).unwrap());
+
+        assert!(signed(format!("https://camo.example.com/{}/{}", digest, HEX)));
+        assert!(signed(format!("https://cdn.example.com/camo/{}/{}", digest, HEX)));
+        assert!(signed(format!("https://camo.example.com/{}?url={}", digest, TARGET)));
+
+        assert!(!signed(format!("https://camo.example.com/{}/{}", DIGEST, HEX)));
+        assert!(!signed(format!("https://camo.example.com/{}", HEX)));
+        assert!(!signed(TARGET.to_string()));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_query_roundtrip(path in "\\PC*") {
+            let url = format!("https://example.com/{}", path);
+            proptest::prop_assume!(Url::parse(&url).is_ok());
+            let link = format!("{}?url={}", DIGEST, urlencoding::encode(&url));
+            proptest::prop_assert_eq!(target_url(&link), Some(url));
+        }
+    }
+
+    #[test]
+    fn test_not_a_url() {
+        assert_eq!(target_url("not-encoded"), None);
+        assert_eq!(target_url(&format!("{}?size=1", DIGEST)), None);
+    }
+}
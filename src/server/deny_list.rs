@@ -0,0 +1,210 @@
+use super::config::Config;
+
+use std::collections::BTreeSet;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+use std::sync::RwLock;
+use url::Url;
+
+/// Signed links taken down by digest or exact target URL
+///
+/// Entries are kept in the `--deny-list` file, one per line (`#` comments
+/// allowed at the start of a line or after whitespace, so URL fragments
+/// stay), which is rewritten when entries are added or removed through the
+/// admin API. Without a file, entries only live in memory, and are carried
+/// over to the list of a reloaded configuration.
+pub struct DenyList {
+    path: Option<PathBuf>,
+    entries: RwLock<BTreeSet<String>>,
+}
+
+impl DenyList {
+    /// Load the configured file; a file that does not exist yet is empty
+    pub fn from_config(config: &Config) -> io::Result<Self> {
+        let entries = match &config.deny_list {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(contents) => parse(&contents),
+                Err(e) if e.kind() == ErrorKind::NotFound => BTreeSet::new(),
+                Err(e) => return Err(e),
+            },
+            None => BTreeSet::new(),
+        };
+
+        Ok(Self {
+            path: config.deny_list.clone(),
+            entries: RwLock::new(entries),
+        })
+    }
+
+    /// Whether the digest or the target URL has been taken down
+    pub fn is_denied(&self, digest: &str, url: &Url) -> bool {
+        let entries = self.entries.read().unwrap();
+        !entries.is_empty()
+            && (entries.contains(&digest.to_ascii_lowercase()) || entries.contains(url.as_str()))
+    }
+
+    pub fn entries(&self) -> Vec<String> {
+        self.entries.read().unwrap().iter().cloned().collect()
+    }
+
+    /// Add an entry and persist the list; returns false if it was already present
+    pub fn add(&self, entry: &str) -> io::Result<bool> {
+        self.update(|entries| entries.insert(normalize(entry)))
+    }
+
+    /// Remove an entry and persist the list; returns false if it was not present
+    pub fn remove(&self, entry: &str) -> io::Result<bool> {
+        self.update(|entries| entries.remove(&normalize(entry)))
+    }
+
+    /// Keep the entries of `previous` when neither list has a file to reload them from
+    pub fn carry_over(&self, previous: &DenyList) {
+        if self.path.is_none() && previous.path.is_none() {
+            let entries = previous.entries.read().unwrap().clone();
+            *self.entries.write().unwrap() = entries;
+        }
+    }
+
+    /// Apply `change` to a copy of the list, and only take it once it is saved
+    fn update(&self, change: impl FnOnce(&mut BTreeSet<String>) -> bool) -> io::Result<bool> {
+        let mut entries = self.entries.write().unwrap();
+        let mut updated = entries.clone();
+        if !change(&mut updated) {
+            return Ok(false);
+        }
+        self.save(&updated)?;
+        *entries = updated;
+        Ok(true)
+    }
+
+    /// Write the list next to the file and rename it into place
+    fn save(&self, entries: &BTreeSet<String>) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let mut contents = String::new();
+        for entry in entries {
+            contents.push_str(entry);
+            contents.push('\n');
+        }
+
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, contents)?;
+        std::fs::rename(&tmp, path)
+    }
+}
+
+fn parse(contents: &str) -> BTreeSet<String> {
+    contents
+        .lines()
+        .map(|line| strip_comment(line).trim())
+        .filter(|line| !line.is_empty())
+        .map(normalize)
+        .collect()
+}
+
+/// The line up to a `#` that starts it or follows whitespace; others belong to a URL fragment
+fn strip_comment(line: &str) -> &str {
+    let mut previous = None;
+    for (i, c) in line.char_indices() {
+        if c == '#' && previous.is_none_or(char::is_whitespace) {
+            return &line[..i];
+        }
+        previous = Some(c);
+    }
+    line
+}
+
+/// URLs in the form the proxy compares them, digests in lowercase
+fn normalize(entry: &str) -> String {
+    let entry = entry.trim();
+    match Url::parse(entry) {
+        Ok(url) => url.to_string(),
+        Err(_) => entry.to_ascii_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    const DIGEST: &str = "54cec8e46f18f585268e3972432cd8da7aec6dc1";
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    fn deny_list(path: Option<&std::path::Path>) -> DenyList {
+        let mut config = Config::parse_from(["camo"]);
+        config.deny_list = path.map(|p| p.to_path_buf());
+        DenyList::from_config(&config).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let entries = parse(&format!(
+            "# takedowns\n{}\n\nHTTPS://Example.com/a.png  # ticket 42\nhttps://example.com/#!/a\n",
+            DIGEST.to_uppercase()
+        ));
+
+        assert_eq!(
+            entries.into_iter().collect::<Vec<_>>(),
+            vec![
+                DIGEST.to_string(),
+                "https://example.com/#!/a".to_string(),
+                "https://example.com/a.png".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_denied() {
+        let list = deny_list(None);
+        list.add(DIGEST).unwrap();
+        list.add("https://example.com/a.png").unwrap();
+
+        let other = url("https://example.com/b.png");
+        assert!(list.is_denied(DIGEST, &other));
+        assert!(list.is_denied("0000", &url("https://example.com/a.png")));
+        assert!(!list.is_denied("0000", &other));
+    }
+
+    #[test]
+    fn test_persisted() {
+        let dir = std::env::temp_dir().join(format!("camo-deny-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("deny.txt");
+
+        let list = deny_list(Some(&path));
+        assert!(list.add(DIGEST).unwrap());
+        assert!(!list.add(DIGEST).unwrap());
+        assert!(list.add("https://example.com/a.png").unwrap());
+        assert!(list.remove("https://example.com/a.png").unwrap());
+
+        let reloaded = deny_list(Some(&path));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(reloaded.entries(), vec![DIGEST.to_string()]);
+    }
+
+    #[test]
+    fn test_failed_save_keeps_list() {
+        let dir = std::env::temp_dir().join(format!("camo-deny-missing-{}", std::process::id()));
+        let list = deny_list(Some(&dir.join("deny.txt")));
+
+        assert!(list.add(DIGEST).is_err());
+        assert!(list.entries().is_empty());
+    }
+
+    #[test]
+    fn test_carry_over() {
+        let list = deny_list(None);
+        list.add(DIGEST).unwrap();
+
+        let reloaded = deny_list(None);
+        reloaded.carry_over(&list);
+        assert_eq!(reloaded.entries(), vec![DIGEST.to_string()]);
+    }
+}
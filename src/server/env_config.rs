@@ -0,0 +1,150 @@
+use super::config::{Config, SecurityHeaders};
+use super::error::CamoError;
+
+use std::str::FromStr;
+
+impl Config {
+    /// Build the configuration from the same `CAMO_*` variables the server reads
+    ///
+    /// Used by the serverless builds, which look variables up through `var`.
+    /// Unset or malformed values fall back to the server defaults. Settings
+    /// without meaning there (listeners, DNS and TLS options, the negative
+    /// cache) keep fixed values, and platform specific ones their defaults.
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Result<Self, CamoError> {
+        let var = &var;
+
+        let key = var("CAMO_KEY").filter(|key| !key.is_empty());
+        if key.is_none() {
+            return Err(CamoError::InvalidUrl("CAMO_KEY not set".into()));
+        }
+
+        // CAMO_MAX_SIZE is the name earlier worker releases used
+        let max_size = parse(var, "CAMO_MAX_SIZE", 5 * 1024 * 1024);
+        let max_size = parse(var, "CAMO_LENGTH_LIMIT", max_size);
+        let size = |name: &str| var(name).and_then(|v| v.trim().parse().ok());
+
+        let defaults = SecurityHeaders::default();
+        let header = |name: &str, default: String| var(name).unwrap_or(default);
+        let security_headers = SecurityHeaders {
+            content_security_policy: header(
+                "CAMO_CONTENT_SECURITY_POLICY",
+                defaults.content_security_policy,
+            ),
+            x_content_type_options: header(
+                "CAMO_X_CONTENT_TYPE_OPTIONS",
+                defaults.x_content_type_options,
+            ),
+            x_frame_options: header("CAMO_X_FRAME_OPTIONS", defaults.x_frame_options),
+            referrer_policy: header("CAMO_REFERRER_POLICY", defaults.referrer_policy),
+            cross_origin_resource_policy: header(
+                "CAMO_CROSS_ORIGIN_RESOURCE_POLICY",
+                defaults.cross_origin_resource_policy,
+            ),
+        };
+
+        Ok(Config {
+            key,
+            tenant_keys: list(var, "CAMO_TENANT_KEYS").unwrap_or_default(),
+            listen: "0.0.0.0:8080".to_string(),
+            tls_cert: None,
+            tls_key: None,
+            max_size,
+            max_size_image: size("CAMO_MAX_SIZE_IMAGE"),
+            max_size_video: size("CAMO_MAX_SIZE_VIDEO"),
+            max_size_audio: size("CAMO_MAX_SIZE_AUDIO"),
+            hard_max_size: size("CAMO_HARD_MAX_SIZE"),
+            oversize: parse(var, "CAMO_OVERSIZE", "reject".to_string()),
+            oversize_strategy: var("CAMO_OVERSIZE_STRATEGY"),
+            max_header_size: parse(var, "CAMO_MAX_HEADER_SIZE", 65536),
+            max_header_count: parse(var, "CAMO_MAX_HEADER_COUNT", 100),
+            max_url_length: parse(var, "CAMO_MAX_URL_LENGTH", 8192),
+            url_encodings: list(var, "CAMO_URL_ENCODINGS")
+                .unwrap_or_else(|| vec!["hex".to_string(), "base64".to_string()]),
+            compat: var("CAMO_COMPAT"),
+            max_redirects: parse(var, "CAMO_MAX_REDIRECTS", 4),
+            max_depth: parse(var, "CAMO_MAX_DEPTH", 2),
+            timeout: parse(var, "CAMO_SOCKET_TIMEOUT", 10),
+            upstream_retries: parse(var, "CAMO_UPSTREAM_RETRIES", 0),
+            upstream_retry_backoff: parse(var, "CAMO_UPSTREAM_RETRY_BACKOFF", 100),
+            forward_headers: list(var, "CAMO_FORWARD_HEADERS").unwrap_or_default(),
+            upstream_headers: list(var, "CAMO_UPSTREAM_HEADERS").unwrap_or_default(),
+            upstream_auth_file: None,
+            allow_video: parse(var, "CAMO_ALLOW_VIDEO", false),
+            allow_audio: parse(var, "CAMO_ALLOW_AUDIO", false),
+            allow_fonts: parse(var, "CAMO_ALLOW_FONTS", false),
+            allow_documents: parse(var, "CAMO_ALLOW_DOCUMENTS", false),
+            allow_content_types: list(var, "CAMO_ALLOW_CONTENT_TYPES").unwrap_or_default(),
+            deny_content_types: list(var, "CAMO_DENY_CONTENT_TYPES").unwrap_or_default(),
+            strip_headers: list(var, "CAMO_STRIP_HEADERS").unwrap_or_default(),
+            default_cache_control: var("CAMO_DEFAULT_CACHE_CONTROL"),
+            min_cache_ttl: parse(var, "CAMO_MIN_CACHE_TTL", 0),
+            cors_origin: list(var, "CAMO_CORS_ORIGIN").unwrap_or_default(),
+            security_headers,
+            allowed_schemes: list(var, "CAMO_ALLOWED_SCHEMES")
+                .unwrap_or_else(|| vec!["http".to_string(), "https".to_string()]),
+            default_scheme: parse(var, "CAMO_DEFAULT_SCHEME", "https".to_string()),
+            https_only_upstream: parse(var, "CAMO_HTTPS_ONLY_UPSTREAM", false),
+            upgrade_insecure_upstream: parse(var, "CAMO_UPGRADE_INSECURE_UPSTREAM", false),
+            reject_mixed_scripts: parse(var, "CAMO_REJECT_MIXED_SCRIPTS", false),
+            userinfo: parse(var, "CAMO_USERINFO", "reject".to_string()),
+            content_disposition: parse(var, "CAMO_CONTENT_DISPOSITION", "inline".to_string()),
+            error_format: parse(var, "CAMO_ERROR_FORMAT", "text".to_string()),
+            data_uri_max_size: parse(var, "CAMO_DATA_URI_MAX_SIZE", 65536),
+            redirect_https_hosts: list(var, "CAMO_REDIRECT_HTTPS_HOSTS").unwrap_or_default(),
+            referer_hosts: list(var, "CAMO_REFERER_HOSTS").unwrap_or_default(),
+            empty_referer: parse(var, "CAMO_EMPTY_REFERER", "allow".to_string()),
+            session_cookie: var("CAMO_SESSION_COOKIE"),
+            session_header: var("CAMO_SESSION_HEADER"),
+            block_private: true,
+            block_cidr: Vec::new(),
+            block_cidr_file: None,
+            dns_cache_ttl: 0,
+            dns_cache_size: 0,
+            negative_ttl_not_found: 0,
+            negative_ttl_too_large: 0,
+            negative_ttl_content_type: 0,
+            negative_ttl_unreachable: 0,
+            negative_cache_size: 0,
+            negative_cache_bypass_token: None,
+            validator_cache_size: 0,
+            etag_max_size: parse(var, "CAMO_ETAG_MAX_SIZE", 0),
+            verify_cache_size: parse(var, "CAMO_VERIFY_CACHE_SIZE", 1024),
+            metrics: false,
+            log_level: "info".to_string(),
+            #[cfg(feature = "worker")]
+            edge_cache: true,
+            #[cfg(feature = "worker")]
+            persistent_cache_ttl: 86400,
+            #[cfg(feature = "worker")]
+            persistent_cache_kv_max_size: 512 * 1024,
+            #[cfg(feature = "worker")]
+            cf_cache_ttl: None,
+            #[cfg(feature = "worker")]
+            cf_cache_everything: false,
+            #[cfg(feature = "worker")]
+            cf_polish: None,
+            #[cfg(feature = "worker")]
+            cf_image: None,
+            tls_ca_file: None,
+            tls_min_version: None,
+            insecure_skip_verify: false,
+        })
+    }
+}
+
+/// Parse a variable, falling back to `default` when unset or invalid
+pub fn parse<T: FromStr>(var: &dyn Fn(&str) -> Option<String>, name: &str, default: T) -> T {
+    var(name)
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+/// Comma-separated variable, `None` when unset
+pub fn list(var: &dyn Fn(&str) -> Option<String>, name: &str) -> Option<Vec<String>> {
+    var(name).map(|v| {
+        v.split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect()
+    })
+}
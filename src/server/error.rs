@@ -1,7 +1,15 @@
+use crate::utils::encoding::DecodeError;
+
 use axum::{
-    http::StatusCode,
+    body::Body,
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
     response::{IntoResponse, Response},
 };
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 #[derive(Debug, thiserror::Error)]
 #[allow(dead_code)]
@@ -15,9 +23,33 @@ pub enum CamoError {
     #[error("invalid url: {0}")]
     InvalidUrl(String),
 
+    #[error("url too long: {0} bytes")]
+    UrlTooLong(usize),
+
     #[error("digest mismatch")]
     DigestMismatch,
 
+    #[error("unknown tenant")]
+    UnknownTenant,
+
+    #[error("referer not allowed")]
+    RefererNotAllowed,
+
+    #[error("session mismatch")]
+    SessionMismatch,
+
+    #[error("link expired")]
+    Expired,
+
+    #[error("tenant quota exceeded")]
+    QuotaExceeded(u64),
+
+    #[error("rate limit exceeded")]
+    RateLimited(u64),
+
+    #[error("server overloaded")]
+    Overloaded,
+
     #[error("content type not allowed: {0}")]
     ContentTypeNotAllowed(String),
 
@@ -27,18 +59,85 @@ pub enum CamoError {
     #[error("too many redirects")]
     TooManyRedirects,
 
+    #[error("redirect to plain http refused")]
+    InsecureRedirect,
+
     #[error("request timeout")]
     Timeout,
 
     #[error("upstream error: {0}")]
     Upstream(String),
 
+    #[error("upstream returned {0}")]
+    UpstreamStatus(StatusCode),
+
+    #[error("upstream response headers too large")]
+    UpstreamHeadersTooLarge,
+
     #[cfg(feature = "server")]
     #[cfg_attr(feature = "server", error("reqwest error: {0}"))]
     ReqwestError(#[from] reqwest::Error),
 
     #[error("private network not allowed")]
     PrivateNetworkNotAllowed,
+
+    #[error("network not allowed")]
+    NetworkNotAllowed,
+
+    #[error("content removed")]
+    Denied,
+
+    #[error("content rejected: {0}")]
+    Rejected(String),
+
+    #[error("request denied: {1}")]
+    PolicyDenied(StatusCode, String),
+
+    #[error("proxy loop detected")]
+    LoopDetected,
+}
+
+impl CamoError {
+    /// Stable name of the error, for clients that tell errors apart
+    pub fn code(&self) -> &'static str {
+        match self {
+            CamoError::InvalidDigest => "invalid_digest",
+            CamoError::InvalidUrlEncoding => "invalid_url_encoding",
+            CamoError::InvalidUrl(_) => "invalid_url",
+            CamoError::UrlTooLong(_) => "url_too_long",
+            CamoError::DigestMismatch => "digest_mismatch",
+            CamoError::UnknownTenant => "unknown_tenant",
+            CamoError::RefererNotAllowed => "referer_not_allowed",
+            CamoError::SessionMismatch => "session_mismatch",
+            CamoError::Expired => "link_expired",
+            CamoError::QuotaExceeded(_) => "quota_exceeded",
+            CamoError::RateLimited(_) => "rate_limited",
+            CamoError::Overloaded => "overloaded",
+            CamoError::ContentTypeNotAllowed(_) => "content_type_not_allowed",
+            CamoError::ContentTooLarge(_) => "content_too_large",
+            CamoError::TooManyRedirects => "too_many_redirects",
+            CamoError::InsecureRedirect => "insecure_redirect",
+            CamoError::Timeout => "timeout",
+            CamoError::Upstream(_) => "upstream_error",
+            CamoError::UpstreamStatus(_) => "upstream_status",
+            CamoError::UpstreamHeadersTooLarge => "upstream_headers_too_large",
+            #[cfg(feature = "server")]
+            CamoError::ReqwestError(_) => "upstream_error",
+            CamoError::PrivateNetworkNotAllowed => "private_network",
+            CamoError::NetworkNotAllowed => "network_not_allowed",
+            CamoError::Denied => "denied",
+            CamoError::Rejected(_) => "content_rejected",
+            CamoError::PolicyDenied(..) => "policy_denied",
+            CamoError::LoopDetected => "loop_detected",
+        }
+    }
+}
+
+/// Code and reason of the error a response was made from, for [`error_format`]
+#[derive(Debug, Clone)]
+pub struct ErrorInfo {
+    pub code: &'static str,
+    pub reason: String,
 }
 
 impl IntoResponse for CamoError {
@@ -49,24 +148,184 @@ impl IntoResponse for CamoError {
             | CamoError::InvalidUrl(_)
             | CamoError::DigestMismatch => StatusCode::BAD_REQUEST,
 
+            CamoError::UrlTooLong(_) => StatusCode::URI_TOO_LONG,
+
+            CamoError::UnknownTenant => StatusCode::NOT_FOUND,
+
+            CamoError::QuotaExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+
+            CamoError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+
+            CamoError::Overloaded => StatusCode::SERVICE_UNAVAILABLE,
+
             CamoError::ContentTypeNotAllowed(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
 
             CamoError::ContentTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
 
             CamoError::TooManyRedirects => StatusCode::BAD_GATEWAY,
 
+            CamoError::InsecureRedirect => StatusCode::BAD_GATEWAY,
+
             CamoError::Timeout => StatusCode::GATEWAY_TIMEOUT,
 
-            CamoError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            CamoError::Upstream(_) | CamoError::UpstreamHeadersTooLarge => {
+                StatusCode::BAD_GATEWAY
+            }
+
+            CamoError::UpstreamStatus(status) => match *status {
+                StatusCode::NOT_FOUND | StatusCode::GONE => *status,
+                _ => StatusCode::BAD_GATEWAY,
+            },
             
             #[cfg(feature = "server")]
             CamoError::ReqwestError(_) => StatusCode::BAD_GATEWAY,
 
-            CamoError::PrivateNetworkNotAllowed => StatusCode::FORBIDDEN,
+            CamoError::PrivateNetworkNotAllowed | CamoError::NetworkNotAllowed => {
+                StatusCode::FORBIDDEN
+            }
+
+            CamoError::Denied | CamoError::Expired => StatusCode::GONE,
+
+            CamoError::Rejected(_)
+            | CamoError::RefererNotAllowed
+            | CamoError::SessionMismatch => StatusCode::FORBIDDEN,
+
+            CamoError::PolicyDenied(status, _) => *status,
+
+            CamoError::LoopDetected => StatusCode::LOOP_DETECTED,
+        };
+
+        let info = ErrorInfo {
+            code: self.code(),
+            reason: self.to_string(),
         };
+        let mut response = (status, info.reason.clone()).into_response();
+        // Seconds until the quota window is over, or a request is let through again
+        if let CamoError::QuotaExceeded(retry_after) | CamoError::RateLimited(retry_after) = self {
+            response.headers_mut().insert(header::RETRY_AFTER, retry_after.into());
+        }
+        response.extensions_mut().insert(info);
+        response
+    }
+}
+
+/// Header carrying the request ID of JSON error responses
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Middleware turning error responses into JSON for clients that ask for it
+///
+/// Clients sending `Accept: application/json`, or all clients when `json`
+/// is set (`--error-format json`), get `{"code", "reason", "request_id"}`
+/// instead of plain text. The request ID is taken from `X-Request-Id` when
+/// the client or a load balancer sent one, and returned in that header.
+pub async fn error_format(State(json): State<bool>, request: Request, next: Next) -> Response {
+    let json = json || accepts_json(request.headers());
+    let request_id = request.headers().get(REQUEST_ID_HEADER).cloned();
+    let response = next.run(request).await;
+    let Some(info) = response.extensions().get::<ErrorInfo>().filter(|_| json).cloned() else {
+        return response;
+    };
+
+    let request_id = request_id
+        .filter(|id| !id.is_empty() && id.len() <= 128)
+        .unwrap_or_else(generate_request_id);
+    let body = serde_json::json!({
+        "code": info.code,
+        "reason": info.reason,
+        "request_id": request_id.to_str().unwrap_or_default(),
+    });
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    parts.headers.insert(REQUEST_ID_HEADER, request_id);
+    Response::from_parts(parts, Body::from(body.to_string()))
+}
+
+fn accepts_json(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|range| {
+            let range = range.split(';').next().unwrap_or_default().trim();
+            range.eq_ignore_ascii_case("application/json")
+        })
+}
+
+/// 16 hex digits, unique within the process and unlikely to repeat across instances
+fn generate_request_id() -> HeaderValue {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let id = format!("{:016x}", RandomState::new().hash_one(count));
+    HeaderValue::from_str(&id).expect("hex is a valid header value")
+}
 
-        (status, self.to_string()).into_response()
+impl From<DecodeError> for CamoError {
+    fn from(err: DecodeError) -> Self {
+        match err {
+            DecodeError::Encoding => CamoError::InvalidUrlEncoding,
+            DecodeError::NotAccepted(_) => CamoError::InvalidUrl(err.to_string()),
+            DecodeError::TooLong(len) => CamoError::UrlTooLong(len),
+            DecodeError::ControlCharacter => CamoError::InvalidUrl(err.to_string()),
+            DecodeError::InvalidUrl(msg) => CamoError::InvalidUrl(msg),
+        }
     }
 }
 
 pub type Result<T> = std::result::Result<T, CamoError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    async fn get_error(json: bool, headers: &[(&str, &str)]) -> Response {
+        let router = Router::new()
+            .route("/", get(|| async { CamoError::QuotaExceeded(30) }))
+            .route("/ok", get(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(json, error_format));
+        let mut request = Request::get("/");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        router.oneshot(request.body(Body::empty()).unwrap()).await.unwrap()
+    }
+
+    async fn text(response: Response) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_plain_text() {
+        let response = get_error(false, &[("accept", "image/webp,*/*")]).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(!response.headers().contains_key(REQUEST_ID_HEADER));
+        assert_eq!(text(response).await, "tenant quota exceeded");
+    }
+
+    #[tokio::test]
+    async fn test_json() {
+        let accept = [("accept", "text/html, application/json;q=0.9"), ("x-request-id", "abc")];
+        let response = get_error(false, &accept).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(response.headers()[header::RETRY_AFTER], "30");
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "abc");
+        let body: serde_json::Value = serde_json::from_str(&text(response).await).unwrap();
+        assert_eq!(body["code"], "quota_exceeded");
+        assert_eq!(body["reason"], "tenant quota exceeded");
+        assert_eq!(body["request_id"], "abc");
+
+        let response = get_error(true, &[]).await;
+        let id = response.headers()[REQUEST_ID_HEADER].to_str().unwrap().to_string();
+        assert_eq!(id.len(), 16);
+        let body: serde_json::Value = serde_json::from_str(&text(response).await).unwrap();
+        assert_eq!(body["request_id"], id);
+        assert_ne!(generate_request_id(), generate_request_id());
+    }
+}
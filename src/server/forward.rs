@@ -0,0 +1,227 @@
+use super::config::Config;
+use super::redirect::{host_matches, host_pattern};
+
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::io;
+use std::path::Path;
+use url::Url;
+
+/// Client request headers that may be passed on upstream
+pub const FORWARDABLE_HEADERS: [&str; 2] = ["accept-language", "dnt"];
+
+/// Request headers sent with each upstream request
+///
+/// Upstream requests start out empty, so client cookies, `Authorization`
+/// and anything else identifying the viewer never leave the proxy. Only the
+/// configured subset of [`FORWARDABLE_HEADERS`] is copied from the client,
+/// followed by the operator's static headers for matching hosts, such as a
+/// CDN token for one origin, and the credentials from `upstream_auth_file`.
+/// Those are marked sensitive, which keeps them out of debug output and
+/// makes the clients drop them on a redirect to another origin.
+#[derive(Clone, Default)]
+pub struct ForwardPolicy {
+    forward: Vec<HeaderName>,
+    fixed: Vec<HostHeader>,
+}
+
+#[derive(Clone)]
+struct HostHeader {
+    host: String,
+    name: HeaderName,
+    value: HeaderValue,
+}
+
+impl ForwardPolicy {
+    /// Headers outside [`FORWARDABLE_HEADERS`] and malformed static headers are ignored
+    pub fn new(forward: &[String], fixed: &[String]) -> Self {
+        Self {
+            forward: forward
+                .iter()
+                .map(|name| name.trim().to_ascii_lowercase())
+                .filter(|name| FORWARDABLE_HEADERS.contains(&name.as_str()))
+                .filter_map(|name| HeaderName::from_bytes(name.as_bytes()).ok())
+                .collect(),
+            fixed: fixed
+                .iter()
+                .filter_map(|entry| split(entry).ok())
+                .map(|(host, name, value)| HostHeader { host, name, value })
+                .collect(),
+        }
+    }
+
+    /// Build the policy from the configuration, reading `upstream_auth_file` if set
+    pub fn from_config(config: &Config) -> io::Result<Self> {
+        let mut policy = Self::new(&config.forward_headers, &config.upstream_headers);
+        if let Some(path) = &config.upstream_auth_file {
+            policy.fixed.extend(read_auth_file(path)?);
+        }
+        Ok(policy)
+    }
+
+    /// Headers for fetching `url` on behalf of a client that sent `request`
+    pub fn headers(&self, request: &HeaderMap, url: &Url) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for name in &self.forward {
+            for value in request.get_all(name) {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+
+        let Some(host) = url.host_str() else {
+            return headers;
+        };
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        for fixed in self.fixed.iter().filter(|fixed| host_matches(&fixed.host, &host)) {
+            headers.insert(fixed.name.clone(), fixed.value.clone());
+        }
+        headers
+    }
+}
+
+/// Validate a `host=Name: value` static upstream header
+pub fn parse_upstream_header(s: &str) -> Result<String, String> {
+    split(s).map(|_| s.to_string())
+}
+
+fn split(entry: &str) -> Result<(String, HeaderName, HeaderValue), String> {
+    let (host, header) = entry
+        .split_once('=')
+        .ok_or_else(|| format!("expected host=Name: value, got {:?}", entry))?;
+    let host = host_pattern(host);
+    if host.is_empty() {
+        return Err(format!("missing host in {:?}", entry));
+    }
+
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| format!("expected Name: value after the host, got {:?}", header))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| format!("invalid header name {:?}", name.trim()))?;
+    let mut value = HeaderValue::from_str(value.trim())
+        .map_err(|_| format!("invalid value for header {}", name))?;
+    value.set_sensitive(true);
+    Ok((host, name, value))
+}
+
+/// Read upstream credentials, one `host bearer <token>` or `host basic <user>:<password>` per line
+fn read_auth_file(path: &Path) -> io::Result<Vec<HostHeader>> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .enumerate()
+        .map(|(n, line)| (n, line.split('#').next().unwrap_or("").trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(n, line)| {
+            parse_auth(line).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: {}", path.display(), n + 1, e),
+                )
+            })
+        })
+        .collect()
+}
+
+fn parse_auth(line: &str) -> Result<HostHeader, String> {
+    let mut fields = line.split_whitespace();
+    let (Some(host), Some(scheme), Some(credentials), None) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        return Err("expected <host> bearer <token> or <host> basic <user>:<password>".into());
+    };
+
+    let value = match scheme.to_ascii_lowercase().as_str() {
+        "bearer" => format!("Bearer {}", credentials),
+        "basic" if credentials.contains(':') => format!("Basic {}", STANDARD.encode(credentials)),
+        "basic" => return Err("basic credentials must be <user>:<password>".into()),
+        _ => return Err(format!("unknown scheme {:?}, expected bearer or basic", scheme)),
+    };
+    let mut value = HeaderValue::from_str(&value).map_err(|_| "invalid credentials")?;
+    value.set_sensitive(true);
+
+    Ok(HostHeader {
+        host: host_pattern(host),
+        name: header::AUTHORIZATION,
+        value,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    fn client() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, HeaderValue::from_static("session=1"));
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer x"));
+        headers.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_static("de"));
+        headers.insert(header::DNT, HeaderValue::from_static("1"));
+        headers
+    }
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn test_scrubbed_by_default() {
+        let policy = ForwardPolicy::default();
+        assert!(policy.headers(&client(), &url("https://example.com/a.png")).is_empty());
+    }
+
+    #[test]
+    fn test_forward() {
+        let policy = ForwardPolicy::new(&strings(&["Accept-Language", "cookie"]), &[]);
+        let headers = policy.headers(&client(), &url("https://example.com/a.png"));
+
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers[header::ACCEPT_LANGUAGE], "de");
+    }
+
+    #[test]
+    fn test_static_headers() {
+        let fixed = strings(&["cdn.example.com=X-Token: abc", "*.example.org=X-Token: def"]);
+        let policy = ForwardPolicy::new(&[], &fixed);
+
+        let headers = policy.headers(&client(), &url("https://CDN.example.com./a.png"));
+        assert_eq!(headers["x-token"], "abc");
+        assert!(headers["x-token"].is_sensitive());
+        let headers = policy.headers(&client(), &url("https://img.example.org/a.png"));
+        assert_eq!(headers["x-token"], "def");
+        assert!(policy.headers(&client(), &url("https://example.com/a.png")).is_empty());
+    }
+
+    #[test]
+    fn test_auth_file() {
+        let path = std::env::temp_dir().join(format!("camo-auth-{}.txt", std::process::id()));
+        let contents = "# buckets\ncdn.example.com bearer abc\n\n*.example.org Basic u:p # ro";
+        std::fs::write(&path, contents).unwrap();
+        let fixed = read_auth_file(&path).unwrap();
+        std::fs::write(&path, "cdn.example.com bearer abc\nexample.org basic user").unwrap();
+        let e = read_auth_file(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let policy = ForwardPolicy { forward: Vec::new(), fixed };
+        let headers = policy.headers(&client(), &url("https://cdn.example.com/a.png"));
+        assert_eq!(headers[header::AUTHORIZATION], "Bearer abc");
+        assert!(headers[header::AUTHORIZATION].is_sensitive());
+        let headers = policy.headers(&client(), &url("https://img.example.org/a.png"));
+        assert_eq!(headers[header::AUTHORIZATION], "Basic dTpw");
+        assert!(policy.headers(&client(), &url("https://example.net/a.png")).is_empty());
+
+        assert!(e.to_string().ends_with(":2: basic credentials must be <user>:<password>"));
+    }
+
+    #[test]
+    fn test_parse_upstream_header() {
+        assert!(parse_upstream_header("cdn.example.com=X-Token: a=b").is_ok());
+        assert!(parse_upstream_header("X-Token: abc").is_err());
+        assert!(parse_upstream_header("=X-Token: abc").is_err());
+        assert!(parse_upstream_header("cdn.example.com=X-Token").is_err());
+        assert!(parse_upstream_header("cdn.example.com=Bad Name: abc").is_err());
+    }
+}
@@ -0,0 +1,493 @@
+use super::config::Config;
+
+use std::collections::HashSet;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Start of the metadata section, searched for from the end of the file
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+
+/// Zero bytes between the search tree and the data section
+const DATA_SEPARATOR: usize = 16;
+
+/// Nesting of maps and arrays decoded at most, so a corrupt file cannot overflow the stack
+const MAX_DEPTH: usize = 32;
+
+/// Where an upstream address is, as far as the databases know
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Location {
+    /// ISO 3166-1 alpha-2 code, upper case
+    pub country: Option<String>,
+    /// Autonomous system number
+    pub asn: Option<u32>,
+}
+
+/// Country and ASN of upstream addresses from MaxMind DB files, from `--geoip-db`
+///
+/// Any database in the MaxMind DB format works; GeoLite2 ships countries
+/// and ASNs as separate files, so several can be given and the first one
+/// knowing a field answers it. The location of each resolved upstream
+/// address tags the `camo_upstream_locations_total{country, asn}` counter
+/// and the debug log, and targets in a `--block-country` or from a
+/// `--block-asn` are refused like `--block-cidr` ones, counted in
+/// `camo_geoip_blocked_total{by}`. Files are read into memory at startup.
+pub struct GeoIp {
+    databases: Vec<Database>,
+    blocked_asns: HashSet<u32>,
+    blocked_countries: HashSet<String>,
+}
+
+impl GeoIp {
+    /// Returns `None` without `--geoip-db`
+    pub fn from_config(config: &Config) -> io::Result<Option<Self>> {
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+        let blocked_asns = config
+            .block_asns
+            .iter()
+            .map(|asn| parse_asn(asn).ok_or_else(|| invalid(format!("invalid ASN: {}", asn))))
+            .collect::<io::Result<_>>()?;
+        let blocked_countries = config
+            .block_countries
+            .iter()
+            .map(|country| match country.trim() {
+                code if code.len() == 2 && code.bytes().all(|b| b.is_ascii_alphabetic()) => {
+                    Ok(code.to_ascii_uppercase())
+                }
+                _ => Err(invalid(format!("invalid country code: {}", country))),
+            })
+            .collect::<io::Result<_>>()?;
+
+        if config.geoip_dbs.is_empty() {
+            if !config.block_asns.is_empty() || !config.block_countries.is_empty() {
+                return Err(invalid("--block-asn and --block-country need --geoip-db".into()));
+            }
+            return Ok(None);
+        }
+        let databases = config
+            .geoip_dbs
+            .iter()
+            .map(|path| Database::open(path))
+            .collect::<io::Result<_>>()?;
+        Ok(Some(Self {
+            databases,
+            blocked_asns,
+            blocked_countries,
+        }))
+    }
+
+    pub fn locate(&self, ip: &IpAddr) -> Location {
+        let mut location = Location::default();
+        for database in &self.databases {
+            let Some(record) = database.lookup(ip) else {
+                continue;
+            };
+            if location.country.is_none() {
+                location.country = ["country", "registered_country"]
+                    .iter()
+                    .find_map(|key| record.get(key)?.get("iso_code")?.as_str())
+                    .map(str::to_ascii_uppercase);
+            }
+            if location.asn.is_none() {
+                location.asn = record
+                    .get("autonomous_system_number")
+                    .and_then(Value::as_u64)
+                    .and_then(|asn| u32::try_from(asn).ok());
+            }
+        }
+        location
+    }
+
+    /// What about `location` is blocked, `"asn"` or `"country"`, if anything
+    pub fn blocks(&self, location: &Location) -> Option<&'static str> {
+        if location.asn.is_some_and(|asn| self.blocked_asns.contains(&asn)) {
+            return Some("asn");
+        }
+        if location.country.as_ref().is_some_and(|c| self.blocked_countries.contains(c)) {
+            return Some("country");
+        }
+        None
+    }
+}
+
+/// `13335` or `AS13335`
+fn parse_asn(asn: &str) -> Option<u32> {
+    let asn = asn.trim();
+    let digits = match asn.get(..2) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("as") => &asn[2..],
+        _ => asn,
+    };
+    digits.parse().ok()
+}
+
+/// One MaxMind DB file: a binary search tree over address bits whose
+/// leaves point into a data section of typed values
+struct Database {
+    bytes: Vec<u8>,
+    node_count: usize,
+    record_size: usize,
+    ip_version: u16,
+    /// Node at which IPv4 addresses start, after 96 zero bits in an IPv6 tree
+    ipv4_start: usize,
+}
+
+impl Database {
+    fn open(path: &Path) -> io::Result<Self> {
+        Self::parse(std::fs::read(path)?).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+        })
+    }
+
+    fn parse(bytes: Vec<u8>) -> Result<Self, String> {
+        let marker = bytes
+            .windows(METADATA_MARKER.len())
+            .rposition(|window| window == METADATA_MARKER)
+            .ok_or("not a MaxMind DB file")?;
+        let metadata = Decoder::new(&bytes[marker + METADATA_MARKER.len()..]).decode_at(0)?.0;
+        let field = |name: &str| {
+            metadata.get(name).and_then(Value::as_u64).ok_or(format!("metadata lacks {}", name))
+        };
+        let node_count = field("node_count")? as usize;
+        let record_size = field("record_size")? as usize;
+        let ip_version = field("ip_version")? as u16;
+        if ![24, 28, 32].contains(&record_size) {
+            return Err(format!("unsupported record size {}", record_size));
+        }
+        let tree_size = node_count.checked_mul(record_size / 4).ok_or("invalid node count")?;
+        if tree_size + DATA_SEPARATOR > marker {
+            return Err("search tree larger than the file".into());
+        }
+
+        let mut database = Self {
+            bytes,
+            node_count,
+            record_size,
+            ip_version,
+            ipv4_start: 0,
+        };
+        if ip_version == 6 {
+            for _ in 0..96 {
+                if database.ipv4_start >= node_count {
+                    break;
+                }
+                database.ipv4_start = database.record(database.ipv4_start, 0);
+            }
+        }
+        Ok(database)
+    }
+
+    /// Left (`bit` 0) or right record of a node
+    fn record(&self, node: usize, bit: u8) -> usize {
+        let size = self.record_size * 2 / 8;
+        let b = &self.bytes[node * size..(node + 1) * size];
+        let be = |bytes: &[u8]| bytes.iter().fold(0, |n, b| n << 8 | *b as usize);
+        match (self.record_size, bit) {
+            (24, 0) => be(&b[..3]),
+            (24, _) => be(&b[3..]),
+            (28, 0) => (b[3] as usize >> 4) << 24 | be(&b[..3]),
+            (28, _) => (b[3] as usize & 0x0f) << 24 | be(&b[4..]),
+            (_, 0) => be(&b[..4]),
+            (_, _) => be(&b[4..]),
+        }
+    }
+
+    fn lookup(&self, ip: &IpAddr) -> Option<Value> {
+        let (bits, mut node) = match ip.to_canonical() {
+            IpAddr::V4(ip) => (ip.octets().to_vec(), self.ipv4_start),
+            IpAddr::V6(_) if self.ip_version == 4 => return None,
+            IpAddr::V6(ip) => (ip.octets().to_vec(), 0),
+        };
+        for i in 0..bits.len() * 8 {
+            if node >= self.node_count {
+                break;
+            }
+            node = self.record(node, bits[i / 8] >> (7 - i % 8) & 1);
+        }
+        // Equal to the node count means no data for the address
+        if node <= self.node_count {
+            return None;
+        }
+        let tree_size = self.node_count * self.record_size / 4;
+        let data = self.bytes.get(tree_size + DATA_SEPARATOR..)?;
+        let offset = node - self.node_count - DATA_SEPARATOR;
+        Decoder::new(data).decode_at(offset).ok().map(|(value, _)| value)
+    }
+}
+
+/// Decoded data section value
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Uint(u128),
+    Int(i32),
+    Double(f64),
+    Bool(bool),
+    Bytes(Vec<u8>),
+    Array(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Uint(n) => u64::try_from(*n).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Reads values out of a data section, or out of the metadata
+struct Decoder<'a> {
+    data: &'a [u8],
+    depth: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, depth: 0 }
+    }
+
+    fn bytes(&self, at: usize, len: usize) -> Result<&'a [u8], String> {
+        self.data.get(at..at + len).ok_or_else(|| "truncated data section".into())
+    }
+
+    fn uint(&self, at: usize, len: usize) -> Result<u128, String> {
+        Ok(self.bytes(at, len)?.iter().fold(0, |n, b| n << 8 | *b as u128))
+    }
+
+    /// The value at `at`, and where the next one starts
+    fn decode_at(&mut self, at: usize) -> Result<(Value, usize), String> {
+        let control = self.bytes(at, 1)?[0];
+        let mut at = at + 1;
+        let mut kind = control >> 5;
+        if kind == 1 {
+            // Pointer to a value elsewhere in the section, which is read in its place
+            let size = (control >> 3 & 0x3) as usize;
+            let high = (control & 0x7) as usize;
+            let pointer = match size {
+                0 => high << 8 | self.uint(at, 1)? as usize,
+                1 => (high << 16 | self.uint(at, 2)? as usize) + 2048,
+                2 => (high << 24 | self.uint(at, 3)? as usize) + 526_336,
+                _ => self.uint(at, 4)? as usize,
+            };
+            if self.depth >= MAX_DEPTH {
+                return Err("data nested too deeply".into());
+            }
+            self.depth += 1;
+            let value = self.decode_at(pointer)?.0;
+            self.depth -= 1;
+            return Ok((value, at + size + 1));
+        }
+        if kind == 0 {
+            kind = 7 + self.bytes(at, 1)?[0];
+            at += 1;
+        }
+        let (len, skip) = match control & 0x1f {
+            len @ 0..29 => (len as usize, 0),
+            29 => (29 + self.uint(at, 1)? as usize, 1),
+            30 => (285 + self.uint(at, 2)? as usize, 2),
+            _ => (65_821 + self.uint(at, 3)? as usize, 3),
+        };
+        at += skip;
+
+        let value = match kind {
+            2 => {
+                let bytes = self.bytes(at, len)?;
+                Value::String(String::from_utf8_lossy(bytes).into_owned())
+            }
+            3 => Value::Double(f64::from_bits(self.uint(at, 8)? as u64)),
+            4 => Value::Bytes(self.bytes(at, len)?.to_vec()),
+            5 | 6 | 9 | 10 => Value::Uint(self.uint(at, len)?),
+            8 => Value::Int(self.uint(at, len)? as u32 as i32),
+            14 => return Ok((Value::Bool(len != 0), at)),
+            15 => Value::Double(f32::from_bits(self.uint(at, 4)? as u32) as f64),
+            7 | 11 => return self.decode_container(kind, len, at),
+            _ => return Err(format!("unsupported data type {}", kind)),
+        };
+        let len = match kind {
+            3 => 8,
+            15 => 4,
+            _ => len,
+        };
+        Ok((value, at + len))
+    }
+
+    fn decode_container(
+        &mut self,
+        kind: u8,
+        len: usize,
+        mut at: usize,
+    ) -> Result<(Value, usize), String> {
+        if self.depth >= MAX_DEPTH {
+            return Err("data nested too deeply".into());
+        }
+        self.depth += 1;
+        let value = if kind == 7 {
+            let mut entries = Vec::with_capacity(len.min(64));
+            for _ in 0..len {
+                let (key, next) = self.decode_at(at)?;
+                let Value::String(key) = key else {
+                    return Err("map key is not a string".into());
+                };
+                let (value, next) = self.decode_at(next)?;
+                entries.push((key, value));
+                at = next;
+            }
+            Value::Map(entries)
+        } else {
+            let mut items = Vec::with_capacity(len.min(64));
+            for _ in 0..len {
+                let (item, next) = self.decode_at(at)?;
+                items.push(item);
+                at = next;
+            }
+            Value::Array(items)
+        };
+        self.depth -= 1;
+        Ok((value, at))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn string(s: &str) -> Vec<u8> {
+        [&[0x40 | s.len() as u8], s.as_bytes()].concat()
+    }
+
+    fn uint32(n: u32) -> Vec<u8> {
+        [&[0xc4], &n.to_be_bytes()[..]].concat()
+    }
+
+    fn map(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut out = vec![0xe0 | entries.len() as u8];
+        for (key, value) in entries {
+            out.extend(string(key));
+            out.extend(value);
+        }
+        out
+    }
+
+    /// An IPv6 database with 24-bit records mapping IPv4 `/8` networks to data
+    fn database(networks: &[(u8, Vec<u8>)]) -> Vec<u8> {
+        const EMPTY: usize = usize::MAX;
+        // Records pointing into the data section, offset by this much
+        const DATA: usize = usize::MAX / 2;
+        let mut nodes = vec![[EMPTY; 2]];
+        let mut data: Vec<u8> = Vec::new();
+        for (first, value) in networks {
+            let bits = (0..96).map(|_| 0).chain((0..8).map(|i| first >> (7 - i) & 1));
+            let mut node = 0;
+            let bits: Vec<u8> = bits.collect();
+            for (i, bit) in bits.iter().enumerate() {
+                let bit = *bit as usize;
+                if i == bits.len() - 1 {
+                    nodes[node][bit] = DATA + data.len();
+                } else {
+                    if nodes[node][bit] == EMPTY {
+                        nodes.push([EMPTY; 2]);
+                        nodes[node][bit] = nodes.len() - 1;
+                    }
+                    node = nodes[node][bit];
+                }
+            }
+            data.extend(value);
+        }
+
+        let count = nodes.len();
+        let mut bytes = Vec::new();
+        for record in nodes.iter().flatten() {
+            let record = match *record {
+                EMPTY => count,
+                node if node < DATA => node,
+                data => count + DATA_SEPARATOR + data - DATA,
+            };
+            bytes.extend(&(record as u32).to_be_bytes()[1..]);
+        }
+        bytes.extend([0; DATA_SEPARATOR]);
+        bytes.extend(data);
+        bytes.extend(METADATA_MARKER);
+        let metadata = map(&[
+            ("node_count", uint32(count as u32)),
+            ("record_size", [0xa1, 24].to_vec()),
+            ("ip_version", [0xa1, 6].to_vec()),
+        ]);
+        bytes.extend(metadata);
+        bytes
+    }
+
+    fn from_args(args: &[&str]) -> io::Result<Option<GeoIp>> {
+        GeoIp::from_config(&Config::parse_from(["camo"].iter().chain(args)))
+    }
+
+    #[test]
+    fn test_lookup() {
+        let country = map(&[("country", map(&[("iso_code", string("de"))]))]);
+        // The second record's key is a pointer back to the first record's "country"
+        let registered = [&[0xe1, 0x20, 0x01][..], &map(&[("iso_code", string("US"))])].concat();
+        let asn = map(&[("autonomous_system_number", uint32(13335))]);
+        let database = database(&[(1, country.clone()), (2, registered), (3, asn)]);
+        let database = Database::parse(database).unwrap();
+
+        let lookup = |ip: &str| database.lookup(&ip.parse().unwrap());
+        let de = lookup("1.2.3.4").unwrap();
+        assert_eq!(de.get("country").unwrap().get("iso_code").unwrap().as_str(), Some("de"));
+        let us = lookup("::ffff:2.0.0.1").unwrap();
+        assert_eq!(us.get("country").unwrap().get("iso_code").unwrap().as_str(), Some("US"));
+        let asn = lookup("3.0.0.0").unwrap();
+        assert_eq!(asn.get("autonomous_system_number").unwrap().as_u64(), Some(13335));
+        assert_eq!(lookup("4.0.0.0"), None);
+        assert_eq!(lookup("2001:db8::1"), None);
+
+        assert!(Database::parse(b"not a database".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_from_config() {
+        let dir = std::env::temp_dir().join(format!("camo-geoip-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let countries = dir.join("country.mmdb");
+        let country = |code: &str| map(&[("country", map(&[("iso_code", string(code))]))]);
+        std::fs::write(&countries, database(&[(1, country("DE")), (2, country("FR"))])).unwrap();
+        let asns = dir.join("asn.mmdb");
+        let asn = |n| map(&[("autonomous_system_number", uint32(n))]);
+        std::fs::write(&asns, database(&[(1, asn(64500)), (3, asn(64501))])).unwrap();
+
+        let dbs = format!("{},{}", countries.display(), asns.display());
+        let args = ["--geoip-db", &dbs, "--block-asn", "AS64501", "--block-country", "fr"];
+        let geoip = from_args(&args).unwrap().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let locate = |ip: &str| geoip.locate(&ip.parse().unwrap());
+        let location = locate("1.1.1.1");
+        assert_eq!(location.country.as_deref(), Some("DE"));
+        assert_eq!(location.asn, Some(64500));
+        assert_eq!(geoip.blocks(&location), None);
+        assert_eq!(geoip.blocks(&locate("2.1.1.1")), Some("country"));
+        assert_eq!(geoip.blocks(&locate("3.1.1.1")), Some("asn"));
+        assert_eq!(locate("4.1.1.1"), Location::default());
+
+        assert!(from_args(&[]).unwrap().is_none());
+        assert!(from_args(&["--block-asn", "64500"]).is_err());
+        assert!(from_args(&["--geoip-db", "/nonexistent.mmdb"]).is_err());
+        assert!(from_args(&["--geoip-db", &dbs, "--block-country", "FRA"]).is_err());
+        assert_eq!(parse_asn("as13335"), Some(13335));
+        assert_eq!(parse_asn("ASN"), None);
+    }
+}
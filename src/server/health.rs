@@ -0,0 +1,168 @@
+//! Kubernetes-style probes
+//!
+//! `/livez` only reports that the process is serving requests. `/readyz`
+//! checks the dependencies a proxied request needs and fails with 503 when
+//! any of them is broken, so the instance is taken out of rotation.
+
+use super::router::AppState;
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Upper bound for the DNS check
+const DNS_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Disabled,
+    Fail,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Check {
+    fn ok() -> Self {
+        Self {
+            status: Status::Ok,
+            error: None,
+        }
+    }
+
+    fn disabled() -> Self {
+        Self {
+            status: Status::Disabled,
+            error: None,
+        }
+    }
+
+    fn fail(error: impl Into<String>) -> Self {
+        Self {
+            status: Status::Fail,
+            error: Some(error.into()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub status: Status,
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+pub async fn livez() -> Json<Report> {
+    Json(Report {
+        status: Status::Ok,
+        checks: BTreeMap::new(),
+    })
+}
+
+pub async fn readyz(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Report>) {
+    let report = readiness(&state).await;
+    let code = match report.status {
+        Status::Fail => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::OK,
+    };
+    (code, Json(report))
+}
+
+async fn readiness(state: &AppState) -> Report {
+    let checks = BTreeMap::from([
+        ("dns", check_dns(&state.config.ready_dns_host).await),
+        ("cache", check_cache(state)),
+        ("metrics", check_metrics(state)),
+    ]);
+
+    let status = if checks.values().any(|check| check.status == Status::Fail) {
+        Status::Fail
+    } else {
+        Status::Ok
+    };
+    Report { status, checks }
+}
+
+async fn check_dns(host: &str) -> Check {
+    if host.is_empty() {
+        return Check::disabled();
+    }
+
+    match tokio::time::timeout(DNS_TIMEOUT, tokio::net::lookup_host((host, 0))).await {
+        Ok(Ok(mut addrs)) => match addrs.next() {
+            Some(_) => Check::ok(),
+            None => Check::fail(format!("{} has no addresses", host)),
+        },
+        Ok(Err(e)) => Check::fail(format!("failed to resolve {}: {}", host, e)),
+        Err(_) => Check::fail(format!("resolving {} timed out", host)),
+    }
+}
+
+fn check_cache(state: &AppState) -> Check {
+    match &state.negative_cache {
+        Some(cache) if cache.is_healthy() => Check::ok(),
+        Some(_) => Check::fail("negative cache lock is poisoned"),
+        None => Check::disabled(),
+    }
+}
+
+fn check_metrics(state: &AppState) -> Check {
+    if !state.config.metrics {
+        return Check::disabled();
+    }
+
+    match super::prometheus::handle() {
+        Some(_) => Check::ok(),
+        None => Check::fail("Prometheus recorder is not installed"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::config::Config;
+    use clap::Parser;
+
+    fn state(extra: &[&str]) -> AppState {
+        let args = ["camo", "--key", "test", "--ready-dns-host", "localhost"];
+        let config = Config::parse_from(args.iter().chain(extra));
+        AppState::from_config(&config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_ready() {
+        let report = readiness(&state(&[])).await;
+
+        assert_eq!(report.status, Status::Ok);
+        assert_eq!(report.checks["dns"].status, Status::Ok);
+        assert_eq!(report.checks["cache"].status, Status::Ok);
+        assert_eq!(report.checks["metrics"].status, Status::Disabled);
+    }
+
+    #[tokio::test]
+    async fn test_disabled_checks() {
+        let mut config = Config::parse_from(["camo", "--negative-cache-size", "0"]);
+        config.ready_dns_host = String::new();
+        let report = readiness(&AppState::from_config(&config).unwrap()).await;
+
+        assert_eq!(report.status, Status::Ok);
+        assert_eq!(report.checks["dns"].status, Status::Disabled);
+        assert_eq!(report.checks["cache"].status, Status::Disabled);
+    }
+
+    #[tokio::test]
+    async fn test_missing_recorder() {
+        let report = readiness(&state(&["--metrics"])).await;
+
+        assert_eq!(report.status, Status::Fail);
+        assert_eq!(report.checks["metrics"].status, Status::Fail);
+        assert!(report.checks["metrics"].error.is_some());
+    }
+}
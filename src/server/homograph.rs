@@ -0,0 +1,112 @@
+//! Lookalike hostnames, like `еxample.com` spelled with a Cyrillic `е`
+//!
+//! The `url` crate turns internationalized hosts into punycode, which is
+//! what every host policy compares, so a lookalike never matches the name
+//! it imitates. It can still fool whoever reads the link, though, and
+//! `--reject-mixed-scripts` refuses hosts with a label mixing scripts the
+//! way no real name does. Labels written entirely in another script, like
+//! a Cyrillic `раураl`, are not caught.
+
+/// Writing system of a character, as far as telling lookalikes apart needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+    /// Digits, `-` and combining marks, at home in any script
+    Common,
+    Latin,
+    Greek,
+    Cyrillic,
+    Armenian,
+    Hebrew,
+    Arabic,
+    Han,
+    Kana,
+    Hangul,
+    Bopomofo,
+    /// Everything else, all taken as one script
+    Other,
+}
+
+impl Script {
+    fn of(c: char) -> Self {
+        match c as u32 {
+            0x30..=0x39 | 0x2D | 0x5F | 0x300..=0x36F => Script::Common,
+            0x41..=0x5A | 0x61..=0x7A | 0xC0..=0xD6 | 0xD8..=0xF6 | 0xF8..=0x2AF => Script::Latin,
+            0x1E00..=0x1EFF => Script::Latin,
+            0x370..=0x3FF | 0x1F00..=0x1FFF => Script::Greek,
+            0x400..=0x52F | 0x1C80..=0x1C8F | 0x2DE0..=0x2DFF | 0xA640..=0xA69F => {
+                Script::Cyrillic
+            }
+            0x530..=0x58F => Script::Armenian,
+            0x590..=0x5FF => Script::Hebrew,
+            0x600..=0x6FF | 0x750..=0x77F | 0x8A0..=0x8FF => Script::Arabic,
+            0x3005..=0x3007 | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF => Script::Han,
+            0x20000..=0x3FFFF => Script::Han,
+            0x3040..=0x30FF | 0x31F0..=0x31FF => Script::Kana,
+            0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF => Script::Hangul,
+            0x3100..=0x312F | 0x31A0..=0x31BF => Script::Bopomofo,
+            _ => Script::Other,
+        }
+    }
+}
+
+/// Scripts written together in East Asian names, with Latin
+const COMBINATIONS: [&[Script]; 3] = [
+    &[Script::Latin, Script::Han, Script::Kana],
+    &[Script::Latin, Script::Han, Script::Hangul],
+    &[Script::Latin, Script::Han, Script::Bopomofo],
+];
+
+/// Whether a label mixes scripts beyond the combinations in use, like Latin and Cyrillic
+fn is_mixed(label: &str) -> bool {
+    let mut scripts: Vec<Script> = Vec::new();
+    for script in label.chars().map(Script::of) {
+        if script != Script::Common && !scripts.contains(&script) {
+            scripts.push(script);
+        }
+    }
+    scripts.len() > 1
+        && !COMBINATIONS.iter().any(|allowed| scripts.iter().all(|s| allowed.contains(s)))
+}
+
+/// Whether a host, as the `url` crate gives it, has a label mixing scripts
+pub fn is_mixed_script(host: &str) -> bool {
+    if host.is_ascii() && !host.split('.').any(|label| label.starts_with("xn--")) {
+        return false;
+    }
+    let (unicode, _) = idna::domain_to_unicode(host);
+    unicode.split('.').any(is_mixed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use url::Url;
+
+    fn mixed(url: &str) -> bool {
+        is_mixed_script(Url::parse(url).unwrap().host_str().unwrap())
+    }
+
+    #[test]
+    fn test_mixed_script() {
+        // Cyrillic е in an otherwise Latin name
+        assert!(mixed("https://еxample.com/a.png"));
+        assert!(mixed("https://xn--xample-2of.com/a.png"));
+        assert!(mixed("https://cdn.pаypal.com/a.png"));
+        // Greek ο
+        assert!(mixed("https://gοogle.com/a.png"));
+
+        for url in [
+            "https://example.com/a.png",
+            "https://bücher.de/a.png",
+            "https://пример.рф/a.png",
+            "https://пример.com/a.png",
+            "https://東京2020.jp/a.png",
+            "https://ソニーsony.jp/a.png",
+            "https://한국abc.kr/a.png",
+            "https://127.0.0.1/a.png",
+            "https://[::1]/a.png",
+        ] {
+            assert!(!mixed(url), "{}", url);
+        }
+    }
+}
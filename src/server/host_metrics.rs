@@ -0,0 +1,111 @@
+use super::config::Config;
+use super::error::CamoError;
+use super::redirect::{host_matches, host_pattern};
+
+use axum::http::StatusCode;
+use std::collections::HashSet;
+use std::sync::RwLock;
+
+/// Label for hosts that do not get one of their own
+const OTHER: &str = "other";
+
+/// Upstream requests per origin host, as `camo_upstream_requests_total{host, result}`
+///
+/// Hosts matching `--metrics-host` always get their own label; a wildcard
+/// entry such as `*.example.com` labels all its subdomains with the
+/// pattern itself. Beyond those, the first `--metrics-host-limit` hosts
+/// seen are labeled by name and all later ones as `other`, so a flood of
+/// distinct origins cannot grow the number of series without bound.
+pub struct HostMetrics {
+    allowed: Vec<String>,
+    limit: usize,
+    seen: RwLock<HashSet<String>>,
+}
+
+impl HostMetrics {
+    /// Returns `None` when metrics are disabled
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config.metrics.then(|| Self {
+            allowed: config
+                .metrics_hosts
+                .iter()
+                .map(|host| host_pattern(host))
+                .filter(|host| !host.is_empty())
+                .collect(),
+            limit: config.metrics_host_limit,
+            seen: RwLock::new(HashSet::new()),
+        })
+    }
+
+    /// Label for `host`, claiming one of the free slots if there are any
+    fn label(&self, host: &str) -> String {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        if let Some(pattern) = self.allowed.iter().find(|p| host_matches(p, &host)) {
+            return pattern.clone();
+        }
+
+        if self.seen.read().unwrap().contains(&host) {
+            return host;
+        }
+        let mut seen = self.seen.write().unwrap();
+        // Another request may have claimed it in between
+        if seen.len() < self.limit || seen.contains(&host) {
+            seen.insert(host.clone());
+            return host;
+        }
+        OTHER.to_string()
+    }
+
+    /// Count one upstream request to `host` and how it ended
+    pub fn record(&self, host: &str, result: Result<(), &CamoError>) {
+        let result = match result {
+            Ok(()) => "ok",
+            Err(CamoError::UpstreamStatus(StatusCode::NOT_FOUND | StatusCode::GONE)) => {
+                "not_found"
+            }
+            Err(CamoError::UpstreamStatus(_)) => "bad_status",
+            Err(CamoError::Timeout) => "timeout",
+            Err(
+                CamoError::ContentTypeNotAllowed(_)
+                | CamoError::ContentTooLarge(_)
+                | CamoError::PrivateNetworkNotAllowed
+                | CamoError::NetworkNotAllowed,
+            ) => "blocked",
+            Err(_) => "error",
+        };
+        metrics::counter!(
+            "camo_upstream_requests_total",
+            "host" => self.label(host),
+            "result" => result
+        )
+        .increment(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics(allowed: &[&str], limit: usize) -> HostMetrics {
+        HostMetrics {
+            allowed: allowed.iter().map(|h| h.to_string()).collect(),
+            limit,
+            seen: RwLock::new(HashSet::new()),
+        }
+    }
+
+    #[test]
+    fn test_label() {
+        let metrics = metrics(&["img.example.com", "*.cdn.example"], 2);
+
+        assert_eq!(metrics.label("IMG.example.com."), "img.example.com");
+        assert_eq!(metrics.label("a.cdn.example"), "*.cdn.example");
+        assert_eq!(metrics.label("b.cdn.example"), "*.cdn.example");
+
+        assert_eq!(metrics.label("one.example"), "one.example");
+        assert_eq!(metrics.label("two.example"), "two.example");
+        assert_eq!(metrics.label("three.example"), OTHER);
+        assert_eq!(metrics.label("one.example"), "one.example");
+        assert_eq!(metrics.label("img.example.com"), "img.example.com");
+    }
+}
@@ -0,0 +1,164 @@
+//! Experimental HTTP/3 listener (QUIC over UDP)
+//!
+//! Runs next to the TLS listener on the same port and certificate and serves
+//! the same router. Browsers discover it through the `Alt-Svc` header added
+//! to responses on the TCP listener.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::Request,
+    http::{header, HeaderValue},
+    middleware,
+    response::Response,
+    Router,
+};
+use bytes::BufMut;
+use h3::server::RequestResolver;
+use http_body_util::BodyExt;
+use quinn::crypto::rustls::QuicServerConfig;
+use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tower::ServiceExt;
+use tracing::{debug, info, warn};
+
+/// How long clients may remember that HTTP/3 is available, in seconds
+const ALT_SVC_MAX_AGE: u64 = 86400;
+
+/// Advertise HTTP/3 on `port` in every response of `app`
+pub fn advertise(app: Router, port: u16) -> Router {
+    let alt_svc = HeaderValue::from_str(&alt_svc(port)).expect("valid Alt-Svc value");
+
+    app.layer(middleware::map_response(move |mut response: Response| {
+        let alt_svc = alt_svc.clone();
+        async move {
+            response.headers_mut().insert(header::ALT_SVC, alt_svc);
+            response
+        }
+    }))
+}
+
+fn alt_svc(port: u16) -> String {
+    format!("h3=\":{}\"; ma={}", port, ALT_SVC_MAX_AGE)
+}
+
+/// Serve HTTP/3 on the UDP socket `addr` until shutdown
+pub async fn serve(addr: SocketAddr, cert: &Path, key: &Path, app: Router) -> anyhow::Result<()> {
+    let endpoint = quinn::Endpoint::server(server_config(cert, key)?, addr)?;
+
+    #[cfg(unix)]
+    reload_on_sighup(endpoint.clone(), cert.to_path_buf(), key.to_path_buf());
+
+    info!("HTTP/3 listening on udp/{}", addr);
+
+    while let Some(incoming) = endpoint.accept().await {
+        let app = app.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(incoming, app).await {
+                debug!("HTTP/3 connection failed: {:#}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn server_config(cert: &Path, key: &Path) -> anyhow::Result<quinn::ServerConfig> {
+    let certs = CertificateDer::pem_file_iter(cert)?.collect::<Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_file(key)?;
+
+    // QUIC requires TLS 1.3
+    let mut tls = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_protocol_versions(&[&rustls::version::TLS13])?
+    .with_no_client_auth()
+    .with_single_cert(certs, key)?;
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+
+    let crypto = QuicServerConfig::try_from(tls)?;
+    Ok(quinn::ServerConfig::with_crypto(Arc::new(crypto)))
+}
+
+async fn serve_connection(incoming: quinn::Incoming, app: Router) -> anyhow::Result<()> {
+    let connection = h3_quinn::Connection::new(incoming.await?);
+    let mut connection = h3::server::Connection::new(connection).await?;
+
+    loop {
+        match connection.accept().await {
+            Ok(Some(resolver)) => {
+                let app = app.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_request(resolver, app).await {
+                        debug!("HTTP/3 request failed: {:#}", e);
+                    }
+                });
+            }
+            Ok(None) => return Ok(()),
+            Err(e) if e.is_h3_no_error() => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+async fn serve_request(
+    resolver: RequestResolver<h3_quinn::Connection, Bytes>,
+    app: Router,
+) -> anyhow::Result<()> {
+    let (request, mut stream) = resolver.resolve_request().await?;
+
+    let mut body = Vec::new();
+    while let Some(chunk) = stream.recv_data().await? {
+        body.put(chunk);
+    }
+
+    let (parts, ()) = request.into_parts();
+    let response = app.oneshot(Request::from_parts(parts, Body::from(body))).await?;
+
+    let (parts, mut body) = response.into_parts();
+    stream.send_response(Response::from_parts(parts, ())).await?;
+    while let Some(frame) = body.frame().await {
+        if let Ok(data) = frame?.into_data() {
+            stream.send_data(data).await?;
+        }
+    }
+    stream.finish().await?;
+    Ok(())
+}
+
+/// Re-read the certificate and key from disk whenever the process receives SIGHUP
+#[cfg(unix)]
+fn reload_on_sighup(endpoint: quinn::Endpoint, cert: PathBuf, key: PathBuf) {
+    use tokio::signal::unix::{signal, SignalKind};
+    use tracing::error;
+
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Failed to install SIGHUP handler, HTTP/3 TLS hot-reload disabled: {}", e);
+                return;
+            }
+        };
+
+        while hangup.recv().await.is_some() {
+            match server_config(&cert, &key) {
+                Ok(config) => {
+                    endpoint.set_server_config(Some(config));
+                    info!("Reloaded HTTP/3 TLS certificate from {}", cert.display());
+                }
+                Err(e) => error!("Failed to reload HTTP/3 TLS certificate: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alt_svc() {
+        assert_eq!(alt_svc(443), "h3=\":443\"; ma=86400");
+    }
+}
@@ -3,7 +3,78 @@ mod reqwest_impl;
 #[cfg(feature = "server")]
 pub use reqwest_impl::ReqwestClient;
 
+#[cfg(feature = "object-store")]
+mod object_store;
+#[cfg(feature = "object-store")]
+pub use object_store::ObjectStoreClient;
+
+#[cfg(feature = "clamd")]
+mod clamd;
+#[cfg(feature = "clamd")]
+pub use clamd::Clamd;
+
 #[cfg(feature = "worker")]
 mod worker_impl;
 #[cfg(feature = "worker")]
 pub use worker_impl::WorkerFetchClient;
+
+#[cfg(feature = "wasm-fetch")]
+mod fetch_impl;
+#[cfg(feature = "wasm-fetch")]
+pub use fetch_impl::{header_map, FetchClient};
+
+mod cache_control;
+#[cfg(feature = "server")]
+mod decompress;
+mod follow;
+mod header_filter;
+mod inspect;
+#[cfg(feature = "server")]
+mod resolver;
+#[cfg(feature = "server")]
+pub use resolver::AddressHealth;
+mod response_filter;
+mod retry;
+#[cfg(any(feature = "worker", feature = "wasm-fetch"))]
+mod unsafe_send;
+mod upgrade;
+pub use cache_control::{cap_max_age, is_shareable, make_private, CachePolicy};
+pub use header_filter::{DispositionPolicy, HeaderFilter};
+pub use inspect::{InspectingClient, ResponseInspector};
+pub use response_filter::{OversizePolicy, ResponseFilter, SizeLimitedStream};
+pub use upgrade::UpgradingClient;
+
+use super::error::Result;
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use url::Url;
+
+/// Upstream response that passed the [`ResponseFilter`]
+pub struct UpstreamResponse {
+    pub headers: HeaderMap,
+    pub body: Body,
+}
+
+impl IntoResponse for UpstreamResponse {
+    fn into_response(self) -> Response {
+        // The first bytes of an oversized body, or a range upstream sent on its own
+        let status = if self.headers.contains_key(header::CONTENT_RANGE) {
+            StatusCode::PARTIAL_CONTENT
+        } else {
+            StatusCode::OK
+        };
+        (status, self.headers, self.body).into_response()
+    }
+}
+
+/// Backend that fetches upstream resources for the proxy
+#[async_trait::async_trait]
+pub trait HttpClient: Send + Sync {
+    /// Fetch `url` with extra request `headers`, applying the [`ResponseFilter`] policy
+    /// to the response
+    async fn get(&self, url: Url, headers: HeaderMap) -> Result<UpstreamResponse>;
+}
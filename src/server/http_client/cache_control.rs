@@ -0,0 +1,222 @@
+use axum::http::{header, HeaderMap, HeaderValue};
+
+/// Caching policy applied to proxied responses
+///
+/// Fills in a default `Cache-Control` when the upstream sent no caching
+/// headers at all, and raises `max-age`/`s-maxage` values below the minimum
+/// so caches in front of the proxy keep responses long enough to matter.
+#[derive(Clone, Default)]
+pub struct CachePolicy {
+    default: Option<HeaderValue>,
+    min_ttl: u64,
+}
+
+impl CachePolicy {
+    /// An unparsable `default` is ignored
+    pub fn new(default: Option<&str>, min_ttl: u64) -> Self {
+        Self {
+            default: default.and_then(|v| HeaderValue::from_str(v).ok()),
+            min_ttl,
+        }
+    }
+
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        match headers.get(header::CACHE_CONTROL) {
+            Some(value) => {
+                if let Some(raised) = value
+                    .to_str()
+                    .ok()
+                    .and_then(|v| raise_max_age(v, self.min_ttl))
+                    && let Ok(value) = HeaderValue::from_str(&raised)
+                {
+                    headers.insert(header::CACHE_CONTROL, value);
+                }
+            }
+            None if !headers.contains_key(header::EXPIRES) => {
+                if let Some(default) = &self.default {
+                    headers.insert(header::CACHE_CONTROL, default.clone());
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+/// Keep a response out of shared caches, which would hand it to other users
+///
+/// `public` and `s-maxage` are dropped and `private` added, leaving what
+/// the browser may keep as it was.
+pub fn make_private(headers: &mut HeaderMap) {
+    let directives = headers
+        .get(header::CACHE_CONTROL)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|directive| {
+            let name = directive.split('=').next().unwrap_or_default().trim();
+            !(directive.is_empty()
+                || name.eq_ignore_ascii_case("public")
+                || name.eq_ignore_ascii_case("private")
+                || name.eq_ignore_ascii_case("s-maxage"))
+        });
+    let value = std::iter::once("private").chain(directives).collect::<Vec<_>>().join(", ");
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.insert(header::CACHE_CONTROL, value);
+    }
+}
+
+/// Whether shared caches may keep a response, which `no-store` and `private` forbid
+pub fn is_shareable(headers: &HeaderMap) -> bool {
+    !headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.split('=').next().unwrap_or_default().trim())
+        .any(|name| name.eq_ignore_ascii_case("no-store") || name.eq_ignore_ascii_case("private"))
+}
+
+/// Keep caches from holding a response longer than `max_ttl` seconds
+///
+/// `max-age`/`s-maxage` directives above it are lowered to it, and a
+/// `max-age` added when there is none, which also overrides `Expires`.
+pub fn cap_max_age(headers: &mut HeaderMap, max_ttl: u64) {
+    let mut has_max_age = false;
+    let mut directives: Vec<String> = headers
+        .get(header::CACHE_CONTROL)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .map(|directive| match directive.split_once('=') {
+            Some((name, value))
+                if name.trim().eq_ignore_ascii_case("max-age")
+                    || name.trim().eq_ignore_ascii_case("s-maxage") =>
+            {
+                has_max_age |= name.trim().eq_ignore_ascii_case("max-age");
+                match value.trim().trim_matches('"').parse::<u64>() {
+                    Ok(ttl) if ttl <= max_ttl => directive.to_string(),
+                    _ => format!("{}={}", name.trim(), max_ttl),
+                }
+            }
+            _ => directive.to_string(),
+        })
+        .collect();
+    if !has_max_age {
+        directives.push(format!("max-age={}", max_ttl));
+    }
+    if let Ok(value) = HeaderValue::from_str(&directives.join(", ")) {
+        headers.insert(header::CACHE_CONTROL, value);
+    }
+}
+
+/// Rewrite `max-age`/`s-maxage` directives below `min_ttl`, or `None` if nothing changes
+fn raise_max_age(cache_control: &str, min_ttl: u64) -> Option<String> {
+    if min_ttl == 0 {
+        return None;
+    }
+
+    let mut changed = false;
+    let directives: Vec<String> = cache_control
+        .split(',')
+        .map(|directive| {
+            let directive = directive.trim();
+            if let Some((name, value)) = directive.split_once('=')
+                && (name.trim().eq_ignore_ascii_case("max-age")
+                    || name.trim().eq_ignore_ascii_case("s-maxage"))
+                && let Ok(ttl) = value.trim().trim_matches('"').parse::<u64>()
+                && ttl < min_ttl
+            {
+                changed = true;
+                return format!("{}={}", name.trim(), min_ttl);
+            }
+            directive.to_string()
+        })
+        .collect();
+
+    changed.then(|| directives.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_default_when_uncached() {
+        let policy = CachePolicy::new(Some("public, max-age=31536000, immutable"), 0);
+
+        let mut h = headers(&[]);
+        policy.apply(&mut h);
+        assert_eq!(h[header::CACHE_CONTROL], "public, max-age=31536000, immutable");
+
+        // Expires counts as a caching header
+        let mut h = headers(&[("expires", "Thu, 01 Jan 2026 00:00:00 GMT")]);
+        policy.apply(&mut h);
+        assert!(!h.contains_key(header::CACHE_CONTROL));
+
+        let mut h = headers(&[("cache-control", "no-store")]);
+        policy.apply(&mut h);
+        assert_eq!(h[header::CACHE_CONTROL], "no-store");
+    }
+
+    #[test]
+    fn test_min_ttl() {
+        let policy = CachePolicy::new(None, 3600);
+
+        let mut h = headers(&[("cache-control", "public, max-age=60, s-maxage=10")]);
+        policy.apply(&mut h);
+        assert_eq!(h[header::CACHE_CONTROL], "public, max-age=3600, s-maxage=3600");
+
+        let mut h = headers(&[("cache-control", "max-age=86400")]);
+        policy.apply(&mut h);
+        assert_eq!(h[header::CACHE_CONTROL], "max-age=86400");
+
+        let mut h = headers(&[("cache-control", "no-cache")]);
+        policy.apply(&mut h);
+        assert_eq!(h[header::CACHE_CONTROL], "no-cache");
+    }
+
+    #[test]
+    fn test_make_private() {
+        let mut h = headers(&[("cache-control", "public, max-age=3600, S-Maxage=60, immutable")]);
+        make_private(&mut h);
+        assert_eq!(h[header::CACHE_CONTROL], "private, max-age=3600, immutable");
+
+        let mut h = HeaderMap::new();
+        make_private(&mut h);
+        assert_eq!(h[header::CACHE_CONTROL], "private");
+    }
+
+    #[test]
+    fn test_cap_max_age() {
+        let mut h = headers(&[("cache-control", "public, max-age=3600, s-maxage=30")]);
+        cap_max_age(&mut h, 60);
+        assert_eq!(h[header::CACHE_CONTROL], "public, max-age=60, s-maxage=30");
+
+        let mut h = headers(&[("expires", "Thu, 01 Jan 2099 00:00:00 GMT")]);
+        cap_max_age(&mut h, 60);
+        assert_eq!(h[header::CACHE_CONTROL], "max-age=60");
+
+        let mut h = headers(&[("cache-control", "no-cache")]);
+        cap_max_age(&mut h, 0);
+        assert_eq!(h[header::CACHE_CONTROL], "no-cache, max-age=0");
+    }
+
+    #[test]
+    fn test_is_shareable() {
+        assert!(is_shareable(&headers(&[])));
+        assert!(is_shareable(&headers(&[("cache-control", "public, max-age=60")])));
+        assert!(!is_shareable(&headers(&[("cache-control", "max-age=60, No-Store")])));
+        assert!(!is_shareable(&headers(&[("cache-control", "private=\"set-cookie\"")])));
+    }
+}
@@ -0,0 +1,164 @@
+//! Virus scanning of proxied bodies with ClamAV's `clamd`
+//!
+//! Bodies are streamed to the daemon with the `INSTREAM` command over TCP
+//! or a Unix socket, and rejected when it reports a signature. A scan that
+//! cannot complete rejects the body too, so nothing reaches clients
+//! unscanned while the daemon is down.
+
+use super::super::error::{CamoError, Result};
+use super::ResponseInspector;
+
+use axum::body::Bytes;
+use axum::http::HeaderMap;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::warn;
+use url::Url;
+
+/// Size of the chunks a body is sent in; clamd's `StreamMaxLength` caps the total
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Longest reply read from clamd
+const MAX_REPLY: u64 = 1024;
+
+/// Time allowed for connecting, sending the body and waiting for the verdict
+const SCAN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// [`ResponseInspector`] asking clamd at `host:port` or `unix:/path` about every body
+pub struct Clamd {
+    address: String,
+}
+
+impl Clamd {
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+        }
+    }
+
+    /// clamd's reply to the body, without the trailing NUL
+    async fn scan(&self, body: &[u8]) -> std::io::Result<String> {
+        match self.address.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) => instream(tokio::net::UnixStream::connect(path).await?, body).await,
+            #[cfg(not(unix))]
+            Some(_) => Err(std::io::Error::other("Unix sockets are not supported")),
+            None => instream(tokio::net::TcpStream::connect(&self.address).await?, body).await,
+        }
+    }
+}
+
+async fn instream<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    body: &[u8],
+) -> std::io::Result<String> {
+    stream.write_all(b"zINSTREAM\0").await?;
+    for chunk in body.chunks(CHUNK_SIZE) {
+        stream.write_all(&(chunk.len() as u32).to_be_bytes()).await?;
+        stream.write_all(chunk).await?;
+    }
+    stream.write_all(&0u32.to_be_bytes()).await?;
+    stream.flush().await?;
+
+    let mut reply = Vec::new();
+    stream.take(MAX_REPLY).read_to_end(&mut reply).await?;
+    let reply = String::from_utf8_lossy(&reply);
+    Ok(reply.trim_end_matches(['\0', '\n']).to_string())
+}
+
+/// `Ok` for a clean body, the signature found, or the error clamd reported
+fn verdict(reply: &str) -> std::result::Result<std::result::Result<(), String>, String> {
+    let result = reply.strip_prefix("stream: ").unwrap_or(reply);
+    if result == "OK" {
+        Ok(Ok(()))
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(Err(signature.to_string()))
+    } else {
+        Err(result.to_string())
+    }
+}
+
+#[async_trait::async_trait]
+impl ResponseInspector for Clamd {
+    async fn inspect(&self, url: &Url, _headers: &HeaderMap, body: &Bytes) -> Result<()> {
+        let reply = tokio::time::timeout(SCAN_TIMEOUT, self.scan(body))
+            .await
+            .unwrap_or_else(|_| Err(std::io::ErrorKind::TimedOut.into()));
+        let (result, outcome) = match reply.map_err(|e| e.to_string()).and_then(|r| verdict(&r)) {
+            Ok(Ok(())) => ("clean", Ok(())),
+            Ok(Err(signature)) => {
+                warn!(url = %url, signature, "Virus found in upstream response");
+                ("infected", Err(CamoError::Rejected(signature)))
+            }
+            Err(e) => {
+                warn!(url = %url, error = %e, "Virus scan failed");
+                ("error", Err(CamoError::Upstream(format!("virus scan failed: {}", e))))
+            }
+        };
+        metrics::counter!("camo_scans_total", "result" => result).increment(1);
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// clamd stand-in answering `reply` once it got the whole stream
+    async fn daemon(reply: &'static str) -> (String, tokio::task::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut command = [0; 10];
+            socket.read_exact(&mut command).await.unwrap();
+            assert_eq!(&command, b"zINSTREAM\0");
+            let mut body = Vec::new();
+            loop {
+                let length = socket.read_u32().await.unwrap() as usize;
+                if length == 0 {
+                    break;
+                }
+                let mut chunk = vec![0; length];
+                socket.read_exact(&mut chunk).await.unwrap();
+                body.extend(chunk);
+            }
+            socket.write_all(reply.as_bytes()).await.unwrap();
+            body
+        });
+        (address, handle)
+    }
+
+    #[test]
+    fn test_verdict() {
+        assert_eq!(verdict("stream: OK"), Ok(Ok(())));
+        assert_eq!(
+            verdict("stream: Eicar-Test-Signature FOUND"),
+            Ok(Err("Eicar-Test-Signature".to_string()))
+        );
+        assert!(verdict("INSTREAM size limit exceeded. ERROR").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_inspect() {
+        let url = Url::parse("https://example.com/a.png").unwrap();
+        let body = Bytes::from(vec![7; CHUNK_SIZE + 10]);
+
+        let (address, sent) = daemon("stream: OK\0").await;
+        let clamd = Clamd::new(&address);
+        assert!(clamd.inspect(&url, &HeaderMap::new(), &body).await.is_ok());
+        assert_eq!(sent.await.unwrap(), body);
+
+        let (address, _) = daemon("stream: Eicar-Test-Signature FOUND\0").await;
+        let result = Clamd::new(&address).inspect(&url, &HeaderMap::new(), &body).await;
+        assert!(matches!(result, Err(CamoError::Rejected(s)) if s == "Eicar-Test-Signature"));
+
+        // Nothing listening: rejected rather than let through
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = closed.local_addr().unwrap().to_string();
+        drop(closed);
+        let result = Clamd::new(&address).inspect(&url, &HeaderMap::new(), &body).await;
+        assert!(matches!(result, Err(CamoError::Upstream(_))));
+    }
+}
@@ -0,0 +1,144 @@
+use super::super::error::{CamoError, Result};
+
+use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder, ZlibDecoder};
+use axum::body::Bytes;
+use axum::http::{header, HeaderMap, HeaderValue};
+use futures_core::Stream;
+use std::fmt::Display;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::AsyncRead;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// Body stream as handed to [`ResponseFilter::limit_body`](super::ResponseFilter::limit_body)
+pub type DecodedBody = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+
+/// Undo the upstream `Content-Encoding`, so the size limit counts what the body expands to
+///
+/// The proxy never asks for a compressed response, but nothing stops an
+/// upstream from sending one, and passed on as is a 100 KB download can
+/// expand to gigabytes at the client. gzip, deflate and br bodies are
+/// decoded as they stream, and their `Content-Encoding` and
+/// `Content-Length` removed from `headers`; any other coding is refused.
+/// The decoded bytes are no longer those upstream's byte ranges and strong
+/// `ETag` describe, so ranges are dropped and the `ETag` made weak.
+pub fn decode_body<S, E>(headers: &mut HeaderMap, stream: S) -> Result<DecodedBody>
+where
+    S: Stream<Item = std::result::Result<Bytes, E>> + Send + 'static,
+    E: Display,
+{
+    let coding = headers
+        .get(header::CONTENT_ENCODING)
+        .map(|v| v.to_str().unwrap_or_default().trim().to_ascii_lowercase());
+    let stream = IoStream(stream);
+    let body: DecodedBody = match coding.as_deref() {
+        None | Some("" | "identity") => {
+            headers.remove(header::CONTENT_ENCODING);
+            return Ok(Box::pin(stream));
+        }
+        Some("gzip" | "x-gzip") => decoded(GzipDecoder::new(StreamReader::new(stream))),
+        Some("deflate") => decoded(ZlibDecoder::new(StreamReader::new(stream))),
+        Some("br") => decoded(BrotliDecoder::new(StreamReader::new(stream))),
+        Some(other) => {
+            return Err(CamoError::Upstream(format!("unsupported content encoding: {}", other)));
+        }
+    };
+    headers.remove(header::CONTENT_ENCODING);
+    // It was the length of the encoded body
+    headers.remove(header::CONTENT_LENGTH);
+    headers.remove(header::CONTENT_RANGE);
+    headers.remove(header::ACCEPT_RANGES);
+    if let Some(etag) = headers.get(header::ETAG).and_then(|v| v.to_str().ok())
+        && !etag.starts_with("W/")
+        && let Ok(weak) = HeaderValue::from_str(&format!("W/{}", etag))
+    {
+        headers.insert(header::ETAG, weak);
+    }
+    Ok(body)
+}
+
+fn decoded<D: AsyncRead + Send + 'static>(decoder: D) -> DecodedBody {
+    Box::pin(ReaderStream::new(decoder))
+}
+
+/// Upstream body stream with its errors as `io::Error`, as `StreamReader` wants them
+#[pin_project::pin_project]
+struct IoStream<S>(#[pin] S);
+
+impl<S, E> Stream for IoStream<S>
+where
+    S: Stream<Item = std::result::Result<Bytes, E>>,
+    E: Display,
+{
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.project().0.poll_next(cx);
+        poll.map(|item| item.map(|chunk| chunk.map_err(|e| io::Error::other(e.to_string()))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_compression::tokio::bufread::GzipEncoder;
+    use tokio::io::AsyncReadExt;
+
+    /// Single chunk stream
+    struct Once(Option<Bytes>);
+
+    impl Stream for Once {
+        type Item = std::result::Result<Bytes, io::Error>;
+
+        fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            Poll::Ready(self.0.take().map(Ok))
+        }
+    }
+
+    async fn collect(mut body: DecodedBody) -> Vec<u8> {
+        let mut out = Vec::new();
+        while let Some(chunk) = std::future::poll_fn(|cx| body.as_mut().poll_next(cx)).await {
+            out.extend_from_slice(&chunk.unwrap());
+        }
+        out
+    }
+
+    fn encoded(encoding: &str, length: usize) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_ENCODING, encoding.parse().unwrap());
+        headers.insert(header::CONTENT_LENGTH, length.into());
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_decodes_gzip() {
+        let plain = vec![0u8; 100_000];
+        let mut gzip = Vec::new();
+        GzipEncoder::new(plain.as_slice()).read_to_end(&mut gzip).await.unwrap();
+        assert!(gzip.len() < 1000);
+
+        let mut headers = encoded("GZIP", gzip.len());
+        headers.insert(header::ETAG, "\"v1\"".parse().unwrap());
+        headers.insert(header::ACCEPT_RANGES, "bytes".parse().unwrap());
+        let body = decode_body(&mut headers, Once(Some(gzip.into()))).unwrap();
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers[header::ETAG], "W/\"v1\"");
+        assert_eq!(collect(body).await, plain);
+    }
+
+    #[tokio::test]
+    async fn test_identity_and_unknown() {
+        let mut headers = encoded("identity", 5);
+        let body = decode_body(&mut headers, Once(Some("hello".into()))).unwrap();
+        assert!(!headers.contains_key(header::CONTENT_ENCODING));
+        assert_eq!(headers[header::CONTENT_LENGTH], "5");
+        assert_eq!(collect(body).await, b"hello");
+
+        let mut headers = encoded("zstd", 5);
+        assert!(matches!(
+            decode_body(&mut headers, Once(None)),
+            Err(CamoError::Upstream(_))
+        ));
+    }
+}
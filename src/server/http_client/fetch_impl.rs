@@ -0,0 +1,214 @@
+use super::super::{
+    config::Config,
+    error::{CamoError, Result},
+};
+use super::follow::Redirects;
+use super::retry::{is_retryable_status, retry_backoff};
+use super::unsafe_send::UnsafeSend;
+use super::{HttpClient, ResponseFilter, UpstreamResponse};
+
+use axum::body::Body;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use futures_core::Stream;
+use js_sys::{Array, Promise, Uint8Array};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use url::Url;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{AbortController, Headers, Request, RequestInit, RequestRedirect, Response};
+
+#[wasm_bindgen]
+extern "C" {
+    /// `fetch()` of the global scope, available in every fetch API runtime
+    #[wasm_bindgen(js_name = fetch)]
+    fn global_fetch(request: &Request) -> Promise;
+
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(handler: &js_sys::Function, timeout: i32) -> JsValue;
+}
+
+/// Upstream client for WebAssembly runtimes built on the standard fetch API
+#[derive(Clone)]
+pub struct FetchClient {
+    config: Config,
+    filter: ResponseFilter,
+}
+
+impl FetchClient {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            config: config.clone(),
+            filter: ResponseFilter::new(config),
+        }
+    }
+
+    /// Send the GET request, retrying failed fetches and 502/503 responses
+    async fn send_with_retries(&self, url: &Url, headers: &HeaderMap) -> Result<Response> {
+        let mut attempt = 0;
+
+        loop {
+            let result = self.send(url, headers).await;
+
+            let retryable = match &result {
+                Ok(response) => {
+                    StatusCode::from_u16(response.status()).is_ok_and(is_retryable_status)
+                }
+                Err(e) => matches!(e, CamoError::Upstream(_)),
+            };
+
+            if !retryable || attempt >= self.config.upstream_retries {
+                return result;
+            }
+
+            attempt += 1;
+            sleep(retry_backoff(
+                Duration::from_millis(self.config.upstream_retry_backoff),
+                attempt,
+            ))
+            .await;
+        }
+    }
+
+    /// A single fetch, aborted once the timeout has passed
+    ///
+    /// Redirects are answered as they are, to be followed by [`Redirects`].
+    /// Server runtimes hand out their `Location`, unlike browsers.
+    async fn send(&self, url: &Url, headers: &HeaderMap) -> Result<Response> {
+        // Covers the body too: aborting the signal also aborts the stream
+        let controller = AbortController::new().map_err(upstream_error)?;
+        let signal = controller.signal();
+        let abort = Closure::once_into_js(move || controller.abort());
+        set_timeout(abort.unchecked_ref(), millis(Duration::from_secs(self.config.timeout)));
+
+        let request_headers = Headers::new().map_err(upstream_error)?;
+        for (name, value) in headers {
+            if let Ok(value) = value.to_str() {
+                request_headers
+                    .append(name.as_str(), value)
+                    .map_err(upstream_error)?;
+            }
+        }
+
+        let init = RequestInit::new();
+        init.set_method("GET");
+        init.set_headers(&request_headers);
+        init.set_redirect(RequestRedirect::Manual);
+        init.set_signal(Some(&signal));
+        let request = Request::new_with_str_and_init(url.as_str(), &init)
+            .map_err(|e| CamoError::InvalidUrl(format!("{:?}", e)))?;
+
+        let response = JsFuture::from(global_fetch(&request)).await.map_err(|e| {
+            if signal.aborted() {
+                CamoError::Timeout
+            } else {
+                upstream_error(e)
+            }
+        })?;
+        response.dyn_into().map_err(upstream_error)
+    }
+
+    async fn fetch(&self, url: Url, headers: HeaderMap) -> Result<UpstreamResponse> {
+        let mut redirects = Redirects::new(&self.config);
+        let (mut url, mut headers) = (url, headers);
+        let (status, upstream, response) = loop {
+            let response = self.send_with_retries(&url, &headers).await?;
+            let status = StatusCode::from_u16(response.status())
+                .map_err(|e| CamoError::Upstream(e.to_string()))?;
+            let upstream = header_map(&response.headers());
+            match redirects.next(&url, status, &upstream, &mut headers)? {
+                Some(next) => url = next,
+                None => break (status, upstream, response),
+            }
+        };
+        let headers = self.filter.check(status, &upstream)?;
+
+        // Stream the response body, enforcing the size limit as chunks arrive
+        let body = match response.body() {
+            Some(stream) => {
+                let chunks = Chunks(wasm_streams::ReadableStream::from_raw(stream).into_stream());
+                Body::from_stream(self.filter.limit_body(UnsafeSend(chunks), &headers))
+            }
+            None => Body::empty(),
+        };
+
+        Ok(UpstreamResponse { headers, body })
+    }
+}
+
+impl HttpClient for FetchClient {
+    /// Returns a Send-safe future for use with axum.
+    /// SAFETY: This is safe because WebAssembly runtimes are single-threaded.
+    fn get<'a, 'async_trait>(
+        &'a self,
+        url: Url,
+        headers: HeaderMap,
+    ) -> Pin<Box<dyn Future<Output = Result<UpstreamResponse>> + Send + 'async_trait>>
+    where
+        'a: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(UnsafeSend(self.fetch(url, headers)))
+    }
+}
+
+/// Copy fetch API headers, skipping any that are not valid HTTP headers
+pub fn header_map(headers: &Headers) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    let Ok(Some(entries)) = js_sys::try_iter(headers) else {
+        return map;
+    };
+
+    for entry in entries.flatten() {
+        let entry = Array::from(&entry);
+        let (Some(name), Some(value)) = (entry.get(0).as_string(), entry.get(1).as_string())
+        else {
+            continue;
+        };
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            map.append(name, value);
+        }
+    }
+    map
+}
+
+/// Resolve after `duration` using the runtime's timer
+async fn sleep(duration: Duration) {
+    let promise = Promise::new(&mut |resolve, _| {
+        set_timeout(&resolve, millis(duration));
+    });
+    let _ = JsFuture::from(promise).await;
+}
+
+fn millis(duration: Duration) -> i32 {
+    duration.as_millis().try_into().unwrap_or(i32::MAX)
+}
+
+fn upstream_error(e: JsValue) -> CamoError {
+    CamoError::Upstream(format!("{:?}", e))
+}
+
+/// `Uint8Array` chunks of a fetch body as bytes
+#[pin_project::pin_project]
+struct Chunks<S>(#[pin] S);
+
+impl<S> Stream for Chunks<S>
+where
+    S: Stream<Item = std::result::Result<JsValue, JsValue>>,
+{
+    type Item = std::result::Result<Vec<u8>, String>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.project().0.poll_next(cx).map(|chunk| {
+            chunk.map(|chunk| match chunk {
+                Ok(chunk) => Ok(Uint8Array::new(&chunk).to_vec()),
+                Err(e) => Err(format!("{:?}", e)),
+            })
+        })
+    }
+}
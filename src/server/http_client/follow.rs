@@ -0,0 +1,158 @@
+use super::super::{
+    config::Config,
+    error::{CamoError, Result},
+};
+
+use axum::http::{header, HeaderMap, StatusCode};
+use url::Url;
+
+/// Redirects followed by the clients themselves, one hop at a time
+///
+/// Letting the HTTP stack follow them would send every hop the headers
+/// meant for the first one. Sensitive headers, the operator's static
+/// headers and upstream credentials, are instead dropped as soon as a
+/// redirect leaves the origin they were configured for, and each hop can
+/// be checked before it is fetched.
+pub struct Redirects {
+    followed: u32,
+    max: u32,
+    https_only: bool,
+}
+
+impl Redirects {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            followed: 0,
+            max: config.max_redirects,
+            https_only: config.https_only_upstream,
+        }
+    }
+
+    /// Where a response to `url` redirects to, with `headers` fit for it
+    ///
+    /// `None` for any other response, including a redirect without a usable
+    /// `Location`, which the [`ResponseFilter`](super::ResponseFilter) then refuses.
+    pub fn next(
+        &mut self,
+        url: &Url,
+        status: StatusCode,
+        response: &HeaderMap,
+        headers: &mut HeaderMap,
+    ) -> Result<Option<Url>> {
+        if !matches!(status.as_u16(), 301 | 302 | 303 | 307 | 308) {
+            return Ok(None);
+        }
+        let Some(next) = response
+            .get(header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| url.join(location).ok())
+            .filter(|next| matches!(next.scheme(), "http" | "https"))
+        else {
+            return Ok(None);
+        };
+
+        if self.followed >= self.max {
+            return Err(CamoError::TooManyRedirects);
+        }
+        if self.https_only && next.scheme() == "http" {
+            return Err(CamoError::InsecureRedirect);
+        }
+        self.followed += 1;
+
+        if next.origin() != url.origin() {
+            let sensitive: Vec<_> = headers
+                .iter()
+                .filter(|(_, value)| value.is_sensitive())
+                .map(|(name, _)| name.clone())
+                .collect();
+            for name in sensitive {
+                headers.remove(name);
+            }
+        }
+        Ok(Some(next))
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use clap::Parser;
+
+    fn redirects(args: &[&str]) -> Redirects {
+        Redirects::new(&Config::parse_from(["camo", "--key", "k"].iter().chain(args)))
+    }
+
+    fn location(to: &str) -> HeaderMap {
+        HeaderMap::from_iter([(header::LOCATION, HeaderValue::from_str(to).unwrap())])
+    }
+
+    fn request() -> HeaderMap {
+        let mut token = HeaderValue::from_static("secret");
+        token.set_sensitive(true);
+        HeaderMap::from_iter([
+            (header::ACCEPT_LANGUAGE, HeaderValue::from_static("en")),
+            (header::AUTHORIZATION, token),
+        ])
+    }
+
+    #[test]
+    fn test_follows_within_limit() {
+        let url = Url::parse("https://example.com/a/b.png").unwrap();
+        let mut redirects = redirects(&["--max-redirects", "1"]);
+        let mut headers = request();
+
+        let next = redirects.next(&url, StatusCode::FOUND, &location("c.png"), &mut headers);
+        let next = next.unwrap().unwrap();
+        assert_eq!(next.as_str(), "https://example.com/a/c.png");
+        // Same origin: everything is kept
+        assert_eq!(headers, request());
+
+        let again = redirects.next(&next, StatusCode::FOUND, &location("/d.png"), &mut headers);
+        assert!(matches!(again, Err(CamoError::TooManyRedirects)));
+    }
+
+    #[test]
+    fn test_drops_sensitive_headers_across_origins() {
+        let url = Url::parse("https://example.com/a.png").unwrap();
+        for to in ["https://other.example/a.png", "https://example.com:8443/a.png"] {
+            let mut headers = request();
+            redirects(&[])
+                .next(&url, StatusCode::MOVED_PERMANENTLY, &location(to), &mut headers)
+                .unwrap();
+            assert!(headers.contains_key(header::ACCEPT_LANGUAGE));
+            assert!(!headers.contains_key(header::AUTHORIZATION), "{}", to);
+        }
+    }
+
+    #[test]
+    fn test_other_responses() {
+        let url = Url::parse("https://example.com/a.png").unwrap();
+        let mut redirects = redirects(&["--max-redirects", "0"]);
+        let mut headers = HeaderMap::new();
+
+        for (status, to) in [
+            (StatusCode::OK, "/b.png"),
+            (StatusCode::NOT_MODIFIED, "/b.png"),
+            (StatusCode::FOUND, "ftp://example.com/b.png"),
+        ] {
+            let next = redirects.next(&url, status, &location(to), &mut headers);
+            assert!(next.unwrap().is_none());
+        }
+        let next = redirects.next(&url, StatusCode::FOUND, &HeaderMap::new(), &mut headers);
+        assert!(next.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_https_only() {
+        let url = Url::parse("https://example.com/a.png").unwrap();
+        let to = location("http://example.com/a.png");
+        let mut headers = HeaderMap::new();
+
+        let next = redirects(&[]).next(&url, StatusCode::FOUND, &to, &mut headers);
+        assert!(next.unwrap().is_some());
+        let mut redirects = redirects(&["--https-only-upstream"]);
+        let next = redirects.next(&url, StatusCode::FOUND, &to, &mut headers);
+        assert!(matches!(next, Err(CamoError::InsecureRedirect)));
+    }
+}
@@ -0,0 +1,273 @@
+use super::super::content_types::extension;
+
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+
+/// Hop-by-hop headers (RFC 9110 section 7.6.1), only meaningful for one connection
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Upstream headers that must not be replayed under the proxy's origin
+const SENSITIVE: &[&str] = &[
+    "set-cookie",
+    "set-cookie2",
+    "server",
+    "x-powered-by",
+    "via",
+    "www-authenticate",
+    "strict-transport-security",
+    "alt-svc",
+    "location",
+    "content-location",
+    "refresh",
+    "link",
+    "clear-site-data",
+    "service-worker-allowed",
+    "report-to",
+    "nel",
+    // Replaced by the proxy's own policy headers
+    "content-security-policy",
+    "content-security-policy-report-only",
+    "access-control-allow-origin",
+    "access-control-allow-credentials",
+    "access-control-allow-headers",
+    "access-control-allow-methods",
+    "access-control-expose-headers",
+    "timing-allow-origin",
+];
+
+/// Longest filename kept from an upstream `Content-Disposition`
+const MAX_FILENAME: usize = 100;
+
+/// How an upstream `Content-Disposition` reaches the client
+///
+/// Never as `attachment`: a proxied image must not be able to start a
+/// download under the proxy's origin, let alone with a filename of the
+/// upstream's choosing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DispositionPolicy {
+    /// Forced to `inline`, keeping a sanitized filename
+    #[default]
+    Inline,
+    /// Always `inline; filename="image.<ext>"`, the extension from the content type
+    Synthesize,
+    /// Dropped
+    Strip,
+}
+
+impl DispositionPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "inline" => Some(DispositionPolicy::Inline),
+            "synthesize" => Some(DispositionPolicy::Synthesize),
+            "strip" => Some(DispositionPolicy::Strip),
+            _ => None,
+        }
+    }
+}
+
+/// Deny-list filter for upstream response headers
+///
+/// Everything is forwarded except hop-by-hop headers, headers named in the
+/// upstream `Connection` header, the built-in sensitive list and any extra
+/// names configured by the operator. `Content-Disposition` is rewritten
+/// according to the [`DispositionPolicy`].
+#[derive(Clone, Default)]
+pub struct HeaderFilter {
+    deny: Vec<HeaderName>,
+    disposition: DispositionPolicy,
+}
+
+impl HeaderFilter {
+    /// Invalid header names in `extra_deny` are ignored
+    pub fn new(extra_deny: &[String], disposition: DispositionPolicy) -> Self {
+        Self {
+            deny: extra_deny
+                .iter()
+                .filter_map(|name| HeaderName::try_from(name.trim()).ok())
+                .collect(),
+            disposition,
+        }
+    }
+
+    pub fn is_allowed(&self, name: &HeaderName) -> bool {
+        !HOP_BY_HOP.contains(&name.as_str())
+            && !SENSITIVE.contains(&name.as_str())
+            && !self.deny.contains(name)
+    }
+
+    /// Copy the headers from `upstream` that may be sent to the client
+    pub fn filter(&self, upstream: &HeaderMap) -> HeaderMap {
+        // Connection may list further hop-by-hop headers
+        let connection: Vec<HeaderName> = upstream
+            .get_all(header::CONNECTION)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|name| HeaderName::try_from(name.trim()).ok())
+            .collect();
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in upstream {
+            if self.is_allowed(name)
+                && !connection.contains(name)
+                && name != header::CONTENT_DISPOSITION
+            {
+                headers.append(name, value.clone());
+            }
+        }
+        if let Some(disposition) = self.disposition(upstream)
+            && self.is_allowed(&header::CONTENT_DISPOSITION)
+        {
+            headers.insert(header::CONTENT_DISPOSITION, disposition);
+        }
+        headers
+    }
+
+    fn disposition(&self, upstream: &HeaderMap) -> Option<HeaderValue> {
+        match self.disposition {
+            DispositionPolicy::Strip => None,
+            DispositionPolicy::Inline => {
+                let upstream = upstream.get(header::CONTENT_DISPOSITION)?;
+                let value = match upstream.to_str().ok().and_then(filename).map(sanitize) {
+                    Some(name) if !name.is_empty() => format!("inline; filename=\"{}\"", name),
+                    _ => "inline".to_string(),
+                };
+                HeaderValue::from_str(&value).ok()
+            }
+            DispositionPolicy::Synthesize => {
+                let content_type = upstream.get(header::CONTENT_TYPE)?.to_str().ok()?;
+                let kind = content_type.split('/').next()?.trim().to_ascii_lowercase();
+                let value = format!("inline; filename=\"{}.{}\"", kind, extension(content_type)?);
+                HeaderValue::from_str(&value).ok()
+            }
+        }
+    }
+}
+
+/// The plain `filename` parameter of a `Content-Disposition` value
+fn filename(value: &str) -> Option<&str> {
+    value.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("filename")
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+/// Last path segment of `name`, with anything but letters, digits, `.`,
+/// `-` and `_` replaced, no leading dots, and at most [`MAX_FILENAME`] long
+fn sanitize(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    base.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .skip_while(|c| *c == '.')
+        .take(MAX_FILENAME)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upstream() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in [
+            ("content-type", "image/png"),
+            ("content-disposition", "inline"),
+            ("vary", "Accept"),
+            ("accept-ranges", "bytes"),
+            ("expires", "Thu, 01 Jan 2026 00:00:00 GMT"),
+            ("set-cookie", "session=1"),
+            ("server", "nginx"),
+            ("transfer-encoding", "chunked"),
+            ("connection", "close, x-debug"),
+            ("x-debug", "1"),
+            ("x-internal-id", "42"),
+        ] {
+            headers.append(name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_forwards_safe_headers() {
+        let headers = HeaderFilter::default().filter(&upstream());
+
+        for name in ["content-type", "content-disposition", "vary", "accept-ranges", "expires"] {
+            assert!(headers.contains_key(name), "{} should be forwarded", name);
+        }
+        assert!(headers.contains_key("x-internal-id"));
+    }
+
+    #[test]
+    fn test_strips_sensitive_and_hop_by_hop() {
+        let headers = HeaderFilter::default().filter(&upstream());
+
+        for name in ["set-cookie", "server", "transfer-encoding", "connection", "x-debug"] {
+            assert!(!headers.contains_key(name), "{} should be stripped", name);
+        }
+    }
+
+    #[test]
+    fn test_configured_deny_list() {
+        let filter = HeaderFilter::new(
+            &["X-Internal-Id".into(), "not a header".into()],
+            DispositionPolicy::Inline,
+        );
+        let headers = filter.filter(&upstream());
+
+        assert!(!headers.contains_key("x-internal-id"));
+        assert!(headers.contains_key("vary"));
+    }
+
+    fn disposition(policy: DispositionPolicy, upstream: Option<&str>) -> Option<String> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, "image/JPEG; q=1".parse().unwrap());
+        if let Some(value) = upstream {
+            headers.insert(header::CONTENT_DISPOSITION, value.parse().unwrap());
+        }
+        let headers = HeaderFilter::new(&[], policy).filter(&headers);
+        headers
+            .get(header::CONTENT_DISPOSITION)
+            .map(|v| v.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        use DispositionPolicy::*;
+
+        let cases = [
+            ("attachment", "inline"),
+            ("attachment; filename=\"cat.jpg\"", "inline; filename=\"cat.jpg\""),
+            ("attachment; FileName=../../evil.exe", "inline; filename=\"evil.exe\""),
+            ("inline; filename=\"C:\\x\\..bashrc\"", "inline; filename=\"bashrc\""),
+            ("attachment; filename=\"a b;c\"", "inline; filename=\"a_b\""),
+            ("attachment; filename=\"...\"", "inline"),
+        ];
+        for (upstream, expected) in cases {
+            let disposition = disposition(Inline, Some(upstream));
+            assert_eq!(disposition.as_deref(), Some(expected), "{}", upstream);
+        }
+        assert_eq!(disposition(Inline, None), None);
+
+        let synthesized = Some("inline; filename=\"image.jpg\"".to_string());
+        assert_eq!(disposition(Synthesize, Some("attachment; filename=x.exe")), synthesized);
+        assert_eq!(disposition(Synthesize, None), synthesized);
+
+        assert_eq!(disposition(Strip, Some("attachment; filename=x.exe")), None);
+    }
+}
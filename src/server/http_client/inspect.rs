@@ -0,0 +1,100 @@
+use super::super::error::{CamoError, Result};
+use super::{HttpClient, UpstreamResponse};
+
+use axum::body::Bytes;
+use axum::http::HeaderMap;
+use std::sync::Arc;
+use url::Url;
+
+/// Check run on every fetched body before it is forwarded, e.g. a virus scan
+///
+/// Returning an error rejects the response: the client gets that error
+/// instead, typically [`CamoError::Rejected`].
+#[async_trait::async_trait]
+pub trait ResponseInspector: Send + Sync {
+    async fn inspect(&self, url: &Url, headers: &HeaderMap, body: &Bytes) -> Result<()>;
+}
+
+/// Hands every request to the `inner` client and its responses to an [`ResponseInspector`]
+///
+/// Bodies are buffered in full for the inspection, which is bounded by the
+/// size limits the inner client's [`ResponseFilter`](super::ResponseFilter)
+/// already applies; the response then goes out from the buffer.
+pub struct InspectingClient {
+    inner: Arc<dyn HttpClient>,
+    inspector: Arc<dyn ResponseInspector>,
+}
+
+impl InspectingClient {
+    pub fn new(inner: Arc<dyn HttpClient>, inspector: Arc<dyn ResponseInspector>) -> Self {
+        Self { inner, inspector }
+    }
+}
+
+#[async_trait::async_trait]
+impl HttpClient for InspectingClient {
+    async fn get(&self, url: Url, headers: HeaderMap) -> Result<UpstreamResponse> {
+        let mut response = self.inner.get(url.clone(), headers).await?;
+        #[cfg(feature = "server")]
+        let body = crate::server::memory_budget::buffer(response.body, usize::MAX).await;
+        #[cfg(not(feature = "server"))]
+        let body = axum::body::to_bytes(response.body, usize::MAX).await;
+        let body = body.map_err(|e| match e.into_inner().downcast::<CamoError>() {
+            Ok(e) => *e,
+            Err(e) => CamoError::Upstream(e.to_string()),
+        })?;
+        self.inspector.inspect(&url, &response.headers, &body).await?;
+        response.body = body.into();
+        Ok(response)
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use std::sync::Mutex;
+
+    struct Upstream(&'static [u8]);
+
+    #[async_trait::async_trait]
+    impl HttpClient for Upstream {
+        async fn get(&self, _url: Url, _headers: HeaderMap) -> Result<UpstreamResponse> {
+            Ok(UpstreamResponse {
+                headers: HeaderMap::new(),
+                body: Body::from(self.0),
+            })
+        }
+    }
+
+    /// Rejects bodies containing `BAD`, remembering what it saw
+    #[derive(Default)]
+    struct Scanner(Mutex<Vec<String>>);
+
+    #[async_trait::async_trait]
+    impl ResponseInspector for Scanner {
+        async fn inspect(&self, url: &Url, _headers: &HeaderMap, body: &Bytes) -> Result<()> {
+            self.0.lock().unwrap().push(url.to_string());
+            if body.windows(3).any(|window| window == b"BAD") {
+                return Err(CamoError::Rejected("found BAD".into()));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_inspects_body() {
+        let url = Url::parse("https://example.com/a.png").unwrap();
+        let scanner = Arc::new(Scanner::default());
+
+        let clean = InspectingClient::new(Arc::new(Upstream(b"\x89PNG")), scanner.clone());
+        let response = clean.get(url.clone(), HeaderMap::new()).await.unwrap();
+        let body = axum::body::to_bytes(response.body, usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), b"\x89PNG");
+
+        let infected = InspectingClient::new(Arc::new(Upstream(b"xxBADxx")), scanner.clone());
+        let result = infected.get(url, HeaderMap::new()).await;
+        assert!(matches!(result, Err(CamoError::Rejected(_))));
+        assert_eq!(scanner.0.lock().unwrap().len(), 2);
+    }
+}
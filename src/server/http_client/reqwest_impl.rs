@@ -1,157 +1,249 @@
 use super::super::{
-    config::Config,
+    config::{Config, TlsVersion},
     error::{CamoError, Result},
+    load::{self, Holding},
+    network::NetworkPolicy,
 };
-
-use axum::{
-    body::Body,
-    http::{header, HeaderMap, HeaderValue},
-};
-use reqwest::Client;
-use std::net::IpAddr;
+use super::decompress::decode_body;
+use super::follow::Redirects;
+use super::resolver::{AddressHealth, UpstreamResolver};
+use super::retry::{is_retryable_status, retry_backoff};
+use super::{HttpClient, ResponseFilter, UpstreamResponse};
+
+use axum::body::Body;
+use axum::http::HeaderMap;
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Client};
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
 use std::time::Duration;
+use tracing::warn;
 use url::Url;
 
 #[derive(Clone)]
 pub struct ReqwestClient {
     client: Client,
     config: Config,
+    filter: ResponseFilter,
+    network_policy: NetworkPolicy,
+    health: Option<Arc<AddressHealth>>,
 }
 
 impl ReqwestClient {
-    pub fn new(config: &Config) -> Self {
-        let client = Client::builder()
+    /// Fails on an unreadable CA bundle or block list
+    pub fn new(config: &Config) -> io::Result<Self> {
+        Self::with_health(config, AddressHealth::from_config(config).map(Arc::new))
+    }
+
+    /// Like [`ReqwestClient::new`], recording connection outcomes in `health`
+    ///
+    /// Lets the caller keep a handle on it, as the admin API does.
+    pub fn with_health(config: &Config, health: Option<Arc<AddressHealth>>) -> io::Result<Self> {
+        let invalid = |e: String| io::Error::new(ErrorKind::InvalidInput, e);
+        let mut builder = Client::builder()
             .timeout(Duration::from_secs(config.timeout))
-            .redirect(reqwest::redirect::Policy::limited(
-                config.max_redirects as usize,
-            ))
-            .user_agent("camo-rs")
-            .build()
-            .expect("Failed to create HTTP client");
+            .redirect(Policy::none())
+            .user_agent("camo-rs");
+
+        if let Some(path) = &config.tls_ca_file {
+            let pem = std::fs::read(path).map_err(|e| {
+                io::Error::new(e.kind(), format!("Failed to read CA bundle {}: {}", path.display(), e))
+            })?;
+            let certs = Certificate::from_pem_bundle(&pem)
+                .map_err(|e| invalid(format!("Failed to parse CA bundle: {}", e)))?;
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
 
-        Self {
-            client,
-            config: config.clone(),
+        if let Some(version) = config.tls_min_version {
+            builder = builder.min_tls_version(match version {
+                TlsVersion::Tls12 => reqwest::tls::Version::TLS_1_2,
+                TlsVersion::Tls13 => reqwest::tls::Version::TLS_1_3,
+            });
         }
-    }
 
-    pub async fn get(&self, url: Url) -> Result<impl axum::response::IntoResponse> {
-        if self.config.block_private {
-            check_private_network(&url).await?;
+        if config.insecure_skip_verify {
+            warn!(
+                "!!! Upstream TLS certificate verification is DISABLED (--insecure-skip-verify). \
+                 Proxied content can be intercepted or spoofed. Never use this in production. !!!"
+            );
+            builder = builder.danger_accept_invalid_certs(true);
         }
 
-        let response = self.client.get(url).send().await?;
+        let network_policy = NetworkPolicy::from_config(config).map_err(|e| {
+            io::Error::new(e.kind(), format!("Failed to load blocked networks: {}", e))
+        })?;
+
+        let resolver = UpstreamResolver::new(health.clone(), network_policy.clone());
+        builder = builder.dns_resolver(Arc::new(resolver));
+        if config.upstream_connect_timeout > 0 {
+            // Split across the addresses of a family, so one that never
+            // answers does not hold up the next
+            builder =
+                builder.connect_timeout(Duration::from_millis(config.upstream_connect_timeout));
+        }
 
-        // Check content type
-        let content_type = response
-            .headers()
-            .get(header::CONTENT_TYPE)
-            .and_then(|v| v.to_str().ok())
-            .unwrap_or("");
+        let client = builder
+            .build()
+            .map_err(|e| invalid(format!("Failed to create HTTP client: {}", e)))?;
 
-        if !self.is_allowed_content_type(content_type) {
-            return Err(CamoError::ContentTypeNotAllowed(content_type.to_string()));
-        }
+        Ok(Self {
+            client,
+            config: config.clone(),
+            filter: ResponseFilter::new(config),
+            network_policy,
+            health,
+        })
+    }
+
+    /// Send the GET request, retrying connection failures and 502/503 responses
+    async fn send_with_retries(&self, url: Url, headers: HeaderMap) -> Result<reqwest::Response> {
+        let mut attempt = 0;
 
-        // Check content length if present
-        if let Some(content_length) = response.content_length() {
-            if content_length > self.config.max_size {
-                return Err(CamoError::ContentTooLarge(content_length));
+        loop {
+            let result = self.client.get(url.clone()).headers(headers.clone()).send().await;
+            if let Some(health) = &self.health {
+                record_health(health, &result);
             }
-        }
 
-        // Build response headers
-        let mut headers = HeaderMap::new();
+            let retryable = match &result {
+                Ok(response) => is_retryable_status(response.status()),
+                Err(e) => is_retryable_error(e),
+            };
 
-        {
-            if let Some(ct) = response.headers().get(header::CONTENT_TYPE) {
-                headers.insert(header::CONTENT_TYPE, ct.clone());
-            }
-            if let Some(cl) = response.headers().get(header::CONTENT_LENGTH) {
-                headers.insert(header::CONTENT_LENGTH, cl.clone());
+            if !retryable || attempt >= self.config.upstream_retries {
+                return result.map_err(upstream_error);
             }
-            if let Some(cc) = response.headers().get(header::CACHE_CONTROL) {
-                headers.insert(header::CACHE_CONTROL, cc.clone());
+
+            attempt += 1;
+            metrics::counter!("camo_upstream_retries_total").increment(1);
+            tokio::time::sleep(retry_backoff(
+                Duration::from_millis(self.config.upstream_retry_backoff),
+                attempt,
+            ))
+            .await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl HttpClient for ReqwestClient {
+    async fn get(&self, url: Url, headers: HeaderMap) -> Result<UpstreamResponse> {
+        let connection = load::UPSTREAM_CONNECTIONS.hold();
+        let mut redirects = Redirects::new(&self.config);
+        let (mut url, mut headers) = (url, headers);
+        let response = loop {
+            // Each hop, for the hostnames on reputation lists and for IP
+            // literals, which never reach the resolver
+            self.network_policy.check(&url)?;
+            let response = self.send_with_retries(url.clone(), headers.clone()).await?;
+            match redirects.next(&url, response.status(), response.headers(), &mut headers)? {
+                Some(next) => url = next,
+                None => break response,
             }
-            if let Some(etag) = response.headers().get(header::ETAG) {
-                headers.insert(header::ETAG, etag.clone());
+        };
+        let status = response.status();
+        let mut upstream = response.headers().clone();
+
+        // Stream the response body, decoded first so the size limits and the
+        // headers describing the body are about what the client receives
+        let stream = Holding::new(response.bytes_stream(), connection);
+        let stream = decode_body(&mut upstream, stream)?;
+        let headers = self.filter.check(status, &upstream)?;
+        let body = Body::from_stream(self.filter.limit_body(stream, &headers));
+
+        Ok(UpstreamResponse { headers, body })
+    }
+}
+
+/// Tell `health` which address a connection was made to, or that none could be
+fn record_health(health: &AddressHealth, result: &reqwest::Result<reqwest::Response>) {
+    match result {
+        Ok(response) => {
+            if let (Some(host), Some(addr)) = (response.url().host_str(), response.remote_addr()) {
+                health.success(host, addr.ip());
             }
-            if let Some(lm) = response.headers().get(header::LAST_MODIFIED) {
-                headers.insert(header::LAST_MODIFIED, lm.clone());
+        }
+        Err(e) if e.is_connect() => {
+            if let Some(host) = e.url().and_then(|url| url.host_str()) {
+                health.failure(host);
             }
-            // Add security headers
-            headers.insert(
-                header::X_CONTENT_TYPE_OPTIONS,
-                HeaderValue::from_static("nosniff"),
-            );
-            headers.insert(
-                header::CONTENT_SECURITY_POLICY,
-                HeaderValue::from_static(
-                    "default-src 'none'; img-src data:; style-src 'unsafe-inline'",
-                ),
-            );
         }
-
-        // Stream the response body
-        let stream = response.bytes_stream();
-        let body = Body::from_stream(stream);
-
-        Ok((headers, body))
+        Err(_) => {}
     }
+}
 
-    fn is_allowed_content_type(&self, content_type: &str) -> bool {
-        let ct_lower = content_type.to_lowercase();
-        let mime_type = ct_lower.split(';').next().unwrap_or("").trim();
-
-        self.config
-            .allowed_content_types()
-            .iter()
-            .any(|allowed| *allowed == mime_type)
+/// Typed error for a failed request
+///
+/// Timeouts get their own variant, so they are answered with 504 for what
+/// they are and counted apart in the metrics. Oversized response heads are
+/// told apart too: hyper refuses HTTP/1 heads beyond its own limits before
+/// the [`ResponseFilter`] gets to see them, and so are addresses the
+/// [`UpstreamResolver`] refused.
+fn upstream_error(e: reqwest::Error) -> CamoError {
+    if e.is_timeout() {
+        return CamoError::Timeout;
     }
+    if let Some(refused) = refusal(&e) {
+        return refused;
+    }
+    let mut source = std::error::Error::source(&e);
+    while let Some(err) = source {
+        if let Some(hyper_err) = err.downcast_ref::<hyper::Error>()
+            && hyper_err.is_parse_too_large()
+        {
+            return CamoError::UpstreamHeadersTooLarge;
+        }
+        source = err.source();
+    }
+    CamoError::from(e)
 }
 
-#[inline]
-async fn check_private_network(url: &Url) -> Result<()> {
-    let host = url
-        .host_str()
-        .ok_or_else(|| CamoError::InvalidUrl("No host".into()))?;
-
-    // Try to resolve the hostname
-    let addrs: Vec<IpAddr> = tokio::net::lookup_host(format!(
-        "{}:{}",
-        host,
-        url.port_or_known_default().unwrap_or(80)
-    ))
-    .await
-    .map_err(|e| CamoError::InvalidUrl(e.to_string()))?
-    .map(|addr| addr.ip())
-    .collect();
-
-    for addr in addrs {
-        if is_private_ip(&addr) {
-            return Err(CamoError::PrivateNetworkNotAllowed);
+/// The [`NetworkPolicy`] error a connection failed with, if it was refused
+fn refusal(e: &reqwest::Error) -> Option<CamoError> {
+    let mut source = std::error::Error::source(e);
+    while let Some(err) = source {
+        match err.downcast_ref::<CamoError>() {
+            Some(CamoError::PrivateNetworkNotAllowed) => {
+                return Some(CamoError::PrivateNetworkNotAllowed);
+            }
+            Some(CamoError::NetworkNotAllowed) => return Some(CamoError::NetworkNotAllowed),
+            _ => source = err.source(),
         }
     }
-
-    Ok(())
+    None
 }
 
-#[inline]
-fn is_private_ip(ip: &IpAddr) -> bool {
-    match ip {
-        IpAddr::V4(ipv4) => {
-            ipv4.is_private()
-                || ipv4.is_loopback()
-                || ipv4.is_link_local()
-                || ipv4.is_broadcast()
-                || ipv4.is_documentation()
-                || ipv4.is_unspecified()
-                // 100.64.0.0/10 (Carrier-grade NAT)
-                || (ipv4.octets()[0] == 100 && (ipv4.octets()[1] & 0xC0) == 64)
+/// Connection failures and resets
+///
+/// Not timeouts, as retrying those multiplies latency, nor addresses the
+/// [`NetworkPolicy`] refused.
+fn is_retryable_error(e: &reqwest::Error) -> bool {
+    if e.is_timeout() || refusal(e).is_some() {
+        return false;
+    }
+    if e.is_connect() {
+        return true;
+    }
+
+    let mut source = std::error::Error::source(e);
+    while let Some(err) = source {
+        if let Some(io) = err.downcast_ref::<std::io::Error>()
+            && matches!(
+                io.kind(),
+                ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe
+            )
+        {
+            return true;
         }
-        IpAddr::V6(ipv6) => {
-            ipv6.is_loopback() || ipv6.is_unspecified()
-            // Could add more IPv6 private ranges here
+        if let Some(hyper) = err.downcast_ref::<hyper::Error>()
+            && hyper.is_incomplete_message()
+        {
+            return true;
         }
+        source = err.source();
     }
+
+    false
 }
@@ -0,0 +1,270 @@
+use super::super::cache::{Lookup, TtlCache};
+use super::super::config::Config;
+use super::super::network::NetworkPolicy;
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Hostnames and addresses tracked at most
+const CAPACITY: usize = 4096;
+
+/// Weight of the latest outcome in an address's failure rate
+const ALPHA: f64 = 0.5;
+
+/// Upstream addresses and how well connections to them have gone
+///
+/// Remembers a failure rate per address from the outcome of each
+/// connection, and hands out a hostname's addresses ordered from the
+/// healthiest, keeping the DNS order among equals. The connector tries
+/// them in that order, so a degraded CDN POP stops being the first choice
+/// after a failed connection. Rates are forgotten `ttl` after the last
+/// outcome, so addresses get another chance once they may have recovered.
+pub struct AddressHealth {
+    ttl: Duration,
+    /// Failure rate per address, between 0 (healthy) and 1
+    health: TtlCache<f64>,
+    /// Addresses last handed out per hostname, in the order they were tried
+    order: TtlCache<Vec<IpAddr>>,
+}
+
+impl AddressHealth {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            health: TtlCache::new(CAPACITY),
+            order: TtlCache::new(CAPACITY),
+        }
+    }
+
+    /// Returns `None` when `--upstream-health-ttl` is zero
+    pub fn from_config(config: &Config) -> Option<Self> {
+        (config.upstream_health_ttl > 0)
+            .then(|| Self::new(Duration::from_secs(config.upstream_health_ttl)))
+    }
+
+    /// Addresses with a failure on record, the least healthy first, with their failure rate
+    pub fn failing(&self) -> Vec<(IpAddr, f64)> {
+        let mut failing: Vec<(IpAddr, f64)> = self
+            .health
+            .fresh()
+            .into_iter()
+            .filter(|(_, rate)| *rate > 0.0)
+            .filter_map(|(ip, rate)| Some((ip.parse().ok()?, rate)))
+            .collect();
+        failing.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        failing
+    }
+
+    /// Addresses last handed out per hostname, in the order they were tried
+    pub fn orders(&self) -> Vec<(String, Vec<IpAddr>)> {
+        let mut orders = self.order.fresh();
+        orders.sort();
+        orders
+    }
+
+    fn failure_rate(&self, ip: &IpAddr) -> f64 {
+        match self.health.lookup(&ip.to_string()) {
+            Lookup::Fresh(rate) => rate,
+            Lookup::Stale | Lookup::Miss => 0.0,
+        }
+    }
+
+    fn update(&self, ip: &IpAddr, failed: bool) {
+        let outcome = if failed { 1.0 } else { 0.0 };
+        let rate = self.failure_rate(ip) * (1.0 - ALPHA) + outcome * ALPHA;
+        self.health.insert(&ip.to_string(), rate, self.ttl);
+    }
+
+    /// `addrs` ordered healthiest first, remembered as the order for `host`
+    fn arrange(&self, host: &str, mut addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        // Stable, so equally healthy addresses stay in DNS order
+        addrs.sort_by(|a, b| self.failure_rate(&a.ip()).total_cmp(&self.failure_rate(&b.ip())));
+        let order = addrs.iter().map(SocketAddr::ip).collect();
+        self.order.insert(host, order, self.ttl);
+        addrs
+    }
+
+    /// A connection to `host` succeeded on `ip`
+    ///
+    /// The addresses tried before it did not answer in time.
+    pub fn success(&self, host: &str, ip: IpAddr) {
+        let Lookup::Fresh(order) = self.order.lookup(host) else {
+            return;
+        };
+        let Some(position) = order.iter().position(|tried| *tried == ip) else {
+            return;
+        };
+        for tried in &order[..position] {
+            self.update(tried, true);
+        }
+        self.update(&ip, false);
+    }
+
+    /// No connection to any address of `host` could be made
+    pub fn failure(&self, host: &str) {
+        if let Lookup::Fresh(order) = self.order.lookup(host) {
+            for tried in &order {
+                self.update(tried, true);
+            }
+        }
+    }
+}
+
+/// `addrs` with address families alternating, as RFC 8305 section 4 orders them
+///
+/// The family of the first address leads and the order within each family
+/// is kept, so the connector has an address of the other family to fall
+/// back to right after the first one.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let leading = first.is_ipv6();
+    let (lead, other): (Vec<_>, Vec<_>) =
+        addrs.into_iter().partition(|addr| addr.is_ipv6() == leading);
+    let (mut lead, mut other) = (lead.into_iter(), other.into_iter());
+    let mut out = Vec::new();
+    loop {
+        match (lead.next(), other.next()) {
+            (None, None) => return out,
+            (a, b) => out.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// DNS resolver for the upstream client
+///
+/// Hands out both address families, interleaved, so that the connector
+/// can race them: when the first family does not connect within 300ms it
+/// tries the other in parallel (Happy Eyeballs), instead of stalling on a
+/// broken IPv6 route. With `AddressHealth`, healthy addresses come first,
+/// which also makes a family that keeps failing lose the lead.
+///
+/// Names are resolved through the [`NetworkPolicy`], so every address is
+/// checked as the connection is made, and the addresses checked are the
+/// ones connected to: nothing can resolve elsewhere in between (DNS
+/// rebinding), and redirect targets are covered alike. Its DNS cache spares
+/// popular hosts a lookup. Resolution fails with the policy's [`CamoError`]
+/// when any address is refused.
+///
+/// [`CamoError`]: super::super::error::CamoError
+pub struct UpstreamResolver {
+    health: Option<Arc<AddressHealth>>,
+    policy: NetworkPolicy,
+}
+
+impl UpstreamResolver {
+    pub fn new(health: Option<Arc<AddressHealth>>, policy: NetworkPolicy) -> Self {
+        Self { health, policy }
+    }
+}
+
+impl Resolve for UpstreamResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let health = self.health.clone();
+        let policy = self.policy.clone();
+        Box::pin(async move {
+            let host = name.as_str();
+            let mut addrs: Vec<SocketAddr> = policy
+                .resolve(host)
+                .await?
+                .into_iter()
+                .map(|ip| SocketAddr::new(ip, 0))
+                .collect();
+            if let Some(health) = &health {
+                addrs = health.arrange(host, addrs);
+            }
+            let addrs: Addrs = Box::new(interleave(addrs).into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::{config::Config, error::CamoError};
+    use super::*;
+    use clap::Parser;
+
+    fn addrs(ips: &[&str]) -> Vec<SocketAddr> {
+        ips.iter().map(|ip| SocketAddr::new(ip.parse().unwrap(), 0)).collect()
+    }
+
+    fn ips(addrs: &[SocketAddr]) -> Vec<String> {
+        addrs.iter().map(|addr| addr.ip().to_string()).collect()
+    }
+
+    #[test]
+    fn test_prefers_healthy_addresses() {
+        let health = AddressHealth::new(Duration::from_secs(60));
+        let dns = addrs(&["192.0.2.1", "192.0.2.2", "192.0.2.3"]);
+
+        let first = health.arrange("cdn.example", dns.clone());
+        assert_eq!(ips(&first), ["192.0.2.1", "192.0.2.2", "192.0.2.3"]);
+
+        // The first address timed out and the second one answered
+        health.success("cdn.example", "192.0.2.2".parse().unwrap());
+        let second = health.arrange("cdn.example", dns.clone());
+        assert_eq!(ips(&second), ["192.0.2.2", "192.0.2.3", "192.0.2.1"]);
+
+        // Nothing answered at all
+        health.failure("cdn.example");
+        let third = health.arrange("cdn.example", dns.clone());
+        assert_eq!(ips(&third), ["192.0.2.2", "192.0.2.3", "192.0.2.1"]);
+
+        // Then only the third address answered
+        health.failure("cdn.example");
+        health.success("cdn.example", "192.0.2.3".parse().unwrap());
+        let fourth = health.arrange("cdn.example", dns.clone());
+        assert_eq!(ips(&fourth), ["192.0.2.3", "192.0.2.1", "192.0.2.2"]);
+
+        // Addresses of other hosts are not affected
+        health.success("other.example", "192.0.2.9".parse().unwrap());
+        assert_eq!(health.failure_rate(&"192.0.2.9".parse().unwrap()), 0.0);
+
+        let failing: Vec<_> = health.failing().into_iter().map(|(ip, _)| ip.to_string()).collect();
+        assert_eq!(failing, ["192.0.2.1", "192.0.2.2", "192.0.2.3"]);
+        let (host, order) = &health.orders()[0];
+        assert_eq!((host.as_str(), order.len()), ("cdn.example", 3));
+    }
+
+    #[test]
+    fn test_failures_are_forgotten() {
+        let health = AddressHealth::new(Duration::ZERO);
+        let dns = addrs(&["192.0.2.1", "192.0.2.2"]);
+
+        health.arrange("cdn.example", dns.clone());
+        health.failure("cdn.example");
+        assert_eq!(ips(&health.arrange("cdn.example", dns)), ["192.0.2.1", "192.0.2.2"]);
+    }
+
+    #[tokio::test]
+    async fn test_checks_addresses() {
+        let localhost = || "localhost".parse().unwrap();
+        let resolve = |policy| UpstreamResolver::new(None, policy).resolve(localhost());
+        assert!(resolve(NetworkPolicy::default()).await.is_ok());
+
+        let config = Config::parse_from(["camo", "--key", "k"]);
+        let e = resolve(NetworkPolicy::from_config(&config).unwrap()).await.err().unwrap();
+        assert!(matches!(e.downcast_ref(), Some(CamoError::PrivateNetworkNotAllowed)));
+    }
+
+    #[test]
+    fn test_interleave() {
+        let dns = addrs(&["2001:db8::1", "2001:db8::2", "192.0.2.1", "192.0.2.2", "192.0.2.3"]);
+        assert_eq!(
+            ips(&interleave(dns)),
+            ["2001:db8::1", "192.0.2.1", "2001:db8::2", "192.0.2.2", "192.0.2.3"]
+        );
+
+        // IPv4 leads when it comes first, e.g. after IPv6 kept failing
+        let dns = addrs(&["192.0.2.1", "2001:db8::1", "192.0.2.2"]);
+        assert_eq!(ips(&interleave(dns)), ["192.0.2.1", "2001:db8::1", "192.0.2.2"]);
+
+        let dns = addrs(&["192.0.2.1", "192.0.2.2"]);
+        assert_eq!(ips(&interleave(dns)), ["192.0.2.1", "192.0.2.2"]);
+        assert!(interleave(Vec::new()).is_empty());
+    }
+}
@@ -0,0 +1,672 @@
+use super::super::{
+    config::{Config, SecurityHeaders},
+    content_types::{DOCUMENT_TYPES, FONT_TYPES},
+    error::{CamoError, Result},
+};
+use super::{CachePolicy, HeaderFilter};
+
+use axum::body::Bytes;
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use futures_core::Stream;
+use std::fmt::Display;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Sent on truncated responses, with the length upstream announced
+const TRUNCATED: HeaderName = HeaderName::from_static("x-camo-truncated");
+
+/// What happens to bodies over the hard size cap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OversizePolicy {
+    /// Failed with 413, or cut off mid-stream when already sending
+    #[default]
+    Reject,
+    /// Cut off at the cap, flagged with `X-Camo-Truncated`
+    Truncate,
+    /// The bytes up to the cap as 206 Partial Content, for video and audio previews
+    Partial,
+}
+
+impl OversizePolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "reject" => Some(OversizePolicy::Reject),
+            "truncate" => Some(OversizePolicy::Truncate),
+            "partial" => Some(OversizePolicy::Partial),
+            _ => None,
+        }
+    }
+}
+
+/// Upstream response policy shared by every [`HttpClient`](super::HttpClient) backend
+///
+/// Decides whether an upstream response may be proxied, which of its headers
+/// reach the client, and enforces the size limit while the body streams.
+///
+/// The size limits are soft when a larger `hard_max_size` is set: bodies
+/// between the two are served and logged, and only those over the hard cap
+/// are handled by the [`OversizePolicy`].
+#[derive(Clone)]
+pub struct ResponseFilter {
+    max_size: u64,
+    /// Limits per media family, overriding `max_size`
+    max_size_image: Option<u64>,
+    max_size_video: Option<u64>,
+    max_size_audio: Option<u64>,
+    /// Cap over all limits above; `None` makes them hard
+    hard_max_size: Option<u64>,
+    oversize: OversizePolicy,
+    /// Overrides `oversize` for video and audio
+    oversize_media: Option<OversizePolicy>,
+    max_header_size: usize,
+    max_header_count: usize,
+    allowed_content_types: Vec<String>,
+    header_filter: HeaderFilter,
+    cache_policy: CachePolicy,
+    security_headers: Vec<(HeaderName, HeaderValue)>,
+    /// Send fonts with `Access-Control-Allow-Origin: *`, when no CORS origins are configured
+    font_cors: bool,
+}
+
+impl ResponseFilter {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            max_size: config.max_size,
+            max_size_image: config.max_size_image,
+            max_size_video: config.max_size_video,
+            max_size_audio: config.max_size_audio,
+            hard_max_size: config.hard_max_size,
+            oversize: config.oversize_policy(),
+            oversize_media: config.oversize_strategy.as_deref().and_then(OversizePolicy::from_name),
+            max_header_size: config.max_header_size,
+            max_header_count: config.max_header_count,
+            allowed_content_types: config.allowed_content_types(),
+            header_filter: HeaderFilter::new(&config.strip_headers, config.disposition_policy()),
+            cache_policy: CachePolicy::new(
+                config.default_cache_control.as_deref(),
+                config.min_cache_ttl,
+            ),
+            security_headers: security_headers(&config.security_headers),
+            font_cors: config.cors_origin.is_empty(),
+        }
+    }
+
+    /// Validate the upstream status and headers, returning the headers to send to the client
+    pub fn check(&self, status: StatusCode, upstream: &HeaderMap) -> Result<HeaderMap> {
+        if upstream.len() > self.max_header_count || header_size(upstream) > self.max_header_size
+        {
+            return Err(CamoError::UpstreamHeadersTooLarge);
+        }
+
+        if !status.is_success() {
+            return Err(CamoError::UpstreamStatus(status));
+        }
+
+        let content_type = upstream
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        if !self.is_allowed_content_type(content_type) {
+            return Err(CamoError::ContentTypeNotAllowed(content_type.to_string()));
+        }
+
+        let hard_max_size = self.hard_max_size(content_type);
+        let oversize = self.oversize(content_type);
+        let content_length = content_length(upstream);
+        if let Some(content_length) = content_length
+            && content_length > hard_max_size
+            && oversize == OversizePolicy::Reject
+        {
+            return Err(CamoError::ContentTooLarge(content_length));
+        }
+
+        let mut headers = self.response_headers(upstream);
+        if let Some(content_length) = content_length
+            && content_length > hard_max_size
+        {
+            headers.insert(header::CONTENT_LENGTH, hard_max_size.into());
+            // Only the first range is ever served
+            headers.remove(header::ACCEPT_RANGES);
+            if oversize == OversizePolicy::Partial {
+                let range = format!("bytes 0-{}/{}", hard_max_size - 1, content_length);
+                if let Ok(range) = HeaderValue::from_str(&range) {
+                    headers.insert(header::CONTENT_RANGE, range);
+                }
+            } else {
+                // The body stops short of what upstream sent, so its validators no longer hold
+                headers.insert(TRUNCATED, content_length.into());
+                headers.remove(header::ETAG);
+                headers.remove(header::LAST_MODIFIED);
+            }
+        }
+        Ok(headers)
+    }
+
+    pub fn is_allowed_content_type(&self, content_type: &str) -> bool {
+        let ct_lower = content_type.to_lowercase();
+        let mime_type = ct_lower.split(';').next().unwrap_or("").trim();
+
+        self.allowed_content_types.iter().any(|t| t == mime_type)
+    }
+
+    /// Size limit for a content type: that of its media family, or `max_size`
+    pub fn max_size(&self, content_type: &str) -> u64 {
+        let ct_lower = content_type.trim_start().to_lowercase();
+        let family = if ct_lower.starts_with("image/") {
+            self.max_size_image
+        } else if ct_lower.starts_with("video/") {
+            self.max_size_video
+        } else if ct_lower.starts_with("audio/") {
+            self.max_size_audio
+        } else {
+            None
+        };
+        family.unwrap_or(self.max_size)
+    }
+
+    /// What to do with a body of a content type over its hard cap
+    pub fn oversize(&self, content_type: &str) -> OversizePolicy {
+        let ct_lower = content_type.trim_start().to_lowercase();
+        match self.oversize_media {
+            Some(policy) if ct_lower.starts_with("video/") || ct_lower.starts_with("audio/") => {
+                policy
+            }
+            _ => self.oversize,
+        }
+    }
+
+    /// Size cap for a content type, never below its [`max_size`](Self::max_size)
+    pub fn hard_max_size(&self, content_type: &str) -> u64 {
+        let max_size = self.max_size(content_type);
+        self.hard_max_size.map_or(max_size, |hard| hard.max(max_size))
+    }
+
+    /// Wrap an upstream body stream so it fails, or ends, once it exceeds the size cap
+    ///
+    /// `headers` are the ones [`check`](Self::check) returned, whose content
+    /// type selects the limits. Bodies are only cut short where those
+    /// headers announce it: one without a `Content-Length` that turns out
+    /// too large fails mid-stream, as with [`OversizePolicy::Reject`],
+    /// rather than passing for the whole of it.
+    pub fn limit_body<S>(&self, stream: S, headers: &HeaderMap) -> SizeLimitedStream<S> {
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        let max_size = self.hard_max_size(content_type);
+        let truncate = match self.oversize(content_type) {
+            OversizePolicy::Reject => false,
+            OversizePolicy::Truncate => headers.contains_key(TRUNCATED),
+            // Not just any upstream range, the one `check` set
+            OversizePolicy::Partial => {
+                headers.contains_key(header::CONTENT_RANGE)
+                    && content_length(headers) == Some(max_size)
+            }
+        };
+        SizeLimitedStream {
+            inner: stream,
+            received: 0,
+            soft_max_size: self.max_size(content_type),
+            max_size,
+            limit: "hard",
+            truncate,
+            done: false,
+        }
+    }
+
+    fn response_headers(&self, upstream: &HeaderMap) -> HeaderMap {
+        let mut headers = self.header_filter.filter(upstream);
+        self.cache_policy.apply(&mut headers);
+
+        for (name, value) in &self.security_headers {
+            headers.insert(name.clone(), value.clone());
+        }
+        self.profile_headers(&mut headers);
+
+        headers
+    }
+
+    /// Headers some content types need on top of, or instead of, the image defaults
+    ///
+    /// Fonts are fetched in CORS mode, so cross-origin pages can only use
+    /// them with `Access-Control-Allow-Origin` and a cross-origin CORP.
+    /// Documents may carry scripts, so their CSP gains `sandbox`.
+    fn profile_headers(&self, headers: &mut HeaderMap) {
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_lowercase();
+        let mime_type = content_type.split(';').next().unwrap_or("").trim();
+
+        if FONT_TYPES.contains(&mime_type) {
+            headers.insert(
+                HeaderName::from_static("cross-origin-resource-policy"),
+                HeaderValue::from_static("cross-origin"),
+            );
+            if self.font_cors {
+                headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+            }
+        } else if DOCUMENT_TYPES.contains(&mime_type) {
+            let csp = match headers.get(header::CONTENT_SECURITY_POLICY) {
+                Some(csp) => format!("{}; sandbox", csp.to_str().unwrap_or_default()),
+                None => "sandbox".to_string(),
+            };
+            if let Ok(csp) = HeaderValue::from_str(&csp) {
+                headers.insert(header::CONTENT_SECURITY_POLICY, csp);
+            }
+        }
+    }
+}
+
+/// Configured security headers; empty and invalid values are left out
+fn security_headers(config: &SecurityHeaders) -> Vec<(HeaderName, HeaderValue)> {
+    [
+        (header::CONTENT_SECURITY_POLICY, &config.content_security_policy),
+        (header::X_CONTENT_TYPE_OPTIONS, &config.x_content_type_options),
+        (header::X_FRAME_OPTIONS, &config.x_frame_options),
+        (header::REFERRER_POLICY, &config.referrer_policy),
+        (
+            HeaderName::from_static("cross-origin-resource-policy"),
+            &config.cross_origin_resource_policy,
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, value)| !value.trim().is_empty())
+    .filter_map(|(name, value)| Some((name, HeaderValue::from_str(value.trim()).ok()?)))
+    .collect()
+}
+
+/// Bytes of header names and values
+fn header_size(headers: &HeaderMap) -> usize {
+    headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len())
+        .sum()
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
+
+/// Log a body going over a size limit, and count it in `camo_oversize_total{limit}`
+#[cfg(feature = "server")]
+fn log_oversize(limit: &'static str, max_size: u64) {
+    tracing::warn!(limit, max_size, "Response body over size limit");
+    metrics::counter!("camo_oversize_total", "limit" => limit).increment(1);
+}
+
+#[cfg(not(feature = "server"))]
+fn log_oversize(_limit: &'static str, _max_size: u64) {}
+
+/// Body stream that fails, or ends when truncating, once more than `max_size` bytes arrived
+///
+/// Catches bodies that lie about, or omit, their `Content-Length`. Going
+/// over `soft_max_size` is only logged.
+#[pin_project::pin_project]
+pub struct SizeLimitedStream<S> {
+    #[pin]
+    inner: S,
+    received: u64,
+    soft_max_size: u64,
+    max_size: u64,
+    /// Label of `max_size` in `camo_oversize_total`
+    limit: &'static str,
+    truncate: bool,
+    done: bool,
+}
+
+impl<S> SizeLimitedStream<S> {
+    /// Fail once more than `max_size` bytes arrived, for a link's own size limit
+    pub fn new(stream: S, max_size: u64) -> Self {
+        Self {
+            inner: stream,
+            received: 0,
+            soft_max_size: max_size,
+            max_size,
+            limit: "link",
+            truncate: false,
+            done: false,
+        }
+    }
+}
+
+impl<S, B, E> Stream for SizeLimitedStream<S>
+where
+    S: Stream<Item = std::result::Result<B, E>>,
+    B: Into<Bytes>,
+    E: Display,
+{
+    type Item = Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        if *this.done {
+            return Poll::Ready(None);
+        }
+        let chunk: Bytes = match this.inner.poll_next(cx) {
+            Poll::Ready(Some(Ok(chunk))) => chunk.into(),
+            Poll::Ready(Some(Err(e))) => {
+                return Poll::Ready(Some(Err(CamoError::Upstream(e.to_string()))));
+            }
+            Poll::Ready(None) => return Poll::Ready(None),
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let before = *this.received;
+        *this.received += chunk.len() as u64;
+        if *this.received > *this.max_size {
+            log_oversize(this.limit, *this.max_size);
+            if !*this.truncate {
+                return Poll::Ready(Some(Err(CamoError::ContentTooLarge(*this.received))));
+            }
+            *this.done = true;
+            let chunk = chunk.slice(..(*this.max_size - before) as usize);
+            return Poll::Ready(Some(Ok(chunk)));
+        }
+        if before <= *this.soft_max_size && *this.received > *this.soft_max_size {
+            log_oversize("soft", *this.soft_max_size);
+        }
+
+        Poll::Ready(Some(Ok(chunk)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter() -> ResponseFilter {
+        ResponseFilter {
+            max_size: 1024,
+            max_size_image: None,
+            max_size_video: Some(4096),
+            max_size_audio: None,
+            hard_max_size: None,
+            oversize: OversizePolicy::Reject,
+            oversize_media: None,
+            max_header_size: 1024,
+            max_header_count: 10,
+            allowed_content_types: vec!["image/png".into(), "image/gif".into()],
+            header_filter: HeaderFilter::default(),
+            cache_policy: CachePolicy::default(),
+            security_headers: security_headers(&SecurityHeaders::default()),
+            font_cors: true,
+        }
+    }
+
+    fn upstream(content_type: &str, content_length: Option<u64>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+        if let Some(len) = content_length {
+            headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+        }
+        headers.insert(header::SET_COOKIE, "session=1".parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_check_accepts_allowed_response() {
+        let headers = filter()
+            .check(StatusCode::OK, &upstream("image/PNG; charset=binary", Some(10)))
+            .unwrap();
+
+        assert_eq!(headers[header::CONTENT_LENGTH], "10");
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(headers[header::X_FRAME_OPTIONS], "deny");
+        assert_eq!(headers["cross-origin-resource-policy"], "cross-origin");
+        assert!(headers.contains_key(header::CONTENT_SECURITY_POLICY));
+        assert!(!headers.contains_key(header::SET_COOKIE));
+    }
+
+    #[test]
+    fn test_security_headers() {
+        let config = SecurityHeaders {
+            x_frame_options: String::new(),
+            referrer_policy: "same-origin".into(),
+            cross_origin_resource_policy: "bad\nvalue".into(),
+            ..SecurityHeaders::default()
+        };
+        let headers = ResponseFilter {
+            security_headers: security_headers(&config),
+            ..filter()
+        }
+        .check(StatusCode::OK, &upstream("image/png", None))
+        .unwrap();
+
+        assert_eq!(headers[header::REFERRER_POLICY], "same-origin");
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert!(!headers.contains_key(header::X_FRAME_OPTIONS));
+        assert!(!headers.contains_key("cross-origin-resource-policy"));
+    }
+
+    #[test]
+    fn test_profile_headers() {
+        let filter = ResponseFilter {
+            allowed_content_types: vec!["font/woff2".into(), "application/pdf".into()],
+            security_headers: security_headers(&SecurityHeaders {
+                cross_origin_resource_policy: "same-site".into(),
+                ..SecurityHeaders::default()
+            }),
+            ..filter()
+        };
+
+        let headers = filter.check(StatusCode::OK, &upstream("font/woff2", None)).unwrap();
+        assert_eq!(headers["cross-origin-resource-policy"], "cross-origin");
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+
+        let headers = filter.check(StatusCode::OK, &upstream("application/pdf", None)).unwrap();
+        let csp = headers[header::CONTENT_SECURITY_POLICY].to_str().unwrap();
+        assert!(csp.starts_with("default-src 'none'") && csp.ends_with("; sandbox"), "{}", csp);
+        assert_eq!(headers["cross-origin-resource-policy"], "same-site");
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let filter = ResponseFilter { font_cors: false, ..filter };
+        let headers = filter.check(StatusCode::OK, &upstream("font/woff2", None)).unwrap();
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[test]
+    fn test_check_rejects() {
+        let filter = filter();
+
+        assert!(matches!(
+            filter.check(StatusCode::NOT_FOUND, &upstream("image/png", None)),
+            Err(CamoError::UpstreamStatus(StatusCode::NOT_FOUND))
+        ));
+        assert!(matches!(
+            filter.check(StatusCode::OK, &upstream("text/html", None)),
+            Err(CamoError::ContentTypeNotAllowed(_))
+        ));
+        assert!(matches!(
+            filter.check(StatusCode::OK, &upstream("image/png", Some(2048))),
+            Err(CamoError::ContentTooLarge(2048))
+        ));
+    }
+
+    #[test]
+    fn test_check_rejects_large_headers() {
+        let filter = filter();
+
+        let mut many = upstream("image/png", None);
+        for i in 0..10 {
+            many.insert(HeaderName::try_from(format!("x-h{}", i)).unwrap(), "1".parse().unwrap());
+        }
+        assert!(matches!(
+            filter.check(StatusCode::OK, &many),
+            Err(CamoError::UpstreamHeadersTooLarge)
+        ));
+
+        let mut large = upstream("image/png", None);
+        large.insert("x-large", "a".repeat(1000).parse().unwrap());
+        assert!(matches!(
+            filter.check(StatusCode::OK, &large),
+            Err(CamoError::UpstreamHeadersTooLarge)
+        ));
+
+        // Values of a repeated header all count
+        let mut repeated = upstream("image/png", None);
+        for _ in 0..4 {
+            repeated.append(header::SET_COOKIE, "a".repeat(300).parse().unwrap());
+        }
+        assert!(filter.check(StatusCode::OK, &repeated).is_err());
+    }
+
+    #[test]
+    fn test_max_size_per_family() {
+        let filter = ResponseFilter {
+            allowed_content_types: ["image/png", "video/mp4", "audio/mpeg"].map(Into::into).into(),
+            ..filter()
+        };
+
+        assert_eq!(filter.max_size("image/png"), 1024);
+        assert_eq!(filter.max_size("Video/MP4; codecs=avc1"), 4096);
+        assert_eq!(filter.max_size("audio/mpeg"), 1024);
+        assert!(filter.check(StatusCode::OK, &upstream("video/mp4", Some(2048))).is_ok());
+        assert!(matches!(
+            filter.check(StatusCode::OK, &upstream("video/mp4", Some(8192))),
+            Err(CamoError::ContentTooLarge(8192))
+        ));
+
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+        let chunks = [Ok::<_, CamoError>(vec![0u8; 2048])];
+        let headers = upstream("video/mp4", None);
+        let mut stream = Box::pin(filter.limit_body(Iter(chunks.into_iter()), &headers));
+        assert!(matches!(stream.as_mut().poll_next(&mut cx), Poll::Ready(Some(Ok(_)))));
+    }
+
+    #[test]
+    fn test_limit_body() {
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+        let chunks = [Ok::<_, CamoError>(vec![0u8; 600]), Ok(vec![0u8; 600])];
+        let headers = upstream("image/png", None);
+        let mut stream = Box::pin(filter().limit_body(Iter(chunks.into_iter()), &headers));
+
+        match stream.as_mut().poll_next(&mut cx) {
+            Poll::Ready(Some(Ok(chunk))) => assert_eq!(chunk.len(), 600),
+            _ => panic!("expected the first chunk"),
+        }
+        assert!(matches!(
+            stream.as_mut().poll_next(&mut cx),
+            Poll::Ready(Some(Err(CamoError::ContentTooLarge(1200))))
+        ));
+    }
+
+    #[test]
+    fn test_soft_and_hard_limits() {
+        let filter = ResponseFilter {
+            hard_max_size: Some(2048),
+            ..filter()
+        };
+        assert_eq!(filter.hard_max_size("image/png"), 2048);
+        // Never below a larger family limit
+        assert_eq!(filter.hard_max_size("video/mp4"), 4096);
+
+        let headers = filter.check(StatusCode::OK, &upstream("image/png", Some(2000))).unwrap();
+        assert_eq!(headers[header::CONTENT_LENGTH], "2000");
+        assert!(!headers.contains_key(TRUNCATED));
+        assert!(matches!(
+            filter.check(StatusCode::OK, &upstream("image/png", Some(3000))),
+            Err(CamoError::ContentTooLarge(3000))
+        ));
+
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+        let chunks = [Ok::<_, CamoError>(vec![0u8; 1500]), Ok(vec![0u8; 1500])];
+        let headers = upstream("image/png", None);
+        let mut stream = Box::pin(filter.limit_body(Iter(chunks.into_iter()), &headers));
+        assert!(matches!(stream.as_mut().poll_next(&mut cx), Poll::Ready(Some(Ok(_)))));
+        assert!(matches!(
+            stream.as_mut().poll_next(&mut cx),
+            Poll::Ready(Some(Err(CamoError::ContentTooLarge(3000))))
+        ));
+    }
+
+    #[test]
+    fn test_truncate() {
+        let filter = ResponseFilter {
+            hard_max_size: Some(2048),
+            oversize: OversizePolicy::Truncate,
+            ..filter()
+        };
+
+        let mut big = upstream("image/png", Some(5000));
+        big.insert(header::ETAG, "\"abc\"".parse().unwrap());
+        let headers = filter.check(StatusCode::OK, &big).unwrap();
+        assert_eq!(headers[header::CONTENT_LENGTH], "2048");
+        assert_eq!(headers[TRUNCATED], "5000");
+        assert!(!headers.contains_key(header::ETAG));
+
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+        let chunks = [Ok::<_, CamoError>(vec![0u8; 1500]), Ok(vec![0u8; 1500]), Ok(vec![0u8; 10])];
+        let mut stream = Box::pin(filter.limit_body(Iter(chunks.into_iter()), &headers));
+        let mut received = 0;
+        while let Poll::Ready(Some(chunk)) = stream.as_mut().poll_next(&mut cx) {
+            received += chunk.unwrap().len();
+        }
+        assert_eq!(received, 2048);
+
+        // Without a Content-Length, nothing told the client it would be cut short
+        let headers = filter.check(StatusCode::OK, &upstream("image/png", None)).unwrap();
+        assert!(!headers.contains_key(TRUNCATED));
+        let chunks = [Ok::<_, CamoError>(vec![0u8; 1500]), Ok(vec![0u8; 1500])];
+        let mut stream = Box::pin(filter.limit_body(Iter(chunks.into_iter()), &headers));
+        assert!(matches!(stream.as_mut().poll_next(&mut cx), Poll::Ready(Some(Ok(_)))));
+        assert!(matches!(
+            stream.as_mut().poll_next(&mut cx),
+            Poll::Ready(Some(Err(CamoError::ContentTooLarge(3000))))
+        ));
+    }
+
+    #[test]
+    fn test_partial_media() {
+        let filter = ResponseFilter {
+            allowed_content_types: ["image/png", "video/mp4"].map(Into::into).into(),
+            oversize_media: Some(OversizePolicy::Partial),
+            ..filter()
+        };
+        assert_eq!(filter.oversize("Video/MP4"), OversizePolicy::Partial);
+        assert_eq!(filter.oversize("image/png"), OversizePolicy::Reject);
+
+        let mut video = upstream("video/mp4", Some(10000));
+        video.insert(header::ETAG, "\"abc\"".parse().unwrap());
+        video.insert(header::ACCEPT_RANGES, "bytes".parse().unwrap());
+        let headers = filter.check(StatusCode::OK, &video).unwrap();
+        assert_eq!(headers[header::CONTENT_LENGTH], "4096");
+        assert_eq!(headers[header::CONTENT_RANGE], "bytes 0-4095/10000");
+        // Still the validator of the whole video the range is part of
+        assert_eq!(headers[header::ETAG], "\"abc\"");
+        assert!(!headers.contains_key(header::ACCEPT_RANGES));
+        assert!(!headers.contains_key(TRUNCATED));
+
+        assert!(matches!(
+            filter.check(StatusCode::OK, &upstream("image/png", Some(2048))),
+            Err(CamoError::ContentTooLarge(2048))
+        ));
+
+        // Without a Content-Length there is no range to announce, so no cut either
+        let headers = filter.check(StatusCode::OK, &upstream("video/mp4", None)).unwrap();
+        assert!(!headers.contains_key(header::CONTENT_RANGE));
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+        let chunks = [Ok::<_, CamoError>(vec![0u8; 3000]), Ok(vec![0u8; 3000])];
+        let mut stream = Box::pin(filter.limit_body(Iter(chunks.into_iter()), &headers));
+        assert!(matches!(stream.as_mut().poll_next(&mut cx), Poll::Ready(Some(Ok(_)))));
+        assert!(matches!(
+            stream.as_mut().poll_next(&mut cx),
+            Poll::Ready(Some(Err(CamoError::ContentTooLarge(6000))))
+        ));
+    }
+
+    /// Minimal stream over an iterator
+    struct Iter<I>(I);
+
+    impl<I: Iterator + Unpin> Stream for Iter<I> {
+        type Item = I::Item;
+
+        fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<I::Item>> {
+            Poll::Ready(self.0.next())
+        }
+    }
+}
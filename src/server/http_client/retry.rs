@@ -0,0 +1,58 @@
+use axum::http::StatusCode;
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::time::Duration;
+
+/// Longest delay between two attempts
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Upstream statuses worth another attempt
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::BAD_GATEWAY || status == StatusCode::SERVICE_UNAVAILABLE
+}
+
+/// Exponential backoff with jitter: a random delay in `[d/2, d)` where `d = base * 2^(attempt - 1)`
+pub fn retry_backoff(base: Duration, attempt: u32) -> Duration {
+    let delay = base
+        .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+        .min(MAX_RETRY_BACKOFF);
+    let half = delay / 2;
+
+    // RandomState is seeded randomly, which is plenty for spreading retries
+    let random = RandomState::new().build_hasher().finish();
+    let jitter = half.as_nanos() as u64;
+    let jitter = if jitter == 0 { 0 } else { random % jitter };
+
+    half + Duration::from_nanos(jitter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_backoff_bounds() {
+        let base = Duration::from_millis(100);
+
+        for _ in 0..100 {
+            let first = retry_backoff(base, 1);
+            assert!(first >= Duration::from_millis(50) && first < Duration::from_millis(100));
+
+            let third = retry_backoff(base, 3);
+            assert!(third >= Duration::from_millis(200) && third < Duration::from_millis(400));
+
+            let capped = retry_backoff(base, 30);
+            assert!(capped >= MAX_RETRY_BACKOFF / 2 && capped < MAX_RETRY_BACKOFF);
+        }
+
+        assert_eq!(retry_backoff(Duration::ZERO, 1), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_retryable_status() {
+        assert!(is_retryable_status(StatusCode::BAD_GATEWAY));
+        assert!(is_retryable_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!is_retryable_status(StatusCode::OK));
+        assert!(!is_retryable_status(StatusCode::NOT_FOUND));
+        assert!(!is_retryable_status(StatusCode::GATEWAY_TIMEOUT));
+    }
+}
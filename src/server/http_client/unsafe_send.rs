@@ -0,0 +1,29 @@
+use futures_core::Stream;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// A wrapper that marks a future or stream as Send.
+/// SAFETY: Only use in single-threaded environments like Cloudflare Workers
+/// and other WebAssembly runtimes.
+#[pin_project::pin_project]
+pub struct UnsafeSend<T>(#[pin] pub T);
+
+// SAFETY: WebAssembly runtimes are single-threaded, so this is safe
+unsafe impl<T> Send for UnsafeSend<T> {}
+
+impl<F: Future> Future for UnsafeSend<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.project().0.poll(cx)
+    }
+}
+
+impl<S: Stream> Stream for UnsafeSend<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.project().0.poll_next(cx)
+    }
+}
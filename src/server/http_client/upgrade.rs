@@ -0,0 +1,153 @@
+use super::super::error::{CamoError, Result};
+use super::{HttpClient, UpstreamResponse};
+
+use axum::http::HeaderMap;
+use std::sync::Arc;
+use url::Url;
+
+/// Fetches `http:` targets over HTTPS first, from `--upgrade-insecure-upstream`
+///
+/// The same host, port and path are tried with `https:`; only when that
+/// cannot be fetched at all, like a host not serving TLS or answering with
+/// an error status, is the original URL fetched instead. Responses refused
+/// by policy, like a disallowed content type, are final: the plain HTTP one
+/// would come from the same origin. With `fallback` off, as under
+/// `--https-only-upstream`, the upgraded attempt is the only one.
+pub struct UpgradingClient {
+    inner: Arc<dyn HttpClient>,
+    fallback: bool,
+}
+
+impl UpgradingClient {
+    pub fn new(inner: Arc<dyn HttpClient>, fallback: bool) -> Self {
+        Self { inner, fallback }
+    }
+}
+
+#[async_trait::async_trait]
+impl HttpClient for UpgradingClient {
+    async fn get(&self, url: Url, headers: HeaderMap) -> Result<UpstreamResponse> {
+        if url.scheme() != "http" {
+            return self.inner.get(url, headers).await;
+        }
+        let mut upgraded = url.clone();
+        // Cannot fail between the two special schemes
+        let _ = upgraded.set_scheme("https");
+
+        match self.inner.get(upgraded, headers.clone()).await {
+            Ok(response) => {
+                record("https");
+                Ok(response)
+            }
+            Err(e) if self.fallback && is_unreachable(&e) => {
+                #[cfg(feature = "server")]
+                tracing::debug!(url = %url, error = %e, "Falling back to plain HTTP");
+                record("fallback");
+                self.inner.get(url, headers).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Count an upgraded fetch in `camo_upstream_upgrades_total{result}`
+fn record(result: &'static str) {
+    #[cfg(feature = "server")]
+    metrics::counter!("camo_upstream_upgrades_total", "result" => result).increment(1);
+    #[cfg(not(feature = "server"))]
+    let _ = result;
+}
+
+/// Whether the HTTPS attempt failed in a way the plain HTTP URL may not
+///
+/// Timeouts are not retried: the whole timeout would be spent twice. A
+/// host dropping connections to port 443 is caught by
+/// `--upstream-connect-timeout` instead.
+fn is_unreachable(e: &CamoError) -> bool {
+    match e {
+        CamoError::Upstream(_)
+        | CamoError::UpstreamStatus(_)
+        | CamoError::TooManyRedirects
+        | CamoError::InsecureRedirect => true,
+        #[cfg(feature = "server")]
+        CamoError::ReqwestError(_) => true,
+        _ => false,
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use std::sync::Mutex;
+
+    /// Answers HTTPS requests with `https`, and records every URL asked for
+    struct Upstream {
+        https: fn() -> Result<()>,
+        requested: Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl HttpClient for Upstream {
+        async fn get(&self, url: Url, _headers: HeaderMap) -> Result<UpstreamResponse> {
+            self.requested.lock().unwrap().push(url.to_string());
+            if url.scheme() == "https" {
+                (self.https)()?;
+            }
+            Ok(UpstreamResponse {
+                headers: HeaderMap::new(),
+                body: Body::empty(),
+            })
+        }
+    }
+
+    async fn fetch(https: fn() -> Result<()>, fallback: bool, url: &str) -> (bool, Vec<String>) {
+        let upstream = Arc::new(Upstream {
+            https,
+            requested: Mutex::default(),
+        });
+        let client = UpgradingClient::new(upstream.clone(), fallback);
+        let ok = client.get(Url::parse(url).unwrap(), HeaderMap::new()).await.is_ok();
+        let requested = upstream.requested.lock().unwrap().clone();
+        (ok, requested)
+    }
+
+    #[tokio::test]
+    async fn test_upgrade() {
+        let url = "http://example.com/a.png";
+        let (ok, requested) = fetch(|| Ok(()), true, url).await;
+        assert!(ok);
+        assert_eq!(requested, ["https://example.com/a.png"]);
+
+        // Explicit ports are kept, default ones follow the scheme
+        let (_, requested) = fetch(|| Ok(()), true, "http://example.com:8080/a.png").await;
+        assert_eq!(requested, ["https://example.com:8080/a.png"]);
+        let (_, requested) = fetch(|| Ok(()), true, "http://example.com:80/a.png").await;
+        assert_eq!(requested, ["https://example.com/a.png"]);
+
+        // Anything else is passed through
+        let (_, requested) = fetch(|| Ok(()), true, "https://example.com/a.png").await;
+        assert_eq!(requested, ["https://example.com/a.png"]);
+    }
+
+    #[tokio::test]
+    async fn test_fallback() {
+        let url = "http://example.com/a.png";
+        let refused = || Err(CamoError::Upstream("connection refused".into()));
+        let (ok, requested) = fetch(refused, true, url).await;
+        assert!(ok);
+        assert_eq!(requested, ["https://example.com/a.png", "http://example.com/a.png"]);
+
+        let missing = || Err(CamoError::UpstreamStatus(StatusCode::NOT_FOUND));
+        assert_eq!(fetch(missing, true, url).await.1.len(), 2);
+
+        // Not without fallback, nor for what plain HTTP would not fix
+        let (ok, requested) = fetch(refused, false, url).await;
+        assert!(!ok);
+        assert_eq!(requested.len(), 1);
+        let too_large = || Err(CamoError::ContentTooLarge(1));
+        assert_eq!(fetch(too_large, true, url).await.1.len(), 1);
+        assert_eq!(fetch(|| Err(CamoError::Timeout), true, url).await.1.len(), 1);
+    }
+}
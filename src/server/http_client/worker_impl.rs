@@ -2,159 +2,143 @@ use super::super::{
     config::Config,
     error::{CamoError, Result},
 };
-use axum::http;
-use http::{HeaderMap, HeaderValue};
+use super::follow::Redirects;
+use super::retry::{is_retryable_status, retry_backoff};
+use super::unsafe_send::UnsafeSend;
+use super::{HttpClient, ResponseFilter, UpstreamResponse};
+
+use axum::body::Body;
+use axum::http::{HeaderMap, StatusCode};
 use std::future::Future;
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::time::Duration;
 use url::Url;
-use worker::{Fetch, Method, RequestInit};
-
-/// A wrapper that marks a future as Send.
-/// SAFETY: Only use in single-threaded environments like Cloudflare Workers.
-#[pin_project::pin_project]
-struct UnsafeSendFuture<F>(#[pin] F);
-
-// SAFETY: Cloudflare Workers are single-threaded, so this is safe
-unsafe impl<F> Send for UnsafeSendFuture<F> {}
-
-impl<F: Future> Future for UnsafeSendFuture<F> {
-    type Output = F::Output;
-
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        self.project().0.poll(cx)
-    }
-}
+use worker::wasm_bindgen_futures;
+use worker::{
+    AbortController, CfProperties, Delay, Fetch, Method, RequestInit, RequestRedirect,
+};
 
 #[derive(Clone)]
 pub struct WorkerFetchClient {
-    pub config: Config,
-}
-
-#[derive(Clone)]
-pub struct WorkerFetchResponse {
-    pub body: Vec<u8>,
-    pub headers: HeaderMap,
+    config: Config,
+    filter: ResponseFilter,
 }
 
 impl WorkerFetchClient {
     pub fn new(config: &Config) -> Self {
         Self {
             config: config.clone(),
+            filter: ResponseFilter::new(config),
         }
     }
 
-    /// Returns a Send-safe future for use with axum.
-    /// SAFETY: This is safe because Cloudflare Workers are single-threaded.
-    pub fn get(&self, url: Url) -> impl Future<Output = Result<WorkerFetchResponse>> + Send {
-        let config = self.config.clone();
-
-        UnsafeSendFuture(async move {
-            let mut init = RequestInit::new();
-            init.with_method(Method::Get);
-            let request = worker::Request::new_with_init(&url.to_string(), &init)
-                .map_err(|e| CamoError::InvalidUrl(e.to_string()))?;
-
-            let mut response = Fetch::Request(request)
-                .send()
-                .await
-                .map_err(|e| CamoError::Upstream(e.to_string()))?;
+    /// Cloudflare cache, Polish and Image Resizing settings for the upstream fetch
+    fn cf_properties(&self) -> CfProperties {
+        CfProperties {
+            cache_ttl: self.config.cf_cache_ttl,
+            cache_everything: Some(self.config.cf_cache_everything),
+            polish: self.config.cf_polish,
+            image: self.config.cf_image.clone(),
+            ..CfProperties::default()
+        }
+    }
 
-            // Check content type
-            let content_type = response
-                .headers()
-                .get("content-type")
-                .ok()
-                .flatten()
-                .unwrap_or_default();
+    /// Send the GET request, retrying failed fetches and 502/503 responses
+    async fn send_with_retries(&self, url: &Url, headers: &HeaderMap) -> Result<worker::Response> {
+        let mut attempt = 0;
 
-            if !config.is_allowed_content_type(&content_type) {
-                return Err(CamoError::ContentTypeNotAllowed(content_type.to_string()));
-            }
+        loop {
+            let result = self.send(url, headers).await;
 
-            // Check content length if present
-            if let Ok(Some(cl_str)) = response.headers().get("content-length") {
-                if let Ok(content_length) = cl_str.parse::<u64>() {
-                    if content_length > config.max_size {
-                        return Err(CamoError::ContentTooLarge(content_length));
-                    }
-                }
-            }
+            let retryable = match &result {
+                Ok(response) => StatusCode::from_u16(response.status_code())
+                    .is_ok_and(is_retryable_status),
+                Err(e) => matches!(e, CamoError::Upstream(_)),
+            };
 
-            // Extract headers before consuming response
-            let resp_content_type = response.headers().get("content-type").ok().flatten();
-            let resp_cache_control = response.headers().get("cache-control").ok().flatten();
-            let resp_etag = response.headers().get("etag").ok().flatten();
-            let resp_last_modified = response.headers().get("last-modified").ok().flatten();
-
-            // Get response body
-            let body = response
-                .bytes()
-                .await
-                .map_err(|e| CamoError::Upstream(e.to_string()))?;
-
-            // Check actual body size
-            if body.len() as u64 > config.max_size {
-                return Err(CamoError::ContentTooLarge(body.len() as u64));
+            if !retryable || attempt >= self.config.upstream_retries {
+                return result;
             }
 
-            // Build response headers using http::HeaderMap (Send-safe)
-            let mut headers = HeaderMap::new();
-
-            if let Some(ct) = resp_content_type {
-                if let Ok(v) = HeaderValue::from_str(&ct) {
-                    headers.insert(http::header::CONTENT_TYPE, v);
-                }
-            }
+            attempt += 1;
+            Delay::from(retry_backoff(
+                Duration::from_millis(self.config.upstream_retry_backoff),
+                attempt,
+            ))
+            .await;
+        }
+    }
 
-            if let Some(cc) = resp_cache_control {
-                if let Ok(v) = HeaderValue::from_str(&cc) {
-                    headers.insert(http::header::CACHE_CONTROL, v);
+    /// A single fetch, aborted once the timeout has passed
+    ///
+    /// Redirects are answered as they are, to be followed by [`Redirects`].
+    async fn send(&self, url: &Url, headers: &HeaderMap) -> Result<worker::Response> {
+        let mut init = RequestInit::new();
+        init.with_method(Method::Get)
+            .with_headers(headers.into())
+            .with_redirect(RequestRedirect::Manual)
+            .with_cf_properties(self.cf_properties());
+        let request = worker::Request::new_with_init(url.as_str(), &init)
+            .map_err(|e| CamoError::InvalidUrl(e.to_string()))?;
+
+        // Covers the body too: aborting the signal also aborts the stream
+        let controller = AbortController::default();
+        let signal = controller.signal();
+        let timeout = Duration::from_secs(self.config.timeout);
+        wasm_bindgen_futures::spawn_local(async move {
+            Delay::from(timeout).await;
+            controller.abort();
+        });
+
+        Fetch::Request(request)
+            .send_with_signal(&signal)
+            .await
+            .map_err(|e| {
+                if signal.aborted() {
+                    CamoError::Timeout
+                } else {
+                    CamoError::Upstream(e.to_string())
                 }
-            }
+            })
+    }
 
-            if let Some(etag) = resp_etag {
-                if let Ok(v) = HeaderValue::from_str(&etag) {
-                    headers.insert(http::header::ETAG, v);
-                }
+    async fn fetch(&self, url: Url, headers: HeaderMap) -> Result<UpstreamResponse> {
+        let mut redirects = Redirects::new(&self.config);
+        let (mut url, mut headers) = (url, headers);
+        let (status, upstream, mut response) = loop {
+            let response = self.send_with_retries(&url, &headers).await?;
+            let status = StatusCode::from_u16(response.status_code())
+                .map_err(|e| CamoError::Upstream(e.to_string()))?;
+            let upstream = HeaderMap::from(response.headers());
+            match redirects.next(&url, status, &upstream, &mut headers)? {
+                Some(next) => url = next,
+                None => break (status, upstream, response),
             }
+        };
+        let headers = self.filter.check(status, &upstream)?;
 
-            if let Some(lm) = resp_last_modified {
-                if let Ok(v) = HeaderValue::from_str(&lm) {
-                    headers.insert(http::header::LAST_MODIFIED, v);
-                }
-            }
+        // Stream the response body, enforcing the size limit as chunks arrive
+        let stream = response
+            .stream()
+            .map_err(|e| CamoError::Upstream(e.to_string()))?;
+        let body = Body::from_stream(self.filter.limit_body(UnsafeSend(stream), &headers));
 
-            // Add security headers
-            headers.insert(
-                http::header::X_CONTENT_TYPE_OPTIONS,
-                HeaderValue::from_static("nosniff"),
-            );
-            headers.insert(
-                http::header::CONTENT_SECURITY_POLICY,
-                HeaderValue::from_static(
-                    "default-src 'none'; img-src data:; style-src 'unsafe-inline'",
-                ),
-            );
-            headers.insert(
-                http::header::CONTENT_LENGTH,
-                HeaderValue::from_str(&body.len().to_string()).unwrap(),
-            );
-
-            Ok(WorkerFetchResponse { body, headers })
-        })
+        Ok(UpstreamResponse { headers, body })
     }
 }
 
-impl axum::response::IntoResponse for WorkerFetchResponse {
-    fn into_response(self) -> axum::http::Response<axum::body::Body> {
-        let mut response = axum::http::Response::builder()
-            .status(200)
-            .body(axum::body::Body::from(self.body))
-            .unwrap();
-
-        *response.headers_mut() = self.headers;
-
-        response
+impl HttpClient for WorkerFetchClient {
+    /// Returns a Send-safe future for use with axum.
+    /// SAFETY: This is safe because Cloudflare Workers are single-threaded.
+    fn get<'a, 'async_trait>(
+        &'a self,
+        url: Url,
+        headers: HeaderMap,
+    ) -> Pin<Box<dyn Future<Output = Result<UpstreamResponse>> + Send + 'async_trait>>
+    where
+        'a: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(UnsafeSend(self.fetch(url, headers)))
     }
 }
@@ -0,0 +1,51 @@
+//! AWS Lambda entrypoint behind API Gateway, Function URLs or an ALB
+//!
+//! Lambda invokes the function once per request, so instead of binding a
+//! listener the router is handed to the Lambda runtime. Images and other
+//! non-text bodies go back base64-encoded, which API Gateway and Function
+//! URLs decode again before answering the client.
+
+use axum::Router;
+
+/// Serve `app` to Lambda invocations until the runtime shuts the function down
+pub async fn run(app: Router) -> Result<(), lambda_http::Error> {
+    lambda_http::run(app).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::config::Config;
+    use crate::server::router::{create_router, AppState};
+    use axum::http::Request;
+    use clap::Parser;
+    use lambda_http::{Body, IntoResponse};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    async fn invoke(uri: &str) -> lambda_http::Response<Body> {
+        let config =
+            Config::parse_from(["camo", "--key", "secret", "--allowed-schemes", "data"]);
+        let router = create_router(Arc::new(AppState::from_config(&config).unwrap())).unwrap();
+
+        let request = Request::get(uri).body(Body::Empty).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        response.into_response().await
+    }
+
+    #[tokio::test]
+    async fn test_text_body() {
+        let response = invoke("/health").await;
+        assert_eq!(response.body(), &Body::Text("OK".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_binary_body() {
+        let png = "data:image/png;base64,iVBORw0KGgo=";
+        let digest = crate::utils::crypto::generate_digest("secret", png);
+        let uri = format!("/{}/{}", digest, crate::utils::encoding::encode_url_hex(png));
+
+        let response = invoke(&uri).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body(), &Body::Binary(b"\x89PNG\r\n\x1a\n".to_vec()));
+    }
+}
@@ -0,0 +1,247 @@
+use super::config::Config;
+use super::slow_client::WriteTimeoutAcceptor;
+
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// A listening socket obtained from the configuration or the service manager
+enum BoundListener {
+    Tcp(std::net::TcpListener),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener),
+}
+
+/// Bind the configured listen address and serve the router until shutdown
+///
+/// The socket is taken from systemd socket activation (`LISTEN_FDS`) when
+/// present, otherwise `listen` is bound, either as a TCP address or as a
+/// Unix domain socket when prefixed with `unix:`. Serves HTTPS when both
+/// `tls_cert` and `tls_key` are set. HTTP/1.1 and HTTP/2 are both accepted,
+/// negotiated through ALPN over TLS or as h2c prior knowledge otherwise.
+/// Connections are closed once the client accepted no bytes for
+/// `client_write_timeout` seconds.
+pub async fn serve(config: &Config, app: Router) -> anyhow::Result<()> {
+    let listener = bind(&config.listen)?;
+    let acceptor = WriteTimeoutAcceptor::new(config.client_write_timeout);
+
+    match (&config.tls_cert, &config.tls_key) {
+        #[cfg(feature = "http3")]
+        (Some(cert), Some(key)) if config.http3 => {
+            let addr = match &listener {
+                BoundListener::Tcp(tcp) => tcp.local_addr()?,
+                #[cfg(unix)]
+                BoundListener::Unix(_) => anyhow::bail!("HTTP/3 requires a TCP listen address"),
+            };
+            let app = super::http3::advertise(app, addr.port());
+
+            tokio::try_join!(
+                serve_tls(listener, acceptor, cert, key, app.clone()),
+                super::http3::serve(addr, cert, key, app),
+            )?;
+            Ok(())
+        }
+        (Some(cert), Some(key)) => serve_tls(listener, acceptor, cert, key, app).await,
+        _ => serve_plain(listener, acceptor, app).await,
+    }
+}
+
+fn bind(listen: &str) -> anyhow::Result<BoundListener> {
+    #[cfg(unix)]
+    if let Some(listener) = systemd::take_listener()? {
+        return Ok(listener);
+    }
+
+    if let Some(path) = listen.strip_prefix("unix:") {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+
+            let path = Path::new(path);
+            // A socket file left behind by a previous run would make bind fail,
+            // anything else at the path is most likely a typo and left alone
+            match std::fs::symlink_metadata(path) {
+                Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+                Ok(_) => anyhow::bail!("{} exists and is not a socket", path.display()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+            let listener = std::os::unix::net::UnixListener::bind(path)?;
+            info!("camo-rs listening on unix:{}", path.display());
+            return Ok(BoundListener::Unix(listener));
+        }
+
+        #[cfg(not(unix))]
+        anyhow::bail!("Unix domain sockets are not supported on this platform: {}", path);
+    }
+
+    let listener = std::net::TcpListener::bind(listen)?;
+    info!("camo-rs listening on {}", listen);
+    Ok(BoundListener::Tcp(listener))
+}
+
+async fn serve_plain(
+    listener: BoundListener,
+    acceptor: WriteTimeoutAcceptor,
+    app: Router,
+) -> anyhow::Result<()> {
+    match listener {
+        BoundListener::Tcp(listener) => {
+            listener.set_nonblocking(true)?;
+            axum_server::from_tcp(listener)?
+                .acceptor(acceptor)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await?;
+        }
+        #[cfg(unix)]
+        BoundListener::Unix(listener) => {
+            listener.set_nonblocking(true)?;
+            axum_server::from_unix(listener)?
+                .acceptor(acceptor)
+                .serve(app.into_make_service())
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn serve_tls(
+    listener: BoundListener,
+    acceptor: WriteTimeoutAcceptor,
+    cert: &Path,
+    key: &Path,
+    app: Router,
+) -> anyhow::Result<()> {
+    // Several rustls providers end up enabled through transitive features,
+    // so pick one explicitly. Fails harmlessly if one is already installed.
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let tls = RustlsConfig::from_pem_file(cert, key).await?;
+
+    #[cfg(unix)]
+    reload_on_sighup(tls.clone(), cert.to_path_buf(), key.to_path_buf());
+
+    info!("TLS enabled with certificate {}", cert.display());
+
+    match listener {
+        BoundListener::Tcp(listener) => {
+            listener.set_nonblocking(true)?;
+            axum_server::from_tcp_rustls(listener, tls)?
+                .map(|tls| tls.acceptor(acceptor))
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await?;
+        }
+        #[cfg(unix)]
+        BoundListener::Unix(listener) => {
+            listener.set_nonblocking(true)?;
+            axum_server::from_unix_rustls(listener, tls)?
+                .map(|tls| tls.acceptor(acceptor))
+                .serve(app.into_make_service())
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Re-read the certificate and key from disk whenever the process receives SIGHUP
+#[cfg(unix)]
+fn reload_on_sighup(tls: RustlsConfig, cert: PathBuf, key: PathBuf) {
+    use tokio::signal::unix::{signal, SignalKind};
+    use tracing::error;
+
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Failed to install SIGHUP handler, TLS hot-reload disabled: {}", e);
+                return;
+            }
+        };
+
+        while hangup.recv().await.is_some() {
+            match tls.reload_from_pem_file(&cert, &key).await {
+                Ok(()) => info!("Reloaded TLS certificate from {}", cert.display()),
+                Err(e) => error!("Failed to reload TLS certificate: {}", e),
+            }
+        }
+    });
+}
+
+/// systemd socket activation (`sd_listen_fds(3)`)
+#[cfg(unix)]
+mod systemd {
+    use super::BoundListener;
+    use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+    use tracing::{info, warn};
+
+    /// First file descriptor passed by the service manager
+    const SD_LISTEN_FDS_START: RawFd = 3;
+
+    /// Take ownership of the first socket passed via `LISTEN_FDS`, if any
+    pub(super) fn take_listener() -> anyhow::Result<Option<BoundListener>> {
+        let Ok(fds) = std::env::var("LISTEN_FDS") else {
+            return Ok(None);
+        };
+
+        // The variables are inherited by children too, so only honour them
+        // when they were meant for this process.
+        if let Ok(pid) = std::env::var("LISTEN_PID")
+            && pid.parse::<u32>().ok() != Some(std::process::id())
+        {
+            return Ok(None);
+        }
+
+        let fds: RawFd = fds
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid LISTEN_FDS value: {}", fds))?;
+
+        if fds < 1 {
+            return Ok(None);
+        }
+        if fds > 1 {
+            warn!("systemd passed {} sockets, only the first one is used", fds);
+        }
+
+        // SAFETY: the service manager hands us ownership of this descriptor
+        // and nothing else in the process uses it.
+        let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(SD_LISTEN_FDS_START) };
+
+        // getsockname() only yields a Unix address for AF_UNIX sockets
+        if let Ok(addr) = unix.local_addr() {
+            info!("camo-rs listening on systemd-activated socket {:?}", addr);
+            return Ok(Some(BoundListener::Unix(unix)));
+        }
+
+        // SAFETY: ownership is moved from the Unix listener wrapper above
+        let tcp = unsafe { std::net::TcpListener::from_raw_fd(unix.into_raw_fd()) };
+        let addr = tcp.local_addr()?;
+        info!("camo-rs listening on systemd-activated socket {}", addr);
+        Ok(Some(BoundListener::Tcp(tcp)))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bind_unix_replaces_only_sockets() {
+        let dir = std::env::temp_dir().join(format!("camo-listen-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // A stale socket from a previous run is replaced
+        let socket = dir.join("camo.sock");
+        drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+        assert!(bind(&format!("unix:{}", socket.display())).is_ok());
+
+        // Any other file is kept
+        let file = dir.join("camo.toml");
+        std::fs::write(&file, "key = \"secret\"\n").unwrap();
+        assert!(bind(&format!("unix:{}", file.display())).is_err());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "key = \"secret\"\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
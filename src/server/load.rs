@@ -0,0 +1,165 @@
+//! Gauges of the work in progress, for autoscaling and for draining
+//!
+//! `camo_requests_in_flight` counts proxy requests from their arrival
+//! until their response was sent or dropped, and
+//! `camo_upstream_connections_active` the upstream connections busy with a
+//! fetch, from the request being sent until its body was read or dropped.
+//! Idle connections kept in reqwest's pool are not counted: it does not
+//! tell. With the bytes of responses on their way to clients,
+//! `camo_memory_in_flight_bytes`, they are also served by the admin API,
+//! which keeps answering while an instance drains.
+
+use super::memory_budget;
+
+use axum::body::{Body, Bytes};
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use futures_core::Stream;
+use serde::Serialize;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+
+/// One of the gauges, mirrored into its metric
+pub struct Gauge {
+    name: &'static str,
+    value: AtomicU64,
+}
+
+pub static REQUESTS: Gauge = Gauge::new("camo_requests_in_flight");
+pub static UPSTREAM_CONNECTIONS: Gauge = Gauge::new("camo_upstream_connections_active");
+
+impl Gauge {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    /// Count one more until the returned guard is dropped
+    pub fn hold(&'static self) -> Held {
+        let value = self.value.fetch_add(1, Ordering::Relaxed) + 1;
+        metrics::gauge!(self.name).set(value as f64);
+        Held(self)
+    }
+}
+
+/// One unit of a [`Gauge`], given back when dropped
+pub struct Held(&'static Gauge);
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        let value = self.0.value.fetch_sub(1, Ordering::Relaxed) - 1;
+        metrics::gauge!(self.0.name).set(value as f64);
+    }
+}
+
+/// Current value of every gauge, as served by the admin API
+#[derive(Debug, Serialize)]
+pub struct Snapshot {
+    pub requests: u64,
+    pub streaming_bytes: u64,
+    pub upstream_connections: u64,
+}
+
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        requests: REQUESTS.get(),
+        streaming_bytes: memory_budget::in_flight(),
+        upstream_connections: UPSTREAM_CONNECTIONS.get(),
+    }
+}
+
+/// Stream holding `held` until it ended, failed or was dropped
+#[pin_project::pin_project]
+pub struct Holding<S> {
+    #[pin]
+    inner: S,
+    held: Option<Held>,
+}
+
+impl<S> Holding<S> {
+    pub fn new(inner: S, held: Held) -> Self {
+        Self {
+            inner,
+            held: Some(held),
+        }
+    }
+}
+
+impl<S, E> Stream for Holding<S>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let poll = this.inner.poll_next(cx);
+        if matches!(poll, Poll::Ready(None | Some(Err(_)))) {
+            *this.held = None;
+        }
+        poll
+    }
+}
+
+/// Middleware counting proxy requests, and the bytes of their responses, while in flight
+pub async fn track(request: Request, next: Next) -> Response {
+    let held = REQUESTS.hold();
+    let response = next.run(request).await;
+    response.map(|body| {
+        let body = Body::from_stream(Holding::new(body.into_data_stream(), held));
+        memory_budget::count(body)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static GAUGE: Gauge = Gauge::new("camo_test_in_flight");
+
+    /// Body of these chunks, then an endless wait
+    struct Chunks(Vec<&'static str>);
+
+    impl Stream for Chunks {
+        type Item = Result<Bytes, std::io::Error>;
+
+        fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            if self.0.is_empty() {
+                return Poll::Pending;
+            }
+            Poll::Ready(Some(Ok(Bytes::from(self.0.remove(0)))))
+        }
+    }
+
+    async fn next<S: Stream + Unpin>(stream: &mut S) -> Option<S::Item> {
+        std::future::poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx)).await
+    }
+
+    #[tokio::test]
+    async fn test_holding() {
+        let held = GAUGE.hold();
+        assert_eq!(GAUGE.get(), 1);
+        drop(held);
+        assert_eq!(GAUGE.get(), 0);
+
+        // Given back at the end of the body
+        let mut body = Holding::new(Body::from("done").into_data_stream(), GAUGE.hold());
+        while next(&mut body).await.is_some() {}
+        assert_eq!(GAUGE.get(), 0);
+
+        // Or when it is dropped before
+        let mut body = Holding::new(Chunks(vec!["a", "b"]), GAUGE.hold());
+        next(&mut body).await;
+        assert_eq!(GAUGE.get(), 1);
+        drop(body);
+        assert_eq!(GAUGE.get(), 0);
+    }
+}
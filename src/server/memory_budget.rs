@@ -0,0 +1,213 @@
+//! Bound on response bytes held in memory across all requests
+//!
+//! Every proxied body is counted while a chunk of it sits in the proxy,
+//! between being read from upstream and being handed to the client, and
+//! bodies buffered in full, for a virus scan or a digest, are counted
+//! whole until they are. Once the total goes over `--memory-budget`, new
+//! proxy requests are shed with a 503 until enough of it drained, rather
+//! than growing the process into its container's memory limit. Health
+//! checks and metrics are never shed.
+
+use super::config::Config;
+use super::error::CamoError;
+
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::BoxError;
+use http_body::{Frame, SizeHint};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+
+/// Bytes held by all requests of the process
+static IN_FLIGHT: AtomicU64 = AtomicU64::new(0);
+
+fn add(bytes: u64) {
+    let total = IN_FLIGHT.fetch_add(bytes, Ordering::Relaxed) + bytes;
+    metrics::gauge!("camo_memory_in_flight_bytes").set(total as f64);
+}
+
+fn sub(bytes: u64) {
+    let total = IN_FLIGHT.fetch_sub(bytes, Ordering::Relaxed) - bytes;
+    metrics::gauge!("camo_memory_in_flight_bytes").set(total as f64);
+}
+
+/// Bytes currently held by all requests
+pub fn in_flight() -> u64 {
+    IN_FLIGHT.load(Ordering::Relaxed)
+}
+
+/// Share of the in-flight total, given back when dropped
+#[derive(Debug, Default)]
+struct Reservation(u64);
+
+impl Reservation {
+    fn resize(&mut self, bytes: u64) {
+        match bytes.cmp(&self.0) {
+            std::cmp::Ordering::Greater => add(bytes - self.0),
+            std::cmp::Ordering::Less => sub(self.0 - bytes),
+            std::cmp::Ordering::Equal => {}
+        }
+        self.0 = bytes;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.resize(0);
+    }
+}
+
+/// Body counting what it holds in the in-flight total
+///
+/// A streamed body holds the chunk last handed out, until the next one is
+/// asked for; a buffered one everything handed out so far.
+struct CountedBody {
+    inner: Body,
+    reservation: Reservation,
+    accumulate: bool,
+}
+
+impl CountedBody {
+    fn new(inner: Body, accumulate: bool) -> Self {
+        Self {
+            inner,
+            reservation: Reservation::default(),
+            accumulate,
+        }
+    }
+}
+
+impl HttpBody for CountedBody {
+    type Data = Bytes;
+    // Unwrapped, so that whoever reads the body can still downcast the error
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) => {
+                let size = frame.data_ref().map_or(0, |data| data.len() as u64);
+                let held = if self.accumulate { self.reservation.0 + size } else { size };
+                self.reservation.resize(held);
+            }
+            Poll::Ready(_) => self.reservation.resize(0),
+            Poll::Pending => {}
+        }
+        poll.map(|frame| frame.map(|frame| frame.map_err(axum::Error::into_inner)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// `body`, counted in the in-flight total while it is streamed
+pub fn count(body: Body) -> Body {
+    Body::new(CountedBody::new(body, false))
+}
+
+/// Read `body` into memory, like [`axum::body::to_bytes`], counting it in the in-flight total
+///
+/// The bytes stop being counted once returned; the response they go out
+/// in counts them again as it is sent.
+pub async fn buffer(body: Body, limit: usize) -> Result<Bytes, axum::Error> {
+    axum::body::to_bytes(Body::new(CountedBody::new(body, true)), limit).await
+}
+
+/// Sheds proxy requests while more than `--memory-budget` bytes are in flight
+///
+/// Sheds are counted in `camo_shed_total`.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryBudget {
+    limit: u64,
+}
+
+impl MemoryBudget {
+    /// Returns `None` without `--memory-budget`
+    pub fn from_config(config: &Config) -> Option<Self> {
+        (config.memory_budget > 0).then_some(Self {
+            limit: config.memory_budget,
+        })
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        in_flight() > self.limit
+    }
+}
+
+/// Middleware refusing requests while over budget
+///
+/// The bodies of the others are counted by [`super::load::track`].
+pub async fn shed(State(budget): State<MemoryBudget>, request: Request, next: Next) -> Response {
+    if budget.is_exceeded() {
+        tracing::warn!(in_flight = in_flight(), limit = budget.limit, "Shedding request");
+        metrics::counter!("camo_shed_total").increment(1);
+        return CamoError::Overloaded.into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Other tests buffer bodies at the same time, so only what this one
+    // holds itself is checked exactly, never the process total
+
+    #[tokio::test]
+    async fn test_buffer() {
+        let bytes = buffer(Body::from("hello"), 16).await.unwrap();
+        assert_eq!(bytes, "hello");
+        assert!(buffer(Body::from("hello"), 4).await.is_err());
+
+        // Errors of the body itself come out as from `to_bytes`
+        let failing = || Body::from_stream(Chunks(vec![Err(CamoError::ContentTooLarge(9))]));
+        let error = buffer(failing(), 16).await.unwrap_err();
+        let expected = axum::body::to_bytes(failing(), 16).await.unwrap_err();
+        assert_eq!(format!("{:?}", error), format!("{:?}", expected));
+    }
+
+    /// Body of these chunks, in order
+    struct Chunks(Vec<Result<Bytes, CamoError>>);
+
+    impl futures_core::Stream for Chunks {
+        type Item = Result<Bytes, CamoError>;
+
+        fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            let next = (!self.0.is_empty()).then(|| self.0.remove(0));
+            Poll::Ready(next)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_counted_body() {
+        for (accumulate, held) in [(false, [2, 3, 0]), (true, [2, 5, 0])] {
+            let chunks = ["ab", "cde"].map(|chunk| Ok(Bytes::from(chunk)));
+            let body = Body::from_stream(Chunks(chunks.into()));
+            let mut body = CountedBody::new(body, accumulate);
+            for held in held {
+                std::future::poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await;
+                assert_eq!(body.reservation.0, held);
+            }
+        }
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut reservation = Reservation::default();
+        reservation.resize(5);
+        assert!(MemoryBudget { limit: 0 }.is_exceeded());
+        assert!(!MemoryBudget { limit: u64::MAX }.is_exceeded());
+        drop(reservation);
+    }
+}
@@ -0,0 +1,240 @@
+use super::cache::{split_key, Lookup, TtlCache};
+use super::config::Config;
+use super::error::CamoError;
+
+use axum::http::StatusCode;
+use std::time::Duration;
+
+/// Header carrying the bypass token that skips the negative cache
+pub const BYPASS_HEADER: &str = "x-camo-cache-bypass";
+
+/// Kinds of upstream failures that are remembered, each with its own TTL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureClass {
+    /// Upstream answered 404 Not Found or 410 Gone
+    NotFound,
+    /// Body exceeded the size limit
+    TooLarge,
+    /// Content type is not allowed
+    ContentType,
+    /// Upstream host could not be resolved or connected to
+    Unreachable,
+}
+
+impl FailureClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureClass::NotFound => "not_found",
+            FailureClass::TooLarge => "too_large",
+            FailureClass::ContentType => "content_type",
+            FailureClass::Unreachable => "unreachable",
+        }
+    }
+}
+
+/// Cloneable copy of a cacheable [`CamoError`]
+#[derive(Debug, Clone)]
+enum CachedFailure {
+    UpstreamStatus(StatusCode),
+    ContentTooLarge(u64),
+    ContentTypeNotAllowed(String),
+    Unreachable(String),
+}
+
+impl CachedFailure {
+    fn from_error(err: &CamoError) -> Option<(FailureClass, Self)> {
+        match err {
+            CamoError::UpstreamStatus(status)
+                if *status == StatusCode::NOT_FOUND || *status == StatusCode::GONE =>
+            {
+                Some((FailureClass::NotFound, CachedFailure::UpstreamStatus(*status)))
+            }
+            CamoError::ContentTooLarge(size) => {
+                Some((FailureClass::TooLarge, CachedFailure::ContentTooLarge(*size)))
+            }
+            CamoError::ContentTypeNotAllowed(ct) => Some((
+                FailureClass::ContentType,
+                CachedFailure::ContentTypeNotAllowed(ct.clone()),
+            )),
+            CamoError::Upstream(msg) => {
+                Some((FailureClass::Unreachable, CachedFailure::Unreachable(msg.clone())))
+            }
+            CamoError::ReqwestError(e) if e.is_connect() => Some((
+                FailureClass::Unreachable,
+                CachedFailure::Unreachable(e.to_string()),
+            )),
+            _ => None,
+        }
+    }
+
+    fn into_error(self) -> CamoError {
+        match self {
+            CachedFailure::UpstreamStatus(status) => CamoError::UpstreamStatus(status),
+            CachedFailure::ContentTooLarge(size) => CamoError::ContentTooLarge(size),
+            CachedFailure::ContentTypeNotAllowed(ct) => CamoError::ContentTypeNotAllowed(ct),
+            CachedFailure::Unreachable(msg) => CamoError::Upstream(msg),
+        }
+    }
+}
+
+/// Short-lived cache of upstream failures keyed by target URL and key ID
+///
+/// Keeps repeated requests for deleted or rejected images from reaching the
+/// upstream again until the class-specific TTL runs out.
+pub struct NegativeCache {
+    cache: TtlCache<(FailureClass, CachedFailure)>,
+    ttl_not_found: Duration,
+    ttl_too_large: Duration,
+    ttl_content_type: Duration,
+    ttl_unreachable: Duration,
+    bypass_token: Option<String>,
+}
+
+impl NegativeCache {
+    /// Returns `None` when the cache is disabled by configuration
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let ttls = [
+            config.negative_ttl_not_found,
+            config.negative_ttl_too_large,
+            config.negative_ttl_content_type,
+            config.negative_ttl_unreachable,
+        ];
+        if config.negative_cache_size == 0 || ttls.iter().all(|ttl| *ttl == 0) {
+            return None;
+        }
+
+        Some(Self {
+            cache: TtlCache::new(config.negative_cache_size),
+            ttl_not_found: Duration::from_secs(config.negative_ttl_not_found),
+            ttl_too_large: Duration::from_secs(config.negative_ttl_too_large),
+            ttl_content_type: Duration::from_secs(config.negative_ttl_content_type),
+            ttl_unreachable: Duration::from_secs(config.negative_ttl_unreachable),
+            bypass_token: config.negative_cache_bypass_token.clone(),
+        })
+    }
+
+    /// Whether the request presented the configured bypass token
+    pub fn is_bypass(&self, token: Option<&str>) -> bool {
+        match (&self.bypass_token, token) {
+            (Some(expected), Some(token)) => {
+                crate::utils::crypto::constant_time_eq(expected.as_bytes(), token.as_bytes())
+            }
+            _ => false,
+        }
+    }
+
+    /// Return the remembered failure for a URL, if it is still fresh
+    ///
+    /// `url` is a [partitioned key](super::cache::partitioned_key), whose
+    /// key ID labels the hit in `camo_negative_cache_hits_total{class, key}`.
+    pub fn get(&self, url: &str) -> Option<CamoError> {
+        match self.cache.lookup(url) {
+            Lookup::Fresh((class, failure)) => {
+                metrics::counter!(
+                    "camo_negative_cache_hits_total",
+                    "class" => class.as_str(),
+                    "key" => split_key(url).0.to_string()
+                )
+                .increment(1);
+                Some(failure.into_error())
+            }
+            Lookup::Stale | Lookup::Miss => None,
+        }
+    }
+
+    /// Remember a failure for a URL if its class is cacheable
+    pub fn record(&self, url: &str, err: &CamoError) {
+        let Some((class, failure)) = CachedFailure::from_error(err) else {
+            return;
+        };
+
+        let ttl = self.ttl(class);
+        if !ttl.is_zero() {
+            self.cache.insert(url, (class, failure), ttl);
+        }
+    }
+
+    /// Forget any failure recorded for a URL, e.g. after a successful fetch
+    ///
+    /// Returns whether a failure was recorded.
+    pub fn remove(&self, url: &str) -> bool {
+        self.cache.remove(url)
+    }
+
+    /// Forget the failures recorded under matching keys; returns how many there were
+    pub fn remove_matching(&self, matches: impl FnMut(&str) -> bool) -> usize {
+        self.cache.remove_matching(matches)
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.cache.is_healthy()
+    }
+
+    fn ttl(&self, class: FailureClass) -> Duration {
+        match class {
+            FailureClass::NotFound => self.ttl_not_found,
+            FailureClass::TooLarge => self.ttl_too_large,
+            FailureClass::ContentType => self.ttl_content_type,
+            FailureClass::Unreachable => self.ttl_unreachable,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache() -> NegativeCache {
+        NegativeCache {
+            cache: TtlCache::new(16),
+            ttl_not_found: Duration::from_secs(60),
+            ttl_too_large: Duration::from_secs(60),
+            ttl_content_type: Duration::ZERO,
+            ttl_unreachable: Duration::from_secs(10),
+            bypass_token: Some("debug-token".into()),
+        }
+    }
+
+    #[test]
+    fn test_records_cacheable_failures() {
+        let cache = cache();
+
+        cache.record("http://a.example/", &CamoError::UpstreamStatus(StatusCode::GONE));
+        assert!(matches!(
+            cache.get("http://a.example/"),
+            Some(CamoError::UpstreamStatus(StatusCode::GONE))
+        ));
+
+        cache.record("http://b.example/", &CamoError::ContentTooLarge(42));
+        assert!(matches!(
+            cache.get("http://b.example/"),
+            Some(CamoError::ContentTooLarge(42))
+        ));
+
+        cache.remove("http://b.example/");
+        assert!(cache.get("http://b.example/").is_none());
+    }
+
+    #[test]
+    fn test_skips_uncacheable_failures() {
+        let cache = cache();
+
+        cache.record("http://a.example/", &CamoError::Timeout);
+        cache.record("http://b.example/", &CamoError::UpstreamStatus(StatusCode::FORBIDDEN));
+        // Disabled class
+        cache.record("http://c.example/", &CamoError::ContentTypeNotAllowed("text/html".into()));
+
+        assert!(cache.get("http://a.example/").is_none());
+        assert!(cache.get("http://b.example/").is_none());
+        assert!(cache.get("http://c.example/").is_none());
+    }
+
+    #[test]
+    fn test_bypass_token() {
+        let cache = cache();
+
+        assert!(cache.is_bypass(Some("debug-token")));
+        assert!(!cache.is_bypass(Some("wrong")));
+        assert!(!cache.is_bypass(None));
+    }
+}
@@ -0,0 +1,457 @@
+use super::config::Config;
+use super::cache::{Lookup, TtlCache};
+use super::error::{CamoError, Result};
+use super::geoip::GeoIp;
+use super::reputation::{self, Reputation};
+
+use ipnet::IpNet;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use url::{Host, Url};
+
+/// Decides which upstream addresses the proxy may connect to
+#[derive(Clone, Default)]
+pub struct NetworkPolicy {
+    block_private: bool,
+    blocked_networks: Vec<IpNet>,
+    reputation: Option<Arc<Reputation>>,
+    geoip: Option<Arc<GeoIp>>,
+    dns_cache: Option<Arc<TtlCache<Vec<IpAddr>>>>,
+    dns_cache_ttl: Duration,
+}
+
+impl NetworkPolicy {
+    /// Build the policy from the configuration, reading `block_cidr_file` if set
+    ///
+    /// Reputation lists are kept up to date in the background for as long
+    /// as the policy lives.
+    pub fn from_config(config: &Config) -> io::Result<Self> {
+        let mut blocked_networks = config.block_cidr.clone();
+        if let Some(path) = &config.block_cidr_file {
+            blocked_networks.extend(read_cidr_file(path)?);
+        }
+
+        let reputation = Reputation::from_config(config)?.map(Arc::new);
+        if let Some(reputation) = &reputation {
+            reputation::spawn(reputation, config);
+        }
+
+        let geoip = GeoIp::from_config(config)?.map(Arc::new);
+
+        let dns_cache = (config.dns_cache_ttl > 0 && config.dns_cache_size > 0)
+            .then(|| Arc::new(TtlCache::new(config.dns_cache_size)));
+
+        Ok(Self {
+            block_private: config.block_private,
+            blocked_networks,
+            reputation,
+            geoip,
+            dns_cache,
+            dns_cache_ttl: Duration::from_secs(config.dns_cache_ttl),
+        })
+    }
+
+    /// The reputation lists, with `--reputation-list`
+    pub fn reputation(&self) -> Option<&Reputation> {
+        self.reputation.as_deref()
+    }
+
+    /// Check a single resolved address against the policy
+    pub fn check_ip(&self, ip: &IpAddr) -> Result<()> {
+        if self.block_private && is_private_ip(ip) {
+            return Err(CamoError::PrivateNetworkNotAllowed);
+        }
+
+        if self.is_blocked_network(ip) {
+            return Err(CamoError::NetworkNotAllowed);
+        }
+
+        if let Some(reputation) = &self.reputation
+            && with_embedded(ip).any(|ip| reputation.lists_ip(&ip))
+        {
+            metrics::counter!("camo_reputation_blocked_total").increment(1);
+            return Err(CamoError::NetworkNotAllowed);
+        }
+
+        if let Some(geoip) = &self.geoip
+            && let Some(by) = geoip.blocks(&geoip.locate(ip))
+        {
+            metrics::counter!("camo_geoip_blocked_total", "by" => by).increment(1);
+            return Err(CamoError::NetworkNotAllowed);
+        }
+
+        Ok(())
+    }
+
+    /// Count and log where `host`'s first address is, with `--geoip-db`
+    fn tag(&self, host: &str, addrs: &[IpAddr]) {
+        let (Some(geoip), Some(ip)) = (&self.geoip, addrs.first()) else {
+            return;
+        };
+        let location = geoip.locate(ip);
+        let country = location.country.unwrap_or_default();
+        let asn = location.asn.map(|asn| asn.to_string()).unwrap_or_default();
+        tracing::debug!(host, %ip, country, asn, "Upstream location");
+        metrics::counter!("camo_upstream_locations_total", "country" => country, "asn" => asn)
+            .increment(1);
+    }
+
+    /// Check what the URL alone tells: a listed hostname, or an IP literal
+    ///
+    /// IP literals never reach a resolver, so they are checked here. The
+    /// addresses of a hostname are checked by [`NetworkPolicy::resolve`],
+    /// which the upstream client resolves through, so those are the
+    /// addresses it connects to.
+    pub fn check(&self, url: &Url) -> Result<()> {
+        match url.host() {
+            Some(Host::Domain(host)) => {
+                if let Some(reputation) = &self.reputation
+                    && reputation.lists_host(host)
+                {
+                    metrics::counter!("camo_reputation_blocked_total").increment(1);
+                    return Err(CamoError::NetworkNotAllowed);
+                }
+                Ok(())
+            }
+            Some(Host::Ipv4(ip)) => self.check_ip(&IpAddr::V4(ip)),
+            Some(Host::Ipv6(ip)) => self.check_ip(&IpAddr::V6(ip)),
+            None => Err(CamoError::InvalidUrl("No host".into())),
+        }
+    }
+
+    /// Resolve a hostname and check every address it resolves to
+    ///
+    /// Hostnames that passed recently are answered from the DNS cache
+    /// without another lookup; their addresses are checked again all the
+    /// same, as the lists may have changed since.
+    pub async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>> {
+        if let Some(cache) = &self.dns_cache {
+            match cache.lookup(host) {
+                Lookup::Fresh(addrs) => {
+                    metrics::counter!("camo_dns_cache_hits_total").increment(1);
+                    self.check_all(host, &addrs)?;
+                    return Ok(addrs);
+                }
+                Lookup::Stale => {
+                    metrics::counter!("camo_dns_cache_revalidations_total").increment(1);
+                }
+                Lookup::Miss => {
+                    metrics::counter!("camo_dns_cache_misses_total").increment(1);
+                }
+            }
+        }
+
+        let addrs = lookup(host).await?;
+        let result = self.check_all(host, &addrs);
+
+        if let Some(cache) = &self.dns_cache {
+            match result {
+                Ok(()) => cache.insert(host, addrs.clone(), self.dns_cache_ttl),
+                Err(_) => {
+                    cache.remove(host);
+                }
+            }
+            metrics::gauge!("camo_dns_cache_entries").set(cache.len() as f64);
+        }
+
+        result.map(|()| addrs)
+    }
+
+    /// Check all of `host`'s addresses, tagging it with its location when they pass
+    fn check_all(&self, host: &str, addrs: &[IpAddr]) -> Result<()> {
+        addrs.iter().try_for_each(|addr| self.check_ip(addr))?;
+        self.tag(host, addrs);
+        Ok(())
+    }
+
+    fn is_blocked_network(&self, ip: &IpAddr) -> bool {
+        self.blocked_networks
+            .iter()
+            .any(|net| with_embedded(ip).any(|ip| net.contains(&ip)))
+    }
+}
+
+/// The address, and the IPv4 address smuggled inside it if it is IPv6, so
+/// IPv4 rules match both
+fn with_embedded(ip: &IpAddr) -> impl Iterator<Item = IpAddr> {
+    let embedded = match ip {
+        IpAddr::V6(ipv6) => embedded_ipv4(&ipv6.segments()).map(IpAddr::V4),
+        IpAddr::V4(_) => None,
+    };
+    std::iter::once(*ip).chain(embedded)
+}
+
+async fn lookup(host: &str) -> Result<Vec<IpAddr>> {
+    let addrs = tokio::net::lookup_host((host, 0))
+        .await
+        .map_err(|e| CamoError::Upstream(format!("failed to resolve {}: {}", host, e)))?
+        .map(|addr| addr.ip())
+        .collect();
+
+    Ok(addrs)
+}
+
+/// Parse a CIDR range, accepting bare addresses as single-host networks
+pub fn parse_cidr(s: &str) -> std::result::Result<IpNet, String> {
+    let s = s.trim();
+    s.parse::<IpNet>()
+        .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("invalid CIDR range: {}", s))
+}
+
+/// Read CIDR ranges from a file, one per line, ignoring blank lines and `#` comments
+fn read_cidr_file(path: &Path) -> io::Result<Vec<IpNet>> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .enumerate()
+        .map(|(n, line)| (n, line.split('#').next().unwrap_or("").trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(n, line)| {
+            parse_cidr(line).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: {}", path.display(), n + 1, e),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Whether an address belongs to a private, internal or otherwise non-public range
+#[inline]
+pub fn is_private_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ipv4) => is_private_ipv4(ipv4),
+        IpAddr::V6(ipv6) => is_private_ipv6(ipv6),
+    }
+}
+
+fn is_private_ipv4(ipv4: &Ipv4Addr) -> bool {
+    ipv4.is_private()
+        || ipv4.is_loopback()
+        || ipv4.is_link_local()
+        || ipv4.is_broadcast()
+        || ipv4.is_documentation()
+        || ipv4.is_unspecified()
+        // 100.64.0.0/10 (Carrier-grade NAT)
+        || (ipv4.octets()[0] == 100 && (ipv4.octets()[1] & 0xC0) == 64)
+}
+
+fn is_private_ipv6(ipv6: &Ipv6Addr) -> bool {
+    if ipv6.is_loopback() || ipv6.is_unspecified() || ipv6.is_multicast() {
+        return true;
+    }
+
+    let segments = ipv6.segments();
+
+    // fc00::/7 (Unique local), fe80::/10 (Link-local), fec0::/10 (deprecated Site-local)
+    if (segments[0] & 0xfe00) == 0xfc00
+        || (segments[0] & 0xffc0) == 0xfe80
+        || (segments[0] & 0xffc0) == 0xfec0
+    {
+        return true;
+    }
+
+    // 2001:db8::/32 (Documentation), 100::/64 (Discard-only)
+    if (segments[0] == 0x2001 && segments[1] == 0x0db8)
+        || (segments[0] == 0x0100 && segments[1..4] == [0, 0, 0])
+    {
+        return true;
+    }
+
+    // Addresses that embed an IPv4 address are judged by that address,
+    // otherwise `::ffff:10.0.0.1` would reach 10.0.0.1 unchecked.
+    embedded_ipv4(&segments).is_some_and(|v4| is_private_ipv4(&v4))
+}
+
+/// Extract an IPv4 address tunnelled or translated inside an IPv6 address
+fn embedded_ipv4(segments: &[u16; 8]) -> Option<Ipv4Addr> {
+    let from_segments = |hi: u16, lo: u16| Ipv4Addr::from(((hi as u32) << 16) | lo as u32);
+
+    match segments {
+        // ::ffff:0:0/96 (IPv4-mapped) and ::/96 (deprecated IPv4-compatible)
+        [0, 0, 0, 0, 0, 0xffff, hi, lo] | [0, 0, 0, 0, 0, 0, hi, lo] => {
+            Some(from_segments(*hi, *lo))
+        }
+        // 64:ff9b::/96 (NAT64 well-known prefix)
+        [0x64, 0xff9b, 0, 0, 0, 0, hi, lo] => Some(from_segments(*hi, *lo)),
+        // 64:ff9b:1::/48 (NAT64 local-use prefix) always targets a local network
+        [0x64, 0xff9b, 1, ..] => Some(Ipv4Addr::UNSPECIFIED),
+        // 2002::/16 (6to4) carries the IPv4 address right after the prefix
+        [0x2002, hi, lo, ..] => Some(from_segments(*hi, *lo)),
+        // 2001::/32 (Teredo) carries the client address inverted in the last 32 bits
+        [0x2001, 0, server_hi, server_lo, _, _, client_hi, client_lo] => {
+            let server = from_segments(*server_hi, *server_lo);
+            let client = from_segments(!*client_hi, !*client_lo);
+            // Report whichever half is private so the caller rejects it
+            if is_private_ipv4(&server) {
+                Some(server)
+            } else {
+                Some(client)
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_private(ip: &str) -> bool {
+        is_private_ip(&ip.parse().unwrap())
+    }
+
+    fn private_only() -> NetworkPolicy {
+        NetworkPolicy {
+            block_private: true,
+            ..Default::default()
+        }
+    }
+
+    fn blocking(cidrs: &[&str]) -> NetworkPolicy {
+        NetworkPolicy {
+            blocked_networks: cidrs.iter().map(|c| parse_cidr(c).unwrap()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_private_ipv4() {
+        assert!(is_private("10.0.0.1"));
+        assert!(is_private("172.16.5.4"));
+        assert!(is_private("192.168.1.1"));
+        assert!(is_private("127.0.0.1"));
+        assert!(is_private("169.254.169.254"));
+        assert!(is_private("100.64.0.1"));
+        assert!(is_private("0.0.0.0"));
+        assert!(!is_private("93.184.216.34"));
+        assert!(!is_private("8.8.8.8"));
+    }
+
+    #[test]
+    fn test_private_ipv6_ranges() {
+        assert!(is_private("::1"));
+        assert!(is_private("::"));
+        assert!(is_private("fc00::1"));
+        assert!(is_private("fd12:3456:789a::1"));
+        assert!(is_private("fe80::1"));
+        assert!(is_private("fec0::1"));
+        assert!(is_private("ff02::1"));
+        assert!(is_private("2001:db8::1"));
+        assert!(is_private("100::1"));
+        assert!(!is_private("2606:4700:4700::1111"));
+        assert!(!is_private("2a00:1450:4001:80b::200e"));
+    }
+
+    #[test]
+    fn test_private_ipv6_embedded_ipv4() {
+        // IPv4-mapped
+        assert!(is_private("::ffff:10.0.0.1"));
+        assert!(is_private("::ffff:127.0.0.1"));
+        assert!(is_private("::ffff:a9fe:a9fe"));
+        assert!(!is_private("::ffff:8.8.8.8"));
+        // IPv4-compatible
+        assert!(is_private("::192.168.0.1"));
+        // NAT64
+        assert!(is_private("64:ff9b::10.1.2.3"));
+        assert!(is_private("64:ff9b:1::1"));
+        assert!(!is_private("64:ff9b::8.8.8.8"));
+        // 6to4
+        assert!(is_private("2002:c0a8:0101::1"));
+        assert!(is_private("2002:7f00:0001::1"));
+        assert!(!is_private("2002:0808:0808::1"));
+        // Teredo with client 10.0.0.1 (inverted: f5ff:fffe)
+        assert!(is_private("2001:0:4136:e378:8000:63bf:f5ff:fffe"));
+        // Teredo with private server address
+        assert!(is_private("2001:0:c0a8:0101:8000:63bf:f7f7:f7f7"));
+        // Teredo with public server and client 8.8.8.8 (inverted: f7f7:f7f7)
+        assert!(!is_private("2001:0:4136:e378:8000:63bf:f7f7:f7f7"));
+    }
+
+    #[test]
+    fn test_check_private_network_ipv6_literals() {
+        for url in [
+            "http://[::1]/",
+            "http://[::ffff:10.0.0.1]/",
+            "http://[::ffff:7f00:1]:8080/",
+            "http://[fd00::1]/",
+            "http://[fe80::1]/",
+            "http://[64:ff9b::a00:1]/",
+            "http://[2002:a00:1::]/",
+        ] {
+            let result = private_only().check(&Url::parse(url).unwrap());
+            assert!(
+                matches!(result, Err(CamoError::PrivateNetworkNotAllowed)),
+                "{} should be blocked",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_private_network_public_literal() {
+        let url = Url::parse("http://[2606:4700:4700::1111]/").unwrap();
+        assert!(private_only().check(&url).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_from_dns_cache() {
+        let policy = NetworkPolicy {
+            dns_cache: Some(Arc::new(TtlCache::new(16))),
+            ..private_only()
+        };
+        let cache = policy.dns_cache.as_deref().unwrap();
+        let public: IpAddr = "93.184.216.34".parse().unwrap();
+        let ttl = Duration::from_secs(60);
+
+        // No lookup could resolve this name
+        cache.insert("cdn.invalid", vec![public], ttl);
+        assert_eq!(policy.resolve("cdn.invalid").await.unwrap(), [public]);
+
+        // Cached addresses still go through the policy
+        cache.insert("internal.invalid", vec![public, "10.0.0.1".parse().unwrap()], ttl);
+        assert!(matches!(
+            policy.resolve("internal.invalid").await,
+            Err(CamoError::PrivateNetworkNotAllowed)
+        ));
+    }
+
+    #[test]
+    fn test_parse_cidr() {
+        assert_eq!(parse_cidr("10.0.0.0/8").unwrap().to_string(), "10.0.0.0/8");
+        assert_eq!(parse_cidr(" 169.254.169.254 ").unwrap().to_string(), "169.254.169.254/32");
+        assert_eq!(parse_cidr("fd00::/8").unwrap().to_string(), "fd00::/8");
+        assert!(parse_cidr("example.com").is_err());
+        assert!(parse_cidr("10.0.0.0/33").is_err());
+    }
+
+    #[test]
+    fn test_blocked_networks() {
+        let policy = blocking(&["192.0.2.0/24", "203.0.113.7", "2001:db8:1::/48"]);
+
+        assert!(policy.check_ip(&"192.0.2.10".parse().unwrap()).is_err());
+        assert!(policy.check_ip(&"203.0.113.7".parse().unwrap()).is_err());
+        assert!(policy.check_ip(&"2001:db8:1::5".parse().unwrap()).is_err());
+        // IPv4 rules also cover the IPv4-mapped form
+        assert!(policy.check_ip(&"::ffff:192.0.2.10".parse().unwrap()).is_err());
+
+        assert!(policy.check_ip(&"203.0.113.8".parse().unwrap()).is_ok());
+        // Private ranges are only blocked when block_private is set
+        assert!(policy.check_ip(&"10.0.0.1".parse().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_read_cidr_file() {
+        let path = std::env::temp_dir().join(format!("camo-cidr-{}.txt", std::process::id()));
+        std::fs::write(&path, "# metadata\n169.254.169.254\n\n10.0.0.0/8 # internal\n").unwrap();
+        let nets = read_cidr_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(nets.len(), 2);
+        assert_eq!(nets[1].to_string(), "10.0.0.0/8");
+    }
+}
@@ -0,0 +1,27 @@
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use std::sync::OnceLock;
+use tracing::error;
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Install the Prometheus recorder as the global metrics recorder
+///
+/// Must be called from within a Tokio runtime, which runs the exporter.
+pub fn install() -> anyhow::Result<()> {
+    let (recorder, exporter) = PrometheusBuilder::new().build()?;
+    let handle = recorder.handle();
+    metrics::set_global_recorder(recorder)?;
+    let _ = HANDLE.set(handle);
+
+    tokio::spawn(async move {
+        if let Err(e) = exporter.await {
+            error!("Prometheus exporter failed: {:?}", e);
+        }
+    });
+    Ok(())
+}
+
+/// Handle to the installed recorder, if [`install`] succeeded
+pub fn handle() -> Option<&'static PrometheusHandle> {
+    HANDLE.get()
+}
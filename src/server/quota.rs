@@ -0,0 +1,348 @@
+use super::config::Config;
+use super::error::CamoError;
+#[cfg(feature = "redis")]
+use super::redis_pool::RedisPool;
+use super::tenant::TenantKeys;
+
+use async_trait::async_trait;
+use axum::body::{Body, Bytes};
+use axum::response::Response;
+use futures_core::Stream;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What a tenant may use per window; 0 is no limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Limits {
+    requests: u64,
+    bytes: u64,
+}
+
+impl Limits {
+    fn is_unlimited(&self) -> bool {
+        self.requests == 0 && self.bytes == 0
+    }
+}
+
+/// Requests and response bytes of a tenant in one window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Usage {
+    requests: u64,
+    bytes: u64,
+}
+
+/// Where usage is counted
+#[async_trait]
+trait Counters: Send + Sync {
+    /// Add `usage` to what `tenant` used in `window`, returning the new totals
+    async fn add(&self, tenant: &str, window: u64, usage: Usage) -> Result<Usage, String>;
+}
+
+/// Counters of this instance alone
+#[derive(Default)]
+struct MemoryCounters(Mutex<HashMap<String, (u64, Usage)>>);
+
+#[async_trait]
+impl Counters for MemoryCounters {
+    async fn add(&self, tenant: &str, window: u64, usage: Usage) -> Result<Usage, String> {
+        let mut counters = self.0.lock().unwrap();
+        let entry = counters.entry(tenant.to_string()).or_default();
+        if entry.0 != window {
+            *entry = (window, Usage::default());
+        }
+        entry.1.requests += usage.requests;
+        entry.1.bytes += usage.bytes;
+        Ok(entry.1)
+    }
+}
+
+/// Counters shared by all instances using the same Redis
+///
+/// Each tenant and window is a hash with `requests` and `bytes` fields,
+/// expiring a window length after its last update.
+#[cfg(feature = "redis")]
+struct RedisCounters {
+    pool: Arc<RedisPool>,
+    ttl: i64,
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl Counters for RedisCounters {
+    async fn add(&self, tenant: &str, window: u64, usage: Usage) -> Result<Usage, String> {
+        let mut connection = self.pool.connection().await?;
+        let key = format!("camo:quota:{}:{}", tenant, window);
+        let (requests, bytes): (u64, u64) = redis::pipe()
+            .atomic()
+            .hincr(&key, "requests", usage.requests)
+            .hincr(&key, "bytes", usage.bytes)
+            .expire(&key, self.ttl)
+            .ignore()
+            .query_async(&mut connection)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(Usage { requests, bytes })
+    }
+}
+
+/// Request and bandwidth quotas per tenant, over fixed windows
+///
+/// A request of a tenant over its request quota, or made after the tenant
+/// was served its byte quota, is refused with 429 and a `Retry-After` up
+/// to the next window. Bytes are counted as response bodies finish, so a
+/// tenant can go over its byte quota by the responses in flight. Counters
+/// live in memory, or in Redis to share them between instances; when Redis
+/// cannot be reached requests are let through rather than failed.
+///
+/// Every tenant's response bytes are counted in `camo_tenant_bytes_total`,
+/// with or without a quota, and refusals in
+/// `camo_tenant_quota_exceeded_total{tenant, quota}`.
+pub struct TenantQuotas {
+    limits: HashMap<String, Limits>,
+    window: u64,
+    counters: Box<dyn Counters>,
+}
+
+impl TenantQuotas {
+    /// Returns `None` without tenants
+    pub fn from_config(config: &Config) -> Result<Option<Self>, String> {
+        let tenants = TenantKeys::from_config(config)?;
+        let default = Limits {
+            requests: config.tenant_quota_requests,
+            bytes: config.tenant_quota_bytes,
+        };
+        let mut limits: HashMap<_, _> =
+            tenants.names().map(|name| (name.to_string(), default)).collect();
+        if limits.is_empty() {
+            return Ok(None);
+        }
+        for entry in &config.tenant_quotas {
+            let (name, quota) = parse_quota(entry)?;
+            match limits.get_mut(name) {
+                Some(limit) => *limit = quota,
+                None => return Err(format!("quota for unknown tenant {}", name)),
+            }
+        }
+
+        let window = config.tenant_quota_window.max(1);
+        #[cfg(feature = "redis")]
+        if let Some(url) = &config.tenant_quota_redis {
+            let counters = RedisCounters {
+                pool: RedisPool::shared(url)?,
+                ttl: window as i64,
+            };
+            return Ok(Some(Self::new(limits, window, Box::new(counters))));
+        }
+        Ok(Some(Self::new(limits, window, Box::<MemoryCounters>::default())))
+    }
+
+    fn new(limits: HashMap<String, Limits>, window: u64, counters: Box<dyn Counters>) -> Self {
+        Self {
+            limits,
+            window,
+            counters,
+        }
+    }
+
+    /// Count a request of `tenant`, refusing it when the tenant is over its quota
+    pub async fn admit(&self, tenant: &str) -> Result<(), CamoError> {
+        self.admit_at(tenant, now()).await
+    }
+
+    async fn admit_at(&self, tenant: &str, now: u64) -> Result<(), CamoError> {
+        let Some(limits) = self.limits.get(tenant).filter(|l| !l.is_unlimited()) else {
+            return Ok(());
+        };
+        let request = Usage {
+            requests: 1,
+            bytes: 0,
+        };
+        let usage = match self.counters.add(tenant, now / self.window, request).await {
+            Ok(usage) => usage,
+            Err(e) => {
+                tracing::warn!(tenant, error = %e, "Failed to count tenant quota");
+                return Ok(());
+            }
+        };
+
+        let exceeded = if limits.requests > 0 && usage.requests > limits.requests {
+            "requests"
+        } else if limits.bytes > 0 && usage.bytes >= limits.bytes {
+            "bytes"
+        } else {
+            return Ok(());
+        };
+        metrics::counter!(
+            "camo_tenant_quota_exceeded_total",
+            "tenant" => tenant.to_string(),
+            "quota" => exceeded
+        )
+        .increment(1);
+        Err(CamoError::QuotaExceeded(self.window - now % self.window))
+    }
+
+    /// Count the body of `response` against the byte quota of `tenant` once it is sent
+    pub fn meter(self: &Arc<Self>, tenant: &str, response: Response) -> Response {
+        if !self.limits.contains_key(tenant) {
+            return response;
+        }
+        let meter = Meter {
+            quotas: self.clone(),
+            tenant: tenant.to_string(),
+            bytes: 0,
+        };
+        response.map(|body| {
+            Body::from_stream(MeteredStream {
+                inner: body.into_data_stream(),
+                meter,
+            })
+        })
+    }
+
+    /// Add `bytes` served to `tenant` to its usage
+    async fn charge_at(&self, tenant: &str, bytes: u64, now: u64) {
+        metrics::counter!("camo_tenant_bytes_total", "tenant" => tenant.to_string())
+            .increment(bytes);
+        if self.limits.get(tenant).is_none_or(|limits| limits.bytes == 0) {
+            return;
+        }
+        let usage = Usage { requests: 0, bytes };
+        if let Err(e) = self.counters.add(tenant, now / self.window, usage).await {
+            tracing::warn!(tenant, error = %e, "Failed to count tenant quota");
+        }
+    }
+}
+
+/// `tenant=requests:bytes`, either number 0 for no limit
+fn parse_quota(entry: &str) -> Result<(&str, Limits), String> {
+    let invalid = || format!("expected tenant=requests:bytes, got {}", entry);
+    let (name, quota) = entry.split_once('=').ok_or_else(invalid)?;
+    let (requests, bytes) = quota.split_once(':').ok_or_else(invalid)?;
+    let limits = Limits {
+        requests: requests.trim().parse().map_err(|_| invalid())?,
+        bytes: bytes.trim().parse().map_err(|_| invalid())?,
+    };
+    Ok((name.trim(), limits))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Bytes of one response body, charged when the body is dropped
+///
+/// Dropped rather than finished, so bodies the client stops reading part
+/// way still count for what was sent.
+struct Meter {
+    quotas: Arc<TenantQuotas>,
+    tenant: String,
+    bytes: u64,
+}
+
+impl Drop for Meter {
+    fn drop(&mut self) {
+        if self.bytes == 0 {
+            return;
+        }
+        let (quotas, bytes) = (self.quotas.clone(), self.bytes);
+        let tenant = std::mem::take(&mut self.tenant);
+        tokio::spawn(async move { quotas.charge_at(&tenant, bytes, now()).await });
+    }
+}
+
+#[pin_project::pin_project]
+struct MeteredStream<S> {
+    #[pin]
+    inner: S,
+    meter: Meter,
+}
+
+impl<S> Stream for MeteredStream<S>
+where
+    S: Stream<Item = Result<Bytes, axum::Error>>,
+{
+    type Item = Result<Bytes, axum::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let poll = this.inner.poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            this.meter.bytes += chunk.len() as u64;
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn quotas(args: &[&str]) -> Option<TenantQuotas> {
+        let base = ["camo", "--key", "secret", "--tenant-key", "shop=s", "--tenant-key", "blog=b"];
+        TenantQuotas::from_config(&Config::parse_from(base.iter().chain(args))).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_request_quota() {
+        let quotas = quotas(&["--tenant-quota-requests", "2", "--tenant-quota-window", "60"]);
+        let quotas = quotas.unwrap();
+
+        assert!(quotas.admit_at("shop", 120).await.is_ok());
+        assert!(quotas.admit_at("shop", 130).await.is_ok());
+        assert!(matches!(
+            quotas.admit_at("shop", 135).await,
+            Err(CamoError::QuotaExceeded(45))
+        ));
+        // Counted per tenant and window
+        assert!(quotas.admit_at("blog", 135).await.is_ok());
+        assert!(quotas.admit_at("shop", 180).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_byte_quota() {
+        let quotas = quotas(&["--tenant-quota", "shop=0:100", "--tenant-quota-bytes", "0"]);
+        let quotas = quotas.unwrap();
+
+        assert!(quotas.admit_at("shop", 0).await.is_ok());
+        quotas.charge_at("shop", 60, 1).await;
+        assert!(quotas.admit_at("shop", 2).await.is_ok());
+        quotas.charge_at("shop", 40, 3).await;
+        assert!(quotas.admit_at("shop", 4).await.is_err());
+        assert!(quotas.admit_at("shop", 3600).await.is_ok());
+
+        // No quota for the other tenant
+        quotas.charge_at("blog", 1000, 5).await;
+        assert!(quotas.admit_at("blog", 6).await.is_ok());
+    }
+
+    #[test]
+    fn test_from_config() {
+        let config = Config::parse_from(["camo", "--key", "secret"]);
+        assert!(TenantQuotas::from_config(&config).unwrap().is_none());
+
+        let quotas = quotas(&["--tenant-quota-requests", "5", "--tenant-quota", "blog=1:2"]);
+        let limits = quotas.unwrap().limits;
+        assert_eq!(limits["shop"], Limits { requests: 5, bytes: 0 });
+        assert_eq!(limits["blog"], Limits { requests: 1, bytes: 2 });
+
+        for invalid in ["news=1:2", "shop=1", "shop=x:2"] {
+            let args = ["camo", "--tenant-key", "shop=s", "--tenant-quota", invalid];
+            assert!(TenantQuotas::from_config(&Config::parse_from(args)).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_meter_passes_body() {
+        let quotas = Arc::new(quotas(&[]).unwrap());
+        let response = quotas.meter("shop", Response::new(Body::from("hello")));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"hello");
+    }
+}
@@ -0,0 +1,252 @@
+use super::config::Config;
+use super::error::CamoError;
+#[cfg(feature = "redis")]
+use super::redis_pool::RedisPool;
+
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::net::IpAddr;
+#[cfg(feature = "redis")]
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Buckets kept in memory before full ones are dropped
+const MAX_BUCKETS: usize = 100_000;
+
+/// Rate and capacity of every client's bucket
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Limit {
+    /// Tokens added per second
+    rate: f64,
+    burst: f64,
+}
+
+impl Limit {
+    /// Milliseconds until a bucket with `tokens` holds a whole token again
+    fn wait(&self, tokens: f64) -> u64 {
+        ((1.0 - tokens) * 1000.0 / self.rate).ceil().max(0.0) as u64
+    }
+}
+
+/// Where the buckets are kept
+#[async_trait]
+trait Buckets: Send + Sync {
+    /// Take a token from `client`'s bucket at `now` (ms), or return how many ms until one is there
+    async fn take(&self, client: &str, limit: Limit, now: u64) -> Result<Option<u64>, String>;
+}
+
+/// Buckets of this instance alone, as tokens and the time they were counted at
+#[derive(Default)]
+struct MemoryBuckets(Mutex<HashMap<String, (f64, u64)>>);
+
+#[async_trait]
+impl Buckets for MemoryBuckets {
+    async fn take(&self, client: &str, limit: Limit, now: u64) -> Result<Option<u64>, String> {
+        let mut buckets = self.0.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(client) {
+            // Full buckets hold nothing that a new one would not
+            buckets.retain(|_, (tokens, at)| refill(limit, *tokens, *at, now) < limit.burst);
+        }
+        let (tokens, at) = buckets.entry(client.to_string()).or_insert((limit.burst, now));
+        let available = refill(limit, *tokens, *at, now);
+        *at = now;
+        if available >= 1.0 {
+            *tokens = available - 1.0;
+            Ok(None)
+        } else {
+            *tokens = available;
+            Ok(Some(limit.wait(available)))
+        }
+    }
+}
+
+/// Tokens in a bucket that held `tokens` at `at`, by `now`
+fn refill(limit: Limit, tokens: f64, at: u64, now: u64) -> f64 {
+    let elapsed = now.saturating_sub(at) as f64 / 1000.0;
+    (tokens + elapsed * limit.rate).min(limit.burst)
+}
+
+/// Token bucket in a Redis hash, refilled and taken from in one atomic step
+///
+/// Sent with `EVAL` every time; it is short, and saves tracking whether the
+/// server has it cached.
+#[cfg(feature = "redis")]
+const TAKE_SCRIPT: &str = r#"
+local rate, burst, now = tonumber(ARGV[1]), tonumber(ARGV[2]), tonumber(ARGV[3])
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'at')
+local tokens, at = tonumber(bucket[1]) or burst, tonumber(bucket[2]) or now
+tokens = math.min(burst, tokens + math.max(0, now - at) * rate / 1000)
+local wait = -1
+if tokens >= 1 then
+    tokens = tokens - 1
+else
+    wait = math.ceil((1 - tokens) * 1000 / rate)
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'at', now)
+redis.call('PEXPIRE', KEYS[1], math.ceil(burst * 1000 / rate) + 1000)
+return wait
+"#;
+
+/// Buckets shared by all instances using the same Redis
+///
+/// Each client's bucket is a hash expiring once it would be full again.
+#[cfg(feature = "redis")]
+struct RedisBuckets {
+    pool: Arc<RedisPool>,
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl Buckets for RedisBuckets {
+    async fn take(&self, client: &str, limit: Limit, now: u64) -> Result<Option<u64>, String> {
+        let mut connection = self.pool.connection().await?;
+        let wait: i64 = redis::cmd("EVAL")
+            .arg(TAKE_SCRIPT)
+            .arg(1)
+            .arg(format!("camo:rate:{}", client))
+            .arg(limit.rate)
+            .arg(limit.burst)
+            .arg(now)
+            .query_async(&mut connection)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(u64::try_from(wait).ok())
+    }
+}
+
+/// Requests per second per client address, from `--rate-limit`
+///
+/// Each client has a token bucket holding `--rate-limit-burst` requests
+/// and refilled at `--rate-limit` per second; a request finding it empty
+/// is refused with 429 and a `Retry-After`. IPv6 clients are limited per
+/// /64, which is what a single host usually gets. Buckets live in memory,
+/// or with `--rate-limit-redis` in Redis so the limit holds across the
+/// fleet; when Redis cannot be reached requests are let through rather
+/// than failed. Refusals are counted in `camo_rate_limited_total`.
+pub struct RateLimiter {
+    limit: Limit,
+    buckets: Box<dyn Buckets>,
+}
+
+impl RateLimiter {
+    /// Returns `None` without `--rate-limit`
+    pub fn from_config(config: &Config) -> Result<Option<Self>, String> {
+        if config.rate_limit <= 0.0 {
+            return Ok(None);
+        }
+        let burst = match config.rate_limit_burst {
+            0 => config.rate_limit.ceil(),
+            burst => burst as f64,
+        };
+        let limit = Limit {
+            rate: config.rate_limit,
+            burst,
+        };
+
+        #[cfg(feature = "redis")]
+        if let Some(url) = &config.rate_limit_redis {
+            let buckets = RedisBuckets {
+                pool: RedisPool::shared(url)?,
+            };
+            return Ok(Some(Self::new(limit, Box::new(buckets))));
+        }
+        Ok(Some(Self::new(limit, Box::<MemoryBuckets>::default())))
+    }
+
+    fn new(limit: Limit, buckets: Box<dyn Buckets>) -> Self {
+        Self { limit, buckets }
+    }
+
+    /// Count a request of `client`, refusing it when its bucket is empty
+    pub async fn admit(&self, client: IpAddr) -> Result<(), CamoError> {
+        self.admit_at(client, now()).await
+    }
+
+    async fn admit_at(&self, client: IpAddr, now: u64) -> Result<(), CamoError> {
+        let key = client_key(client);
+        let wait = match self.buckets.take(&key, self.limit, now).await {
+            Ok(wait) => wait,
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to check rate limit");
+                return Ok(());
+            }
+        };
+        match wait {
+            None => Ok(()),
+            Some(wait) => {
+                metrics::counter!("camo_rate_limited_total").increment(1);
+                Err(CamoError::RateLimited(wait.div_ceil(1000).max(1)))
+            }
+        }
+    }
+}
+
+/// The address, or its /64 for IPv6
+fn client_key(client: IpAddr) -> String {
+    match client.to_canonical() {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            format!("{:x}:{:x}:{:x}:{:x}::/64", segments[0], segments[1], segments[2], segments[3])
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn limiter(args: &[&str]) -> Option<RateLimiter> {
+        let config = Config::parse_from(["camo", "--key", "secret"].iter().chain(args));
+        RateLimiter::from_config(&config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_bucket() {
+        let limiter = limiter(&["--rate-limit", "2", "--rate-limit-burst", "3"]).unwrap();
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+
+        for _ in 0..3 {
+            assert!(limiter.admit_at(client, 10_000).await.is_ok());
+        }
+        assert!(matches!(
+            limiter.admit_at(client, 10_000).await,
+            Err(CamoError::RateLimited(1))
+        ));
+        // Other clients have their own bucket
+        assert!(limiter.admit_at("192.0.2.2".parse().unwrap(), 10_000).await.is_ok());
+        // Refilled at two per second
+        assert!(limiter.admit_at(client, 10_500).await.is_ok());
+        assert!(limiter.admit_at(client, 10_500).await.is_err());
+        assert!(limiter.admit_at(client, 12_000).await.is_ok());
+        assert!(limiter.admit_at(client, 12_000).await.is_ok());
+        assert!(limiter.admit_at(client, 12_000).await.is_ok());
+        assert!(limiter.admit_at(client, 12_000).await.is_err());
+    }
+
+    #[test]
+    fn test_from_config() {
+        assert!(limiter(&[]).is_none());
+        let limiter = limiter(&["--rate-limit", "0.5"]).unwrap();
+        assert_eq!(limiter.limit, Limit { rate: 0.5, burst: 1.0 });
+        assert_eq!(limiter.limit.wait(0.0), 2000);
+    }
+
+    #[test]
+    fn test_client_key() {
+        let key = |ip: &str| client_key(ip.parse().unwrap());
+        assert_eq!(key("192.0.2.1"), "192.0.2.1");
+        assert_eq!(key("::ffff:192.0.2.1"), "192.0.2.1");
+        assert_eq!(key("2001:db8:1:2:3:4:5:6"), "2001:db8:1:2::/64");
+        assert_eq!(key("2001:db8:1:2::ffff"), key("2001:db8:1:2:3:4:5:6"));
+    }
+}
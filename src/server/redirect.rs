@@ -0,0 +1,108 @@
+use url::Url;
+
+/// Trusted HTTPS hosts whose images are not streamed through the proxy
+///
+/// A matching target is answered with `302 Found` pointing at the origin,
+/// which already satisfies the mixed-content rules camo exists for.
+/// Entries are exact hostnames or `*.example.com` for any subdomain.
+#[derive(Clone, Default)]
+pub struct RedirectPolicy {
+    hosts: Vec<String>,
+}
+
+impl RedirectPolicy {
+    pub fn new(hosts: &[String]) -> Self {
+        Self {
+            hosts: hosts
+                .iter()
+                .map(|host| host_pattern(host))
+                .filter(|host| !host.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Whether the client should be sent to `url` directly
+    pub fn should_redirect(&self, url: &Url) -> bool {
+        if self.hosts.is_empty() || url.scheme() != "https" {
+            return false;
+        }
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+
+        self.hosts.iter().any(|allowed| host_matches(allowed, &host))
+    }
+}
+
+/// A configured hostname or `*.example.com` pattern, in the form [`host_matches`] expects
+///
+/// Trimmed, lowercase and without a trailing dot, with internationalized
+/// names in punycode as the `url` crate gives them for target URLs, so
+/// that `*.bücher.de` matches `https://shop.xn--bcher-kva.de/`.
+pub fn host_pattern(pattern: &str) -> String {
+    let pattern = pattern.trim().trim_end_matches('.');
+    let (wildcard, domain) = match pattern.strip_prefix("*.") {
+        Some(domain) => ("*.", domain),
+        None => ("", pattern),
+    };
+    let domain = idna::domain_to_ascii(domain).unwrap_or_else(|_| domain.to_ascii_lowercase());
+    format!("{}{}", wildcard, domain)
+}
+
+/// Whether `host` is `pattern` or, for `*.example.com`, one of its subdomains
+///
+/// Both are expected in lowercase without a trailing dot.
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.ends_with('.') && sub.len() > 1),
+        None => host == pattern,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(hosts: &[&str]) -> RedirectPolicy {
+        RedirectPolicy::new(&hosts.iter().map(|h| h.to_string()).collect::<Vec<_>>())
+    }
+
+    fn redirects(policy: &RedirectPolicy, url: &str) -> bool {
+        policy.should_redirect(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn test_exact_host() {
+        let policy = policy(&["images.example.com"]);
+
+        assert!(redirects(&policy, "https://images.example.com/a.png"));
+        assert!(redirects(&policy, "https://IMAGES.example.com./a.png"));
+        assert!(!redirects(&policy, "http://images.example.com/a.png"));
+        assert!(!redirects(&policy, "https://cdn.images.example.com/a.png"));
+        assert!(!redirects(&policy, "https://example.com/a.png"));
+    }
+
+    #[test]
+    fn test_wildcard() {
+        let policy = policy(&["*.example.com"]);
+
+        assert!(redirects(&policy, "https://cdn.example.com/a.png"));
+        assert!(redirects(&policy, "https://a.b.example.com/a.png"));
+        assert!(!redirects(&policy, "https://example.com/a.png"));
+        assert!(!redirects(&policy, "https://badexample.com/a.png"));
+
+        // Internationalized names match in either spelling
+        let idn = RedirectPolicy::new(&[" *.Bücher.de. ".to_string()]);
+        assert!(redirects(&idn, "https://shop.bücher.de/a.png"));
+        assert!(redirects(&idn, "https://shop.xn--bcher-kva.de/a.png"));
+        assert!(!redirects(&idn, "https://shop.bucher.de/a.png"));
+    }
+
+    #[test]
+    fn test_disabled() {
+        assert!(!redirects(&policy(&[]), "https://example.com/a.png"));
+    }
+}
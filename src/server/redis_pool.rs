@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex, Weak};
+
+/// Live pools by URL, so that features configured with the same Redis share its connections
+static POOLS: LazyLock<Mutex<HashMap<String, Weak<RedisPool>>>> = LazyLock::new(Default::default);
+
+/// Connection to one Redis, opened on first use and reconnecting on its own
+///
+/// Tenant quotas and rate limits given the same URL get the same pool.
+pub struct RedisPool {
+    client: redis::Client,
+    connection: tokio::sync::OnceCell<redis::aio::ConnectionManager>,
+}
+
+impl RedisPool {
+    /// The pool for `url`, shared with every other user of it
+    pub fn shared(url: &str) -> Result<Arc<Self>, String> {
+        let mut pools = POOLS.lock().unwrap();
+        if let Some(pool) = pools.get(url).and_then(Weak::upgrade) {
+            return Ok(pool);
+        }
+        let pool = Arc::new(Self {
+            client: redis::Client::open(url).map_err(|e| e.to_string())?,
+            connection: tokio::sync::OnceCell::new(),
+        });
+        pools.retain(|_, pool| pool.strong_count() > 0);
+        pools.insert(url.to_string(), Arc::downgrade(&pool));
+        Ok(pool)
+    }
+
+    pub async fn connection(&self) -> Result<redis::aio::ConnectionManager, String> {
+        self.connection
+            .get_or_try_init(|| redis::aio::ConnectionManager::new(self.client.clone()))
+            .await
+            .cloned()
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared() {
+        let a = RedisPool::shared("redis://127.0.0.1:6390/").unwrap();
+        let b = RedisPool::shared("redis://127.0.0.1:6390/").unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &RedisPool::shared("redis://127.0.0.1:6390/1").unwrap()));
+        assert!(RedisPool::shared("not a url").is_err());
+    }
+}
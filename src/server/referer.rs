@@ -0,0 +1,96 @@
+use super::redirect::{host_matches, host_pattern};
+
+use axum::http::{header, HeaderMap};
+use url::Url;
+
+/// Hotlink protection: the pages allowed to embed proxied resources
+///
+/// A request's `Origin`, or its `Referer` when it has none, must name a
+/// host on the list, given as exact hostnames or `*.example.com` for any
+/// subdomain. Requests with neither header, as from privacy-minded
+/// browsers or `Referrer-Policy: no-referrer` pages, are let through
+/// unless `deny_empty` is set. Without hosts every request is allowed.
+#[derive(Clone, Default)]
+pub struct RefererPolicy {
+    hosts: Vec<String>,
+    deny_empty: bool,
+}
+
+impl RefererPolicy {
+    pub fn new(hosts: &[String], empty: &str) -> Self {
+        Self {
+            hosts: hosts
+                .iter()
+                .map(|host| host_pattern(host))
+                .filter(|host| !host.is_empty())
+                .collect(),
+            deny_empty: empty == "deny",
+        }
+    }
+
+    /// Whether a request with these headers may be served
+    pub fn allows(&self, headers: &HeaderMap) -> bool {
+        if self.hosts.is_empty() {
+            return true;
+        }
+        // Opaque origins, like sandboxed frames, send `Origin: null`
+        let origin = headers
+            .get(header::ORIGIN)
+            .filter(|origin| origin.as_bytes() != b"null")
+            .or_else(|| headers.get(header::REFERER));
+        let Some(origin) = origin else {
+            return !self.deny_empty;
+        };
+
+        let host = origin
+            .to_str()
+            .ok()
+            .and_then(|origin| Url::parse(origin).ok())
+            .and_then(|url| Some(url.host_str()?.trim_end_matches('.').to_ascii_lowercase()));
+        host.is_some_and(|host| self.hosts.iter().any(|allowed| host_matches(allowed, &host)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn policy(hosts: &[&str], empty: &str) -> RefererPolicy {
+        RefererPolicy::new(&hosts.iter().map(|h| h.to_string()).collect::<Vec<_>>(), empty)
+    }
+
+    fn request(name: header::HeaderName, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let policy = policy(&["example.com", "*.example.org"], "allow");
+
+        let referer = |value| policy.allows(&request(header::REFERER, value));
+        assert!(referer("https://example.com/post/1"));
+        assert!(referer("https://EXAMPLE.com./"));
+        assert!(referer("https://blog.example.org/"));
+        assert!(!referer("https://example.org/"));
+        assert!(!referer("https://evil.example/?https://example.com/"));
+        assert!(!referer("not a url"));
+
+        assert!(policy.allows(&request(header::ORIGIN, "https://example.com")));
+        let mut headers = request(header::ORIGIN, "https://evil.example");
+        headers.insert(header::REFERER, HeaderValue::from_static("https://example.com/"));
+        assert!(!policy.allows(&headers));
+        headers.insert(header::ORIGIN, HeaderValue::from_static("null"));
+        assert!(policy.allows(&headers));
+    }
+
+    #[test]
+    fn test_empty_referer() {
+        assert!(policy(&["example.com"], "allow").allows(&HeaderMap::new()));
+        assert!(!policy(&["example.com"], "deny").allows(&HeaderMap::new()));
+        assert!(!policy(&["example.com"], "deny").allows(&request(header::ORIGIN, "null")));
+        assert!(policy(&[], "deny").allows(&HeaderMap::new()));
+    }
+}
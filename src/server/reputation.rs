@@ -0,0 +1,353 @@
+use super::config::Config;
+use super::network::parse_cidr;
+
+use ipnet::IpNet;
+use std::collections::HashSet;
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock, Weak};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Largest list body read from a URL
+const MAX_LIST_SIZE: usize = 64 * 1024 * 1024;
+
+/// Time allowed for downloading one list
+const FETCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// External IP and host blocklists, from `--reputation-list`
+///
+/// Each list is a file or an http(s) URL with one entry per line: an IP
+/// address, a CIDR range or a hostname, which also covers its subdomains.
+/// `#` and `;` start comments, and lines in hosts file format
+/// (`0.0.0.0 host`) name the host, so most published feeds of malware
+/// hosting networks and domains can be used as they are. Lines that are
+/// none of these are skipped.
+///
+/// Targets whose host is listed, or that resolve into a listed network,
+/// are refused like `--block-cidr` ones and counted in
+/// `camo_reputation_blocked_total`.
+///
+/// Files are read at startup, where an unreadable one is an error. URLs
+/// are fetched in the background, first right after startup, and every
+/// list is read again every `--reputation-refresh` seconds. A list that
+/// fails to load keeps its previous entries.
+pub struct Reputation {
+    sources: Vec<String>,
+    client: reqwest::Client,
+    /// Entries of each source, in the order of `sources`
+    lists: RwLock<Vec<Arc<Blocklist>>>,
+    merged: RwLock<Arc<Blocklist>>,
+}
+
+/// Entries of one or more lists
+#[derive(Debug, Default)]
+struct Blocklist {
+    /// Sorted and without overlaps, so lookups can bisect
+    networks: Vec<IpNet>,
+    hosts: HashSet<String>,
+}
+
+impl Blocklist {
+    fn parse(contents: &str) -> Self {
+        let mut networks = Vec::new();
+        let mut hosts = HashSet::new();
+        for line in contents.lines() {
+            let line = line.split(['#', ';']).next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let Some(mut entry) = fields.next() else {
+                continue;
+            };
+            // Hosts files point each listed name at a null address
+            if let (Ok(ip), Some(host)) = (entry.parse::<IpAddr>(), fields.next())
+                && (ip.is_unspecified() || ip.is_loopback())
+            {
+                entry = host;
+            }
+
+            if let Ok(net) = parse_cidr(entry) {
+                networks.push(net);
+            } else if let Some(host) = normalize_host(entry) {
+                hosts.insert(host);
+            }
+        }
+        Self::new(networks, hosts)
+    }
+
+    fn new(networks: Vec<IpNet>, hosts: HashSet<String>) -> Self {
+        let mut networks = IpNet::aggregate(&networks);
+        networks.sort_unstable();
+        Self { networks, hosts }
+    }
+
+    fn merge(lists: &[Arc<Blocklist>]) -> Self {
+        let networks = lists.iter().flat_map(|list| list.networks.iter().copied()).collect();
+        let hosts = lists.iter().flat_map(|list| list.hosts.iter().cloned()).collect();
+        Self::new(networks, hosts)
+    }
+
+    fn lists_ip(&self, ip: &IpAddr) -> bool {
+        let after = self.networks.partition_point(|net| net.network() <= *ip);
+        after > 0 && self.networks[after - 1].contains(ip)
+    }
+
+    /// Whether the host or any domain it is under is listed
+    fn lists_host(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.');
+        let mut domain = host;
+        loop {
+            if self.hosts.contains(domain) {
+                return true;
+            }
+            match domain.split_once('.') {
+                Some((_, parent)) => domain = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+/// Lowercased hostname, if `entry` looks like one; single labels like `localhost` do not
+fn normalize_host(entry: &str) -> Option<String> {
+    let host = entry.trim_end_matches('.').to_ascii_lowercase();
+    let valid = host.contains('.')
+        && host
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_'));
+    valid.then_some(host)
+}
+
+fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+impl Reputation {
+    /// Returns `None` without `--reputation-list`; reads the files among the lists
+    pub fn from_config(config: &Config) -> io::Result<Option<Self>> {
+        if config.reputation_lists.is_empty() {
+            return Ok(None);
+        }
+
+        let mut lists = Vec::new();
+        for source in &config.reputation_lists {
+            let list = if is_url(source) {
+                Blocklist::default()
+            } else {
+                let contents = std::fs::read_to_string(source).map_err(|e| {
+                    io::Error::new(e.kind(), format!("reputation list {}: {}", source, e))
+                })?;
+                Blocklist::parse(&contents)
+            };
+            lists.push(Arc::new(list));
+        }
+
+        let client = reqwest::Client::builder()
+            .timeout(FETCH_TIMEOUT)
+            .user_agent("camo-rs")
+            .build()
+            .map_err(io::Error::other)?;
+        Ok(Some(Self {
+            sources: config.reputation_lists.clone(),
+            client,
+            merged: RwLock::new(Arc::new(Blocklist::merge(&lists))),
+            lists: RwLock::new(lists),
+        }))
+    }
+
+    /// Whether the address is in a listed network
+    pub fn lists_ip(&self, ip: &IpAddr) -> bool {
+        self.merged.read().unwrap().lists_ip(ip)
+    }
+
+    /// Whether the hostname is listed, itself or through a parent domain
+    pub fn lists_host(&self, host: &str) -> bool {
+        self.merged.read().unwrap().lists_host(host)
+    }
+
+    /// Fetch the lists given as URLs, which start out empty
+    pub async fn fetch_urls(&self) {
+        self.refresh(false).await;
+    }
+
+    /// Load the lists again, keeping the previous entries of those that fail
+    async fn refresh(&self, files: bool) {
+        let mut changed = false;
+        for (i, source) in self.sources.iter().enumerate() {
+            let result = if is_url(source) {
+                fetch(&self.client, source).await
+            } else if files {
+                tokio::fs::read_to_string(source).await.map_err(|e| e.to_string())
+            } else {
+                continue;
+            };
+            match result {
+                Ok(contents) => {
+                    let list = Blocklist::parse(&contents);
+                    info!(
+                        source,
+                        networks = list.networks.len(),
+                        hosts = list.hosts.len(),
+                        "Loaded reputation list"
+                    );
+                    self.lists.write().unwrap()[i] = Arc::new(list);
+                    changed = true;
+                    metrics::counter!("camo_reputation_refreshes_total", "result" => "ok")
+                        .increment(1);
+                }
+                Err(e) => {
+                    warn!(source, error = %e, "Failed to load reputation list");
+                    metrics::counter!("camo_reputation_refreshes_total", "result" => "error")
+                        .increment(1);
+                }
+            }
+        }
+
+        if changed {
+            let merged = Blocklist::merge(&self.lists.read().unwrap());
+            *self.merged.write().unwrap() = Arc::new(merged);
+        }
+    }
+}
+
+async fn fetch(client: &reqwest::Client, url: &str) -> Result<String, String> {
+    let mut response = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| e.to_string())?;
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+        if body.len() + chunk.len() > MAX_LIST_SIZE {
+            return Err(format!("list is larger than {} bytes", MAX_LIST_SIZE));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    String::from_utf8(body).map_err(|e| e.to_string())
+}
+
+/// Keep the lists up to date in the background
+///
+/// URLs are fetched right away, and all lists again every
+/// `--reputation-refresh` seconds unless it is 0. The task ends once the
+/// lists are dropped, as they are when the configuration is reloaded.
+pub fn spawn(reputation: &Arc<Reputation>, config: &Config) {
+    let has_urls = reputation.sources.iter().any(|source| is_url(source));
+    if !has_urls && config.reputation_refresh == 0 {
+        return;
+    }
+    let reputation: Weak<Reputation> = Arc::downgrade(reputation);
+    let interval = Duration::from_secs(config.reputation_refresh);
+
+    tokio::spawn(async move {
+        match reputation.upgrade() {
+            Some(reputation) => reputation.fetch_urls().await,
+            None => return,
+        }
+        if interval.is_zero() {
+            return;
+        }
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick is immediate, and the lists were just loaded
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let Some(reputation) = reputation.upgrade() else {
+                return;
+            };
+            reputation.refresh(true).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use wiremock::matchers::path;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const LIST: &str = "\
+# Known bad networks
+192.0.2.0/24 ; SBL123
+198.51.100.7
+2001:db8:bad::/48
+0.0.0.0 malware.example
+127.0.0.1 localhost
+cdn.bad.example # hosts everything
+not a valid entry!
+";
+
+    #[test]
+    fn test_parse() {
+        let list = Blocklist::parse(LIST);
+        assert_eq!(list.networks.len(), 3);
+        assert_eq!(list.hosts.len(), 2);
+
+        for listed in ["192.0.2.99", "198.51.100.7", "2001:db8:bad::1"] {
+            assert!(list.lists_ip(&listed.parse().unwrap()), "{}", listed);
+        }
+        for unlisted in ["192.0.3.1", "198.51.100.8", "2001:db8::1", "10.0.0.1"] {
+            assert!(!list.lists_ip(&unlisted.parse().unwrap()), "{}", unlisted);
+        }
+
+        assert!(list.lists_host("malware.example"));
+        assert!(list.lists_host("img.cdn.bad.example."));
+        assert!(!list.lists_host("bad.example"));
+        assert!(!list.lists_host("localhost"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let lists = [
+            Arc::new(Blocklist::parse("10.0.0.0/9\nevil.example")),
+            Arc::new(Blocklist::parse("10.128.0.0/9\n10.1.0.0/16")),
+        ];
+        let merged = Blocklist::merge(&lists);
+        assert_eq!(merged.networks, [parse_cidr("10.0.0.0/8").unwrap()]);
+        assert!(merged.lists_host("evil.example"));
+    }
+
+    #[tokio::test]
+    async fn test_refresh() {
+        let upstream = MockServer::start().await;
+        Mock::given(path("/list.txt"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(LIST))
+            .up_to_n_times(1)
+            .mount(&upstream)
+            .await;
+        Mock::given(path("/list.txt"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&upstream)
+            .await;
+
+        let file = std::env::temp_dir().join(format!("camo-reputation-{}.txt", std::process::id()));
+        std::fs::write(&file, "203.0.113.0/24\n").unwrap();
+        let url = format!("{}/list.txt", upstream.uri());
+        let config = Config::parse_from([
+            "camo",
+            "--key",
+            "secret",
+            "--reputation-list",
+            &format!("{},{}", file.display(), url),
+        ]);
+        let reputation = Reputation::from_config(&config).unwrap().unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        // Files are read right away, URLs once refreshed
+        assert!(reputation.lists_ip(&"203.0.113.5".parse().unwrap()));
+        assert!(!reputation.lists_host("malware.example"));
+
+        reputation.fetch_urls().await;
+        assert!(reputation.lists_host("malware.example"));
+        assert!(reputation.lists_ip(&"203.0.113.5".parse().unwrap()));
+
+        // A failed refresh keeps what was loaded before
+        reputation.refresh(true).await;
+        assert!(reputation.lists_host("malware.example"));
+        assert!(reputation.lists_ip(&"203.0.113.5".parse().unwrap()));
+
+        let missing = Config::parse_from(["camo", "--reputation-list", "/nonexistent/list.txt"]);
+        assert!(Reputation::from_config(&missing).is_err());
+    }
+}
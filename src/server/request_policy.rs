@@ -0,0 +1,43 @@
+use axum::http::{HeaderMap, StatusCode};
+use std::net::SocketAddr;
+use url::Url;
+
+/// Who asked for a proxied resource, as seen by a [`RequestPolicy`]
+#[derive(Debug, Clone, Copy)]
+pub struct ClientInfo<'a> {
+    /// Peer address, when the listener provides one
+    pub addr: Option<SocketAddr>,
+    /// Request headers as received, e.g. a session cookie or an API key
+    pub headers: &'a HeaderMap,
+    /// Tenant whose key signed the link, `None` for `--key`
+    pub tenant: Option<&'a str>,
+}
+
+/// Outcome of a [`RequestPolicy`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    /// Refuse the request with this status and reason
+    Deny { status: StatusCode, reason: String },
+}
+
+impl Decision {
+    pub fn deny(status: StatusCode, reason: impl Into<String>) -> Self {
+        Decision::Deny {
+            status,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Allow/deny hook for embedders, asked about every request before anything is fetched
+///
+/// Runs once the link's signature is verified and the target passed the
+/// built-in checks, so only requests the proxy would otherwise serve get
+/// here: a place for billing checks or per-user quotas without forking
+/// the pipeline. Set it with
+/// [`AppState::with_request_policy`](super::router::AppState::with_request_policy).
+#[async_trait::async_trait]
+pub trait RequestPolicy: Send + Sync {
+    async fn allow(&self, target: &Url, client: &ClientInfo<'_>) -> Decision;
+}
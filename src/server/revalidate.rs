@@ -0,0 +1,125 @@
+use super::cache::split_key;
+use super::error::CamoError;
+use super::forward::ForwardPolicy;
+use super::http_client::HttpClient;
+use super::router::{AppState, DEPTH_HEADER};
+use super::validator_cache::ValidatorCache;
+
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::debug;
+use url::Url;
+
+/// Keep the most requested validator cache entries fresh in the background
+///
+/// Every `--revalidate-interval` seconds the `--revalidate-top` entries
+/// requested most since the previous run are revalidated upstream with a
+/// conditional request, at most `--revalidate-concurrency` at a time. A 304
+/// extends the entry by its `max-age`, new validators replace it, and an
+/// error status drops it; network errors leave it to expire. Results are
+/// counted in `camo_revalidations_total{result, key}`.
+///
+/// The task ends once the cache is dropped, as it is when the configuration
+/// is reloaded.
+pub fn spawn(state: &AppState, client: Arc<dyn HttpClient>) {
+    let config = &state.config;
+    let Some(cache) = &state.validator_cache else {
+        return;
+    };
+    if config.revalidate_interval == 0 {
+        return;
+    }
+    let revalidator = Revalidator {
+        cache: Arc::downgrade(cache),
+        client,
+        forward: state.forward.clone(),
+        top: config.revalidate_top,
+        concurrency: Arc::new(Semaphore::new(config.revalidate_concurrency.max(1))),
+    };
+    let interval = Duration::from_secs(config.revalidate_interval);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick is immediate, before anything was requested
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let Some(cache) = revalidator.cache.upgrade() else {
+                return;
+            };
+            revalidator.run(cache).await;
+        }
+    });
+}
+
+struct Revalidator {
+    cache: Weak<ValidatorCache>,
+    client: Arc<dyn HttpClient>,
+    forward: ForwardPolicy,
+    top: usize,
+    concurrency: Arc<Semaphore>,
+}
+
+impl Revalidator {
+    async fn run(&self, cache: Arc<ValidatorCache>) {
+        let mut tasks = JoinSet::new();
+        for (key, stored) in cache.hottest(self.top) {
+            // Keys are URLs the proxy fetched, so this should not fail
+            let Ok(parsed) = Url::parse(split_key(&key).1) else {
+                cache.remove(&key);
+                continue;
+            };
+            let headers = self.request_headers(&parsed, &stored);
+            let Ok(permit) = self.concurrency.clone().acquire_owned().await else {
+                break;
+            };
+            let (cache, client) = (cache.clone(), self.client.clone());
+            tasks.spawn(async move {
+                let result = match client.get(parsed, headers).await {
+                    // The body is dropped unread; only the validators are kept
+                    Ok(response) => {
+                        cache.refresh(&key, &response.headers);
+                        "modified"
+                    }
+                    Err(CamoError::UpstreamStatus(StatusCode::NOT_MODIFIED)) => {
+                        cache.refresh(&key, &stored);
+                        "not_modified"
+                    }
+                    Err(e) => {
+                        debug!(key, error = %e, "Revalidation failed");
+                        if matches!(e, CamoError::UpstreamStatus(_)) {
+                            cache.remove(&key);
+                        }
+                        "error"
+                    }
+                };
+                metrics::counter!(
+                    "camo_revalidations_total",
+                    "result" => result,
+                    "key" => split_key(&key).0.to_string()
+                )
+                .increment(1);
+                drop(permit);
+            });
+        }
+        while tasks.join_next().await.is_some() {}
+    }
+
+    /// What the proxy would send upstream for the URL, made conditional on the stored validators
+    fn request_headers(&self, url: &Url, stored: &HeaderMap) -> HeaderMap {
+        let mut headers = self.forward.headers(&HeaderMap::new(), url);
+        headers.insert(DEPTH_HEADER, HeaderValue::from(1));
+        for (validator, condition) in [
+            (header::ETAG, header::IF_NONE_MATCH),
+            (header::LAST_MODIFIED, header::IF_MODIFIED_SINCE),
+        ] {
+            if let Some(value) = stored.get(validator) {
+                headers.insert(condition, value.clone());
+            }
+        }
+        headers
+    }
+}
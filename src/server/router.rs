@@ -1,64 +1,330 @@
+use super::conditional;
 use super::config::Config;
-use super::error::CamoError;
+use super::cors::CorsPolicy;
+#[cfg(feature = "server")]
+use super::body_digest;
+#[cfg(feature = "server")]
+use super::cache;
+use super::data_uri;
+use super::decode;
+use super::forward::ForwardPolicy;
+use super::homograph::is_mixed_script;
+use super::http_client::{
+    cap_max_age, make_private, ResponseFilter, SizeLimitedStream, UpstreamResponse,
+};
+use super::redirect::RedirectPolicy;
+use super::referer::RefererPolicy;
+use super::request_policy::{ClientInfo, Decision, RequestPolicy};
+use super::session::SessionBinding;
+use super::error::{self, CamoError};
+use super::signature::Verifiers;
+use super::tenant::TenantKeys;
+use super::verify_cache::VerifyCache;
 
-use crate::utils::crypto::verify_digest;
-use crate::utils::encoding::decode_url;
+#[cfg(feature = "server")]
+use crate::utils::canonical::canonicalize_url;
+#[cfg(feature = "server")]
+use crate::utils::crypto::Verifier;
+use crate::utils::encoding::{decode_url_with, validate_url, Encoding};
+use crate::utils::params::LinkParams;
+use crate::utils::scheme::resolve_scheme;
+use crate::utils::userinfo::{has_userinfo, UserinfoPolicy};
 
+use crate::server::http_client::{HttpClient, UpgradingClient};
+#[cfg(feature = "server")]
+use crate::server::audit::{AuditLog, Reason};
+#[cfg(feature = "server")]
+use crate::server::deny_list::DenyList;
+#[cfg(feature = "server")]
+use crate::server::health;
+#[cfg(feature = "server")]
+use crate::server::host_metrics::HostMetrics;
+#[cfg(feature = "object-store")]
+use crate::server::http_client::ObjectStoreClient;
+#[cfg(feature = "clamd")]
+use crate::server::http_client::{Clamd, InspectingClient};
+#[cfg(feature = "server")]
+use crate::server::cancellation;
+#[cfg(feature = "server")]
+use crate::server::http_client::{AddressHealth, ReqwestClient};
+#[cfg(feature = "server")]
+use crate::server::load;
+#[cfg(feature = "server")]
+use crate::server::memory_budget::{self, MemoryBudget};
+#[cfg(feature = "server")]
+use crate::server::negative_cache::{self, NegativeCache};
+#[cfg(feature = "server")]
+use crate::server::quota::TenantQuotas;
+#[cfg(feature = "server")]
+use crate::server::rate_limit::RateLimiter;
+#[cfg(feature = "server")]
+use crate::server::revalidate;
 #[cfg(feature = "server")]
-use crate::server::http_client::ReqwestClient;
+use crate::server::shadow::{self, Shadow};
+#[cfg(feature = "server")]
+use crate::server::slow_client;
+#[cfg(feature = "server")]
+use crate::server::validator_cache::ValidatorCache;
+#[cfg(feature = "server")]
+use axum::extract::ConnectInfo;
+#[cfg(feature = "server")]
+use tower_http::compression::{
+    predicate::NotForContentType, CompressionLayer, DefaultPredicate, Predicate,
+};
 
+#[cfg(feature = "wasm-fetch")]
+use crate::server::http_client::FetchClient;
 #[cfg(feature = "worker")]
 use crate::server::http_client::WorkerFetchClient;
 
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
+    extract::{Path, RawQuery, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
+    middleware,
     response::{IntoResponse, Response},
     routing::get,
     Extension, Router,
 };
 use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
 use std::sync::Arc;
+#[cfg(feature = "server")]
+use std::time::Duration;
+
+/// Number of camo proxies a request has passed through, sent with every upstream request
+pub const DEPTH_HEADER: &str = "x-camo-depth";
 
 #[derive(Clone)]
 pub struct AppState {
     pub config: Config,
+    pub cors: CorsPolicy,
+    pub redirect: RedirectPolicy,
+    pub referer: RefererPolicy,
+    pub session: SessionBinding,
+    pub forward: ForwardPolicy,
+    pub tenants: TenantKeys,
+    pub verifiers: Verifiers,
+    pub verify_cache: Option<Arc<VerifyCache>>,
+    #[cfg(feature = "server")]
+    pub negative_cache: Option<Arc<NegativeCache>>,
+    #[cfg(feature = "server")]
+    pub deny_list: Arc<DenyList>,
+    #[cfg(feature = "server")]
+    pub validator_cache: Option<Arc<ValidatorCache>>,
+    #[cfg(feature = "server")]
+    pub audit: Arc<AuditLog>,
+    #[cfg(feature = "server")]
+    pub host_metrics: Option<Arc<HostMetrics>>,
+    #[cfg(feature = "server")]
+    pub quotas: Option<Arc<TenantQuotas>>,
+    #[cfg(feature = "server")]
+    pub rate_limit: Option<Arc<RateLimiter>>,
+    #[cfg(feature = "server")]
+    pub shadow: Option<Arc<Shadow>>,
+    /// Shared with the default upstream client, see [`default_client`]
+    #[cfg(feature = "server")]
+    pub address_health: Option<Arc<AddressHealth>>,
+    /// Embedder's allow/deny hook, never set from the configuration
+    pub request_policy: Option<Arc<dyn RequestPolicy>>,
 }
 
 impl AppState {
-    pub fn from_config(config: &Config) -> Self {
-        AppState {
+    /// State for `config`, or why it is invalid
+    ///
+    /// Fails rather than panics, so that a configuration reload with a bad
+    /// edit can keep the state already serving.
+    pub fn from_config(config: &Config) -> io::Result<Self> {
+        let context = |what: &'static str| {
+            move |e: String| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", what, e))
+        };
+        let load = |what: &'static str| {
+            move |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", what, e))
+        };
+        Ok(AppState {
             config: config.clone(),
-        }
+            cors: CorsPolicy::new(&config.cors_origin),
+            redirect: RedirectPolicy::new(&config.redirect_https_hosts),
+            referer: RefererPolicy::new(&config.referer_hosts, &config.empty_referer),
+            session: SessionBinding::from_config(config)
+                .map_err(context("Invalid session binding"))?,
+            forward: ForwardPolicy::from_config(config)
+                .map_err(load("Failed to load upstream credentials"))?,
+            tenants: TenantKeys::from_config(config).map_err(context("Invalid tenant key"))?,
+            verifiers: Verifiers::from_config(config)
+                .map_err(context("Invalid verification key"))?,
+            verify_cache: VerifyCache::new(config.verify_cache_size).map(Arc::new),
+            #[cfg(feature = "server")]
+            negative_cache: NegativeCache::from_config(config).map(Arc::new),
+            #[cfg(feature = "server")]
+            deny_list: Arc::new(
+                DenyList::from_config(config).map_err(load("Failed to load deny list"))?,
+            ),
+            #[cfg(feature = "server")]
+            validator_cache: ValidatorCache::from_config(config).map(Arc::new),
+            #[cfg(feature = "server")]
+            audit: Arc::new(AuditLog::from_config(config)),
+            #[cfg(feature = "server")]
+            host_metrics: HostMetrics::from_config(config).map(Arc::new),
+            #[cfg(feature = "server")]
+            quotas: TenantQuotas::from_config(config)
+                .map_err(context("Invalid tenant quota"))?
+                .map(Arc::new),
+            #[cfg(feature = "server")]
+            rate_limit: RateLimiter::from_config(config)
+                .map_err(context("Invalid rate limit"))?
+                .map(Arc::new),
+            #[cfg(feature = "server")]
+            shadow: Shadow::from_config(config)
+                .map_err(context("Invalid shadow URL"))?
+                .map(Arc::new),
+            #[cfg(feature = "server")]
+            address_health: AddressHealth::from_config(config).map(Arc::new),
+            request_policy: None,
+        })
+    }
+
+    /// Ask `policy` about every request before it is served
+    pub fn with_request_policy(mut self, policy: impl RequestPolicy + 'static) -> Self {
+        self.request_policy = Some(Arc::new(policy));
+        self
     }
 }
 
-pub fn create_router(state: Arc<AppState>) -> Router {
-    let mut router = Router::new()
-        .route("/", get(health_check))
-        .route("/health", get(health_check))
-        .route("/favicon.ico", get(favicon))
+/// Proxy router fetching upstream resources with the build's default client
+pub fn create_router(state: Arc<AppState>) -> io::Result<Router> {
+    let http_client = default_client(&state)?;
+    Ok(create_router_with_client(state, http_client))
+}
+
+/// The build's default upstream client for `state`, with the wrappers its configuration asks for
+pub fn default_client(state: &AppState) -> io::Result<Arc<dyn HttpClient>> {
+    let config = &state.config;
+    #[cfg(feature = "worker")]
+    let http_client: Arc<dyn HttpClient> = Arc::new(WorkerFetchClient::new(config));
+    #[cfg(feature = "wasm-fetch")]
+    let http_client: Arc<dyn HttpClient> = Arc::new(FetchClient::new(config));
+    #[cfg(feature = "server")]
+    let http_client: Arc<dyn HttpClient> =
+        Arc::new(ReqwestClient::with_health(config, state.address_health.clone())?);
+    let http_client: Arc<dyn HttpClient> = if config.upgrade_insecure_upstream {
+        Arc::new(UpgradingClient::new(http_client, !config.https_only_upstream))
+    } else {
+        http_client
+    };
+    #[cfg(feature = "object-store")]
+    let http_client: Arc<dyn HttpClient> = Arc::new(ObjectStoreClient::new(config, http_client));
+    #[cfg(feature = "clamd")]
+    let http_client: Arc<dyn HttpClient> = match &config.clamd {
+        Some(address) => {
+            Arc::new(InspectingClient::new(http_client, Arc::new(Clamd::new(address))))
+        }
+        None => http_client,
+    };
+
+    Ok(http_client)
+}
+
+/// Proxy router on top of any upstream client
+///
+/// The request handling itself does not depend on the runtime; each build
+/// only brings the client and its entry point.
+pub fn create_router_with_client(
+    state: Arc<AppState>,
+    http_client: Arc<dyn HttpClient>,
+) -> Router {
+    let router = Router::new()
         // Query string format: /<digest>?url=<url>
         .route("/{digest}", get(proxy_query))
         // Path format: /<digest>/<encoded_url>
         .route("/{digest}/{*encoded_url}", get(proxy_path))
+        // Both again for links signed with a tenant's key
+        .route("/t/{tenant}/{digest}", get(proxy_query))
+        .route("/t/{tenant}/{digest}/{*encoded_url}", get(proxy_path));
+
+    // Only proxy requests are mirrored, so it goes on before the other routes
+    #[cfg(feature = "server")]
+    let router = match &state.shadow {
+        Some(shadow) => {
+            router.route_layer(middleware::from_fn_with_state(shadow.clone(), shadow::mirror))
+        }
+        None => router,
+    };
+
+    // Under the shed, so shed requests are never in flight
+    #[cfg(feature = "server")]
+    let router = router.route_layer(middleware::from_fn(load::track));
+
+    // Goes on last, so shed requests are not even mirrored
+    #[cfg(feature = "server")]
+    let router = match MemoryBudget::from_config(&state.config) {
+        Some(budget) => {
+            router.route_layer(middleware::from_fn_with_state(budget, memory_budget::shed))
+        }
+        None => router,
+    };
+
+    let mut router = router
+        .route("/", get(health_check))
+        .route("/health", get(health_check))
+        .route("/favicon.ico", get(favicon))
         .with_state(state.clone());
 
-    #[cfg(feature = "worker")]
+    // Liveness and readiness probes
+    #[cfg(feature = "server")]
     {
-        router = router.layer(Extension(WorkerFetchClient::new(&state.config)));
-        return router;
+        router = router
+            .route("/livez", get(health::livez))
+            .route("/readyz", get(health::readyz).with_state(state.clone()));
     }
 
+    #[cfg(feature = "server")]
+    revalidate::spawn(&state, http_client.clone());
+
+    // Add metrics endpoint if enabled
+    #[cfg(feature = "server")]
+    if state.config.metrics {
+        router = router.route("/metrics", get(metrics_handler));
+    }
+
+    router = router
+        .layer(Extension(http_client))
+        .layer(middleware::from_fn_with_state(
+            state.config.json_errors(),
+            error::error_format,
+        ));
+
     #[cfg(feature = "server")]
     {
-        // Add metrics endpoint if enabled
-        if state.config.metrics {
-            router = router.route("/metrics", get(metrics_handler));
+        if state.config.send_timeout > 0 {
+            router = router.layer(middleware::from_fn_with_state(
+                Duration::from_secs(state.config.send_timeout),
+                slow_client::send_deadline,
+            ));
         }
-        router = router.layer(Extension(ReqwestClient::new(&state.config)));
-        return router.layer(tower_http::trace::TraceLayer::new_for_http());
+        if state.config.compression {
+            router = router.layer(compression());
+        }
+        // Outside of the send deadline, whose cut-offs are not the client's doing
+        router = router.layer(middleware::from_fn(cancellation::track));
+        router = router.layer(tower_http::trace::TraceLayer::new_for_http());
     }
+
+    router
+}
+
+/// Re-compress responses the upstream sent uncompressed, based on the client's `Accept-Encoding`
+///
+/// Raster images, video and audio are already compressed and left alone;
+/// SVG and other text types are not. Responses that already carry a
+/// `Content-Encoding` from upstream pass through untouched.
+#[cfg(feature = "server")]
+fn compression() -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new().compress_when(
+        DefaultPredicate::new()
+            .and(NotForContentType::const_new("video/"))
+            .and(NotForContentType::const_new("audio/")),
+    )
 }
 
 async fn health_check() -> &'static str {
@@ -70,50 +336,248 @@ async fn favicon() -> StatusCode {
 }
 
 async fn proxy_query(
-    Path(digest): Path<String>,
-    Query(params): Query<HashMap<String, String>>,
+    Path(params): Path<HashMap<String, String>>,
+    RawQuery(query): RawQuery,
     State(state): State<Arc<AppState>>,
-    #[cfg(feature = "worker")] Extension(http_client): Extension<WorkerFetchClient>,
-    #[cfg(feature = "server")] Extension(http_client): Extension<ReqwestClient>,
+    headers: HeaderMap,
+    Extension(http_client): Extension<Arc<dyn HttpClient>>,
+    #[cfg(feature = "server")] peer: Option<Extension<ConnectInfo<SocketAddr>>>,
 ) -> Response {
-    let url = match params.get("url") {
-        Some(u) => u.clone(),
-        None => return (StatusCode::BAD_REQUEST, "Missing url parameter").into_response(),
+    #[cfg(feature = "server")]
+    let client = peer.map(|Extension(ConnectInfo(addr))| addr);
+    #[cfg(not(feature = "server"))]
+    let client = None;
+
+    let (tenant, digest) = (params.get("tenant").map(String::as_str), &params["digest"]);
+    let query = query.unwrap_or_default();
+    if let Err(e) = validate_request(&state.config, digest, &query) {
+        return e.into_response();
+    }
+
+    let url = match query_url(&state.config, &query) {
+        Ok(url) => url,
+        Err(e) => return e.into_response(),
+    };
+    let params = match link_params(&query) {
+        Ok(params) => params,
+        Err(e) => return e.into_response(),
     };
 
-    proxy_request(&state, &digest, &url, &http_client).await
+    let digest = state.config.compat().digest(digest);
+    let signer = Signer {
+        tenant,
+        digest: &digest,
+        encoding: None,
+        params,
+    };
+    proxy_request(&state, &headers, client, signer, &url, http_client.as_ref()).await
 }
 
 async fn proxy_path(
-    Path((digest, encoded_url)): Path<(String, String)>,
+    Path(params): Path<HashMap<String, String>>,
+    RawQuery(query): RawQuery,
     State(state): State<Arc<AppState>>,
-    #[cfg(feature = "worker")] Extension(http_client): Extension<WorkerFetchClient>,
-    #[cfg(feature = "server")] Extension(http_client): Extension<ReqwestClient>,
+    headers: HeaderMap,
+    Extension(http_client): Extension<Arc<dyn HttpClient>>,
+    #[cfg(feature = "server")] peer: Option<Extension<ConnectInfo<SocketAddr>>>,
 ) -> Response {
-    let url = match decode_url(&encoded_url) {
-        Some(u) => u,
-        None => return (StatusCode::BAD_REQUEST, "Invalid URL encoding").into_response(),
+    #[cfg(feature = "server")]
+    let client = peer.map(|Extension(ConnectInfo(addr))| addr);
+    #[cfg(not(feature = "server"))]
+    let client = None;
+
+    let (tenant, digest) = (params.get("tenant").map(String::as_str), &params["digest"]);
+    let compat = state.config.compat();
+    let encoded_url = compat.encoded_url(&params["encoded_url"]);
+    if let Err(e) = validate_request(&state.config, digest, encoded_url) {
+        return e.into_response();
+    }
+
+    let encodings = state.config.url_encodings();
+    let (url, encoding) =
+        match decode_url_with(encoded_url, &encodings, state.config.max_url_length) {
+            Ok(u) => (u, Some(Encoding::detect(encoded_url))),
+            Err(_) if compat.falls_back_to_query() && query.is_some() => {
+                match query_url(&state.config, query.as_deref().unwrap_or_default()) {
+                    Ok(url) => (url, None),
+                    Err(e) => return e.into_response(),
+                }
+            }
+            Err(e) => return CamoError::from(e).into_response(),
+        };
+    let params = match link_params(query.as_deref().unwrap_or_default()) {
+        Ok(params) => params,
+        Err(e) => return e.into_response(),
     };
 
-    proxy_request(&state, &digest, &url, &http_client).await
+    let digest = compat.digest(digest);
+    let signer = Signer {
+        tenant,
+        digest: &digest,
+        encoding,
+        params,
+    };
+    proxy_request(&state, &headers, client, signer, &url, http_client.as_ref()).await
+}
+
+/// The target URL of a query string format link
+fn query_url(config: &Config, query: &str) -> Result<String, CamoError> {
+    let url = config
+        .compat()
+        .url_param(query)?
+        .ok_or_else(|| CamoError::InvalidUrl("Missing url parameter".into()))?;
+    validate_url(&url, config.max_url_length)?;
+    Ok(url)
+}
+
+/// The limits a link carries in its query string, see [`LinkParams`]
+fn link_params(query: &str) -> Result<LinkParams, CamoError> {
+    LinkParams::parse(query).ok_or_else(|| CamoError::InvalidUrl("Invalid link parameters".into()))
+}
+
+/// Who signed a link: the tenant from a `/t/<tenant>` prefix, and the digest
+#[derive(Clone, Copy)]
+struct Signer<'a> {
+    tenant: Option<&'a str>,
+    digest: &'a str,
+    /// How the URL was encoded in the path; `None` when it came in the query string
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    encoding: Option<Encoding>,
+    /// Signed along with the URL
+    params: LinkParams,
 }
 
+/// Whether a cache in front of the router may answer this request, as the worker's do
+///
+/// Cached responses never reach [`proxy_signed`], so the checks that depend
+/// on who asks, or when, rather than on what is fetched are made here first:
+/// the referer, and the expiry of the link. The signature need not be, as
+/// the caches are keyed on the full link, `expires` included. Requests
+/// failing them go through the router, which refuses them as usual.
+pub fn cache_admits(state: &AppState, uri: &Uri, headers: &HeaderMap) -> bool {
+    state.referer.allows(headers)
+        && LinkParams::parse(uri.query().unwrap_or_default())
+            .is_some_and(|params| !params.is_expired(unix_now()))
+}
+
+/// How long caches in front of the router may keep the response to `uri`
+///
+/// The seconds until its link expires, `None` for links that never do.
+pub fn cache_lifetime(uri: &Uri) -> Option<u64> {
+    LinkParams::parse(uri.query().unwrap_or_default()).and_then(link_lifetime)
+}
+
+/// Cheap checks before any decoding or HMAC work: the digest must be a
+/// 40 (SHA-1), 64 (SHA-256, BLAKE3) or 128 (Ed25519) character hex string, lowercase with
+/// `--compat strict`, and the encoded URL must fit in `max_url_length`
+fn validate_request(config: &Config, digest: &str, encoded_url: &str) -> Result<(), CamoError> {
+    if encoded_url.len() > config.max_url_length {
+        return Err(CamoError::UrlTooLong(encoded_url.len()));
+    }
+    if !matches!(digest.len(), 40 | 64 | 128)
+        || !digest.bytes().all(|b| b.is_ascii_hexdigit())
+        || !config.compat().accepts_digest(digest)
+    {
+        return Err(CamoError::InvalidDigest);
+    }
+    Ok(())
+}
+
+/// Handle a decoded request, counted for its tenant if it has one
 async fn proxy_request(
     state: &Arc<AppState>,
-    digest: &str,
+    headers: &HeaderMap,
+    client: Option<SocketAddr>,
+    signer: Signer<'_>,
     url: &str,
-    #[cfg(feature = "worker")] http_client: &WorkerFetchClient,
-    #[cfg(feature = "server")] http_client: &ReqwestClient,
+    http_client: &dyn HttpClient,
 ) -> Response {
+    let response = proxy_signed(state, headers, client, signer, url, http_client).await;
+    let Some(tenant) = signer.tenant else {
+        return response;
+    };
+    if state.config.metrics {
+        state.tenants.record(tenant, response.status());
+    }
+    #[cfg(feature = "server")]
+    if let Some(quotas) = &state.quotas {
+        return quotas.meter(tenant, response);
+    }
+    response
+}
+
+/// Handle a decoded request; `client` is the peer address when known
+async fn proxy_signed(
+    state: &Arc<AppState>,
+    headers: &HeaderMap,
+    client: Option<SocketAddr>,
+    signer: Signer<'_>,
+    url: &str,
+    http_client: &dyn HttpClient,
+) -> Response {
+    let digest = signer.digest;
+
     // Record metrics
     // #[cfg(feature = "metrics")]
     // if state.config.metrics {
     //     metrics::counter!("camo_requests_total").increment(1);
     // }
 
-    // Verify digest
-    let key = state.config.key.as_ref().expect("key must be set");
-    if !verify_digest(key, url, digest) {
+    // Refuse requests that already went through too many camo proxies
+    let depth = headers
+        .get(DEPTH_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u32>().ok())
+        .unwrap_or(0);
+    if depth >= state.config.max_depth {
+        return CamoError::LoopDetected.into_response();
+    }
+
+    // Each client only gets its share, whatever it asks for
+    #[cfg(feature = "server")]
+    if let (Some(client), Some(rate_limit)) = (client, state.rate_limit.as_deref())
+        && let Err(e) = rate_limit.admit(client.ip()).await
+    {
+        return e.into_response();
+    }
+
+    // Only the allowed sites may embed our links
+    if !state.referer.allows(headers) {
+        #[cfg(feature = "server")]
+        state.audit.blocked(Reason::Referer, url, headers, client);
+        return CamoError::RefererNotAllowed.into_response();
+    }
+
+    // Verify digest, with the key of the tenant that signed the link
+    let verifier = match state.verifiers.get(signer.tenant) {
+        Some(verifier) => verifier,
+        None if signer.tenant.is_some() => return CamoError::UnknownTenant.into_response(),
+        None => panic!("key must be set"),
+    };
+    // Session-bound links only verify with the session they were signed for
+    let session = state.session.session(headers);
+    if signer.params.session && session.is_none() {
+        #[cfg(feature = "server")]
+        state.audit.blocked(Reason::Session, url, headers, client);
+        return CamoError::SessionMismatch.into_response();
+    }
+    let message = signer.params.signed_message(url, session);
+    let valid = match &state.verify_cache {
+        Some(cache) => cache.verify(signer.tenant, verifier, &message, digest),
+        None => verifier.verify(&message, digest),
+    };
+    if !valid && signer.params.session {
+        #[cfg(feature = "server")]
+        state.audit.blocked(Reason::Session, url, headers, client);
+        return CamoError::SessionMismatch.into_response();
+    }
+    if !valid {
+        #[cfg(feature = "server")]
+        state.audit.blocked(Reason::DigestMismatch, url, headers, client);
+        #[cfg(feature = "server")]
+        if state.config.debug_digest {
+            log_digest_mismatch(verifier, signer, url);
+        }
         // #[cfg(feature = "metrics")]
         // if state.config.metrics {
         //     metrics::counter!("camo_errors_total", "type" => "digest").increment(1);
@@ -121,7 +585,20 @@ async fn proxy_request(
         return CamoError::DigestMismatch.into_response();
     }
 
-    let url = match url::Url::parse(url) {
+    if signer.params.is_expired(unix_now()) {
+        return CamoError::Expired.into_response();
+    }
+
+    // Only links the tenant signed count against its quota
+    #[cfg(feature = "server")]
+    if let (Some(tenant), Some(quotas)) = (signer.tenant, state.quotas.as_deref())
+        && let Err(e) = quotas.admit(tenant).await
+    {
+        return e.into_response();
+    }
+
+    // Protocol-relative links are signed as they are, and fetched with the default scheme
+    let mut url = match url::Url::parse(&resolve_scheme(url, &state.config.default_scheme)) {
         Ok(u) => u,
         Err(_) => {
             // #[cfg(feature = "metrics")]
@@ -132,40 +609,647 @@ async fn proxy_request(
         }
     };
 
+    // Credentials in the target would leak into logs and caches
+    if has_userinfo(&url) {
+        match state.config.userinfo_policy() {
+            UserinfoPolicy::Allow => {}
+            UserinfoPolicy::Strip => {
+                let _ = url.set_username("");
+                let _ = url.set_password(None);
+            }
+            UserinfoPolicy::Reject => {
+                #[cfg(feature = "server")]
+                state.audit.blocked(Reason::Credentials, url.as_str(), headers, client);
+                return CamoError::InvalidUrl("URL contains credentials".into()).into_response();
+            }
+        }
+    }
+
     // Validate URL scheme
-    if url.scheme() != "http" && url.scheme() != "https" {
-        return CamoError::InvalidUrl("Only http/https schemes allowed".into()).into_response();
+    if !state.config.allows_scheme(url.scheme()) {
+        return CamoError::InvalidUrl(format!("Scheme {} not allowed", url.scheme()))
+            .into_response();
+    }
+
+    // Lookalikes of another name, which host policies already tell apart, but readers may not
+    if state.config.reject_mixed_scripts && url.host_str().is_some_and(is_mixed_script) {
+        #[cfg(feature = "server")]
+        state.audit.blocked(Reason::Homograph, url.as_str(), headers, client);
+        return CamoError::InvalidUrl("Host mixes scripts".into()).into_response();
+    }
+
+    // A link signed with one of our keys would only fetch this proxy again
+    if state.verifiers.all().any(|verifier| decode::is_signed_link(verifier, &url)) {
+        return CamoError::LoopDetected.into_response();
+    }
+
+    // Taken-down links stay gone even though their signature is valid
+    #[cfg(feature = "server")]
+    if state.deny_list.is_denied(digest, &url) {
+        metrics::counter!("camo_denied_total").increment(1);
+        state.audit.blocked(Reason::Denied, url.as_str(), headers, client);
+        return CamoError::Denied.into_response();
+    }
+
+    // The embedder has the last word on what is served
+    if let Some(policy) = &state.request_policy {
+        let info = ClientInfo {
+            addr: client,
+            headers,
+            tenant: signer.tenant,
+        };
+        if let Decision::Deny { status, reason } = policy.allow(&url, &info).await {
+            #[cfg(feature = "server")]
+            state.audit.blocked(Reason::Policy, url.as_str(), headers, client);
+            return CamoError::PolicyDenied(status, reason).into_response();
+        }
+    }
+
+    // Inline data: URIs need no upstream request
+    if url.scheme() == "data" {
+        let mut response = match serve_data_uri(&state.config, &url, signer.params) {
+            Ok(response) => response,
+            Err(e) => {
+                #[cfg(feature = "server")]
+                if let Some(reason) = Reason::of(&e) {
+                    state.audit.blocked(reason, url.as_str(), headers, client);
+                }
+                return e.into_response();
+            }
+        };
+        state.cors.apply(headers, response.headers_mut());
+        limit_caching(signer.params, response.headers_mut());
+        return response;
+    }
+
+    // Trusted HTTPS origins are fetched by the client itself
+    if state.redirect.should_redirect(&url) {
+        #[cfg(feature = "server")]
+        metrics::counter!("camo_redirects_total").increment(1);
+        return (StatusCode::FOUND, [(header::LOCATION, url.as_str())]).into_response();
+    }
+
+    // Same normal form as signers that canonicalize, so spellings share entries,
+    // kept apart per tenant so rotating one key can flush what it signed
+    #[cfg(feature = "server")]
+    let target = canonicalize_url(url.as_str()).into_owned();
+    #[cfg(feature = "server")]
+    let cache_key = cache::partitioned_key(signer.tenant, &target);
+
+    // Clients revalidating a still fresh image need no upstream request
+    #[cfg(feature = "server")]
+    let validator_cache = state.validator_cache.as_deref();
+    #[cfg(feature = "server")]
+    if let Some(cache) = validator_cache
+        && conditional::is_conditional(headers)
+        && let Some(not_modified) = cache.not_modified(&cache_key, headers)
+    {
+        let mut response = (StatusCode::NOT_MODIFIED, not_modified).into_response();
+        state.cors.apply(headers, response.headers_mut());
+        limit_caching(signer.params, response.headers_mut());
+        return response;
+    }
+
+    // Serve recently failed URLs from the negative cache
+    #[cfg(feature = "server")]
+    let negative_cache = state.negative_cache.as_deref();
+    #[cfg(feature = "server")]
+    if let Some(cache) = negative_cache {
+        let token = headers
+            .get(negative_cache::BYPASS_HEADER)
+            .and_then(|v| v.to_str().ok());
+        if !cache.is_bypass(token)
+            && let Some(e) = cache.get(&cache_key)
+        {
+            if let Some(reason) = Reason::of(&e) {
+                state.audit.blocked(reason, &target, headers, client);
+            }
+            let mut response = e.into_response();
+            response
+                .headers_mut()
+                .insert("x-camo-cache", HeaderValue::from_static("negative-hit"));
+            return response;
+        }
     }
 
     // Proxy the request
-    match http_client.get(url).await {
+    let mut upstream_headers = state.forward.headers(headers, &url);
+    upstream_headers.insert(DEPTH_HEADER, HeaderValue::from(depth + 1));
+    #[cfg(feature = "server")]
+    let host_metrics = state
+        .host_metrics
+        .as_deref()
+        .map(|metrics| (metrics, url.host_str().unwrap_or_default().to_string()));
+    let result = match http_client.get(url, upstream_headers).await {
+        Ok(response) if state.config.etag_max_size > 0 => {
+            generate_etag(response, state.config.etag_max_size).await
+        }
+        result => result,
+    };
+    #[cfg(feature = "server")]
+    let result = match (result, state.config.body_digest_max_size) {
+        (Ok(response), Some(max_size)) => body_digest::apply(response, max_size, &target).await,
+        (result, _) => result,
+    };
+    #[cfg(feature = "server")]
+    if let Some((metrics, host)) = host_metrics {
+        metrics.record(&host, result.as_ref().map(|_| ()));
+    }
+    match result {
         Ok(response) => {
+            #[cfg(feature = "server")]
+            if let Some(cache) = negative_cache {
+                cache.remove(&cache_key);
+            }
+            #[cfg(feature = "server")]
+            if let Some(cache) = validator_cache {
+                cache.record(&cache_key, &response.headers);
+            }
+
+            // After the caches, which are shared with links signed without these limits
+            let response = match apply_params(signer.params, response) {
+                Ok(response) => response,
+                Err(e) => {
+                    #[cfg(feature = "server")]
+                    if let Some(reason) = Reason::of(&e) {
+                        state.audit.blocked(reason, &target, headers, client);
+                    }
+                    return e.into_response();
+                }
+            };
+
+            // The client already has this version; drop the upstream body
+            if let Some(not_modified) = conditional::not_modified(headers, &response.headers) {
+                let mut response = (StatusCode::NOT_MODIFIED, not_modified).into_response();
+                state.cors.apply(headers, response.headers_mut());
+                limit_caching(signer.params, response.headers_mut());
+                return response;
+            }
+
             // #[cfg(feature = "metrics")]
             // if state.config.metrics {
             //     metrics::counter!("camo_success_total").increment(1);
             // }
-            response.into_response()
+            let mut response = response.into_response();
+            state.cors.apply(headers, response.headers_mut());
+            limit_caching(signer.params, response.headers_mut());
+            response
         }
         Err(e) => {
+            #[cfg(feature = "server")]
+            if let Some(reason) = Reason::of(&e) {
+                state.audit.blocked(reason, &target, headers, client);
+            }
             if state.config.metrics {
                 let _error_type = match &e {
                     CamoError::ContentTypeNotAllowed(_) => "content_type",
                     CamoError::ContentTooLarge(_) => "content_size",
                     CamoError::Timeout => "timeout",
                     CamoError::PrivateNetworkNotAllowed => "private_network",
+                    CamoError::NetworkNotAllowed => "blocked_network",
                     _ => "upstream",
                 };
                 // #[cfg(feature = "metrics")]
                 // metrics::counter!("camo_errors_total", "type" => error_type).increment(1);
             }
+            #[cfg(feature = "server")]
+            if let Some(cache) = negative_cache {
+                cache.record(&cache_key, &e);
+            }
             e.into_response()
         }
     }
 }
 
+/// Log what a link that failed verification was checked against, for `--debug-digest`
+///
+/// The hashed URL, with the link parameters if any, is logged quoted, so
+/// stray whitespace and escapes show, next to its canonical form and
+/// whether the digest is that of the canonical form instead, as when the
+/// signer normalized the URL first.
+#[cfg(feature = "server")]
+fn log_digest_mismatch(verifier: &dyn Verifier, signer: Signer<'_>, url: &str) {
+    let canonical = canonicalize_url(url);
+    let canonical_matches = canonical != url
+        && verifier.verify(&signer.params.signed_message(&canonical, None), signer.digest);
+    tracing::warn!(
+        digest = signer.digest,
+        scheme = verifier.name(),
+        tenant = signer.tenant,
+        format = if signer.encoding.is_some() { "path" } else { "query" },
+        encoding = signer.encoding.map(Encoding::name),
+        hashed = ?signer.params.signed_message(url, None),
+        canonical = %canonical,
+        canonical_matches,
+        "Digest mismatch"
+    );
+}
+
+/// Give a response upstream sent without validators a weak ETag from its body
+///
+/// Only bodies with a `Content-Length` of at most `max_size` are buffered
+/// and hashed; others, and partial ones, are passed through untouched.
+async fn generate_etag(
+    mut response: UpstreamResponse,
+    max_size: u64,
+) -> Result<UpstreamResponse, CamoError> {
+    let headers = &response.headers;
+    let length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if headers.contains_key(header::ETAG)
+        || headers.contains_key(header::LAST_MODIFIED)
+        || headers.contains_key(header::CONTENT_RANGE)
+        || length.is_none_or(|length| length > max_size)
+    {
+        return Ok(response);
+    }
+
+    #[cfg(feature = "server")]
+    let body = memory_budget::buffer(response.body, max_size as usize).await;
+    #[cfg(not(feature = "server"))]
+    let body = axum::body::to_bytes(response.body, max_size as usize).await;
+    let body = body.map_err(|e| match e.into_inner().downcast::<CamoError>() {
+        Ok(e) => *e,
+        Err(e) => CamoError::Upstream(e.to_string()),
+    })?;
+    response.headers.insert(header::ETAG, conditional::weak_etag(&body));
+    response.body = body.into();
+    Ok(response)
+}
+
+/// Refuse a response the link's parameters do not allow, and cap its body at their size limit
+fn apply_params(
+    params: LinkParams,
+    mut response: UpstreamResponse,
+) -> Result<UpstreamResponse, CamoError> {
+    let length = response
+        .headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    check_params(params, &response.headers, length)?;
+    if let Some(max_size) = params.max_size {
+        let body = response.body.into_data_stream();
+        response.body = axum::body::Body::from_stream(SizeLimitedStream::new(body, max_size));
+    }
+    Ok(response)
+}
+
+/// Keep caches in front of the proxy from serving a response beyond what its link allows
+fn limit_caching(params: LinkParams, headers: &mut HeaderMap) {
+    // Shared caches key on the link alone, which other sessions could then load
+    if params.session {
+        make_private(headers);
+    }
+    // Nor may they keep serving a link past its expiry, whatever upstream allows
+    if let Some(lifetime) = link_lifetime(params) {
+        cap_max_age(headers, lifetime);
+    }
+}
+
+/// Seconds until a link expires, `None` for links that never do
+fn link_lifetime(params: LinkParams) -> Option<u64> {
+    params.expires.map(|expires| expires.saturating_sub(unix_now()))
+}
+
+/// Whether a response of these headers and length, when known, fits the link's parameters
+fn check_params(
+    params: LinkParams,
+    headers: &HeaderMap,
+    length: Option<u64>,
+) -> Result<(), CamoError> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if params.class.is_some_and(|class| !class.matches(content_type)) {
+        return Err(CamoError::ContentTypeNotAllowed(content_type.to_string()));
+    }
+    if let (Some(max_size), Some(length)) = (params.max_size, length)
+        && length > max_size
+    {
+        return Err(CamoError::ContentTooLarge(length));
+    }
+    Ok(())
+}
+
+/// Seconds since the Unix epoch, for link expiry
+#[cfg(feature = "worker")]
+fn unix_now() -> u64 {
+    worker::Date::now().as_millis() / 1000
+}
+
+#[cfg(feature = "wasm-fetch")]
+fn unix_now() -> u64 {
+    (js_sys::Date::now() / 1000.0) as u64
+}
+
+#[cfg(not(any(feature = "worker", feature = "wasm-fetch")))]
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Respond with a data: URI's content, subject to the same content policy as upstream responses
+fn serve_data_uri(
+    config: &Config,
+    url: &url::Url,
+    params: LinkParams,
+) -> Result<Response, CamoError> {
+    let data = data_uri::decode(url, config.data_uri_max_size)?;
+
+    let mut upstream = HeaderMap::new();
+    let content_type = HeaderValue::from_str(&data.content_type)
+        .map_err(|_| CamoError::ContentTypeNotAllowed(data.content_type.clone()))?;
+    upstream.insert(header::CONTENT_TYPE, content_type);
+    // The content is part of the signed URL, so it never changes
+    upstream.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static("public, max-age=31536000, immutable"),
+    );
+
+    let headers = ResponseFilter::new(config).check(StatusCode::OK, &upstream)?;
+    check_params(params, &headers, Some(data.body.len() as u64))?;
+    Ok((headers, data.body).into_response())
+}
+
 #[allow(dead_code)]
 async fn metrics_handler() -> impl IntoResponse {
     // Prometheus metrics will be rendered by the metrics-exporter-prometheus crate
     // This is a placeholder - actual implementation depends on how metrics recorder is set up
     "# Metrics endpoint\n"
 }
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+    use crate::utils::{
+        crypto::generate_digest,
+        encoding::{encode_url_base64, encode_url_hex},
+    };
+    use axum::body::Body;
+    use clap::Parser;
+    use tower::ServiceExt;
+
+    const DIGEST: &str = "54cec8e46f18f585268e3972432cd8da7aec6dc1";
+
+    #[test]
+    fn test_validate_request() {
+        let mut config = Config::parse_from(["camo"]);
+        config.max_url_length = 16;
+
+        assert!(validate_request(&config, DIGEST, "68747470").is_ok());
+        assert!(validate_request(&config, &"a".repeat(64), "68747470").is_ok());
+
+        assert!(matches!(
+            validate_request(&config, &DIGEST[1..], "68747470"),
+            Err(CamoError::InvalidDigest)
+        ));
+        assert!(matches!(
+            validate_request(&config, &DIGEST.replace('c', "z"), "68747470"),
+            Err(CamoError::InvalidDigest)
+        ));
+        assert!(matches!(
+            validate_request(&config, DIGEST, &"6".repeat(17)),
+            Err(CamoError::UrlTooLong(17))
+        ));
+    }
+
+    #[test]
+    fn test_cache_admits() {
+        let config =
+            Config::parse_from(["camo", "--key", "secret", "--referer-hosts", "example.com"]);
+        let state = AppState::from_config(&config).unwrap();
+        let referer = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::REFERER, HeaderValue::from_static(value));
+            headers
+        };
+
+        let uri = Uri::from_static("/0123/abcd");
+        assert!(cache_admits(&state, &uri, &referer("https://example.com/post/1")));
+        assert!(cache_admits(&state, &uri, &HeaderMap::new()));
+        // A cached image is no more embeddable than a fetched one
+        assert!(!cache_admits(&state, &uri, &referer("https://hotlinker.example/")));
+
+        // Nor does it outlive its link
+        let expired = Uri::from_static("/0123/abcd?expires=1");
+        assert!(!cache_admits(&state, &expired, &HeaderMap::new()));
+        assert_eq!(cache_lifetime(&expired), Some(0));
+        assert_eq!(cache_lifetime(&uri), None);
+        let fresh: Uri = format!("/0123/abcd?expires={}", unix_now() + 60).parse().unwrap();
+        assert!(cache_admits(&state, &fresh, &HeaderMap::new()));
+        assert!(cache_lifetime(&fresh).is_some_and(|lifetime| lifetime <= 60));
+    }
+
+    #[tokio::test]
+    async fn test_debug_digest_not_returned() {
+        let config = Config::parse_from(["camo", "--key", "secret", "--debug-digest"]);
+        let router = create_router(Arc::new(AppState::from_config(&config).unwrap())).unwrap();
+        let target = encode_url_hex("https://example.com/a.png");
+
+        let request = axum::http::Request::get(format!("/{}/{}", DIGEST, target))
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(!String::from_utf8_lossy(&body).contains("example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_loop_detection() {
+        let config =
+            Config::parse_from(["camo", "--key", "secret", "--allowed-schemes", "https,data"]);
+        let router = create_router(Arc::new(AppState::from_config(&config).unwrap())).unwrap();
+        let link = |target: &str| {
+            format!("/{}/{}", generate_digest("secret", target), encode_url_hex(target))
+        };
+
+        let png = "data:image/png;base64,iVBORw0KGgo=";
+        let request = axum::http::Request::get(link(png))
+            .header(DEPTH_HEADER, "2")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::LOOP_DETECTED);
+
+        let own = format!("https://camo.example.com{}", link("https://example.com/a.png"));
+        let request = axum::http::Request::get(link(&own)).body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::LOOP_DETECTED);
+    }
+
+    #[tokio::test]
+    async fn test_not_modified() {
+        let config = Config::parse_from(["camo", "--key", "secret"]);
+        let state = AppState::from_config(&config).unwrap();
+        // Never resolves, so only the validator cache can answer
+        let target = "https://camo-test.invalid/a.png";
+        let mut stored = HeaderMap::new();
+        stored.insert(header::ETAG, HeaderValue::from_static("\"v1\""));
+        stored.insert(header::CACHE_CONTROL, HeaderValue::from_static("max-age=60"));
+        state.validator_cache.as_ref().unwrap().record(target, &stored);
+        let router = create_router(Arc::new(state)).unwrap();
+
+        let uri = format!("/{}/{}", generate_digest("secret", target), encode_url_hex(target));
+        let request = axum::http::Request::get(uri)
+            .header(header::IF_NONE_MATCH, "\"v1\"")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], "\"v1\"");
+    }
+
+    #[tokio::test]
+    async fn test_url_encodings() {
+        let mut config =
+            Config::parse_from(["camo", "--key", "secret", "--allowed-schemes", "data"]);
+        config.url_encodings = vec!["hex".to_string()];
+        let router = create_router(Arc::new(AppState::from_config(&config).unwrap())).unwrap();
+        let target = "data:image/png;base64,iVBORw0KGgo=";
+        let digest = generate_digest("secret", target);
+
+        let uri = format!("/{}/{}", digest, encode_url_hex(target));
+        let request = axum::http::Request::get(uri).body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let uri = format!("/{}/{}", digest, encode_url_base64(target));
+        let request = axum::http::Request::get(uri).body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("base64 urls are not accepted"));
+    }
+
+    /// Lets tenant `shop` through and denies everyone else
+    struct ShopOnly;
+
+    #[async_trait::async_trait]
+    impl RequestPolicy for ShopOnly {
+        async fn allow(&self, target: &url::Url, client: &ClientInfo<'_>) -> Decision {
+            assert_eq!(target.scheme(), "data");
+            match client.tenant {
+                Some("shop") => Decision::Allow,
+                _ => Decision::deny(StatusCode::PAYMENT_REQUIRED, "plan does not include images"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_policy() {
+        let config = Config::parse_from([
+            "camo",
+            "--key",
+            "secret",
+            "--tenant-key",
+            "shop=shop-secret",
+            "--allowed-schemes",
+            "data",
+        ]);
+        let state = AppState::from_config(&config).unwrap().with_request_policy(ShopOnly);
+        let router = create_router(Arc::new(state)).unwrap();
+        let target = "data:image/png;base64,iVBORw0KGgo=";
+
+        let digest = generate_digest("shop-secret", target);
+        let uri = format!("/t/shop/{}/{}", digest, encode_url_hex(target));
+        let request = axum::http::Request::get(uri).body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let uri = format!("/{}/{}", generate_digest("secret", target), encode_url_hex(target));
+        let request = axum::http::Request::get(uri).body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), b"request denied: plan does not include images");
+    }
+
+    /// Requests built from fuzz input: the raw path, and the input signed as a target
+    ///
+    /// None may panic or end in a 500. Mirrors `fuzz/fuzz_targets/router_path.rs`.
+    async fn check_path(router: &Router, input: &str) {
+        let digest = generate_digest("secret", input);
+        let uris = [
+            format!("/{}", input.trim_start_matches('/')),
+            format!("/{}/{}", digest, encode_url_hex(input)),
+            format!("/{}/{}", digest, encode_url_base64(input)),
+            format!("/{}?url={}", digest, urlencoding::encode(input)),
+        ];
+        for uri in uris {
+            let Ok(request) = axum::http::Request::get(uri).body(Body::empty()) else {
+                continue;
+            };
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_ne!(response.status(), StatusCode::INTERNAL_SERVER_ERROR, "{:?}", input);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fuzz_regressions() {
+        let config = Config::parse_from(["camo", "--key", "secret", "--allowed-schemes", "data"]);
+        let router = create_router(Arc::new(AppState::from_config(&config).unwrap())).unwrap();
+
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/fuzz/regressions/router_path");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let input = std::fs::read(entry.unwrap().path()).unwrap();
+            if let Ok(input) = std::str::from_utf8(&input) {
+                check_path(&router, input).await;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nested() {
+        let config = Config::parse_from(["camo", "--key", "secret", "--allowed-schemes", "data"]);
+        let app = Router::new()
+            .route("/", get(|| async { "app" }))
+            .nest("/camo", crate::server::create_router(&config).unwrap());
+
+        let target = "data:image/png;base64,iVBORw0KGgo=";
+        let uri = format!("/camo/{}/{}", generate_digest("secret", target), encode_url_hex(target));
+        let request = axum::http::Request::get(uri).body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+
+        let request = axum::http::Request::get("/").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"app");
+    }
+
+    async fn fetch_data_uri(compression: bool, content_type: &str) -> Response {
+        let mut config =
+            Config::parse_from(["camo", "--key", "secret", "--allowed-schemes", "data"]);
+        config.compression = compression;
+        let router = create_router(Arc::new(AppState::from_config(&config).unwrap())).unwrap();
+
+        let target = format!("data:{},{}", content_type, "<svg></svg>".repeat(20));
+        let digest = generate_digest("secret", &target);
+        let uri = format!("/{}/{}", digest, encode_url_hex(&target));
+        let request = axum::http::Request::get(uri)
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        router.oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_compression() {
+        let response = fetch_data_uri(true, "image/svg+xml").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+
+        let response = fetch_data_uri(true, "image/png").await;
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+
+        let response = fetch_data_uri(false, "image/svg+xml").await;
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+    }
+}
@@ -0,0 +1,87 @@
+#[cfg(feature = "secret-manager")]
+mod aws;
+#[cfg(feature = "secret-manager")]
+mod gcp;
+
+use super::config::Config;
+
+use anyhow::Context as _;
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// Resolve the HMAC key from `--key-file` or `--key-env-b64`, if either is set
+///
+/// `--key-file` wins over `--key-env-b64`, which wins over `--key`.
+pub async fn load_key(config: &mut Config) -> anyhow::Result<()> {
+    if let Some(source) = &config.key_file {
+        config.key = Some(read_source(source).await?);
+    } else if let Some(var) = &config.key_env_b64 {
+        let value = std::env::var(var)
+            .with_context(|| format!("Environment variable {} is not set", var))?;
+        config.key = Some(decode_b64(&value).with_context(|| format!("Invalid key in {}", var))?);
+    }
+
+    Ok(())
+}
+
+/// Read the key from a file, or a secret manager URI when that feature is enabled
+async fn read_source(source: &str) -> anyhow::Result<String> {
+    if let Some(secret_id) = source.strip_prefix("aws-sm://") {
+        #[cfg(feature = "secret-manager")]
+        return Ok(trim_key(&aws::fetch(secret_id).await?));
+        #[cfg(not(feature = "secret-manager"))]
+        anyhow::bail!("{} requires the secret-manager feature", secret_id);
+    }
+
+    if let Some(name) = source.strip_prefix("gcp-sm://") {
+        #[cfg(feature = "secret-manager")]
+        return Ok(trim_key(&gcp::fetch(name).await?));
+        #[cfg(not(feature = "secret-manager"))]
+        anyhow::bail!("{} requires the secret-manager feature", name);
+    }
+
+    let contents = tokio::fs::read_to_string(source)
+        .await
+        .with_context(|| format!("Failed to read key file {}", source))?;
+    Ok(trim_key(&contents))
+}
+
+/// Secret files usually end with a newline that is not part of the key
+fn trim_key(key: &str) -> String {
+    key.trim_end_matches(['\r', '\n']).to_string()
+}
+
+fn decode_b64(value: &str) -> anyhow::Result<String> {
+    let bytes = STANDARD.decode(value.trim())?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trim_key() {
+        assert_eq!(trim_key("secret\n"), "secret");
+        assert_eq!(trim_key("secret\r\n"), "secret");
+        assert_eq!(trim_key(" secret "), " secret ");
+    }
+
+    #[test]
+    fn test_decode_b64() {
+        assert_eq!(decode_b64("c2VjcmV0\n").unwrap(), "secret");
+        assert!(decode_b64("not base64!").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_key_file() {
+        let path = std::env::temp_dir().join(format!("camo-key-{}", std::process::id()));
+        std::fs::write(&path, "from-file\n").unwrap();
+
+        let mut config = <Config as clap::Parser>::parse_from(["camo", "--key", "from-arg"]);
+        config.key_file = Some(path.to_string_lossy().into_owned());
+        load_key(&mut config).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.key.as_deref(), Some("from-file"));
+    }
+}
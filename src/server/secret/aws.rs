@@ -0,0 +1,96 @@
+//! AWS Secrets Manager: `aws-sm://<secret name or ARN>`
+//!
+//! Credentials come from `AWS_ACCESS_KEY_ID`/`AWS_SECRET_ACCESS_KEY`
+//! (and `AWS_SESSION_TOKEN`), or from the EC2 instance role via IMDSv2.
+//! Requests are signed with Signature Version 4.
+
+use super::super::sigv4::{self, Credentials};
+
+use anyhow::Context as _;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::time::{Duration, SystemTime};
+
+const SERVICE: &str = "secretsmanager";
+
+pub async fn fetch(secret_id: &str) -> anyhow::Result<String> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+
+    let region = region(secret_id)?;
+    let credentials = Credentials::load(&client).await?;
+
+    let host = format!("{}.{}.amazonaws.com", SERVICE, region);
+    let body = serde_json::json!({ "SecretId": secret_id }).to_string();
+    let amz_date = sigv4::amz_date(SystemTime::now());
+
+    let mut headers = vec![
+        ("content-type", "application/x-amz-json-1.1".to_string()),
+        ("host", host.clone()),
+        ("x-amz-date", amz_date.clone()),
+    ];
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token", token.clone()));
+    }
+    headers.push(("x-amz-target", "secretsmanager.GetSecretValue".to_string()));
+
+    let authorization = sigv4::authorization(
+        &credentials,
+        &sigv4::Request {
+            method: "POST",
+            path: "/",
+            region: &region,
+            service: SERVICE,
+            amz_date: &amz_date,
+            headers: &headers,
+            payload_hash: &sigv4::sha256_hex(body.as_bytes()),
+        },
+    );
+
+    let mut request = client.post(format!("https://{}/", host)).body(body);
+    for (name, value) in headers.into_iter().filter(|(name, _)| *name != "host") {
+        request = request.header(name, value);
+    }
+    let response = request
+        .header("authorization", authorization)
+        .send()
+        .await?
+        .error_for_status()
+        .with_context(|| format!("Failed to get secret {}", secret_id))?;
+
+    let body: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    if let Some(secret) = body["SecretString"].as_str() {
+        return Ok(secret.to_string());
+    }
+    let binary = body["SecretBinary"]
+        .as_str()
+        .context("Secrets Manager response has no secret value")?;
+    Ok(String::from_utf8(STANDARD.decode(binary)?)?)
+}
+
+/// Region from the secret ARN, falling back to `AWS_REGION`/`AWS_DEFAULT_REGION`
+fn region(secret_id: &str) -> anyhow::Result<String> {
+    if let Some(arn) = secret_id.strip_prefix("arn:")
+        && let Some(region) = arn.split(':').nth(2)
+        && !region.is_empty()
+    {
+        return Ok(region.to_string());
+    }
+
+    std::env::var("AWS_REGION")
+        .or_else(|_| std::env::var("AWS_DEFAULT_REGION"))
+        .context("Set AWS_REGION or use a secret ARN")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_from_arn() {
+        assert_eq!(
+            region("arn:aws:secretsmanager:eu-west-1:123456789012:secret:camo-AbCdEf").unwrap(),
+            "eu-west-1"
+        );
+    }
+}
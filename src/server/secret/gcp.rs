@@ -0,0 +1,58 @@
+//! Google Secret Manager: `gcp-sm://projects/<project>/secrets/<secret>[/versions/<version>]`
+
+use anyhow::Context as _;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::time::Duration;
+
+/// Access token endpoint of the GCE/GKE metadata server
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+pub async fn fetch(name: &str) -> anyhow::Result<String> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+
+    let name = if name.contains("/versions/") {
+        name.to_string()
+    } else {
+        format!("{}/versions/latest", name)
+    };
+
+    let token = access_token(&client).await?;
+    let response = client
+        .get(format!("https://secretmanager.googleapis.com/v1/{}:access", name))
+        .bearer_auth(token)
+        .send()
+        .await?
+        .error_for_status()
+        .with_context(|| format!("Failed to access secret {}", name))?;
+
+    let body: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    let data = body["payload"]["data"]
+        .as_str()
+        .context("Secret Manager response has no payload")?;
+
+    Ok(String::from_utf8(STANDARD.decode(data)?)?)
+}
+
+/// `GOOGLE_OAUTH_ACCESS_TOKEN` if set, otherwise a token for the attached service account
+async fn access_token(client: &reqwest::Client) -> anyhow::Result<String> {
+    if let Ok(token) = std::env::var("GOOGLE_OAUTH_ACCESS_TOKEN") {
+        return Ok(token);
+    }
+
+    let response = client
+        .get(METADATA_TOKEN_URL)
+        .header("Metadata-Flavor", "Google")
+        .send()
+        .await
+        .context("Failed to reach the GCP metadata server")?
+        .error_for_status()?;
+
+    let body: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    body["access_token"]
+        .as_str()
+        .map(str::to_string)
+        .context("Metadata server returned no access token")
+}
@@ -0,0 +1,91 @@
+//! The proxy as a plain [`tower::Service`], for hyper, warp, actix and other frameworks
+
+use super::config::Config;
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    http::{Request, Response},
+    routing::future::RouteFuture,
+    BoxError, Router,
+};
+use std::convert::Infallible;
+use std::task::{Context, Poll};
+use tower::Service;
+
+/// Proxy handler accepting any `http::Request` whose body yields [`Bytes`]
+///
+/// Serves the same routes as the `camo` binary. Responses carry a body that
+/// implements [`http_body::Body`](HttpBody), so callers only deal with `http`
+/// and `tower` types.
+///
+/// ```
+/// use axum::http::Request;
+/// use camo::server::{config::Config, CamoService};
+/// use clap::Parser;
+/// use tower::ServiceExt;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let config = Config::parse_from(["camo", "--key", "your-secret-key"]);
+/// let request = Request::get("/health").body(String::new()).unwrap();
+///
+/// let service = CamoService::new(&config).unwrap();
+/// let response = service.oneshot(request).await.unwrap();
+/// assert!(response.status().is_success());
+/// # }
+/// ```
+///
+/// With hyper, wrap it in `hyper_util::service::TowerToHyperService`.
+#[derive(Clone)]
+pub struct CamoService {
+    router: Router,
+}
+
+impl CamoService {
+    /// Fails on an invalid configuration, like an unreadable deny list or key
+    pub fn new(config: &Config) -> std::io::Result<Self> {
+        Ok(Self {
+            router: super::create_router(config)?,
+        })
+    }
+}
+
+impl<B> Service<Request<B>> for CamoService
+where
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = RouteFuture<Infallible>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        self.router.call(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use clap::Parser;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_any_body_type() {
+        let config = Config::parse_from(["camo", "--key", "secret"]);
+        let service = CamoService::new(&config).unwrap();
+
+        let request = Request::get("/health").body(String::new()).unwrap();
+        let response = service.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = Request::get("/0000/68747470").body(String::new()).unwrap();
+        let response = service.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
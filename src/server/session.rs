@@ -0,0 +1,90 @@
+use super::config::Config;
+
+use axum::http::{header, HeaderMap, HeaderName};
+
+/// Where the session of session-bound links is read from, `--session-cookie` or `--session-header`
+///
+/// Such links are signed over the ID of the session they were made for,
+/// so they only verify in requests carrying the same ID, and sharing one
+/// outside the session gets a 403. The header is looked at first.
+#[derive(Clone, Default)]
+pub struct SessionBinding {
+    cookie: Option<String>,
+    header: Option<HeaderName>,
+}
+
+impl SessionBinding {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let header = match config.session_header.as_deref().map(str::trim) {
+            Some(name) => Some(
+                HeaderName::try_from(name)
+                    .map_err(|_| format!("--session-header {} is not a header name", name))?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            cookie: config.session_cookie.clone(),
+            header,
+        })
+    }
+
+    /// The session ID a request carries, if any
+    pub fn session<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        let from_header = self
+            .header
+            .as_ref()
+            .and_then(|name| headers.get(name))
+            .and_then(|value| value.to_str().ok())
+            .and_then(non_empty);
+        let from_cookie = || {
+            let name = self.cookie.as_deref()?;
+            headers
+                .get_all(header::COOKIE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|cookies| cookies.split(';'))
+                .find_map(|cookie| match cookie.trim().split_once('=') {
+                    Some((cookie, value)) if cookie == name => non_empty(value),
+                    _ => None,
+                })
+        };
+        from_header.or_else(from_cookie)
+    }
+}
+
+fn non_empty(value: &str) -> Option<&str> {
+    Some(value.trim()).filter(|value| !value.is_empty())
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use clap::Parser;
+
+    fn binding(args: &[&str]) -> SessionBinding {
+        SessionBinding::from_config(&Config::parse_from(["camo"].iter().chain(args))).unwrap()
+    }
+
+    #[test]
+    fn test_session() {
+        let mut headers = HeaderMap::new();
+        headers.append(header::COOKIE, HeaderValue::from_static("theme=dark; sid=abc"));
+        headers.append(header::COOKIE, HeaderValue::from_static("sid2=other"));
+
+        assert_eq!(binding(&["--session-cookie", "sid"]).session(&headers), Some("abc"));
+        assert_eq!(binding(&["--session-cookie", "sid2"]).session(&headers), Some("other"));
+        assert_eq!(binding(&["--session-cookie", "id"]).session(&headers), None);
+        assert_eq!(binding(&[]).session(&headers), None);
+
+        let both = binding(&["--session-cookie", "sid", "--session-header", "X-Session"]);
+        assert_eq!(both.session(&headers), Some("abc"));
+        headers.insert("x-session", HeaderValue::from_static("def"));
+        assert_eq!(both.session(&headers), Some("def"));
+        headers.insert("x-session", HeaderValue::from_static(" "));
+        assert_eq!(both.session(&headers), Some("abc"));
+
+        let config = Config::parse_from(["camo", "--session-header", "bad header"]);
+        assert!(SessionBinding::from_config(&config).is_err());
+    }
+}
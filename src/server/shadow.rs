@@ -0,0 +1,260 @@
+use super::config::Config;
+
+use axum::body::{Body, Bytes};
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use futures_core::Stream;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::{oneshot, Semaphore};
+use tracing::warn;
+use url::Url;
+
+/// Sent with mirrored requests, so the other deployment can tell them apart
+pub const SHADOW_HEADER: &str = "x-camo-shadow";
+
+/// Mirrored requests waiting on the other deployment; more are not mirrored
+const MAX_IN_FLIGHT: usize = 256;
+
+/// Request headers not passed on to the other deployment
+///
+/// `Accept-Encoding` is dropped so both sides are compared uncompressed.
+const DROPPED_HEADERS: [header::HeaderName; 6] = [
+    header::HOST,
+    header::CONNECTION,
+    header::TE,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+    header::ACCEPT_ENCODING,
+];
+
+/// Mirror of sampled proxy requests to another camo deployment, from `--shadow-to`
+///
+/// One in `--shadow-sample` proxy requests is sent again, with the same
+/// path, query and headers, to the other deployment once the response to
+/// the client has been sent. When the status or the body size differ the
+/// pair is logged, so a canary or a replacement for the Node camo can be
+/// checked against live traffic. Outcomes are counted in
+/// `camo_shadow_requests_total{result}`.
+///
+/// Mirroring never holds up or fails the client's request: mirrors past a
+/// limit in flight are skipped, and ones whose client went away before its
+/// body ended are compared on status alone. Requests that are themselves
+/// mirrors are never mirrored again.
+pub struct Shadow {
+    target: String,
+    sample: u64,
+    seen: AtomicU64,
+    in_flight: Arc<Semaphore>,
+    client: reqwest::Client,
+}
+
+/// Status and body size of one response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Outcome {
+    status: StatusCode,
+    bytes: Option<u64>,
+}
+
+impl Shadow {
+    /// Returns `None` without `--shadow-to`
+    pub fn from_config(config: &Config) -> Result<Option<Self>, String> {
+        let Some(target) = &config.shadow_to else {
+            return Ok(None);
+        };
+        let url = Url::parse(target).map_err(|e| format!("invalid shadow URL: {}", e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("shadow URL must be http or https, got {}", url.scheme()));
+        }
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            // Redirects are part of what is compared
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(Some(Self {
+            target: target.trim_end_matches('/').to_string(),
+            sample: config.shadow_sample,
+            seen: AtomicU64::new(0),
+            in_flight: Arc::new(Semaphore::new(MAX_IN_FLIGHT)),
+            client,
+        }))
+    }
+
+    /// Whether this request is mirrored
+    fn sampled(&self) -> bool {
+        if self.sample == 0 {
+            return false;
+        }
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed);
+        seen.is_multiple_of(self.sample)
+    }
+
+    /// Send the request to the other deployment and compare its response with `primary`
+    async fn compare(&self, path: &str, headers: HeaderMap, primary: Outcome) {
+        let url = format!("{}{}", self.target, path);
+        let shadow = match self.fetch(&url, headers).await {
+            Ok(shadow) => shadow,
+            Err(e) => {
+                warn!(path, error = %e, "Shadow request failed");
+                metrics::counter!("camo_shadow_requests_total", "result" => "error").increment(1);
+                return;
+            }
+        };
+
+        let result = if same_outcome(primary, shadow) {
+            "match"
+        } else {
+            warn!(
+                path,
+                primary_status = primary.status.as_u16(),
+                shadow_status = shadow.status.as_u16(),
+                primary_bytes = primary.bytes,
+                shadow_bytes = shadow.bytes,
+                "Shadow response differs"
+            );
+            "mismatch"
+        };
+        metrics::counter!("camo_shadow_requests_total", "result" => result).increment(1);
+    }
+
+    async fn fetch(&self, url: &str, headers: HeaderMap) -> reqwest::Result<Outcome> {
+        let mut response = self.client.get(url).headers(headers).send().await?;
+        let status = response.status();
+        let mut bytes = 0;
+        while let Some(chunk) = response.chunk().await? {
+            bytes += chunk.len() as u64;
+        }
+        Ok(Outcome {
+            status,
+            bytes: Some(bytes),
+        })
+    }
+}
+
+/// Sizes are only compared when both are known
+fn same_outcome(primary: Outcome, shadow: Outcome) -> bool {
+    primary.status == shadow.status
+        && (primary.bytes.is_none() || shadow.bytes.is_none() || primary.bytes == shadow.bytes)
+}
+
+/// Middleware mirroring sampled requests to the [`Shadow`] deployment
+pub async fn mirror(State(shadow): State<Arc<Shadow>>, request: Request, next: Next) -> Response {
+    if request.headers().contains_key(SHADOW_HEADER) || !shadow.sampled() {
+        return next.run(request).await;
+    }
+    let Ok(permit) = shadow.in_flight.clone().try_acquire_owned() else {
+        metrics::counter!("camo_shadow_requests_total", "result" => "skipped").increment(1);
+        return next.run(request).await;
+    };
+
+    let path = request
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_default();
+    let mut headers = request.headers().clone();
+    for name in DROPPED_HEADERS {
+        headers.remove(name);
+    }
+    headers.insert(SHADOW_HEADER, HeaderValue::from_static("1"));
+
+    let response = next.run(request).await;
+    let status = response.status();
+    let (sent, size) = oneshot::channel();
+    let response = response.map(|body| {
+        Body::from_stream(CountedStream {
+            inner: body.into_data_stream(),
+            bytes: 0,
+            sent: Some(sent),
+        })
+    });
+
+    tokio::spawn(async move {
+        let primary = Outcome {
+            status,
+            bytes: size.await.ok(),
+        };
+        shadow.compare(&path, headers, primary).await;
+        drop(permit);
+    });
+    response
+}
+
+/// Body stream that reports its size once it ends
+#[pin_project::pin_project]
+struct CountedStream<S> {
+    #[pin]
+    inner: S,
+    bytes: u64,
+    sent: Option<oneshot::Sender<u64>>,
+}
+
+impl<S> Stream for CountedStream<S>
+where
+    S: Stream<Item = Result<Bytes, axum::Error>>,
+{
+    type Item = Result<Bytes, axum::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let poll = this.inner.poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => *this.bytes += chunk.len() as u64,
+            Poll::Ready(None) => {
+                if let Some(sent) = this.sent.take() {
+                    let _ = sent.send(*this.bytes);
+                }
+            }
+            _ => {}
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn shadow(args: &[&str]) -> Result<Option<Shadow>, String> {
+        Shadow::from_config(&Config::parse_from(["camo", "--key", "secret"].iter().chain(args)))
+    }
+
+    #[test]
+    fn test_from_config() {
+        assert!(shadow(&[]).unwrap().is_none());
+        let shadow_to = shadow(&["--shadow-to", "https://canary.example.com/"]).unwrap();
+        assert_eq!(shadow_to.unwrap().target, "https://canary.example.com");
+        assert!(shadow(&["--shadow-to", "canary"]).is_err());
+        assert!(shadow(&["--shadow-to", "ftp://canary.example.com"]).is_err());
+    }
+
+    #[test]
+    fn test_sampled() {
+        let every_third = shadow(&["--shadow-to", "http://canary", "--shadow-sample", "3"]);
+        let every_third = every_third.unwrap().unwrap();
+        let sampled: Vec<_> = (0..6).map(|_| every_third.sampled()).collect();
+        assert_eq!(sampled, [true, false, false, true, false, false]);
+
+        let never = shadow(&["--shadow-to", "http://canary", "--shadow-sample", "0"]);
+        assert!(!never.unwrap().unwrap().sampled());
+    }
+
+    #[test]
+    fn test_same_outcome() {
+        let outcome = |status: u16, bytes| Outcome {
+            status: StatusCode::from_u16(status).unwrap(),
+            bytes,
+        };
+        assert!(same_outcome(outcome(200, Some(10)), outcome(200, Some(10))));
+        assert!(same_outcome(outcome(200, None), outcome(200, Some(10))));
+        assert!(!same_outcome(outcome(200, Some(10)), outcome(200, Some(11))));
+        assert!(!same_outcome(outcome(200, None), outcome(404, Some(10))));
+    }
+}
@@ -0,0 +1,183 @@
+use super::config::Config;
+use super::tenant::TenantKeys;
+#[cfg(feature = "signers")]
+use crate::utils::crypto::{Blake3Keyed, HmacSha256};
+#[cfg(feature = "server")]
+use crate::utils::crypto::Ed25519PublicKey;
+use crate::utils::crypto::{HmacSha1, Signer, Verifier};
+#[cfg(feature = "server")]
+use crate::utils::encoding::Encoding;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Signature scheme of links, from `--signature`
+///
+/// Every key, `--key` and the tenants' alike, is used with the same scheme.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Scheme {
+    #[default]
+    HmacSha1,
+    #[cfg(feature = "signers")]
+    HmacSha256,
+    #[cfg(feature = "signers")]
+    Blake3,
+}
+
+impl Scheme {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "hmac-sha1" => Some(Scheme::HmacSha1),
+            #[cfg(feature = "signers")]
+            "hmac-sha256" => Some(Scheme::HmacSha256),
+            #[cfg(feature = "signers")]
+            "blake3" => Some(Scheme::Blake3),
+            _ => None,
+        }
+    }
+
+    /// Signer of links with `key` in this scheme
+    pub fn signer(self, key: &str) -> Arc<dyn Signer> {
+        match self {
+            Scheme::HmacSha1 => Arc::new(HmacSha1::new(key)),
+            #[cfg(feature = "signers")]
+            Scheme::HmacSha256 => Arc::new(HmacSha256::new(key)),
+            #[cfg(feature = "signers")]
+            Scheme::Blake3 => Arc::new(Blake3Keyed::new(key)),
+        }
+    }
+}
+
+/// What links are verified with: `--key`, and each `--tenant-key` for links under its prefix
+///
+/// With `--verify-pubkey`, links without a tenant are verified with that
+/// Ed25519 public key instead of `--key`, which must then be unset.
+#[derive(Clone, Debug, Default)]
+pub struct Verifiers {
+    default: Option<Arc<dyn Verifier>>,
+    tenants: HashMap<String, Arc<dyn Verifier>>,
+}
+
+impl Verifiers {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        #[cfg(feature = "server")]
+        let scheme = config.signature();
+        #[cfg(not(feature = "server"))]
+        let scheme = Scheme::default();
+
+        let verifier = |key: &str| -> Arc<dyn Verifier> { scheme.signer(key) };
+        #[cfg_attr(not(feature = "server"), allow(unused_mut))]
+        let mut default = config.key.as_deref().map(verifier);
+        #[cfg(feature = "server")]
+        if let Some(public_key) = &config.verify_pubkey {
+            if default.is_some() {
+                return Err("--verify-pubkey and --key cannot both be set".to_string());
+            }
+            default = Some(Arc::new(parse_public_key(public_key)?));
+        }
+
+        let tenants = TenantKeys::from_config(config)?;
+        Ok(Self {
+            default,
+            tenants: tenants
+                .names()
+                .filter_map(|name| Some((name.to_string(), verifier(tenants.key(name)?))))
+                .collect(),
+        })
+    }
+
+    /// The verifier of links signed by `tenant`, or with `--key` when `None`
+    pub fn get(&self, tenant: Option<&str>) -> Option<&dyn Verifier> {
+        match tenant {
+            None => self.default.as_deref(),
+            Some(tenant) => self.tenants.get(tenant).map(Arc::as_ref),
+        }
+    }
+
+    /// Verifiers of all keys
+    pub fn all(&self) -> impl Iterator<Item = &dyn Verifier> {
+        self.default.iter().chain(self.tenants.values()).map(Arc::as_ref)
+    }
+}
+
+/// An Ed25519 public key as 64 hex digits or base64
+#[cfg(feature = "server")]
+fn parse_public_key(key: &str) -> Result<Ed25519PublicKey, String> {
+    let key = key.trim();
+    let bytes = match Encoding::detect(key) {
+        Encoding::Hex if key.len() == 64 => hex::decode(key).ok(),
+        _ => Encoding::Base64.decode(key),
+    };
+    bytes
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(Ed25519PublicKey::new)
+        .ok_or_else(|| "--verify-pubkey is not a 32-byte key in hex or base64".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "server")]
+    use clap::Parser;
+
+    const URL: &str = "https://example.com/a.png";
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn test_from_config() {
+        let args = ["camo", "--key", "secret", "--tenant-key", "shop=shop-secret"];
+        let verifiers = Verifiers::from_config(&Config::parse_from(args)).unwrap();
+        let digest = crate::utils::crypto::generate_digest("shop-secret", URL);
+        assert!(verifiers.get(Some("shop")).unwrap().verify(URL, &digest));
+        assert!(!verifiers.get(None).unwrap().verify(URL, &digest));
+        assert!(verifiers.get(Some("blog")).is_none());
+        assert_eq!(verifiers.all().count(), 2);
+
+        let args = ["camo", "--key", "secret", "--signature", "blake3"];
+        let verifiers = Verifiers::from_config(&Config::parse_from(args)).unwrap();
+        let blake3 = verifiers.get(None).unwrap();
+        assert_eq!(blake3.name(), "blake3");
+        assert!(blake3.verify(URL, &Blake3Keyed::new("secret").sign(URL)));
+        assert!(!blake3.verify(URL, &HmacSha1::new("secret").sign(URL)));
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn test_verify_pubkey() {
+        use crate::utils::crypto::Ed25519;
+        use base64::{engine::general_purpose::STANDARD, Engine};
+
+        let private_key = Ed25519::from_seed(&[3; 32]);
+        let public_key = private_key.public_key().as_bytes().to_vec();
+        let digest = private_key.sign(URL);
+        for encoded in [hex::encode(&public_key), STANDARD.encode(&public_key)] {
+            let args = ["camo", "--verify-pubkey", &encoded];
+            let verifiers = Verifiers::from_config(&Config::parse_from(args)).unwrap();
+            let verifier = verifiers.get(None).unwrap();
+            assert_eq!(verifier.name(), "ed25519");
+            assert!(verifier.verify(URL, &digest));
+            assert!(!verifier.verify("https://example.com/b.png", &digest));
+        }
+
+        let config = |args: &[&str]| Config::parse_from(["camo"].iter().chain(args));
+        let both = config(&["--key", "secret", "--verify-pubkey", &hex::encode(&public_key)]);
+        assert!(Verifiers::from_config(&both).is_err());
+        for invalid in ["abcd", "not a key", &hex::encode([0; 31])] {
+            assert!(Verifiers::from_config(&config(&["--verify-pubkey", invalid])).is_err());
+        }
+    }
+
+    #[test]
+    fn test_schemes() {
+        assert_eq!(Scheme::from_name("hmac-sha1"), Some(Scheme::HmacSha1));
+        assert_eq!(Scheme::from_name("md5"), None);
+        let signer = Scheme::HmacSha1.signer("secret");
+        assert_eq!(signer.sign(URL), crate::utils::crypto::generate_digest("secret", URL));
+        #[cfg(feature = "signers")]
+        for name in ["hmac-sha256", "blake3"] {
+            let signer = Scheme::from_name(name).unwrap().signer("secret");
+            assert_eq!(signer.name(), name);
+            assert_eq!(signer.sign(URL).len(), 64);
+        }
+    }
+}
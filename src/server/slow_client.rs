@@ -0,0 +1,258 @@
+//! Limits on how long a client may take to receive a response
+//!
+//! A client that stops reading, like a phone dropping off the network
+//! mid-download, would otherwise keep its connection, the upstream body
+//! behind it and the buffered chunks in between alive for as long as the
+//! TCP stack does. Two limits guard against it: `--client-write-timeout`
+//! closes connections that accepted no bytes for a while, and
+//! `--send-timeout` caps the time from a request arriving to the last byte
+//! of its response, which also catches clients reading just fast enough
+//! never to stall.
+
+use super::error::CamoError;
+
+use axum::body::{Body, Bytes};
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum_server::accept::Accept;
+use futures_core::Stream;
+use std::future::{Future, Ready};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+/// Count a client cut off by one of the limits in `camo_client_timeouts_total{limit}`
+fn log_timeout(limit: &'static str) {
+    tracing::debug!(limit, "Client too slow to receive the response");
+    metrics::counter!("camo_client_timeouts_total", "limit" => limit).increment(1);
+}
+
+/// Acceptor wrapping every connection in a [`WriteTimeoutStream`]
+///
+/// Goes under TLS, so that a stalled handshake or record write counts too.
+#[derive(Debug, Clone, Copy)]
+pub struct WriteTimeoutAcceptor {
+    idle: Option<Duration>,
+}
+
+impl WriteTimeoutAcceptor {
+    /// `idle` in seconds, 0 for no limit
+    pub fn new(idle: u64) -> Self {
+        Self {
+            idle: (idle > 0).then(|| Duration::from_secs(idle)),
+        }
+    }
+}
+
+impl<I, S> Accept<I, S> for WriteTimeoutAcceptor {
+    type Stream = WriteTimeoutStream<I>;
+    type Service = S;
+    type Future = Ready<io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        std::future::ready(Ok((WriteTimeoutStream::new(stream, self.idle), service)))
+    }
+}
+
+/// Connection whose writes fail once the client accepted nothing for `idle`
+///
+/// The clock starts when a write cannot go through because the socket
+/// buffer is full, and stops as soon as one does.
+pub struct WriteTimeoutStream<I> {
+    inner: I,
+    idle: Option<Duration>,
+    stalled: Option<Pin<Box<Sleep>>>,
+}
+
+impl<I> WriteTimeoutStream<I> {
+    pub fn new(inner: I, idle: Option<Duration>) -> Self {
+        Self {
+            inner,
+            idle,
+            stalled: None,
+        }
+    }
+
+    fn check<T>(&mut self, cx: &mut Context<'_>, poll: Poll<io::Result<T>>) -> Poll<io::Result<T>> {
+        let Some(idle) = self.idle else {
+            return poll;
+        };
+        if poll.is_ready() {
+            self.stalled = None;
+            return poll;
+        }
+        let stalled = self.stalled.get_or_insert_with(|| Box::pin(tokio::time::sleep(idle)));
+        if stalled.as_mut().poll(cx).is_ready() {
+            log_timeout("write");
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "client write timed out",
+            )));
+        }
+        Poll::Pending
+    }
+}
+
+impl<I: AsyncRead + Unpin> AsyncRead for WriteTimeoutStream<I> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<I: AsyncWrite + Unpin> AsyncWrite for WriteTimeoutStream<I> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        self.check(cx, poll)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        self.check(cx, poll)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.inner).poll_flush(cx);
+        self.check(cx, poll)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.inner).poll_shutdown(cx);
+        self.check(cx, poll)
+    }
+}
+
+/// Middleware giving each request `deadline` to be answered and its response sent
+///
+/// A handler still busy by then gets a 504; a body still being sent is cut
+/// short, which closes the connection.
+pub async fn send_deadline(
+    State(deadline): State<Duration>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let deadline = Instant::now() + deadline;
+    match tokio::time::timeout_at(deadline, next.run(request)).await {
+        Ok(response) => {
+            response.map(|body| Body::from_stream(DeadlineStream::new(body, deadline)))
+        }
+        Err(_) => {
+            log_timeout("send");
+            CamoError::Timeout.into_response()
+        }
+    }
+}
+
+/// Body stream that fails once `deadline` passed
+#[pin_project::pin_project]
+pub struct DeadlineStream {
+    #[pin]
+    inner: axum::body::BodyDataStream,
+    sleep: Pin<Box<Sleep>>,
+    done: bool,
+}
+
+impl DeadlineStream {
+    pub fn new(body: Body, deadline: Instant) -> Self {
+        Self {
+            inner: body.into_data_stream(),
+            sleep: Box::pin(tokio::time::sleep_until(deadline)),
+            done: false,
+        }
+    }
+}
+
+impl Stream for DeadlineStream {
+    type Item = Result<Bytes, axum::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        if *this.done {
+            return Poll::Ready(None);
+        }
+        if this.sleep.as_mut().poll(cx).is_ready() {
+            *this.done = true;
+            log_timeout("send");
+            let error = io::Error::new(io::ErrorKind::TimedOut, "response send timed out");
+            return Poll::Ready(Some(Err(axum::Error::new(error))));
+        }
+        this.inner.poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const IDLE: Duration = Duration::from_millis(50);
+
+    #[tokio::test]
+    async fn test_write_timeout() {
+        let (server, mut client) = tokio::io::duplex(16);
+        let mut server = WriteTimeoutStream::new(server, Some(IDLE));
+
+        // A client reading along is never cut off, however long it takes
+        let reader = tokio::spawn(async move {
+            let mut buf = [0; 16];
+            for _ in 0..4 {
+                tokio::time::sleep(IDLE / 2).await;
+                client.read_exact(&mut buf).await.unwrap();
+            }
+            client
+        });
+        server.write_all(&[0; 64]).await.unwrap();
+        let _client = reader.await.unwrap();
+
+        // One that stopped is, once the buffer is full
+        let error = server.write_all(&[0; 64]).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+
+        let (server, _client) = tokio::io::duplex(16);
+        let mut unlimited = WriteTimeoutStream::new(server, None);
+        let write = unlimited.write_all(&[0; 64]);
+        assert!(tokio::time::timeout(IDLE * 2, write).await.is_err());
+    }
+
+    /// Body that never sends anything
+    struct Stalled;
+
+    impl Stream for Stalled {
+        type Item = Result<Bytes, io::Error>;
+
+        fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            Poll::Pending
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deadline_stream() {
+        let deadline = Instant::now() + IDLE;
+        let body = DeadlineStream::new(Body::from("done"), deadline);
+        let bytes = to_bytes(Body::from_stream(body), usize::MAX).await.unwrap();
+        assert_eq!(bytes, "done");
+
+        let body = DeadlineStream::new(Body::from_stream(Stalled), deadline);
+        assert!(to_bytes(Body::from_stream(body), usize::MAX).await.is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use super::config::Config;
+
+use axum::http::StatusCode;
+use std::collections::HashMap;
+
+/// Key ID of links signed with `--key`, where a tenant's is its name
+///
+/// Used in metrics labels and by the admin API to name cache partitions,
+/// so no tenant may have this name.
+pub const DEFAULT_KEY_ID: &str = "default";
+
+/// HMAC keys of the tenants sharing this deployment, from `--tenant-key`
+///
+/// Each tenant signs its links with its own secret, and its links carry
+/// the tenant name as a `/t/<tenant>` path prefix so the key to verify
+/// them with is known. Links without the prefix use `--key` as before.
+#[derive(Clone, Debug, Default)]
+pub struct TenantKeys {
+    keys: HashMap<String, String>,
+}
+
+impl TenantKeys {
+    /// Parse `tenant=secret` entries; names are letters, digits, `-` and `_`
+    pub fn new(entries: &[String]) -> Result<Self, String> {
+        let mut keys = HashMap::new();
+        for entry in entries {
+            // Not echoed, as a bare secret would end up in the logs
+            let (name, key) = entry
+                .split_once('=')
+                .ok_or_else(|| "expected tenant=secret".to_string())?;
+            let name = name.trim();
+            if !is_valid_name(name) {
+                return Err(format!("invalid tenant name {:?}", name));
+            }
+            if name == DEFAULT_KEY_ID {
+                return Err(format!("tenant name {} is reserved for --key", name));
+            }
+            if key.is_empty() {
+                return Err(format!("empty key for tenant {}", name));
+            }
+            if keys.insert(name.to_string(), key.to_string()).is_some() {
+                return Err(format!("duplicate tenant {}", name));
+            }
+        }
+        Ok(Self { keys })
+    }
+
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        Self::new(&config.tenant_keys)
+    }
+
+    /// The key of `tenant`, `None` for tenants that are not configured
+    pub fn key(&self, tenant: &str) -> Option<&str> {
+        self.keys.get(tenant).map(String::as_str)
+    }
+
+    /// Names of all tenants
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.keys.keys().map(String::as_str)
+    }
+
+    /// Keys of all tenants
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.keys.values().map(String::as_str)
+    }
+
+    /// Count a request of `tenant` in `camo_tenant_requests_total{tenant, status}`
+    ///
+    /// Only configured tenants are counted, so made up names in request
+    /// paths cannot add series.
+    #[cfg(feature = "server")]
+    pub fn record(&self, tenant: &str, status: StatusCode) {
+        let Some((name, _)) = self.keys.get_key_value(tenant) else {
+            return;
+        };
+        metrics::counter!(
+            "camo_tenant_requests_total",
+            "tenant" => name.clone(),
+            "status" => status.as_str().to_string()
+        )
+        .increment(1);
+    }
+
+    #[cfg(not(feature = "server"))]
+    pub fn record(&self, _tenant: &str, _status: StatusCode) {}
+}
+
+/// Safe both as a path segment and as a metrics label
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn test_new() {
+        let tenants = TenantKeys::new(&entries(&["shop=s1", "blog_2=a=b"])).unwrap();
+        assert_eq!(tenants.key("shop"), Some("s1"));
+        // Keys may contain `=`, as base64 ones do
+        assert_eq!(tenants.key("blog_2"), Some("a=b"));
+        assert_eq!(tenants.key("other"), None);
+        assert_eq!(tenants.keys().count(), 2);
+
+        for invalid in [&["secret"][..], &["a/b=s"], &["=s"], &["shop="], &["a=1", "a=2"]] {
+            assert!(TenantKeys::new(&entries(invalid)).is_err(), "{:?}", invalid);
+        }
+        // Names the partition of links signed with `--key`
+        assert!(TenantKeys::new(&entries(&["default=s"])).is_err());
+        let error = TenantKeys::new(&entries(&["long-secret"])).unwrap_err();
+        assert!(!error.contains("long-secret"));
+    }
+}
@@ -0,0 +1,214 @@
+use super::cache::{split_key, Lookup, TtlCache};
+use super::conditional;
+use super::config::Config;
+
+use axum::http::{header, HeaderMap};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Mutex;
+
+/// Headers kept per entry: the validators and what a 304 repeats
+const KEPT_HEADERS: [header::HeaderName; 6] = [
+    header::CACHE_CONTROL,
+    header::DATE,
+    header::ETAG,
+    header::EXPIRES,
+    header::LAST_MODIFIED,
+    header::VARY,
+];
+
+/// Validators of recent upstream responses, keyed by target URL and key ID
+///
+/// Answers conditional requests for images that are still fresh with
+/// `304 Not Modified`, without another upstream request. Entries live as
+/// long as the response's `max-age` allows and only hold the headers needed
+/// to build the 304, never the body.
+///
+/// With background revalidation on, lookups and updates of each URL are
+/// also counted, so the [revalidator](super::revalidate) knows which
+/// entries are worth keeping fresh.
+pub struct ValidatorCache {
+    cache: TtlCache<HeaderMap>,
+    capacity: usize,
+    requests: Option<Mutex<HashMap<String, u64>>>,
+}
+
+impl ValidatorCache {
+    /// Returns `None` when the cache is disabled by configuration
+    pub fn from_config(config: &Config) -> Option<Self> {
+        (config.validator_cache_size > 0).then(|| Self {
+            cache: TtlCache::new(config.validator_cache_size),
+            capacity: config.validator_cache_size,
+            requests: (config.revalidate_interval > 0).then(Mutex::default),
+        })
+    }
+
+    /// Headers for a 304 when the request's validators match a fresh entry
+    ///
+    /// Counts the lookup in `camo_conditional_requests_total` as a `hit`, a
+    /// `changed` validator or a `miss`, so the hit rate can be graphed, with
+    /// the key ID of the [partitioned key](super::cache::partitioned_key).
+    pub fn not_modified(&self, url: &str, request: &HeaderMap) -> Option<HeaderMap> {
+        self.count(url);
+        let (result, headers) = match self.cache.lookup(url) {
+            Lookup::Fresh(stored) => match conditional::not_modified(request, &stored) {
+                Some(headers) => ("hit", Some(headers)),
+                None => ("changed", None),
+            },
+            Lookup::Stale | Lookup::Miss => ("miss", None),
+        };
+        metrics::counter!(
+            "camo_conditional_requests_total",
+            "result" => result,
+            "key" => split_key(url).0.to_string()
+        )
+        .increment(1);
+        headers
+    }
+
+    /// Remember the validators of a successful response that may be reused
+    pub fn record(&self, url: &str, headers: &HeaderMap) {
+        self.count(url);
+        self.refresh(url, headers);
+    }
+
+    /// Like [`record`](Self::record), without counting it as a request for the URL
+    pub fn refresh(&self, url: &str, headers: &HeaderMap) {
+        if !headers.contains_key(header::ETAG) && !headers.contains_key(header::LAST_MODIFIED) {
+            return;
+        }
+        let Some(ttl) = conditional::freshness(headers) else {
+            self.cache.remove(url);
+            return;
+        };
+
+        let mut kept = HeaderMap::new();
+        for name in KEPT_HEADERS {
+            for value in headers.get_all(&name) {
+                kept.append(name.clone(), value.clone());
+            }
+        }
+        self.cache.insert(url, kept, ttl);
+    }
+
+    /// Forget the validators for a URL; returns whether there were any
+    pub fn remove(&self, url: &str) -> bool {
+        self.cache.remove(url)
+    }
+
+    /// Forget the validators under matching keys; returns how many there were
+    pub fn remove_matching(&self, matches: impl FnMut(&str) -> bool) -> usize {
+        self.cache.remove_matching(matches)
+    }
+
+    /// Up to `n` entries, the most requested since the last call first
+    ///
+    /// Expired entries are included, as revalidating them is how they get
+    /// fresh again. Request counts start over with every call.
+    pub fn hottest(&self, n: usize) -> Vec<(String, HeaderMap)> {
+        let Some(requests) = &self.requests else {
+            return Vec::new();
+        };
+        let mut requests: Vec<_> = mem::take(&mut *requests.lock().unwrap()).into_iter().collect();
+        requests.sort_unstable_by_key(|(_, count)| Reverse(*count));
+        requests
+            .into_iter()
+            .filter_map(|(url, _)| Some((url.clone(), self.cache.peek(&url)?)))
+            .take(n)
+            .collect()
+    }
+
+    /// Count a request for `url`; URLs beyond the capacity are not tracked until the next run
+    fn count(&self, url: &str) {
+        let Some(requests) = &self.requests else {
+            return;
+        };
+        let mut requests = requests.lock().unwrap();
+        if let Some(count) = requests.get_mut(url) {
+            *count += 1;
+        } else if requests.len() < self.capacity {
+            requests.insert(url.to_string(), 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    fn with_capacity(capacity: usize, counted: bool) -> ValidatorCache {
+        ValidatorCache {
+            cache: TtlCache::new(capacity),
+            capacity,
+            requests: counted.then(Mutex::default),
+        }
+    }
+
+    #[test]
+    fn test_record_and_match() {
+        let cache = with_capacity(16, false);
+        let url = "https://example.com/a.png";
+        let request = headers(&[("if-none-match", "\"v1\"")]);
+
+        assert!(cache.not_modified(url, &request).is_none());
+
+        let response = headers(&[
+            ("etag", "\"v1\""),
+            ("cache-control", "max-age=60"),
+            ("content-type", "image/png"),
+        ]);
+        cache.record(url, &response);
+        let matched = cache.not_modified(url, &request).unwrap();
+        assert_eq!(matched[header::ETAG], "\"v1\"");
+        assert!(!matched.contains_key(header::CONTENT_TYPE));
+        assert!(cache.not_modified(url, &headers(&[("if-none-match", "\"v0\"")])).is_none());
+
+        // A response that may not be reused replaces the entry
+        cache.record(url, &headers(&[("etag", "\"v2\""), ("cache-control", "no-store")]));
+        assert!(cache.not_modified(url, &request).is_none());
+
+        // Without validators there is nothing to compare against
+        cache.record(url, &headers(&[("cache-control", "max-age=60")]));
+        assert!(cache.not_modified(url, &headers(&[("if-none-match", "*")])).is_none());
+    }
+
+    #[test]
+    fn test_hottest() {
+        let cache = with_capacity(2, true);
+        let response = headers(&[("etag", "\"v1\""), ("cache-control", "max-age=60")]);
+        let request = headers(&[("if-none-match", "\"v1\"")]);
+
+        cache.record("https://example.com/a.png", &response);
+        cache.record("https://example.com/b.png", &response);
+        for _ in 0..3 {
+            cache.not_modified("https://example.com/b.png", &request);
+        }
+        // Not cached, and past the capacity
+        cache.not_modified("https://example.com/c.png", &request);
+
+        let hottest = cache.hottest(5);
+        let urls: Vec<_> = hottest.iter().map(|(url, _)| url.as_str()).collect();
+        assert_eq!(urls, ["https://example.com/b.png", "https://example.com/a.png"]);
+        assert_eq!(hottest[0].1[header::ETAG], "\"v1\"");
+        assert_eq!(cache.hottest(1).len(), 0);
+
+        cache.not_modified("https://example.com/a.png", &request);
+        cache.refresh("https://example.com/b.png", &response);
+        assert_eq!(cache.hottest(5).len(), 1);
+
+        // Not counted unless revalidation is on
+        let untracked = with_capacity(2, false);
+        untracked.record("https://example.com/a.png", &response);
+        assert!(untracked.hottest(5).is_empty());
+    }
+}
@@ -0,0 +1,129 @@
+use crate::utils::crypto::Verifier;
+
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Mutex;
+
+/// Recent digest verification results, so hot URLs skip the HMAC
+///
+/// Approximates an LRU with two generations: entries are added to the
+/// current one, and looking one up in the previous generation moves it
+/// back into the current one. Once the current generation holds half the
+/// capacity it becomes the previous one, dropping whatever was not used
+/// since the last switch. Both outcomes are kept, so a forged link sent
+/// over and over is rejected just as cheaply.
+///
+/// Entries are keyed by tenant, digest and the full URL, never a hash of
+/// them, so a collision cannot pass a link the key did not sign.
+pub struct VerifyCache {
+    generation: usize,
+    entries: Mutex<Generations>,
+}
+
+#[derive(Default)]
+struct Generations {
+    current: HashMap<String, bool>,
+    previous: HashMap<String, bool>,
+}
+
+impl VerifyCache {
+    /// Returns `None` when `capacity` is 0
+    pub fn new(capacity: usize) -> Option<Self> {
+        (capacity > 0).then(|| Self {
+            generation: capacity.div_ceil(2),
+            entries: Mutex::default(),
+        })
+    }
+
+    /// Whether `digest` is the digest of `url` by `verifier`, which holds the key of `tenant`
+    pub fn verify(
+        &self,
+        tenant: Option<&str>,
+        verifier: &dyn Verifier,
+        url: &str,
+        digest: &str,
+    ) -> bool {
+        // Tenant names and digests have no newlines, so the parts cannot run together
+        let entry = format!("{}\n{}\n{}", tenant.unwrap_or_default(), digest, url);
+        if let Some(valid) = self.lookup(&entry) {
+            return valid;
+        }
+        let valid = verifier.verify(url, digest);
+        self.insert(entry, valid);
+        valid
+    }
+
+    fn lookup(&self, entry: &str) -> Option<bool> {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(valid) = entries.current.get(entry) {
+            return Some(*valid);
+        }
+        let (entry, valid) = entries.previous.remove_entry(entry)?;
+        self.insert_locked(&mut entries, entry, valid);
+        Some(valid)
+    }
+
+    fn insert(&self, entry: String, valid: bool) {
+        let mut entries = self.entries.lock().unwrap();
+        self.insert_locked(&mut entries, entry, valid);
+    }
+
+    fn insert_locked(&self, entries: &mut Generations, entry: String, valid: bool) {
+        if entries.current.len() >= self.generation {
+            entries.previous = mem::take(&mut entries.current);
+        }
+        entries.current.insert(entry, valid);
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        let entries = self.entries.lock().unwrap();
+        entries.current.len() + entries.previous.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::crypto::{generate_digest, HmacSha1};
+
+    const URL: &str = "https://example.com/a.png";
+
+    #[test]
+    fn test_verify() {
+        let cache = VerifyCache::new(8).unwrap();
+        let (secret, other) = (HmacSha1::new("secret"), HmacSha1::new("other"));
+        let digest = generate_digest("secret", URL);
+
+        for _ in 0..2 {
+            assert!(cache.verify(None, &secret, URL, &digest));
+            assert!(!cache.verify(None, &secret, "https://example.com/b.png", &digest));
+        }
+        assert_eq!(cache.len(), 2);
+
+        // Results under one tenant's key are not reused for another
+        assert!(!cache.verify(Some("shop"), &other, URL, &digest));
+        assert!(cache.verify(None, &secret, URL, &digest));
+        assert!(VerifyCache::new(0).is_none());
+    }
+
+    #[test]
+    fn test_keeps_recently_used() {
+        let cache = VerifyCache::new(4).unwrap();
+        let urls: Vec<_> = (0..5).map(|i| format!("https://example.com/{}.png", i)).collect();
+        let secret = HmacSha1::new("secret");
+        let verify = |url: &str| cache.verify(None, &secret, url, "0");
+
+        verify(&urls[0]);
+        verify(&urls[1]);
+        verify(&urls[2]);
+        // Moves back into the current generation, ahead of 1
+        assert_eq!(cache.lookup(&format!("\n0\n{}", urls[0])), Some(false));
+        verify(&urls[3]);
+        verify(&urls[4]);
+
+        assert!(cache.len() <= 4);
+        assert!(cache.lookup(&format!("\n0\n{}", urls[0])).is_some());
+        assert!(cache.lookup(&format!("\n0\n{}", urls[1])).is_none());
+    }
+}
@@ -0,0 +1,71 @@
+//! `camo` filters for template engines
+//!
+//! Templates sign image URLs at render time with a signer configured once
+//! through [`set_signer`], instead of passing a [`CamoUrl`] into every
+//! context:
+//!
+//! ```text
+//! <img src="{{ avatar_url | camo }}">
+//! ```
+//!
+//! Each engine has its own module, enabled by the feature of the same name.
+
+#[cfg(feature = "askama")]
+pub mod askama;
+#[cfg(feature = "minijinja")]
+pub mod minijinja;
+#[cfg(feature = "tera")]
+pub mod tera;
+
+use crate::CamoUrl;
+use std::sync::RwLock;
+
+static SIGNER: RwLock<Option<Signer>> = RwLock::new(None);
+
+struct Signer {
+    camo: CamoUrl,
+    base: String,
+}
+
+/// Configure the signer and proxy base URL used by every `camo` filter
+///
+/// Can be called again, e.g. after rotating the key.
+pub fn set_signer(camo: CamoUrl, base: impl Into<String>) {
+    *SIGNER.write().unwrap() = Some(Signer {
+        camo,
+        base: base.into(),
+    });
+}
+
+/// Sign `url` with the global signer, `None` until [`set_signer`] is called
+pub fn sign(url: &str) -> Option<String> {
+    let signer = SIGNER.read().unwrap();
+    let signer = signer.as_ref()?;
+    Some(signer.camo.sign_url(url, &signer.base))
+}
+
+/// Error message of the filters when no signer is configured
+const NOT_CONFIGURED: &str = "camo filter used before camo::template::set_signer";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Key and base used by the engine tests, which share the global signer
+    pub(super) fn configure() {
+        set_signer(CamoUrl::new("secret"), "https://camo.example.com");
+    }
+
+    pub(super) fn expected(url: &str) -> String {
+        CamoUrl::new("secret").sign_url(url, "https://camo.example.com")
+    }
+
+    #[test]
+    fn test_sign() {
+        configure();
+        assert_eq!(
+            sign("http://example.com/a.png").as_deref(),
+            Some(expected("http://example.com/a.png").as_str())
+        );
+    }
+}
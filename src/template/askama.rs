@@ -0,0 +1,49 @@
+//! `camo` filter for [Askama](https://docs.rs/askama)
+//!
+//! Askama looks filters up in a `filters` module next to the template:
+//!
+//! ```
+//! use askama::Template;
+//!
+//! mod filters {
+//!     pub use camo::template::askama::camo;
+//! }
+//!
+//! #[derive(Template)]
+//! #[template(source = r#"<img src="{{ url|camo }}">"#, ext = "html")]
+//! struct Avatar<'a> {
+//!     url: &'a str,
+//! }
+//! ```
+
+use std::fmt::Display;
+
+#[askama::filter_fn]
+pub fn camo(url: impl Display, _env: &dyn askama::Values) -> askama::Result<String> {
+    super::sign(&url.to_string()).ok_or_else(|| askama::Error::custom(super::NOT_CONFIGURED))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::template::tests::{configure, expected};
+    use askama::Template;
+
+    mod filters {
+        pub use super::super::camo;
+    }
+
+    #[derive(Template)]
+    #[template(source = "{{ url|camo }}", ext = "txt")]
+    struct Image<'a> {
+        url: &'a str,
+    }
+
+    #[test]
+    fn test_filter() {
+        configure();
+        let image = Image {
+            url: "http://example.com/a.png",
+        };
+        assert_eq!(image.render().unwrap(), expected("http://example.com/a.png"));
+    }
+}
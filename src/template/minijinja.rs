@@ -0,0 +1,40 @@
+//! `camo` filter and function for [MiniJinja](https://docs.rs/minijinja)
+//!
+//! ```text
+//! {{ avatar_url | camo }}
+//! {{ camo(avatar_url) }}
+//! ```
+
+use minijinja::{Environment, Error, ErrorKind};
+
+/// Register the `camo` filter and function on `env`
+pub fn register(env: &mut Environment) {
+    env.add_filter("camo", camo);
+    env.add_function("camo", camo);
+}
+
+pub fn camo(url: &str) -> Result<String, Error> {
+    super::sign(url).ok_or_else(|| Error::new(ErrorKind::InvalidOperation, super::NOT_CONFIGURED))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::tests::{configure, expected};
+
+    #[test]
+    fn test_filter_and_function() {
+        configure();
+        let mut env = Environment::new();
+        register(&mut env);
+
+        let rendered = env
+            .render_str(
+                "{{ url | camo }} {{ camo(url) }}",
+                minijinja::context! { url => "http://example.com/a.png" },
+            )
+            .unwrap();
+        let expected = expected("http://example.com/a.png");
+        assert_eq!(rendered, format!("{} {}", expected, expected));
+    }
+}
@@ -0,0 +1,58 @@
+//! `camo` filter and function for [Tera](https://keats.github.io/tera/)
+//!
+//! ```text
+//! {{ avatar_url | camo }}
+//! {{ camo(url=avatar_url) }}
+//! ```
+
+use std::collections::HashMap;
+use tera::{Error, Result, Tera, Value};
+
+/// Register the `camo` filter and function on `tera`
+pub fn register(tera: &mut Tera) {
+    tera.register_filter("camo", filter);
+    tera.register_function("camo", function);
+}
+
+pub fn filter(value: &Value, _args: &HashMap<String, Value>) -> Result<Value> {
+    sign(value)
+}
+
+pub fn function(args: &HashMap<String, Value>) -> Result<Value> {
+    match args.get("url") {
+        Some(url) => sign(url),
+        None => Err(Error::msg("camo() requires a `url` argument")),
+    }
+}
+
+fn sign(url: &Value) -> Result<Value> {
+    let url = url
+        .as_str()
+        .ok_or_else(|| Error::msg("camo expects a string URL"))?;
+    let signed = super::sign(url).ok_or_else(|| Error::msg(super::NOT_CONFIGURED))?;
+    Ok(Value::String(signed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::tests::{configure, expected};
+
+    #[test]
+    fn test_filter_and_function() {
+        configure();
+        let mut tera = Tera::default();
+        register(&mut tera);
+
+        tera.add_raw_template("t", "{{ url | camo }} {{ camo(url=url) }}")
+            .unwrap();
+        let mut context = tera::Context::new();
+        context.insert("url", "http://example.com/a.png");
+
+        let expected = expected("http://example.com/a.png");
+        assert_eq!(
+            tera.render("t", &context).unwrap(),
+            format!("{} {}", expected, expected)
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use std::borrow::Cow;
+
+/// Normalized form of a target URL, so equivalent spellings sign alike
+///
+/// Parsing with the `url` crate lowercases the scheme and host, turns
+/// internationalized hosts into punycode, drops the default port, resolves
+/// dot segments and percent-encodes what must be.
+/// Existing escapes are then uppercased, and those of letters, digits,
+/// `-`, `_` and `~` are decoded. `%2E` stays encoded so that decoding can
+/// not produce new dot segments. Strings that do not parse are returned as is.
+pub fn canonicalize_url(url: &str) -> Cow<'_, str> {
+    let Ok(parsed) = url::Url::parse(url) else {
+        return Cow::Borrowed(url);
+    };
+
+    let serialized = parsed.as_str();
+    let bytes = serialized.as_bytes();
+    let mut out = String::with_capacity(serialized.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = (bytes[i] == b'%')
+            .then(|| serialized.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escape {
+            Some(b) if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'~') => {
+                out.push(b as char);
+                i += 3;
+            }
+            Some(_) => {
+                out.push('%');
+                out.push_str(&serialized[i + 1..i + 3].to_ascii_uppercase());
+                i += 3;
+            }
+            None => {
+                let len = serialized[i..].chars().next().map_or(1, char::len_utf8);
+                out.push_str(&serialized[i..i + len]);
+                i += len;
+            }
+        }
+    }
+
+    if out == url {
+        Cow::Borrowed(url)
+    } else {
+        Cow::Owned(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonicalize_url() {
+        let cases = [
+            ("HTTP://Example.COM/a%2fb", "http://example.com/a%2Fb"),
+            ("https://example.com:443/a.png", "https://example.com/a.png"),
+            ("http://example.com:8080/a.png", "http://example.com:8080/a.png"),
+            ("http://example.com", "http://example.com/"),
+            ("http://example.com/%7euser/%41b%2e/../c", "http://example.com/~user/c"),
+            ("http://example.com/a b/ü.png?q=x y", "http://example.com/a%20b/%C3%BC.png?q=x%20y"),
+            ("http://example.com/a%2e/b", "http://example.com/a%2E/b"),
+            ("not a url", "not a url"),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(canonicalize_url(url), expected, "{}", url);
+            assert_eq!(canonicalize_url(expected), expected, "{}", expected);
+        }
+
+        assert!(matches!(canonicalize_url("http://example.com/a.png"), Cow::Borrowed(_)));
+    }
+}
@@ -1,13 +1,256 @@
 use hmac::{Hmac, Mac};
 use sha1::Sha1;
+use std::fmt;
+use std::sync::Arc;
 
-type HmacSha1 = Hmac<Sha1>;
+/// Checks the signatures of links
+///
+/// The proxy only needs this half of a scheme, so with a public-key
+/// scheme it never holds what it takes to sign links itself.
+pub trait Verifier: Send + Sync {
+    /// Name of the scheme, as `--signature` takes it
+    fn name(&self) -> &'static str;
+
+    /// Whether `signature`, in lowercase hex, is a signature of `url`
+    fn verify(&self, url: &str, signature: &str) -> bool;
+}
+
+/// Signs links, and checks their signatures
+///
+/// Signatures are written in links as lowercase hex. Implemented by
+/// [`HmacSha1`], the scheme of the original camo, and with the `signers`
+/// feature by [`HmacSha256`], [`Blake3Keyed`] and [`Ed25519`]; anything
+/// else that signs a string can be plugged in with `CamoUrl::with_signer`.
+pub trait Signer: Verifier {
+    /// Signature of `url`, in lowercase hex
+    fn sign(&self, url: &str) -> String;
+}
+
+impl<T: Verifier + ?Sized> Verifier for Arc<T> {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn verify(&self, url: &str, signature: &str) -> bool {
+        (**self).verify(url, signature)
+    }
+}
+
+impl<T: Signer + ?Sized> Signer for Arc<T> {
+    fn sign(&self, url: &str) -> String {
+        (**self).sign(url)
+    }
+}
+
+impl fmt::Debug for dyn Verifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl fmt::Debug for dyn Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// HMAC-SHA1 with a shared secret, 40 hex digits
+pub struct HmacSha1 {
+    key: String,
+}
+
+impl HmacSha1 {
+    pub fn new(key: impl Into<String>) -> Self {
+        Self { key: key.into() }
+    }
+}
+
+impl Verifier for HmacSha1 {
+    fn name(&self) -> &'static str {
+        "hmac-sha1"
+    }
+
+    fn verify(&self, url: &str, signature: &str) -> bool {
+        verify_digest(&self.key, url, signature)
+    }
+}
+
+impl Signer for HmacSha1 {
+    fn sign(&self, url: &str) -> String {
+        generate_digest(&self.key, url)
+    }
+}
+
+/// HMAC-SHA256 with a shared secret, 64 hex digits
+#[cfg(feature = "signers")]
+pub struct HmacSha256 {
+    key: ring::hmac::Key,
+}
+
+#[cfg(feature = "signers")]
+impl HmacSha256 {
+    pub fn new(key: impl AsRef<[u8]>) -> Self {
+        Self {
+            key: ring::hmac::Key::new(ring::hmac::HMAC_SHA256, key.as_ref()),
+        }
+    }
+}
+
+#[cfg(feature = "signers")]
+impl Verifier for HmacSha256 {
+    fn name(&self) -> &'static str {
+        "hmac-sha256"
+    }
+
+    fn verify(&self, url: &str, signature: &str) -> bool {
+        constant_time_eq(self.sign(url).as_bytes(), signature.as_bytes())
+    }
+}
+
+#[cfg(feature = "signers")]
+impl Signer for HmacSha256 {
+    fn sign(&self, url: &str) -> String {
+        hex::encode(ring::hmac::sign(&self.key, url.as_bytes()))
+    }
+}
+
+/// Keyed BLAKE3 with a shared secret, 64 hex digits
+///
+/// BLAKE3 takes a 32-byte key, which is derived from the secret with
+/// BLAKE3's own key derivation, so any secret works as with HMAC.
+#[cfg(feature = "signers")]
+pub struct Blake3Keyed {
+    key: [u8; 32],
+}
+
+#[cfg(feature = "signers")]
+impl Blake3Keyed {
+    const CONTEXT: &str = "camo-rs 2025-01-01 link signature key";
+
+    pub fn new(key: impl AsRef<[u8]>) -> Self {
+        Self {
+            key: blake3::derive_key(Self::CONTEXT, key.as_ref()),
+        }
+    }
+}
+
+#[cfg(feature = "signers")]
+impl Verifier for Blake3Keyed {
+    fn name(&self) -> &'static str {
+        "blake3"
+    }
+
+    fn verify(&self, url: &str, signature: &str) -> bool {
+        constant_time_eq(self.sign(url).as_bytes(), signature.as_bytes())
+    }
+}
+
+#[cfg(feature = "signers")]
+impl Signer for Blake3Keyed {
+    fn sign(&self, url: &str) -> String {
+        blake3::keyed_hash(&self.key, url.as_bytes()).to_hex().to_string()
+    }
+}
+
+/// Ed25519 with a private key, 128 hex digits
+///
+/// Links are checked with the public key alone, see [`Ed25519PublicKey`],
+/// so whoever checks them cannot sign new ones.
+#[cfg(feature = "signers")]
+pub struct Ed25519 {
+    key_pair: ring::signature::Ed25519KeyPair,
+}
+
+#[cfg(feature = "signers")]
+impl Ed25519 {
+    /// The key pair of a 32-byte private key
+    pub fn from_seed(seed: &[u8; 32]) -> Self {
+        Self {
+            key_pair: ring::signature::Ed25519KeyPair::from_seed_unchecked(seed)
+                .expect("any 32 bytes are an Ed25519 seed"),
+        }
+    }
+
+    /// The public key links are checked with
+    pub fn public_key(&self) -> Ed25519PublicKey {
+        use ring::signature::KeyPair;
+
+        let mut key = [0; 32];
+        key.copy_from_slice(self.key_pair.public_key().as_ref());
+        Ed25519PublicKey::new(key)
+    }
+}
+
+#[cfg(feature = "signers")]
+impl Verifier for Ed25519 {
+    fn name(&self) -> &'static str {
+        "ed25519"
+    }
+
+    fn verify(&self, url: &str, signature: &str) -> bool {
+        self.public_key().verify(url, signature)
+    }
+}
+
+#[cfg(feature = "signers")]
+impl Signer for Ed25519 {
+    fn sign(&self, url: &str) -> String {
+        hex::encode(self.key_pair.sign(url.as_bytes()))
+    }
+}
+
+/// Ed25519 public key, checking links signed with its private key
+#[cfg(feature = "signers")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ed25519PublicKey([u8; 32]);
+
+#[cfg(feature = "signers")]
+impl Ed25519PublicKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+#[cfg(feature = "signers")]
+impl Verifier for Ed25519PublicKey {
+    fn name(&self) -> &'static str {
+        "ed25519"
+    }
+
+    fn verify(&self, url: &str, signature: &str) -> bool {
+        // Signatures are lowercase in links, like the HMAC digests
+        if signature.bytes().any(|b| b.is_ascii_uppercase()) {
+            return false;
+        }
+        let Ok(signature) = hex::decode(signature) else {
+            return false;
+        };
+        ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, self.0)
+            .verify(url.as_bytes(), &signature)
+            .is_ok()
+    }
+}
+
+type HmacSha1Mac = Hmac<Sha1>;
 
 /// Generate HMAC-SHA1 digest for a URL
 pub fn generate_digest(key: &str, url: &str) -> String {
-    let mut mac = HmacSha1::new_from_slice(key.as_bytes()).expect("HMAC accepts any key size");
+    let digest = digest_hex(key, url);
+    std::str::from_utf8(&digest).expect("hex is ASCII").to_string()
+}
+
+/// HMAC-SHA1 digest for a URL as 40 lowercase hex digits, without allocating
+pub fn digest_hex(key: &str, url: &str) -> [u8; 40] {
+    let mut mac = HmacSha1Mac::new_from_slice(key.as_bytes()).expect("HMAC accepts any key size");
     mac.update(url.as_bytes());
-    hex::encode(mac.finalize().into_bytes())
+    let mut digest = [0u8; 40];
+    hex::encode_to_slice(mac.finalize().into_bytes(), &mut digest)
+        .expect("a SHA-1 digest is 20 bytes");
+    digest
 }
 
 /// Verify HMAC-SHA1 digest (returns bool)
@@ -17,7 +260,7 @@ pub fn verify_digest(key: &str, url: &str, digest: &str) -> bool {
 }
 
 /// Constant-time string comparison
-fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
     if a.len() != b.len() {
         return false;
     }
@@ -32,6 +275,7 @@ fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_hmac_generation() {
@@ -50,4 +294,56 @@ mod tests {
 
         assert!(!verify_digest(key, url, "invalid-digest"));
     }
+
+    #[cfg(feature = "signers")]
+    #[test]
+    fn test_signers() {
+        let url = "https://example.com/image.png";
+        let signers: [(Box<dyn Signer>, usize); 4] = [
+            (Box::new(HmacSha1::new("secret")), 40),
+            (Box::new(HmacSha256::new("secret")), 64),
+            (Box::new(Blake3Keyed::new("secret")), 64),
+            (Box::new(Ed25519::from_seed(&[7; 32])), 128),
+        ];
+        for (signer, len) in &signers {
+            let signature = signer.sign(url);
+            assert_eq!(signature.len(), *len, "{:?}", signer);
+            assert!(!signature.bytes().any(|b| b.is_ascii_uppercase()));
+            assert!(signer.verify(url, &signature), "{:?}", signer);
+            assert!(!signer.verify("https://example.com/other.png", &signature));
+            assert!(!signer.verify(url, &signature[1..]));
+        }
+        assert_eq!(signers[0].0.sign(url), generate_digest("secret", url));
+
+        // Known answers from other implementations
+        assert_eq!(
+            HmacSha256::new("key").sign("The quick brown fox jumps over the lazy dog"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+        let ed25519 = Ed25519::from_seed(&[0; 32]);
+        assert_eq!(
+            hex::encode(ed25519.public_key().as_bytes()),
+            "3b6a27bcceb6a42d62a3a8d02a6f0d73653215771de243a63ac048a18b59da29"
+        );
+        // Another key's signature does not pass
+        assert!(!ed25519.verify(url, &signers[3].0.sign(url)));
+        let public_key = Ed25519::from_seed(&[7; 32]).public_key();
+        let signature = signers[3].0.sign(url);
+        assert!(public_key.verify(url, &signature));
+        assert!(!public_key.verify(url, &signature.to_uppercase()));
+        assert_ne!(Blake3Keyed::new("other").sign(url), signers[2].0.sign(url));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_digest_roundtrip(key in "\\PC*", url in "\\PC*", other in "\\PC*") {
+            let digest = generate_digest(&key, &url);
+            prop_assert_eq!(digest.len(), 40);
+            prop_assert!(verify_digest(&key, &url, &digest));
+            prop_assert!(!verify_digest(&key, &url, &digest[1..]));
+            if other != url {
+                prop_assert!(!verify_digest(&key, &other, &digest));
+            }
+        }
+    }
 }
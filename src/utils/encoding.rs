@@ -1,43 +1,213 @@
-use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use base64::alphabet;
+use base64::engine::general_purpose::{
+    GeneralPurpose, GeneralPurposeConfig, URL_SAFE, URL_SAFE_NO_PAD,
+};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
 
-/// Decode URL from hex or base64 encoding
-///
-/// Returns None if decoding fails
-///
-/// This function is only available with the `server` or `worker` feature.
-#[cfg(any(feature = "server", feature = "worker"))]
-pub fn decode_url(encoded: &str) -> Option<String> {
-    // Try hex first (40+ chars typically)
-    if let Ok(bytes) = hex::decode(encoded) {
-        if let Ok(s) = String::from_utf8(bytes) {
-            return Some(s);
+/// URL-safe base64 that decodes with or without padding
+const URL_SAFE_ANY_PAD: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// URL encoding format
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// Hexadecimal encoding (default, compatible with original Camo)
+    #[default]
+    Hex,
+    /// URL-safe Base64 encoding
+    ///
+    /// Written without padding; padded and standard-alphabet input is
+    /// decoded too, as other camo clients produce it.
+    Base64,
+    /// Hexadecimal encoding with uppercase digits
+    ///
+    /// For verifiers that compare the encoded URL case-sensitively.
+    HexUpper,
+    /// URL-safe Base64 encoding with `=` padding
+    ///
+    /// For verifiers that require padding.
+    Base64Padded,
+}
+
+impl Encoding {
+    /// Encoding of a path segment, by the same rule as upstream camo
+    ///
+    /// An even number of hex digits is hex, anything else is base64. Only
+    /// that one encoding is tried, so an ambiguous segment always decodes
+    /// the same way. Base64 of an `http(s)` URL never looks like hex.
+    ///
+    /// Only [`Encoding::Hex`] or [`Encoding::Base64`] is returned, which
+    /// decode the uppercase and padded variants as well.
+    pub fn detect(encoded: &str) -> Self {
+        if encoded.len().is_multiple_of(2) && encoded.bytes().all(|b| b.is_ascii_hexdigit()) {
+            Encoding::Hex
+        } else {
+            Encoding::Base64
         }
     }
+}
 
-    // Try base64
-    if let Ok(bytes) = URL_SAFE_NO_PAD.decode(encoded) {
-        if let Ok(s) = String::from_utf8(bytes) {
-            return Some(s);
+impl Encoding {
+    /// Decode a segment in this encoding
+    ///
+    /// Base64 may use either alphabet, even mixed, and have its padding or not.
+    pub fn decode(self, encoded: &str) -> Option<Vec<u8>> {
+        if self.is_hex() {
+            hex::decode(encoded).ok()
+        } else if encoded.contains(['+', '/']) {
+            URL_SAFE_ANY_PAD.decode(encoded.replace('+', "-").replace('/', "_")).ok()
+        } else {
+            URL_SAFE_ANY_PAD.decode(encoded).ok()
         }
     }
 
-    // Try URL decoding (query string format)
-    urlencoding::decode(encoded).ok().map(|s| s.into_owned())
+    /// Encode a URL in this encoding
+    pub fn encode(self, url: &str) -> String {
+        let mut out = String::new();
+        self.encode_into(url, &mut out);
+        out
+    }
+
+    /// Append a URL in this encoding to `out`
+    pub fn encode_into(self, url: &str, out: &mut String) {
+        match self {
+            Encoding::Hex => hex_into(url, b"0123456789abcdef", out),
+            Encoding::HexUpper => hex_into(url, b"0123456789ABCDEF", out),
+            Encoding::Base64 => URL_SAFE_NO_PAD.encode_string(url.as_bytes(), out),
+            Encoding::Base64Padded => URL_SAFE.encode_string(url.as_bytes(), out),
+        }
+    }
+
+    /// Whether this is one of the hex encodings
+    pub fn is_hex(self) -> bool {
+        matches!(self, Encoding::Hex | Encoding::HexUpper)
+    }
+}
+
+#[cfg(any(feature = "server", feature = "worker", feature = "wasm-fetch"))]
+impl Encoding {
+    /// `hex` or `base64`, whatever the case or padding
+    pub fn name(self) -> &'static str {
+        if self.is_hex() {
+            "hex"
+        } else {
+            "base64"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "hex" => Some(Encoding::Hex),
+            "base64" => Some(Encoding::Base64),
+            _ => None,
+        }
+    }
+}
+
+/// Longest decoded URL [`decode_url`] accepts, in bytes
+#[cfg(any(feature = "server", feature = "worker", feature = "wasm-fetch"))]
+pub const DEFAULT_MAX_URL_LENGTH: usize = 8192;
+
+/// Why an encoded URL was rejected
+#[cfg(any(feature = "server", feature = "worker", feature = "wasm-fetch"))]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DecodeError {
+    /// Not valid hex or base64, or not UTF-8 once decoded
+    #[error("invalid url encoding")]
+    Encoding,
+
+    /// Well-formed, but in an encoding the deployment does not accept
+    #[error("{} urls are not accepted", .0.name())]
+    NotAccepted(Encoding),
+
+    /// Decoded, but longer than the limit
+    #[error("url too long: {0} bytes")]
+    TooLong(usize),
+
+    /// Decoded, but contains NUL, a line break or another control character
+    #[error("control character in url")]
+    ControlCharacter,
+
+    /// Decoded, but neither an absolute nor a protocol-relative URL
+    #[error("invalid url: {0}")]
+    InvalidUrl(String),
+}
+
+/// Decode URL from hex or base64 encoding, up to [`DEFAULT_MAX_URL_LENGTH`] bytes
+///
+/// This function is only available with the `server` or `worker` feature.
+#[cfg(any(feature = "server", feature = "worker", feature = "wasm-fetch"))]
+pub fn decode_url(encoded: &str) -> Result<String, DecodeError> {
+    decode_url_with(encoded, &[Encoding::Hex, Encoding::Base64], DEFAULT_MAX_URL_LENGTH)
+}
+
+/// Decode URL in the encoding [`Encoding::detect`] picks and check the result
+///
+/// Fails with [`DecodeError::NotAccepted`] when that encoding is not in `accepted`.
+#[cfg(any(feature = "server", feature = "worker", feature = "wasm-fetch"))]
+pub fn decode_url_with(
+    encoded: &str,
+    accepted: &[Encoding],
+    max_length: usize,
+) -> Result<String, DecodeError> {
+    let encoding = Encoding::detect(encoded);
+    if !accepted.contains(&encoding) {
+        return Err(DecodeError::NotAccepted(encoding));
+    }
+
+    let url = encoding
+        .decode(encoded)
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or(DecodeError::Encoding)?;
+    validate_url(&url, max_length)?;
+    Ok(url)
+}
+
+/// Check a decoded target URL: length cap, no control characters, absolute URL
+///
+/// Protocol-relative URLs (`//host/path`) pass too, to be resolved against
+/// the server's `--default-scheme` once their digest checked out.
+#[cfg(any(feature = "server", feature = "worker", feature = "wasm-fetch"))]
+pub fn validate_url(url: &str, max_length: usize) -> Result<(), DecodeError> {
+    if url.len() > max_length {
+        return Err(DecodeError::TooLong(url.len()));
+    }
+    if url.chars().any(char::is_control) {
+        return Err(DecodeError::ControlCharacter);
+    }
+    url::Url::parse(&crate::utils::scheme::resolve_scheme(url, "https"))
+        .map_err(|e| DecodeError::InvalidUrl(e.to_string()))?;
+    Ok(())
 }
 
 /// Encode URL to hex
+#[cfg(any(feature = "client", feature = "server", test))]
 pub fn encode_url_hex(url: &str) -> String {
     hex::encode(url.as_bytes())
 }
 
 /// Encode URL to base64
+#[cfg(any(feature = "client", feature = "server", test))]
 pub fn encode_url_base64(url: &str) -> String {
     URL_SAFE_NO_PAD.encode(url.as_bytes())
 }
 
+fn hex_into(url: &str, digits: &[u8; 16], out: &mut String) {
+    out.reserve(url.len() * 2);
+    for b in url.bytes() {
+        out.push(digits[usize::from(b >> 4)] as char);
+        out.push(digits[usize::from(b & 0xf)] as char);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(any(feature = "server", feature = "worker", feature = "wasm-fetch"))]
+    use proptest::prelude::*;
 
     #[test]
     fn test_hex_encoding() {
@@ -47,6 +217,18 @@ mod tests {
         assert!(hex::decode(&encoded).is_ok());
     }
 
+    #[test]
+    fn test_encode_into() {
+        let url = "https://例子.测试/a.png";
+        let mut out = String::from("/");
+        Encoding::Hex.encode_into(url, &mut out);
+        assert_eq!(out, format!("/{}", encode_url_hex(url)));
+
+        out.clear();
+        Encoding::Base64.encode_into(url, &mut out);
+        assert_eq!(out, encode_url_base64(url));
+    }
+
     #[test]
     fn test_base64_encoding() {
         let url = "https://example.com/image.png";
@@ -55,7 +237,7 @@ mod tests {
         assert!(URL_SAFE_NO_PAD.decode(&encoded).is_ok());
     }
 
-    #[cfg(any(feature = "server", feature = "worker"))]
+    #[cfg(any(feature = "server", feature = "worker", feature = "wasm-fetch"))]
     #[test]
     fn test_hex_roundtrip() {
         let url = "https://example.com/image.png";
@@ -64,7 +246,7 @@ mod tests {
         assert_eq!(decoded, url);
     }
 
-    #[cfg(any(feature = "server", feature = "worker"))]
+    #[cfg(any(feature = "server", feature = "worker", feature = "wasm-fetch"))]
     #[test]
     fn test_base64_roundtrip() {
         let url = "https://example.com/image.png";
@@ -72,4 +254,144 @@ mod tests {
         let decoded = decode_url(&encoded).unwrap();
         assert_eq!(decoded, url);
     }
+
+    #[test]
+    fn test_base64_variants() {
+        use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE};
+
+        // `?>` and `??` encode to characters that differ between the alphabets
+        let url = b"https://example.com/a.png?>??";
+        let decode = |encoded: String| Encoding::Base64.decode(&encoded);
+        for engine in [URL_SAFE_NO_PAD, URL_SAFE, STANDARD, STANDARD_NO_PAD] {
+            assert_eq!(decode(engine.encode(url)).as_deref(), Some(&url[..]));
+        }
+        assert!(STANDARD.encode(url).contains(['+', '/', '=']));
+        assert_eq!(decode("aHR0cA*".into()), None);
+    }
+
+    #[test]
+    fn test_encodings_roundtrip() {
+        let url = "https://例子.测试/a.png?>";
+        for encoding in [
+            Encoding::Hex,
+            Encoding::HexUpper,
+            Encoding::Base64,
+            Encoding::Base64Padded,
+        ] {
+            let encoded = encoding.encode(url);
+            assert_eq!(Encoding::detect(&encoded).is_hex(), encoding.is_hex(), "{:?}", encoding);
+            let decoded = Encoding::detect(&encoded).decode(&encoded).unwrap();
+            assert_eq!(decoded, url.as_bytes(), "{:?}", encoding);
+        }
+        assert_eq!(Encoding::HexUpper.encode("?>"), "3F3E");
+        assert_eq!(Encoding::Base64Padded.encode("?>"), "Pz4=");
+        assert_eq!(Encoding::Base64.encode("?>"), "Pz4");
+    }
+
+    #[cfg(any(feature = "server", feature = "worker", feature = "wasm-fetch"))]
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(decode_url("%ff"), Err(DecodeError::Encoding));
+        assert_eq!(decode_url("aHR0cHM6Ly9leGFtcGxlLmNvbS9h!"), Err(DecodeError::Encoding));
+        assert_eq!(
+            decode_url(&encode_url_hex("https://example.com/a\0.png")),
+            Err(DecodeError::ControlCharacter)
+        );
+        assert_eq!(
+            decode_url(&encode_url_hex("https://example.com/\r\nHost: evil")),
+            Err(DecodeError::ControlCharacter)
+        );
+        assert!(matches!(
+            decode_url(&encode_url_hex("/relative.png")),
+            Err(DecodeError::InvalidUrl(_))
+        ));
+        let relative = "//example.com/a.png";
+        assert_eq!(decode_url(&encode_url_hex(relative)).as_deref(), Ok(relative));
+
+        let long = format!("https://example.com/{}", "a".repeat(DEFAULT_MAX_URL_LENGTH));
+        assert_eq!(
+            decode_url(&encode_url_base64(&long)),
+            Err(DecodeError::TooLong(long.len()))
+        );
+        let both = [Encoding::Hex, Encoding::Base64];
+        assert_eq!(decode_url_with(&encode_url_base64(&long), &both, long.len()), Ok(long));
+    }
+
+    #[cfg(any(feature = "server", feature = "worker", feature = "wasm-fetch"))]
+    #[test]
+    fn test_detect() {
+        let url = "https://example.com/image.png";
+        assert_eq!(Encoding::detect(&encode_url_hex(url)), Encoding::Hex);
+        assert_eq!(Encoding::detect(&encode_url_base64(url)), Encoding::Base64);
+        // Valid base64 too, but hex wins
+        assert_eq!(Encoding::detect("deadbeef"), Encoding::Hex);
+        assert_eq!(Encoding::detect("deadbee"), Encoding::Base64);
+        assert_eq!(Encoding::detect("DEADBEEF"), Encoding::Hex);
+    }
+
+    #[cfg(any(feature = "server", feature = "worker", feature = "wasm-fetch"))]
+    #[test]
+    fn test_accepted_encodings() {
+        let url = "https://example.com/image.png";
+        let hex_only = [Encoding::Hex];
+        assert_eq!(decode_url_with(&encode_url_hex(url), &hex_only, 100).as_deref(), Ok(url));
+        assert_eq!(
+            decode_url_with(&encode_url_base64(url), &hex_only, 100),
+            Err(DecodeError::NotAccepted(Encoding::Base64))
+        );
+        assert_eq!(
+            DecodeError::NotAccepted(Encoding::Base64).to_string(),
+            "base64 urls are not accepted"
+        );
+    }
+
+    /// What any successfully decoded URL must satisfy, also checked by `fuzz/`
+    #[cfg(any(feature = "server", feature = "worker", feature = "wasm-fetch"))]
+    fn check_decoded(encoded: &str) {
+        let Ok(url) = decode_url(encoded) else {
+            return;
+        };
+        assert!(url.len() <= DEFAULT_MAX_URL_LENGTH);
+        assert!(!url.chars().any(char::is_control), "{:?}", url);
+        assert!(url::Url::parse(&url).is_ok(), "{:?}", url);
+        assert_eq!(decode_url(&encode_url_hex(&url)).as_deref(), Ok(url.as_str()));
+        assert_eq!(decode_url(&encode_url_base64(&url)).as_deref(), Ok(url.as_str()));
+    }
+
+    #[cfg(any(feature = "server", feature = "worker", feature = "wasm-fetch"))]
+    #[test]
+    fn test_fuzz_regressions() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/fuzz/regressions/decode_url");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let input = std::fs::read(entry.unwrap().path()).unwrap();
+            if let Ok(encoded) = std::str::from_utf8(&input) {
+                check_decoded(encoded);
+            }
+        }
+    }
+
+    #[cfg(any(feature = "server", feature = "worker", feature = "wasm-fetch"))]
+    proptest! {
+        #[test]
+        fn prop_roundtrip(path in "\\PC*", query in proptest::collection::vec(any::<u8>(), 0..32)) {
+            // Percent-escapes in the target must come back untouched
+            let query = urlencoding::encode_binary(&query);
+            let url = format!("https://example.com/{}?q={}", path, query);
+            let expected = validate_url(&url, DEFAULT_MAX_URL_LENGTH).map(|()| url.clone());
+            prop_assert_eq!(decode_url(&encode_url_hex(&url)), expected.clone());
+            prop_assert_eq!(decode_url(&encode_url_base64(&url)), expected);
+        }
+
+        #[test]
+        fn prop_detect(path in "\\PC*") {
+            let url = format!("https://example.com/{}", path);
+            prop_assert_eq!(Encoding::detect(&encode_url_hex(&url)), Encoding::Hex);
+            prop_assert_eq!(Encoding::detect(&encode_url_base64(&url)), Encoding::Base64);
+        }
+
+        #[test]
+        fn prop_decoded_is_valid(encoded in "\\PC*|[0-9a-fA-F]*|[A-Za-z0-9_-]*") {
+            check_decoded(&encoded);
+        }
+    }
 }
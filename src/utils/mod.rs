@@ -1,2 +1,19 @@
+#[cfg(any(feature = "client", feature = "server"))]
+pub mod canonical;
 pub mod crypto;
-pub mod encoding;
\ No newline at end of file
+pub mod encoding;
+pub mod params;
+#[cfg(any(
+    feature = "client",
+    feature = "server",
+    feature = "worker",
+    feature = "wasm-fetch"
+))]
+pub mod scheme;
+#[cfg(any(
+    feature = "client",
+    feature = "server",
+    feature = "worker",
+    feature = "wasm-fetch"
+))]
+pub mod userinfo;
@@ -0,0 +1,186 @@
+//! Policy parameters signed into a link, like `?max_size=1000000&class=image`
+
+use std::borrow::Cow;
+use std::fmt;
+
+/// Family of media a link may serve, from its `class` parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentClass {
+    Image,
+    Video,
+    Audio,
+}
+
+impl ContentClass {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "image" => Some(ContentClass::Image),
+            "video" => Some(ContentClass::Video),
+            "audio" => Some(ContentClass::Audio),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ContentClass::Image => "image",
+            ContentClass::Video => "video",
+            ContentClass::Audio => "audio",
+        }
+    }
+
+    /// Whether a `Content-Type` is of this family
+    pub fn matches(self, content_type: &str) -> bool {
+        let family = content_type.trim_start().split('/').next().unwrap_or_default();
+        family.eq_ignore_ascii_case(self.name())
+    }
+}
+
+/// Limits a link carries on top of the server's, covered by its digest
+///
+/// A link with parameters is signed over the target URL and the parameters
+/// in a fixed order, `<url>\n<query>`, so they can be neither removed nor
+/// added to a link without invalidating it. Links without any are signed
+/// over the URL alone, as before. A session-bound link is signed over
+/// `<url>\n<query>\n<session ID>`, the ID itself staying out of the link.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct LinkParams {
+    /// Largest body served, when below the server's own limit
+    pub max_size: Option<u64>,
+    /// Only responses of this media family are served
+    pub class: Option<ContentClass>,
+    /// Unix time in seconds after which the link is refused
+    pub expires: Option<u64>,
+    /// Only valid for the session whose ID was signed along, from `session=1`
+    pub session: bool,
+}
+
+impl LinkParams {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Parameters in a query string; other names, like `url`, are ignored
+    ///
+    /// `None` when one is repeated or has an invalid value.
+    pub fn parse(query: &str) -> Option<Self> {
+        let mut params = Self::default();
+        for pair in query.split('&') {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let repeated = match name {
+                "max_size" => params.max_size.replace(value.parse().ok()?).is_some(),
+                "class" => params.class.replace(ContentClass::from_name(value)?).is_some(),
+                "expires" => params.expires.replace(value.parse().ok()?).is_some(),
+                "session" if value == "1" => std::mem::replace(&mut params.session, true),
+                "session" => return None,
+                _ => false,
+            };
+            if repeated {
+                return None;
+            }
+        }
+        Some(params)
+    }
+
+    /// Whether the link is past its expiry at Unix time `now`
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| now > expires)
+    }
+
+    /// What the digest is computed over: the URL, followed by the parameters if any
+    ///
+    /// `session` is only signed along for session-bound links.
+    pub fn signed_message<'a>(&self, url: &'a str, session: Option<&str>) -> Cow<'a, str> {
+        match session {
+            _ if self.is_empty() => Cow::Borrowed(url),
+            Some(session) if self.session => Cow::Owned(format!("{}\n{}\n{}", url, self, session)),
+            _ => Cow::Owned(format!("{}\n{}", url, self)),
+        }
+    }
+}
+
+/// The parameters as a query string, without the `?`, in the order they are signed
+impl fmt::Display for LinkParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut separator = "";
+        if let Some(max_size) = self.max_size {
+            write!(f, "max_size={}", max_size)?;
+            separator = "&";
+        }
+        if let Some(class) = self.class {
+            write!(f, "{}class={}", separator, class.name())?;
+            separator = "&";
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "{}expires={}", separator, expires)?;
+            separator = "&";
+        }
+        if self.session {
+            write!(f, "{}session=1", separator)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let query = "expires=1700000000&url=x&class=image&max_size=1000";
+        let params = LinkParams::parse(query).unwrap();
+        assert_eq!(params.max_size, Some(1000));
+        assert_eq!(params.class, Some(ContentClass::Image));
+        assert_eq!(params.expires, Some(1_700_000_000));
+        assert_eq!(params.to_string(), "max_size=1000&class=image&expires=1700000000");
+        assert_eq!(LinkParams::parse(&params.to_string()), Some(params));
+
+        assert!(LinkParams::parse("").unwrap().is_empty());
+        assert!(LinkParams::parse("url=https%3A%2F%2Fexample.com").unwrap().is_empty());
+        let params = LinkParams::parse("session=1&class=audio").unwrap();
+        assert!(params.session);
+        assert_eq!(params.to_string(), "class=audio&session=1");
+
+        let invalid = ["max_size=big", "class=pdf", "expires=-1", "session=0"];
+        let repeated = ["max_size=1&max_size=2", "session=1&session=1"];
+        for invalid in invalid.iter().chain(&repeated) {
+            assert_eq!(LinkParams::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_signed_message() {
+        let url = "https://example.com/a.png";
+        assert_eq!(LinkParams::default().signed_message(url, Some("s1")), url);
+        let params = LinkParams {
+            class: Some(ContentClass::Video),
+            ..Default::default()
+        };
+        let message = params.signed_message(url, Some("s1"));
+        assert_eq!(message, "https://example.com/a.png\nclass=video");
+        let bound = LinkParams {
+            session: true,
+            ..params
+        };
+        assert_eq!(
+            bound.signed_message(url, Some("s1")),
+            "https://example.com/a.png\nclass=video&session=1\ns1"
+        );
+    }
+
+    #[test]
+    fn test_class_and_expiry() {
+        assert!(ContentClass::Image.matches("Image/PNG"));
+        assert!(!ContentClass::Image.matches("video/mp4"));
+        assert!(!ContentClass::Audio.matches(""));
+
+        let params = LinkParams {
+            expires: Some(100),
+            ..Default::default()
+        };
+        assert!(!params.is_expired(100));
+        assert!(params.is_expired(101));
+        assert!(!LinkParams::default().is_expired(u64::MAX));
+    }
+}
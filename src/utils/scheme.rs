@@ -0,0 +1,29 @@
+//! Protocol-relative targets, like `//example.com/image.png`, as pasted from markup
+
+use std::borrow::Cow;
+
+/// `url` with `scheme` in front if it is protocol-relative, otherwise as is
+///
+/// That is how a page served over `scheme` would load it.
+pub fn resolve_scheme<'a>(url: &'a str, scheme: &str) -> Cow<'a, str> {
+    if url.starts_with("//") {
+        Cow::Owned(format!("{}:{}", scheme, url))
+    } else {
+        Cow::Borrowed(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_scheme() {
+        let url = "//example.com/image.png";
+        assert_eq!(resolve_scheme(url, "https"), "https://example.com/image.png");
+        assert_eq!(resolve_scheme(url, "http"), "http://example.com/image.png");
+        for url in ["http://example.com/a.png", "/image.png", "example.com/a.png"] {
+            assert!(matches!(resolve_scheme(url, "https"), Cow::Borrowed(u) if u == url));
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Entry point for WebAssembly runtimes built on the standard fetch API
+//!
+//! Exports `handle(request, env)` to JavaScript for Deno Deploy, Vercel Edge
+//! and similar platforms. `env` is a plain object of `CAMO_*` variables, e.g.
+//! `Deno.env.toObject()` or `process.env`.
+
+use crate::server::{
+    config::Config,
+    http_client::header_map,
+    router::{create_router, AppState},
+};
+use axum::body::Body;
+use axum::http::{Method, Request};
+use std::sync::Arc;
+use tower_service::Service;
+use wasm_bindgen::prelude::*;
+
+/// Handle one request with the configuration found in `env`
+#[wasm_bindgen]
+pub async fn handle(
+    request: web_sys::Request,
+    env: js_sys::Object,
+) -> Result<web_sys::Response, JsValue> {
+    let var = |name: &str| {
+        js_sys::Reflect::get(&env, &JsValue::from_str(name))
+            .ok()
+            .and_then(|value| value.as_string())
+    };
+    let config = Config::from_env(var).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let invalid = |e: std::io::Error| JsValue::from_str(&e.to_string());
+    let state = AppState::from_config(&config).map_err(invalid)?;
+    let mut router = create_router(Arc::new(state)).map_err(invalid)?;
+
+    let response = match router.call(into_http_request(&request)?).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    };
+    into_web_response(response).await
+}
+
+/// Method, URL and headers of the incoming request; the proxy never reads the body
+fn into_http_request(request: &web_sys::Request) -> Result<Request<Body>, JsValue> {
+    let method = Method::from_bytes(request.method().as_bytes())
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+    let mut http_request = Request::builder()
+        .method(method)
+        .uri(request.url())
+        .body(Body::empty())
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    *http_request.headers_mut() = header_map(&request.headers());
+    Ok(http_request)
+}
+
+/// Buffered copy of the router's response, already capped by `CAMO_LENGTH_LIMIT`
+async fn into_web_response(
+    response: axum::response::Response,
+) -> Result<web_sys::Response, JsValue> {
+    let (parts, body) = response.into_parts();
+
+    let headers = web_sys::Headers::new()?;
+    for (name, value) in &parts.headers {
+        if let Ok(value) = value.to_str() {
+            headers.append(name.as_str(), value)?;
+        }
+    }
+
+    let init = web_sys::ResponseInit::new();
+    init.set_status(parts.status.as_u16());
+    init.set_headers(&headers);
+
+    let mut body = axum::body::to_bytes(body, usize::MAX)
+        .await
+        .map_err(|e| JsValue::from_str(&e.to_string()))?
+        .to_vec();
+    let body = (!body.is_empty()).then_some(body.as_mut_slice());
+    web_sys::Response::new_with_opt_u8_array_and_init(body, &init)
+}
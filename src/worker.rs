@@ -1,56 +1,180 @@
+mod cf_options;
+mod persistent_cache;
+
 use crate::server::{
+    conditional,
     config::Config,
+    cors::CorsPolicy,
+    env_config::parse,
     error::CamoError,
-    router::{create_router, AppState},
+    http_client::is_shareable,
+    router::{cache_admits, cache_lifetime, create_router, AppState},
 };
+use axum::http::{HeaderMap, HeaderValue, Method};
+use persistent_cache::PersistentCache;
 use std::sync::Arc;
 use tower_service::Service;
-use worker::{event, Context, Env, HttpRequest, Result};
+use worker::{event, Cache, Context, Env, HttpRequest, HttpResponse, Result};
+
+/// Response header reporting whether the edge cache answered the request
+const CACHE_STATUS_HEADER: &str = "x-camo-cache";
 
 #[event(fetch)]
-pub async fn fetch(
-    req: HttpRequest,
-    env: Env,
-    _ctx: Context,
-) -> Result<axum::http::Response<axum::body::Body>> {
+pub async fn fetch(req: HttpRequest, env: Env, ctx: Context) -> Result<worker::Response> {
     console_error_panic_hook::set_once();
-    Ok(create_router(Arc::new(AppState::from_worker_env(&env)?))
-        .call(req)
-        .await?)
+    let state = Arc::new(AppState::from_worker_env(&env)?);
+    let edge_cache = state.config.edge_cache.then(Cache::default);
+    let persistent_cache = PersistentCache::from_env(&env, &state.config);
+    let admitted = cache_admits(&state, req.uri(), req.headers());
+    let mut router = create_router(state.clone()).map_err(|e| worker::Error::RustError(e.to_string()))?;
+
+    // Requests the router would refuse are not served from the caches either
+    if req.method() != Method::GET
+        || (edge_cache.is_none() && persistent_cache.is_none())
+        || !admitted
+    {
+        return worker::Response::try_from(router.call(req).await?);
+    }
+
+    // Keyed by the full camo URL, so every signed URL is its own entry. The
+    // entries hold no CORS headers, those are for each request's `Origin`
+    let cache_key = req.uri().to_string();
+    let lifetime = cache_lifetime(req.uri());
+    let request_headers = req.headers().clone();
+
+    if let Some(cache) = &edge_cache
+        && let Some(hit) = cache.get(cache_key.as_str(), false).await?
+    {
+        return from_cache(&state.cors, &request_headers, hit, "hit");
+    }
+
+    if let Some(store) = &persistent_cache {
+        match store.get(&cache_key).await {
+            Ok(Some(mut hit)) => {
+                if let Some(cache) = edge_cache {
+                    store_in_edge_cache(&ctx, cache, cache_key, hit.cloned()?);
+                }
+                return from_cache(&state.cors, &request_headers, hit, "persistent-hit");
+            }
+            Ok(None) => {}
+            Err(e) => worker::console_warn!("Failed to read from persistent cache: {}", e),
+        }
+    }
+
+    let response = router.call(req).await?;
+    // Neither cache may hand a response marked for one user to everyone
+    if response.status() != axum::http::StatusCode::OK || !is_shareable(response.headers()) {
+        return worker::Response::try_from(response);
+    }
+
+    let mut response = worker::Response::try_from(response)?;
+    let mut stored = HttpResponse::try_from(response.cloned()?)?;
+    state.cors.strip(stored.headers_mut());
+    let mut stored = worker::Response::try_from(stored)?;
+    if let Some(store) = persistent_cache {
+        let stored = stored.cloned()?;
+        let key = cache_key.clone();
+        ctx.wait_until(async move {
+            if let Err(e) = store.put(&key, stored, lifetime).await {
+                worker::console_warn!("Failed to store response in persistent cache: {}", e);
+            }
+        });
+    }
+    if let Some(cache) = edge_cache {
+        store_in_edge_cache(&ctx, cache, cache_key, stored);
+    }
+
+    with_cache_status(response, "miss")
+}
+
+fn store_in_edge_cache(ctx: &Context, cache: Cache, key: String, response: worker::Response) {
+    ctx.wait_until(async move {
+        if let Err(e) = cache.put(key.as_str(), response).await {
+            worker::console_warn!("Failed to store response in cache: {}", e);
+        }
+    });
+}
+
+/// Answer with a cached response, or with 304 when the client's validators match it
+fn from_cache(
+    cors: &CorsPolicy,
+    request: &HeaderMap,
+    hit: worker::Response,
+    status: &'static str,
+) -> Result<worker::Response> {
+    let response = match conditional::not_modified(request, &HeaderMap::from(hit.headers())) {
+        Some(headers) => worker::Response::empty()?
+            .with_status(304)
+            .with_headers(headers.into()),
+        None => hit,
+    };
+    let mut response = HttpResponse::try_from(response)?;
+    cors.apply(request, response.headers_mut());
+    response
+        .headers_mut()
+        .insert(CACHE_STATUS_HEADER, HeaderValue::from_static(status));
+    worker::Response::try_from(response)
+}
+
+/// Copy the response so its headers are mutable and tag it with the cache status
+fn with_cache_status(response: worker::Response, status: &'static str) -> Result<worker::Response> {
+    let mut response = HttpResponse::try_from(response)?;
+    response
+        .headers_mut()
+        .insert(CACHE_STATUS_HEADER, HeaderValue::from_static(status));
+    worker::Response::try_from(response)
 }
 
 impl Config {
+    /// Build the configuration from the worker's variables and secrets
+    ///
+    /// Reads the same `CAMO_*` variables as the server (see
+    /// [`Config::from_env`]) plus the Workers specific cache and `cf` settings.
     pub fn from_worker_env(env: &worker::Env) -> Result<Self, CamoError> {
-        let key = env.secret("CAMO_KEY").map(|s| s.to_string()).ok();
+        let var = |name: &str| {
+            env.var(name)
+                .or_else(|_| env.secret(name))
+                .ok()
+                .map(|v| v.to_string())
+        };
+        let mut config = Config::from_env(var)?;
 
-        if key.is_none() || key.as_ref().map(|s| s.is_empty()).unwrap_or(true) {
-            return Err(CamoError::InvalidUrl("CAMO_KEY not set".into()));
-        }
+        config.edge_cache = parse(&var, "CAMO_EDGE_CACHE", config.edge_cache);
+        config.persistent_cache_ttl =
+            parse(&var, "CAMO_PERSISTENT_CACHE_TTL", config.persistent_cache_ttl);
+        config.persistent_cache_kv_max_size = parse(
+            &var,
+            "CAMO_PERSISTENT_CACHE_KV_MAX_SIZE",
+            config.persistent_cache_kv_max_size,
+        );
+        config.cf_cache_ttl = var("CAMO_CF_CACHE_TTL").and_then(|v| v.trim().parse().ok());
+        config.cf_cache_everything =
+            parse(&var, "CAMO_CF_CACHE_EVERYTHING", config.cf_cache_everything);
 
-        let max_size = env
-            .var("CAMO_MAX_SIZE")
-            .map(|v| v.to_string().parse().unwrap_or(5 * 1024 * 1024))
-            .unwrap_or(5 * 1024 * 1024);
-
-        Ok(Config {
-            key,
-            listen: "0.0.0.0:8080".to_string(),
-            max_size,
-            max_redirects: 4,
-            timeout: 10,
-            allow_video: false,
-            allow_audio: false,
-            block_private: true,
-            metrics: false,
-            log_level: "info".to_string(),
-        })
+        config.cf_polish = var("CAMO_CF_POLISH").and_then(|v| {
+            let polish = cf_options::parse_polish(&v);
+            if polish.is_none() {
+                worker::console_warn!("Ignoring invalid CAMO_CF_POLISH: {}", v);
+            }
+            polish
+        });
+
+        config.cf_image = var("CAMO_CF_IMAGE").and_then(|v| {
+            cf_options::parse_image(&v)
+                .inspect_err(|option| {
+                    worker::console_warn!("Ignoring CAMO_CF_IMAGE, invalid option: {}", option)
+                })
+                .ok()
+        });
+
+        Ok(config)
     }
 }
 
 impl AppState {
-    pub fn from_worker_env(env: &worker::Env) -> Result<Self, CamoError> {
+    pub fn from_worker_env(env: &worker::Env) -> Result<Self> {
         let config = Config::from_worker_env(env)?;
-        Ok(AppState::from_config(&config))
+        AppState::from_config(&config).map_err(|e| worker::Error::RustError(e.to_string()))
     }
 }
 
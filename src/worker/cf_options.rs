@@ -0,0 +1,69 @@
+//! Cloudflare `cf` fetch options read from the environment
+
+use worker::{PolishConfig, ResizeConfig, ResizeFit, ResizeFormat, ResizeMetadata, ResizeQuality};
+
+/// `off`, `lossy` or `lossless`
+pub fn parse_polish(value: &str) -> Option<PolishConfig> {
+    match value.trim() {
+        "off" => Some(PolishConfig::Off),
+        "lossy" => Some(PolishConfig::Lossy),
+        "lossless" => Some(PolishConfig::Lossless),
+        _ => None,
+    }
+}
+
+/// Image Resizing options in Cloudflare's URL syntax, e.g. `width=800,fit=scale-down,quality=85`
+///
+/// Returns the first unknown or invalid option as the error.
+pub fn parse_image(value: &str) -> Result<ResizeConfig, String> {
+    let mut image = ResizeConfig::default();
+
+    for option in value.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+        let invalid = || option.to_string();
+        let (name, value) = option.split_once('=').ok_or_else(invalid)?;
+        let (name, value) = (name.trim(), value.trim());
+
+        match name {
+            "width" => image.width = Some(value.parse().map_err(|_| invalid())?),
+            "height" => image.height = Some(value.parse().map_err(|_| invalid())?),
+            "dpr" => image.dpr = Some(value.parse().map_err(|_| invalid())?),
+            "sharpen" => image.sharpen = Some(value.parse().map_err(|_| invalid())?),
+            "anim" => image.anim = Some(value.parse().map_err(|_| invalid())?),
+            "quality" => {
+                let value = value.parse().map_err(|_| invalid())?;
+                image.quality = Some(ResizeQuality::Specific { value });
+            }
+            "fit" => {
+                image.fit = Some(match value {
+                    "scale-down" => ResizeFit::ScaleDown,
+                    "contain" => ResizeFit::Contain,
+                    "cover" => ResizeFit::Cover,
+                    "crop" => ResizeFit::Crop,
+                    "pad" => ResizeFit::Pad,
+                    _ => return Err(invalid()),
+                })
+            }
+            "format" => {
+                image.format = Some(match value {
+                    "avif" => ResizeFormat::Avif,
+                    "webp" => ResizeFormat::Webp,
+                    "jpeg" => ResizeFormat::Jpeg,
+                    "baseline-jpeg" => ResizeFormat::BaselineJpeg,
+                    "png" => ResizeFormat::Png,
+                    _ => return Err(invalid()),
+                })
+            }
+            "metadata" => {
+                image.metadata = Some(match value {
+                    "keep" => ResizeMetadata::Keep,
+                    "copyright" => ResizeMetadata::Copyright,
+                    "none" => ResizeMetadata::None,
+                    _ => return Err(invalid()),
+                })
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(image)
+}
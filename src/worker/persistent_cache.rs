@@ -0,0 +1,159 @@
+use crate::server::{config::Config, http_client::is_shareable};
+
+use axum::http::HeaderMap;
+use std::collections::HashMap;
+use worker::{Bucket, Date, Env, KvStore, Response, ResponseBody, Result};
+
+/// R2 bucket binding for the persistent cache
+pub const BUCKET_BINDING: &str = "CAMO_CACHE_BUCKET";
+
+/// KV namespace binding for the persistent cache
+pub const KV_BINDING: &str = "CAMO_CACHE_KV";
+
+/// Metadata key holding the expiry time in milliseconds since the epoch
+const EXPIRES_KEY: &str = "camo-expires";
+
+/// KV rejects values above 25 MiB
+const KV_VALUE_LIMIT: usize = 25 * 1024 * 1024;
+
+/// KV rejects expiration TTLs below 60 seconds
+const KV_MIN_TTL: u64 = 60;
+
+/// KV rejects metadata above 1 KiB once serialized as JSON
+const KV_METADATA_LIMIT: usize = 1024;
+
+/// R2 rejects custom metadata above 8 KiB
+const R2_METADATA_LIMIT: usize = 8 * 1024;
+
+/// Durable cache of upstream responses shared by every colo
+///
+/// Small bodies go to KV, larger ones to R2. Either binding may be absent;
+/// the cache is disabled when neither is configured. Response headers and
+/// the expiry time are kept in the object metadata.
+pub struct PersistentCache {
+    bucket: Option<Bucket>,
+    kv: Option<KvStore>,
+    ttl: u64,
+    kv_max_size: usize,
+}
+
+impl PersistentCache {
+    /// Returns `None` when no storage is bound or the TTL is zero
+    pub fn from_env(env: &Env, config: &Config) -> Option<Self> {
+        let bucket = env.bucket(BUCKET_BINDING).ok();
+        let kv = env.kv(KV_BINDING).ok();
+
+        if (bucket.is_none() && kv.is_none()) || config.persistent_cache_ttl == 0 {
+            return None;
+        }
+
+        Some(Self {
+            bucket,
+            kv,
+            ttl: config.persistent_cache_ttl,
+            kv_max_size: (config.persistent_cache_kv_max_size as usize).min(KV_VALUE_LIMIT),
+        })
+    }
+
+    /// Look a response up in KV first, then in R2
+    pub async fn get(&self, key: &str) -> Result<Option<Response>> {
+        let key = storage_key(key);
+
+        if let Some(kv) = &self.kv {
+            let (body, metadata) = kv
+                .get(&key)
+                .bytes_with_metadata::<HashMap<String, String>>()
+                .await?;
+            // KV keeps entries at least a minute, longer than an expiring link may ask for
+            if let (Some(body), Some(metadata)) = (body, metadata)
+                && !is_expired(&metadata)
+            {
+                return build_response(ResponseBody::Body(body), metadata).map(Some);
+            }
+        }
+
+        if let Some(bucket) = &self.bucket
+            && let Some(object) = bucket.get(&key).execute().await?
+        {
+            let metadata = object.custom_metadata()?;
+            // R2 has no native expiry, so stale objects are skipped here
+            // and replaced on the next store.
+            if is_expired(&metadata) {
+                return Ok(None);
+            }
+            if let Some(body) = object.body() {
+                return build_response(body.response_body()?, metadata).map(Some);
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Store a successful response, choosing the backend by body and header size
+    ///
+    /// Responses marked `no-store` or `private` are not kept, and neither
+    /// are those whose headers fit in the metadata of no bound backend.
+    /// `lifetime` caps the TTL for links that expire.
+    pub async fn put(
+        &self,
+        key: &str,
+        mut response: Response,
+        lifetime: Option<u64>,
+    ) -> Result<()> {
+        let ttl = lifetime.map_or(self.ttl, |lifetime| lifetime.min(self.ttl));
+        if ttl == 0 || !is_shareable(&HeaderMap::from(response.headers())) {
+            return Ok(());
+        }
+        let key = storage_key(key);
+        let body = response.bytes().await?;
+
+        let expires = Date::now().as_millis() + ttl * 1000;
+        let mut metadata: HashMap<String, String> = response.headers().entries().collect();
+        metadata.insert(EXPIRES_KEY.to_string(), expires.to_string());
+        let kv_fits = body.len() <= self.kv_max_size
+            && serde_json::to_vec(&metadata).is_ok_and(|json| json.len() <= KV_METADATA_LIMIT);
+        let r2_fits = metadata.iter().map(|(name, value)| name.len() + value.len()).sum::<usize>()
+            <= R2_METADATA_LIMIT;
+
+        match (&self.kv, &self.bucket) {
+            (Some(kv), _) if kv_fits => {
+                kv.put_bytes(&key, &body)?
+                    .expiration_ttl(ttl.max(KV_MIN_TTL))
+                    .metadata(metadata)?
+                    .execute()
+                    .await?;
+            }
+            (_, Some(bucket)) if r2_fits => {
+                bucket.put(&key, body).custom_metadata(metadata).execute().await?;
+            }
+            // Too large for KV and no bucket bound, or headers too large for both
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Hash the camo URL so keys stay within the KV key length limit
+fn storage_key(key: &str) -> String {
+    use sha1::{Digest, Sha1};
+
+    format!("camo/{}", hex::encode(Sha1::digest(key.as_bytes())))
+}
+
+fn is_expired(metadata: &HashMap<String, String>) -> bool {
+    metadata
+        .get(EXPIRES_KEY)
+        .and_then(|v| v.parse::<u64>().ok())
+        .is_none_or(|expires| expires <= Date::now().as_millis())
+}
+
+fn build_response(body: ResponseBody, metadata: HashMap<String, String>) -> Result<Response> {
+    let response = Response::from_body(body)?;
+    for (name, value) in metadata {
+        if name != EXPIRES_KEY {
+            response.headers().set(&name, &value)?;
+        }
+    }
+    Ok(response)
+}
@@ -0,0 +1,1043 @@
+//! End-to-end tests: the full router in front of a mock upstream
+//!
+//! Every test starts its own upstream, so they can run in parallel. The
+//! upstream listens on loopback, which the proxy only reaches with
+//! `block_private` turned off.
+
+#![cfg(all(feature = "server", feature = "client"))]
+
+use axum::Extension;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{Request, StatusCode};
+use axum_test::TestServer;
+use camo::server::{config::Config, create_router};
+use camo::{CamoUrl, ContentClass};
+use clap::Parser;
+use futures_core::Stream;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::time::{Duration, SystemTime};
+use tower::ServiceExt;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const KEY: &str = "test-key";
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Proxy with the defaults plus `args`, allowed to reach the loopback upstream
+fn proxy(args: &[&str]) -> TestServer {
+    let mut config = Config::parse_from(["camo", "--key", KEY].iter().chain(args));
+    config.block_private = false;
+    TestServer::new(create_router(&config).unwrap()).unwrap()
+}
+
+fn signed(url: &str) -> String {
+    CamoUrl::new(KEY).sign(url).to_path()
+}
+
+fn image() -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_raw(PNG, "image/png")
+}
+
+#[tokio::test]
+async fn test_proxies_image() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/a.png"))
+        .respond_with(image().insert_header("cache-control", "max-age=60"))
+        .expect(2)
+        .mount(&upstream)
+        .await;
+    let proxy = proxy(&[]);
+    let target = format!("{}/a.png", upstream.uri());
+
+    let response = proxy.get(&signed(&target)).await;
+    response.assert_status_ok();
+    response.assert_header("content-type", "image/png");
+    response.assert_header("cache-control", "max-age=60");
+    response.assert_header("x-content-type-options", "nosniff");
+    assert_eq!(response.as_bytes().as_ref(), PNG);
+
+    let digest = CamoUrl::new(KEY).sign(&target).digest;
+    let query = format!("/{}?url={}", digest, urlencoding::encode(&target));
+    proxy.get(&query).await.assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_rejects_bad_signature() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(image())
+        .expect(0)
+        .mount(&upstream)
+        .await;
+    let target = format!("{}/a.png", upstream.uri());
+
+    let forged = CamoUrl::new("other-key").sign(&target).to_path();
+    proxy(&[]).get(&forged).await.assert_status_bad_request();
+}
+
+#[tokio::test]
+async fn test_redirects() {
+    let upstream = MockServer::start().await;
+    for (from, to) in [("/one", "/two"), ("/two", "/a.png"), ("/loop", "/loop")] {
+        Mock::given(path(from))
+            .respond_with(ResponseTemplate::new(302).insert_header("location", to))
+            .mount(&upstream)
+            .await;
+    }
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .mount(&upstream)
+        .await;
+
+    let target = |p: &str| signed(&format!("{}{}", upstream.uri(), p));
+    proxy(&[]).get(&target("/one")).await.assert_status_ok();
+    let response = proxy(&["--max-redirects", "1"]).get(&target("/one")).await;
+    response.assert_status(StatusCode::BAD_GATEWAY);
+    response.assert_text("too many redirects");
+    let response = proxy(&[]).get(&target("/loop")).await;
+    response.assert_status(StatusCode::BAD_GATEWAY);
+    response.assert_text("too many redirects");
+}
+
+#[tokio::test]
+async fn test_static_headers_stay_with_their_origin() {
+    let (origin, other) = (MockServer::start().await, MockServer::start().await);
+    Mock::given(path("/same"))
+        .respond_with(ResponseTemplate::new(302).insert_header("location", "/a.png"))
+        .mount(&origin)
+        .await;
+    let elsewhere = format!("{}/a.png", other.uri());
+    Mock::given(path("/other"))
+        .respond_with(ResponseTemplate::new(302).insert_header("location", elsewhere.as_str()))
+        .mount(&origin)
+        .await;
+    for upstream in [&origin, &other] {
+        Mock::given(path("/a.png"))
+            .respond_with(image())
+            .mount(upstream)
+            .await;
+    }
+
+    let proxy = proxy(&["--upstream-header", "127.0.0.1=X-Api-Key: secret"]);
+    for from in ["/same", "/other"] {
+        let target = signed(&format!("{}{}", origin.uri(), from));
+        proxy.get(&target).await.assert_status_ok();
+    }
+
+    // Every request to the origin carried the key, none to the other one
+    let received = origin.received_requests().await.unwrap();
+    assert_eq!(received.len(), 3);
+    assert!(received.iter().all(|request| request.headers["x-api-key"] == "secret"));
+    let received = other.received_requests().await.unwrap();
+    assert_eq!(received.len(), 1);
+    assert!(!received[0].headers.contains_key("x-api-key"));
+}
+
+#[tokio::test]
+async fn test_redirect_to_blocked_network() {
+    let listener = std::net::TcpListener::bind("127.0.0.2:0").unwrap();
+    let origin = MockServer::builder().listener(listener).start().await;
+    let blocked = MockServer::start().await;
+    let to = format!("{}/a.png", blocked.uri());
+    Mock::given(path("/a.png"))
+        .respond_with(ResponseTemplate::new(302).insert_header("location", to.as_str()))
+        .mount(&origin)
+        .await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .expect(0)
+        .mount(&blocked)
+        .await;
+    assert!(blocked.uri().starts_with("http://127.0.0.1:"));
+
+    let target = signed(&format!("{}/a.png", origin.uri()));
+    let response = proxy(&["--block-cidr", "127.0.0.1"]).get(&target).await;
+    response.assert_status_forbidden();
+    response.assert_text("network not allowed");
+}
+
+#[tokio::test]
+async fn test_https_only_upstream() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .mount(&upstream)
+        .await;
+    let target = signed(&format!("{}/a.png", upstream.uri()));
+
+    proxy(&[]).get(&target).await.assert_status_ok();
+    // Even when http is allowed explicitly
+    for allowed in ["http,https", "http"] {
+        let args = ["--https-only-upstream", "--allowed-schemes", allowed];
+        let response = proxy(&args).get(&target).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        response.assert_text("invalid url: Scheme http not allowed");
+    }
+}
+
+/// HTTPS upstream with a self-signed certificate, answering `/a.png` with a redirect to `to`
+async fn https_redirect(to: &str) -> String {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let certified = rcgen::generate_simple_self_signed(["localhost".to_string()]).unwrap();
+    let cert = certified.cert.pem().into_bytes();
+    let key = certified.signing_key.serialize_pem().into_bytes();
+    let tls = axum_server::tls_rustls::RustlsConfig::from_pem(cert, key).await.unwrap();
+
+    let to = to.to_string();
+    let app = axum::Router::new().route(
+        "/a.png",
+        axum::routing::get(move || async move { axum::response::Redirect::to(&to) }),
+    );
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = axum_server::from_tcp_rustls(listener, tls).unwrap();
+    tokio::spawn(server.serve(app.into_make_service()));
+    format!("https://localhost:{}/a.png", port)
+}
+
+#[tokio::test]
+async fn test_https_only_refuses_insecure_redirect() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .expect(1)
+        .mount(&upstream)
+        .await;
+    let target = signed(&https_redirect(&format!("{}/a.png", upstream.uri())).await);
+
+    let proxy = |args: &[&str]| proxy(&[&["--insecure-skip-verify"], args].concat());
+    proxy(&[]).get(&target).await.assert_status_ok();
+    let response = proxy(&["--https-only-upstream"]).get(&target).await;
+    response.assert_status(StatusCode::BAD_GATEWAY);
+    response.assert_text("redirect to plain http refused");
+}
+
+#[tokio::test]
+async fn test_upgrade_insecure_upstream() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .mount(&upstream)
+        .await;
+    let target = signed(&format!("{}/a.png", upstream.uri()));
+
+    // The mock speaks no TLS, so only the fallback gets through
+    let upgrade = "--upgrade-insecure-upstream";
+    proxy(&[upgrade]).get(&target).await.assert_status_ok();
+    let response = proxy(&[upgrade, "--https-only-upstream"]).get(&target).await;
+    response.assert_status(StatusCode::BAD_GATEWAY);
+}
+
+#[tokio::test]
+async fn test_protocol_relative() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .mount(&upstream)
+        .await;
+    let relative = format!("{}/a.png", upstream.uri().trim_start_matches("http:"));
+    let target = signed(&relative);
+
+    proxy(&["--default-scheme", "http"]).get(&target).await.assert_status_ok();
+    // The mock speaks no TLS
+    let response = proxy(&[]).get(&target).await;
+    response.assert_status(StatusCode::BAD_GATEWAY);
+
+    // Or resolved when signing, which needs no server support
+    let camo = CamoUrl::new(KEY).with_default_scheme("http");
+    let target = camo.sign(&relative).to_path();
+    proxy(&[]).get(&target).await.assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_reject_mixed_scripts() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .mount(&upstream)
+        .await;
+    let args = ["--reject-mixed-scripts"];
+    let target = signed(&format!("{}/a.png", upstream.uri()));
+    proxy(&args).get(&target).await.assert_status_ok();
+
+    // Cyrillic е
+    let response = proxy(&args).get(&signed("http://еxample.com/a.png")).await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    response.assert_text("invalid url: Host mixes scripts");
+}
+
+#[tokio::test]
+async fn test_content_type_rejected() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/page"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw("<html>", "text/html"))
+        .mount(&upstream)
+        .await;
+    Mock::given(path("/clip"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(&b"clip"[..], "video/mp4"))
+        .mount(&upstream)
+        .await;
+    let target = |p: &str| signed(&format!("{}{}", upstream.uri(), p));
+
+    let response = proxy(&[]).get(&target("/page")).await;
+    response.assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let response = proxy(&[]).get(&target("/clip")).await;
+    response.assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    proxy(&["--allow-video"]).get(&target("/clip")).await.assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_content_type_lists() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/doc"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw("%PDF", "application/pdf"))
+        .mount(&upstream)
+        .await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .mount(&upstream)
+        .await;
+    let target = |p: &str| signed(&format!("{}{}", upstream.uri(), p));
+
+    let response = proxy(&[]).get(&target("/doc")).await;
+    response.assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let allow = ["--allow-content-type", "Application/PDF"];
+    proxy(&allow).get(&target("/doc")).await.assert_status_ok();
+
+    let response = proxy(&["--deny-content-type", "image/png"]).get(&target("/a.png")).await;
+    response.assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
+
+#[tokio::test]
+async fn test_compat() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .mount(&upstream)
+        .await;
+    let target = format!("{}/a.png", upstream.uri());
+    let signed = CamoUrl::new(KEY).sign(&target);
+    let uppercase = format!("/{}/{}", signed.digest.to_uppercase(), signed.encoded_url);
+
+    proxy(&[]).get(&uppercase).await.assert_status_bad_request();
+    proxy(&["--compat", "go"]).get(&uppercase).await.assert_status_ok();
+    let response = proxy(&["--compat", "strict"]).get(&uppercase).await;
+    response.assert_status_bad_request();
+    response.assert_text("invalid digest");
+
+    let trailing = format!("{}/", signed.to_path());
+    proxy(&[]).get(&trailing).await.assert_status_bad_request();
+    proxy(&["--compat", "node"]).get(&trailing).await.assert_status_ok();
+
+    let query = format!("?url={}", urlencoding::encode(&target));
+    let fallback = format!("/{}/not-a-url{}", signed.digest, query);
+    proxy(&["--compat", "node"]).get(&fallback).await.assert_status_ok();
+
+    let repeated = format!("/{}{}&url=https%3A%2F%2Fexample.com%2F", signed.digest, query);
+    proxy(&[]).get(&repeated).await.assert_status_ok();
+    proxy(&["--compat", "strict"]).get(&repeated).await.assert_status_bad_request();
+}
+
+#[tokio::test]
+async fn test_signature() {
+    use camo::{Blake3Keyed, HmacSha256};
+
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .mount(&upstream)
+        .await;
+    let target = format!("{}/a.png", upstream.uri());
+    let sha256 = CamoUrl::new(KEY).with_signer(HmacSha256::new(KEY)).sign_url(&target, "");
+    let blake3 = CamoUrl::new(KEY).with_signer(Blake3Keyed::new(KEY)).sign_url(&target, "");
+
+    proxy(&[]).get(&sha256).await.assert_status_bad_request();
+    let server = proxy(&["--signature", "hmac-sha256"]);
+    server.get(&sha256).await.assert_status_ok();
+    server.get(&blake3).await.assert_status_bad_request();
+    let sha1 = CamoUrl::new(KEY).sign_url(&target, "");
+    server.get(&sha1).await.assert_status_bad_request();
+    proxy(&["--signature", "blake3"]).get(&blake3).await.assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_verify_pubkey() {
+    use camo::Ed25519;
+
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .mount(&upstream)
+        .await;
+    let target = format!("{}/a.png", upstream.uri());
+    let private_key = Ed25519::from_seed(&[9; 32]);
+    let public_key = hex::encode(private_key.public_key().as_bytes());
+    let mut config = Config::parse_from(["camo", "--verify-pubkey", &public_key]);
+    config.block_private = false;
+    let server = TestServer::new(create_router(&config).unwrap()).unwrap();
+
+    let link = CamoUrl::new("").with_signer(private_key).sign(&target);
+    server.get(&link.to_path()).await.assert_status_ok();
+    server.get(&link.clone().base64().to_path()).await.assert_status_ok();
+    let other = CamoUrl::new("").with_signer(Ed25519::from_seed(&[8; 32])).sign(&target);
+    server.get(&other.to_path()).await.assert_status_bad_request();
+    // Holding no secret, the proxy accepts no HMAC links
+    server.get(&signed(&target)).await.assert_status_bad_request();
+    let hmac = CamoUrl::new("").sign(&target);
+    server.get(&hmac.to_path()).await.assert_status_bad_request();
+}
+
+#[tokio::test]
+async fn test_base64_variants() {
+    use base64::engine::general_purpose::{STANDARD, URL_SAFE};
+    use base64::Engine;
+
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .mount(&upstream)
+        .await;
+    let mut target = format!("{}/a.png?>>>", upstream.uri());
+    // A length that is not a multiple of 3 encodes with padding
+    if target.len() % 3 == 0 {
+        target.push('>');
+    }
+    let digest = CamoUrl::new(KEY).sign(&target).digest;
+
+    for encoded in [URL_SAFE.encode(&target), STANDARD.encode(&target)] {
+        assert!(encoded.ends_with('='));
+        let link = format!("/{}/{}", digest, encoded);
+        proxy(&[]).get(&link).await.assert_status_ok();
+    }
+}
+
+#[tokio::test]
+async fn test_size_limit() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/big.png"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(vec![0u8; 2048], "image/png"))
+        .mount(&upstream)
+        .await;
+    let target = signed(&format!("{}/big.png", upstream.uri()));
+
+    let response = proxy(&["--max-size", "1024"]).get(&target).await;
+    response.assert_status_payload_too_large();
+    proxy(&["--max-size", "4096"]).get(&target).await.assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_oversize_truncate() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/big.png"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(vec![0u8; 4096], "image/png"))
+        .mount(&upstream)
+        .await;
+    let target = signed(&format!("{}/big.png", upstream.uri()));
+
+    // Over the soft limit, under the hard cap
+    let response = proxy(&["--max-size", "1024", "--hard-max-size", "8192"]).get(&target).await;
+    response.assert_status_ok();
+    assert_eq!(response.as_bytes().len(), 4096);
+
+    let args = ["--max-size", "1024", "--hard-max-size", "2048"];
+    proxy(&args).get(&target).await.assert_status_payload_too_large();
+
+    let response = proxy(&[&args[..], &["--oversize", "truncate"]].concat()).get(&target).await;
+    response.assert_status_ok();
+    response.assert_header("x-camo-truncated", "4096");
+    assert_eq!(response.as_bytes().len(), 2048);
+}
+
+/// Upstream answering `/big` with `length` bytes of `content_type` and an `ETag`, chunked
+async fn chunked_upstream(content_type: &'static str, length: usize) -> String {
+    let app = axum::Router::new().route(
+        "/big",
+        axum::routing::get(move || async move {
+            let body = std::io::Cursor::new(vec![0u8; length]);
+            let stream = tokio_util::io::ReaderStream::with_capacity(body, 1024);
+            ([("content-type", content_type), ("etag", "\"v1\"")], Body::from_stream(stream))
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    format!("http://{}/big", addr)
+}
+
+/// Proxy `args` ahead of `target`, for responses whose body may fail mid-stream
+async fn oneshot(args: &[&str], target: &str) -> axum::response::Response {
+    let mut config = Config::parse_from(["camo", "--key", KEY].iter().chain(args));
+    config.block_private = false;
+    let request = Request::get(signed(target)).body(Body::empty()).unwrap();
+    create_router(&config).unwrap().oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_oversize_truncate_unknown_length() {
+    let target = chunked_upstream("image/png", 4096).await;
+    let args = ["--max-size", "1024", "--hard-max-size", "2048", "--oversize", "truncate"];
+
+    // Nothing announced a cut, so the body fails rather than passing for the whole image
+    let response = oneshot(&args, &target).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key("x-camo-truncated"));
+    assert!(axum::body::to_bytes(response.into_body(), usize::MAX).await.is_err());
+
+    let response = oneshot(&["--hard-max-size", "8192", "--oversize", "truncate"], &target).await;
+    assert_eq!(response.headers()["etag"], "\"v1\"");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(body.len(), 4096);
+}
+
+#[tokio::test]
+async fn test_oversize_partial_media() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/clip.mp4"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(vec![1u8; 4096], "video/mp4"))
+        .mount(&upstream)
+        .await;
+    let target = signed(&format!("{}/clip.mp4", upstream.uri()));
+    let args = ["--allow-content-type", "video/mp4", "--max-size", "1024"];
+
+    proxy(&args).get(&target).await.assert_status_payload_too_large();
+
+    let partial = [&args[..], &["--oversize-strategy", "partial"]].concat();
+    let response = proxy(&partial).get(&target).await;
+    response.assert_status(StatusCode::PARTIAL_CONTENT);
+    response.assert_header("content-range", "bytes 0-1023/4096");
+    assert_eq!(response.as_bytes().len(), 1024);
+}
+
+#[tokio::test]
+async fn test_oversize_partial_unknown_length() {
+    let target = chunked_upstream("video/mp4", 4096).await;
+    let args = ["--allow-content-type", "video/mp4", "--max-size", "1024"];
+
+    // No length to put in a Content-Range, so not a 206 but a body that fails
+    let partial = [&args[..], &["--oversize-strategy", "partial"]].concat();
+    let response = oneshot(&partial, &target).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key("content-range"));
+    assert!(axum::body::to_bytes(response.into_body(), usize::MAX).await.is_err());
+}
+
+#[tokio::test]
+async fn test_header_limits() {
+    let upstream = MockServer::start().await;
+    let mut template = image();
+    for i in 0..20 {
+        template = template.insert_header(format!("x-h{}", i).as_str(), "a".repeat(100).as_str());
+    }
+    Mock::given(path("/headers.png"))
+        .respond_with(template)
+        .mount(&upstream)
+        .await;
+    let target = signed(&format!("{}/headers.png", upstream.uri()));
+
+    let response = proxy(&["--max-header-count", "10"]).get(&target).await;
+    response.assert_status(StatusCode::BAD_GATEWAY);
+    let response = proxy(&["--max-header-size", "1024"]).get(&target).await;
+    response.assert_status(StatusCode::BAD_GATEWAY);
+    proxy(&[]).get(&target).await.assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_compressed_body() {
+    use async_compression::tokio::bufread::GzipEncoder;
+    use tokio::io::AsyncReadExt;
+
+    let plain = vec![0u8; 100_000];
+    let mut gzip = Vec::new();
+    GzipEncoder::new(plain.as_slice()).read_to_end(&mut gzip).await.unwrap();
+
+    let upstream = MockServer::start().await;
+    Mock::given(path("/bomb.png"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_raw(gzip, "image/png")
+                .insert_header("content-encoding", "gzip")
+                .insert_header("etag", "\"v1\""),
+        )
+        .mount(&upstream)
+        .await;
+    let url = format!("{}/bomb.png", upstream.uri());
+    let target = signed(&url);
+
+    let response = proxy(&[]).get(&target).await;
+    response.assert_status_ok();
+    assert!(response.maybe_header("content-encoding").is_none());
+    response.assert_header("etag", "W/\"v1\"");
+    assert_eq!(response.as_bytes().len(), plain.len());
+
+    // The cap is on the decoded bytes, whose length is only known once streamed
+    let args = ["--max-size", "1024", "--hard-max-size", "2048", "--oversize", "truncate"];
+    let response = oneshot(&args, &url).await;
+    assert!(!response.headers().contains_key("x-camo-truncated"));
+    assert!(axum::body::to_bytes(response.into_body(), usize::MAX).await.is_err());
+}
+
+#[tokio::test]
+async fn test_generated_etag() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .mount(&upstream)
+        .await;
+    let target = signed(&format!("{}/a.png", upstream.uri()));
+
+    let response = proxy(&[]).get(&target).await;
+    assert!(response.maybe_header("etag").is_none());
+
+    let proxy = proxy(&["--etag-max-size", "1024"]);
+    let response = proxy.get(&target).await;
+    response.assert_status_ok();
+    assert_eq!(response.as_bytes().as_ref(), PNG);
+    let etag = response.header("etag");
+    assert!(etag.to_str().unwrap().starts_with("W/\""), "{:?}", etag);
+
+    let response = proxy.get(&target).add_header("if-none-match", etag.clone()).await;
+    response.assert_status(StatusCode::NOT_MODIFIED);
+    assert_eq!(response.header("etag"), etag);
+
+    let response = proxy.get(&target).await;
+    assert_eq!(response.header("etag"), etag, "stable across requests");
+}
+
+#[tokio::test]
+async fn test_background_revalidation() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .and(header("if-none-match", "\"v1\""))
+        .respond_with(ResponseTemplate::new(304))
+        .with_priority(1)
+        .expect(1..)
+        .mount(&upstream)
+        .await;
+    let cacheable = image()
+        .insert_header("etag", "\"v1\"")
+        .insert_header("cache-control", "max-age=60");
+    Mock::given(path("/a.png"))
+        .respond_with(cacheable)
+        .expect(1)
+        .mount(&upstream)
+        .await;
+    let target = signed(&format!("{}/a.png", upstream.uri()));
+
+    let proxy = proxy(&["--revalidate-interval", "1"]);
+    proxy.get(&target).await.assert_status_ok();
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    // Still answered from the refreshed entry
+    let response = proxy.get(&target).add_header("if-none-match", "\"v1\"").await;
+    response.assert_status(StatusCode::NOT_MODIFIED);
+}
+
+#[tokio::test]
+async fn test_shadow() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .mount(&upstream)
+        .await;
+    let canary = MockServer::start().await;
+    Mock::given(header("x-camo-shadow", "1"))
+        .respond_with(image())
+        .expect(1)
+        .mount(&canary)
+        .await;
+    let target = signed(&format!("{}/a.png", upstream.uri()));
+
+    let proxy = proxy(&["--shadow-to", &canary.uri()]);
+    let response = proxy.get(&target).await;
+    response.assert_status_ok();
+    assert_eq!(response.as_bytes().as_ref(), PNG);
+    proxy.get("/health").await.assert_status_ok();
+
+    for _ in 0..50 {
+        if !canary.received_requests().await.unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let mirrored = canary.received_requests().await.unwrap();
+    assert_eq!(mirrored.len(), 1);
+    assert_eq!(mirrored[0].url.path(), target);
+}
+
+#[tokio::test]
+async fn test_timeout() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/slow.png"))
+        .respond_with(image().set_delay(Duration::from_secs(3)))
+        .mount(&upstream)
+        .await;
+    let target = signed(&format!("{}/slow.png", upstream.uri()));
+
+    let response = proxy(&["--timeout", "1"]).get(&target).await;
+    response.assert_status(StatusCode::GATEWAY_TIMEOUT);
+    response.assert_text("request timeout");
+}
+
+#[tokio::test]
+async fn test_private_network_blocked() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(image())
+        .expect(0)
+        .mount(&upstream)
+        .await;
+    let target = signed(&format!("{}/a.png", upstream.uri()));
+
+    let config = Config::parse_from(["camo", "--key", KEY]);
+    let proxy = TestServer::new(create_router(&config).unwrap()).unwrap();
+    proxy.get(&target).await.assert_status_forbidden();
+}
+
+#[tokio::test]
+async fn test_reputation_list() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(image())
+        .expect(0)
+        .mount(&upstream)
+        .await;
+    let list = std::env::temp_dir().join(format!("camo-reputation-{}.txt", std::process::id()));
+    std::fs::write(&list, "127.0.0.0/8 ; SBL1\n0.0.0.0 malware.example\n").unwrap();
+    let proxy = proxy(&["--reputation-list", list.to_str().unwrap()]);
+    std::fs::remove_file(&list).unwrap();
+
+    let target = format!("{}/a.png", upstream.uri());
+    proxy.get(&signed(&target)).await.assert_status_forbidden();
+    // Listed hosts are refused before they are resolved
+    let listed = signed("http://img.malware.example/a.png");
+    proxy.get(&listed).await.assert_status_forbidden();
+}
+
+#[tokio::test]
+async fn test_header_filtering() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .and(header("accept-language", "de"))
+        .respond_with(
+            image()
+                .insert_header("set-cookie", "session=1")
+                .insert_header("server", "origin/1.0")
+                .insert_header("x-upstream", "kept")
+                .insert_header("x-internal", "secret"),
+        )
+        .mount(&upstream)
+        .await;
+    let target = signed(&format!("{}/a.png", upstream.uri()));
+    let args = ["--forward-header", "accept-language", "--strip-header", "x-internal"];
+
+    let response = proxy(&args)
+        .get(&target)
+        .add_header("accept-language", "de")
+        .add_header("cookie", "viewer=1")
+        .add_header("authorization", "Bearer viewer")
+        .await;
+    response.assert_status_ok();
+    response.assert_header("x-upstream", "kept");
+    for name in ["set-cookie", "server", "x-internal"] {
+        assert!(!response.contains_header(name), "{} was not stripped", name);
+    }
+
+    let requests = upstream.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+    for name in ["cookie", "authorization"] {
+        assert!(!requests[0].headers.contains_key(name), "{} reached upstream", name);
+    }
+}
+
+#[tokio::test]
+async fn test_userinfo() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .mount(&upstream)
+        .await;
+    let with_credentials = upstream.uri().replace("http://", "http://user:pass@");
+    let target = signed(&format!("{}/a.png", with_credentials));
+
+    proxy(&[]).get(&target).await.assert_status_bad_request();
+    proxy(&["--userinfo", "strip"]).get(&target).await.assert_status_ok();
+
+    let requests = upstream.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+    assert!(!requests[0].headers.contains_key("authorization"));
+}
+
+#[tokio::test]
+async fn test_tenant_keys() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .expect(3)
+        .mount(&upstream)
+        .await;
+    let proxy = proxy(&["--tenant-key", "shop=shop-key", "--tenant-key", "blog=blog-key"]);
+    let target = format!("{}/a.png", upstream.uri());
+
+    let shop = CamoUrl::for_tenant("shop", "shop-key").sign(&target);
+    proxy.get(&shop.to_path()).await.assert_status_ok();
+    let query = format!("/t/shop/{}?url={}", shop.digest, urlencoding::encode(&target));
+    proxy.get(&query).await.assert_status_ok();
+    proxy.get(&signed(&target)).await.assert_status_ok();
+
+    // Each tenant's links only verify with its own key
+    let forged = CamoUrl::for_tenant("blog", "shop-key").sign(&target);
+    proxy.get(&forged.to_path()).await.assert_status_bad_request();
+    let unprefixed = CamoUrl::new("shop-key").sign(&target);
+    proxy.get(&unprefixed.to_path()).await.assert_status_bad_request();
+    let unknown = CamoUrl::for_tenant("news", "shop-key").sign(&target);
+    proxy.get(&unknown.to_path()).await.assert_status_not_found();
+}
+
+#[tokio::test]
+async fn test_tenant_cache_partitions() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/gone.png"))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(2)
+        .mount(&upstream)
+        .await;
+    let proxy = proxy(&["--tenant-key", "shop=shop-key"]);
+    let target = format!("{}/gone.png", upstream.uri());
+    let shop = CamoUrl::for_tenant("shop", "shop-key").sign(&target).to_path();
+
+    // The failure is remembered separately for each key that signed a link to it
+    for link in [&shop, &signed(&target), &shop] {
+        proxy.get(link).await.assert_status_not_found();
+    }
+    let response = proxy.get(&signed(&target)).await;
+    response.assert_header("x-camo-cache", "negative-hit");
+}
+
+#[tokio::test]
+async fn test_tenant_quota() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .expect(3)
+        .mount(&upstream)
+        .await;
+    let proxy = proxy(&["--tenant-key", "shop=shop-key", "--tenant-quota-requests", "2"]);
+    let target = format!("{}/a.png", upstream.uri());
+    let shop = CamoUrl::for_tenant("shop", "shop-key").sign(&target).to_path();
+
+    // Forged links do not use up the quota
+    let forged = CamoUrl::for_tenant("shop", "other-key").sign(&target).to_path();
+    proxy.get(&forged).await.assert_status_bad_request();
+
+    proxy.get(&shop).await.assert_status_ok();
+    proxy.get(&shop).await.assert_status_ok();
+    let response = proxy.get(&shop).await;
+    response.assert_status(StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.contains_header("retry-after"));
+
+    // Links without a tenant are not limited
+    proxy.get(&signed(&target)).await.assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_referer_hosts() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .expect(3)
+        .mount(&upstream)
+        .await;
+    let target = signed(&format!("{}/a.png", upstream.uri()));
+    let proxy = proxy(&["--referer-hosts", "example.com,*.example.org"]);
+
+    let referer = |value: &'static str| proxy.get(&target).add_header("referer", value);
+    referer("https://example.com/post/1").await.assert_status_ok();
+    referer("https://blog.example.org/").await.assert_status_ok();
+    let response = referer("https://hotlinker.example/").await;
+    response.assert_status(StatusCode::FORBIDDEN);
+    response.assert_text("referer not allowed");
+
+    // Without a referer the page cannot be told, allowed unless configured otherwise
+    proxy.get(&target).await.assert_status_ok();
+    let strict = self::proxy(&["--referer-hosts", "example.com", "--empty-referer", "deny"]);
+    strict.get(&target).await.assert_status(StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_session_binding() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image().insert_header("cache-control", "public, max-age=60"))
+        .expect(3)
+        .mount(&upstream)
+        .await;
+    let target = format!("{}/a.png", upstream.uri());
+    let proxy = proxy(&["--session-cookie", "sid", "--session-header", "x-session"]);
+    let bound = CamoUrl::new(KEY).with_session("abc").sign(&target).to_path();
+
+    let response = proxy.get(&bound).add_header("cookie", "theme=dark; sid=abc").await;
+    response.assert_status_ok();
+    response.assert_header("cache-control", "private, max-age=60");
+    proxy.get(&bound).add_header("x-session", "abc").await.assert_status_ok();
+
+    // Shared outside the session, the link is refused
+    let response = proxy.get(&bound).add_header("cookie", "sid=xyz").await;
+    response.assert_status(StatusCode::FORBIDDEN);
+    response.assert_text("session mismatch");
+    proxy.get(&bound).await.assert_status(StatusCode::FORBIDDEN);
+
+    // Links that are not bound work for anyone
+    proxy.get(&signed(&target)).await.assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_rate_limit() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .expect(3)
+        .mount(&upstream)
+        .await;
+    let target = signed(&format!("{}/a.png", upstream.uri()));
+    let mut config = Config::parse_from([
+        "camo",
+        "--key",
+        KEY,
+        "--rate-limit",
+        "0.1",
+        "--rate-limit-burst",
+        "2",
+    ]);
+    config.block_private = false;
+    // As the listener would, so the proxy knows who is asking
+    let client = |ip: [u8; 4]| ConnectInfo(SocketAddr::from((ip, 40000)));
+    let router = create_router(&config).unwrap();
+    let proxy = TestServer::new(router.clone().layer(Extension(client([192, 0, 2, 1])))).unwrap();
+
+    proxy.get(&target).await.assert_status_ok();
+    proxy.get(&target).await.assert_status_ok();
+    let response = proxy.get(&target).await;
+    response.assert_status(StatusCode::TOO_MANY_REQUESTS);
+    response.assert_header("retry-after", "10");
+    response.assert_text("rate limit exceeded");
+
+    // Another client has its own share
+    let other = TestServer::new(router.layer(Extension(client([192, 0, 2, 2])))).unwrap();
+    other.get(&target).await.assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_send_timeout() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/slow.png"))
+        .respond_with(image().set_delay(Duration::from_secs(3)))
+        .mount(&upstream)
+        .await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .mount(&upstream)
+        .await;
+    let proxy = proxy(&["--send-timeout", "1"]);
+
+    let response = proxy.get(&signed(&format!("{}/slow.png", upstream.uri()))).await;
+    response.assert_status(StatusCode::GATEWAY_TIMEOUT);
+    response.assert_text("request timeout");
+    let response = proxy.get(&signed(&format!("{}/a.png", upstream.uri()))).await;
+    response.assert_status_ok();
+    assert_eq!(response.as_bytes().as_ref(), PNG);
+}
+
+#[tokio::test]
+async fn test_memory_budget() {
+    let upstream = MockServer::start().await;
+    let large = [PNG, &[0; 64 * 1024]].concat();
+    Mock::given(path("/large.png"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(large, "image/png"))
+        .mount(&upstream)
+        .await;
+    let target = signed(&format!("{}/large.png", upstream.uri()));
+    let mut config = Config::parse_from(["camo", "--key", KEY, "--memory-budget", "1024"]);
+    config.block_private = false;
+    let router = create_router(&config).unwrap();
+    let get = || Request::get(target.as_str()).body(Body::empty()).unwrap();
+
+    // A client that has not taken its body yet keeps it in memory
+    let response = router.clone().oneshot(get()).await.unwrap();
+    let mut body = response.into_body().into_data_stream();
+    let chunk = std::future::poll_fn(|cx| Pin::new(&mut body).poll_next(cx)).await;
+    assert!(chunk.unwrap().unwrap().len() > 1024);
+
+    let response = router.clone().oneshot(get()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let response = router.clone().oneshot(Request::get("/health").body(Body::empty()).unwrap());
+    assert_eq!(response.await.unwrap().status(), StatusCode::OK);
+
+    drop(body);
+    let response = router.oneshot(get()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_link_params() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image())
+        .mount(&upstream)
+        .await;
+    let proxy = proxy(&[]);
+    let target = format!("{}/a.png", upstream.uri());
+    let camo = CamoUrl::new(KEY);
+
+    let sized = |max_size| camo.clone().with_max_size(max_size).sign(&target).to_path();
+    proxy.get(&sized(PNG.len() as u64)).await.assert_status_ok();
+    proxy.get(&sized(4)).await.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+
+    let video = camo.clone().with_content_class(ContentClass::Video).sign(&target).to_path();
+    proxy.get(&video).await.assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let image = camo.clone().with_content_class(ContentClass::Image).sign(&target).to_path();
+    proxy.get(&image).await.assert_status_ok();
+
+    let expired = camo.clone().with_expiry(SystemTime::now() - Duration::from_secs(60));
+    proxy.get(&expired.sign(&target).to_path()).await.assert_status(StatusCode::GONE);
+    let fresh = camo.clone().with_ttl(Duration::from_secs(60)).sign(&target).to_path();
+    proxy.get(&fresh).await.assert_status_ok();
+
+    // The parameters are signed: dropping or changing them breaks the link
+    let dropped = sized(4).split('?').next().unwrap().to_string();
+    proxy.get(&dropped).await.assert_status_bad_request();
+    let raised = sized(4).replace("max_size=4", "max_size=4000");
+    proxy.get(&raised).await.assert_status_bad_request();
+
+    // Limits failing one link do not affect others to the same target
+    proxy.get(&signed(&target)).await.assert_status_ok();
+    let digest = camo.with_max_size(4).sign(&target).digest;
+    let query = format!("/{}?url={}&max_size=4", digest, urlencoding::encode(&target));
+    proxy.get(&query).await.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn test_link_expiry_caps_caching() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/a.png"))
+        .respond_with(image().insert_header("cache-control", "public, max-age=86400"))
+        .mount(&upstream)
+        .await;
+    let proxy = proxy(&["--min-cache-ttl", "3600"]);
+    let target = format!("{}/a.png", upstream.uri());
+
+    // Caches in front of the proxy may not serve the link past its expiry
+    let expiring = CamoUrl::new(KEY).with_ttl(Duration::from_secs(60)).sign(&target);
+    let response = proxy.get(&expiring.to_path()).await;
+    let cache_control = response.header("cache-control");
+    let max_age = cache_control.to_str().unwrap().strip_prefix("public, max-age=").unwrap();
+    assert!(max_age.parse::<u64>().unwrap() <= 60);
+
+    proxy
+        .get(&signed(&target))
+        .await
+        .assert_header("cache-control", "public, max-age=86400");
+}